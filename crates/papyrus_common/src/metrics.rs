@@ -16,6 +16,9 @@ pub const PAPYRUS_STATE_MARKER: &str = "papyrus_state_marker";
 /// corresponding compiled classes.
 pub const PAPYRUS_COMPILED_CLASS_MARKER: &str = "papyrus_compiled_class_marker";
 
+/// The number of compiled classes the sync didn't download because they were already stored.
+pub const PAPYRUS_NUM_SKIPPED_COMPILED_CLASSES: &str = "papyrus_num_skipped_compiled_classes";
+
 /// The base layer marker is the first block number for which the node does not guarantee L1
 /// finality.
 pub const PAPYRUS_BASE_LAYER_MARKER: &str = "papyrus_base_layer_marker";
@@ -33,6 +36,34 @@ pub const PAPYRUS_NUM_ACTIVE_INBOUND_SESSIONS: &str = "papyrus_num_active_inboun
 /// The number of active sessions this peer has in which it requests data.
 pub const PAPYRUS_NUM_ACTIVE_OUTBOUND_SESSIONS: &str = "papyrus_num_active_outbound_sessions";
 
+/// The number of inbound connections that are currently being upgraded.
+pub const PAPYRUS_NUM_IN_PROGRESS_INBOUND_UPGRADES: &str =
+    "papyrus_num_in_progress_inbound_upgrades";
+
+/// The number of headers this node served to other peers whose stored hash doesn't match their
+/// content.
+pub const PAPYRUS_NUM_SERVED_HEADER_HASH_MISMATCHES: &str =
+    "papyrus_num_served_header_hash_mismatches";
+
+/// The number of state diffs the sync stored.
+pub const PAPYRUS_SYNC_NUM_STORED_STATE_DIFFS: &str = "papyrus_sync_num_stored_state_diffs";
+
+/// The estimated size in bytes of the state diffs the sync stored.
+pub const PAPYRUS_SYNC_STATE_DIFF_BYTES: &str = "papyrus_sync_state_diff_bytes";
+
+/// The number of sync events whose storing took longer than the slow store warning threshold.
+pub const PAPYRUS_SYNC_NUM_SLOW_STORES: &str = "papyrus_sync_num_slow_stores";
+
+/// The number of blocks the sync reverted.
+pub const PAPYRUS_SYNC_NUM_REVERTED_BLOCKS: &str = "papyrus_sync_num_reverted_blocks";
+
+/// The number of reverts the sync made. A single revert may revert several blocks.
+pub const PAPYRUS_SYNC_NUM_REVERTS: &str = "papyrus_sync_num_reverts";
+
+/// The time, in seconds, it took to download a state diff from the central source.
+pub const PAPYRUS_SYNC_STATE_DIFF_DOWNLOAD_LATENCY_SECONDS: &str =
+    "papyrus_sync_state_diff_download_latency_seconds";
+
 // TODO: consider making this value non static and add a way to change this while the app is
 // running. e.g via a monitoring endpoint.
 /// Global variable set by the main config to enable collecting profiling metrics.
@@ -187,6 +187,22 @@ fn test_env_nested_params() {
     assert_eq!(4, loaded_config.inner_config.o);
 }
 
+#[test]
+fn none_config_does_not_remove_params_with_the_same_prefix() {
+    let mut config_map = BTreeMap::from([
+        ("opt.#is_none".to_owned(), json!(true)),
+        ("opt.a".to_owned(), json!(1)),
+        ("opt_sibling.a".to_owned(), json!(2)),
+    ]);
+
+    update_optional_values(&mut config_map);
+
+    assert_eq!(
+        config_map,
+        BTreeMap::from([("opt".to_owned(), json!(null)), ("opt_sibling.a".to_owned(), json!(2))])
+    );
+}
+
 #[test]
 fn test_config_presentation() {
     let config = TypicalConfig { a: Duration::from_secs(1), b: "bbb".to_owned(), c: false };
@@ -166,16 +166,16 @@ pub(crate) fn update_optional_values(config_map: &mut BTreeMap<ParamPath, Value>
             none_params.push(optional_param);
         }
     }
-    // Remove param paths that start with any None param.
+    // Remove param paths that are nested in any None param.
     config_map.retain(|param_path, _| {
-        !any(&none_params, |none_param| param_path.starts_with(none_param))
+        !any(&none_params, |none_param| is_nested_param(param_path, none_param))
     });
 
     // Set null for the None params.
     for none_param in &none_params {
         let mut is_nested_in_outer_none_config = false;
         for other_none_param in &none_params {
-            if none_param.starts_with(other_none_param) && none_param != other_none_param {
+            if is_nested_param(none_param, other_none_param) && none_param != other_none_param {
                 is_nested_in_outer_none_config = true;
             }
         }
@@ -186,6 +186,14 @@ pub(crate) fn update_optional_values(config_map: &mut BTreeMap<ParamPath, Value>
     }
 }
 
+// Returns whether the param is the given config or one of its (possibly nested) params. A param
+// whose name only starts with the name of the config, such as "a_b" for "a", isn't nested in it.
+fn is_nested_param(param_path: &str, config_path: &str) -> bool {
+    param_path
+        .strip_prefix(config_path)
+        .is_some_and(|suffix| suffix.is_empty() || suffix.starts_with('.'))
+}
+
 pub(crate) fn update_config_map(
     config_map: &mut BTreeMap<ParamPath, Value>,
     types_map: &BTreeMap<ParamPath, SerializationType>,
@@ -1,5 +1,6 @@
 // TODO(shahak): Add a test for executing when there's a missing casm that's not required and when
 // there's a missing casm that is required.
+use std::cell::Cell;
 use std::sync::Arc;
 
 use assert_matches::assert_matches;
@@ -7,11 +8,26 @@ use blockifier::abi::abi_utils::get_storage_var_address;
 use blockifier::execution::call_info::Retdata;
 use blockifier::execution::errors::ConstructorEntryPointExecutionError;
 use blockifier::execution::stack_trace::gen_transaction_execution_error_trace;
+use blockifier::state::cached_state::CachedState;
 use blockifier::transaction::errors::TransactionExecutionError as BlockifierTransactionExecutionError;
 use indexmap::indexmap;
+use papyrus_storage::body::BodyStorageWriter;
+use papyrus_storage::class::ClassStorageWriter;
+use papyrus_storage::header::{HeaderStorageReader, HeaderStorageWriter};
+use papyrus_storage::state::{StateStorageReader, StateStorageWriter};
 use papyrus_storage::test_utils::get_test_storage;
+use papyrus_storage::StorageWriter;
 use pretty_assertions::assert_eq;
-use starknet_api::block::{BlockNumber, StarknetVersion};
+use starknet_api::block::{
+    BlockBody,
+    BlockHash,
+    BlockHeader,
+    BlockNumber,
+    BlockTimestamp,
+    GasPrice,
+    GasPricePerToken,
+    StarknetVersion,
+};
 use starknet_api::core::{
     ChainId,
     ClassHash,
@@ -20,46 +36,85 @@ use starknet_api::core::{
     EntryPointSelector,
     Nonce,
     PatriciaKey,
+    SequencerContractAddress,
+};
+use starknet_api::data_availability::L1DataAvailabilityMode;
+use starknet_api::deprecated_contract_class::EntryPointType;
+use starknet_api::state::{StateNumber, StorageKey, ThinStateDiff};
+use starknet_api::transaction::{
+    Calldata,
+    ContractAddressSalt,
+    Fee,
+    InvokeTransaction,
+    InvokeTransactionOutput,
+    InvokeTransactionV1,
+    Tip,
+    Transaction,
+    TransactionHash,
+    TransactionOutput,
+    TransactionVersion,
 };
-use starknet_api::state::{StateNumber, ThinStateDiff};
-use starknet_api::transaction::{Calldata, Fee};
 use starknet_api::{calldata, class_hash, contract_address, felt, patricia_key};
 use starknet_types_core::felt::Felt;
 
-use crate::execution_utils::selector_from_name;
+use crate::execution_utils::{calculate_contract_address, selector_from_name};
+use crate::hot_state::HotStateCache;
 use crate::objects::{
+    BlockContextOverrides,
     DeclareTransactionTrace,
     DeployAccountTransactionTrace,
+    ExecutionResourceBounds,
     FeeEstimation,
+    FunctionCall,
     FunctionInvocationResult,
     InvokeTransactionTrace,
+    L1HandlerTransactionTrace,
     PriceUnit,
+    RevertReason,
     TransactionSimulationOutput,
     TransactionTrace,
 };
 use crate::test_utils::{
+    call_contract,
+    deploy_with_constructor,
     execute_simulate_transactions,
+    execute_simulate_transactions_with_overrides,
+    get_test_block_info_contract_class,
+    l1_gas_resource_bounds,
     prepare_storage,
     TxsScenarioBuilder,
     ACCOUNT_ADDRESS,
     ACCOUNT_CLASS_HASH,
     ACCOUNT_INITIAL_BALANCE,
+    BLOCK_TIMESTAMP,
     CHAIN_ID,
     CONTRACT_ADDRESS,
     DEPRECATED_CONTRACT_ADDRESS,
     GAS_PRICE,
+    L1_SENDER_ADDRESS,
+    MAX_FEE,
+    MAX_L1_GAS_AMOUNT,
     NEW_ACCOUNT_ADDRESS,
     SEQUENCER_ADDRESS,
     TEST_ERC20_CONTRACT_ADDRESS,
 };
+use crate::state_reader::ExecutionStateReader;
 use crate::testing_instances::get_test_execution_config;
 use crate::{
+    create_block_context,
     estimate_fee,
     execute_call,
+    execute_simulate_blocks,
+    execute_transactions_on_state,
+    execute_transactions_speculatively,
     get_versioned_constants,
+    replay_block,
+    simulate_transactions,
     ExecutableTransactionInput,
+    ExecutionConfig,
     ExecutionError,
     FeeEstimationResult,
+    ProposedBlock,
     RevertedTransaction,
 };
 
@@ -170,6 +225,36 @@ fn execute_call_cairo1() {
     assert_eq!(retdata, Retdata(vec![value]));
 }
 
+#[test]
+fn call_contract_without_transaction() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    prepare_storage(storage_writer);
+
+    let retdata = call_contract(
+        storage_reader.clone(),
+        BlockNumber(0),
+        *DEPRECATED_CONTRACT_ADDRESS,
+        selector_from_name("return_result"),
+        calldata![felt!(123_u128)],
+    )
+    .unwrap();
+    assert_eq!(retdata, vec![felt!(123_u128)]);
+
+    let missing_contract_address = contract_address!("0x1234");
+    let result = call_contract(
+        storage_reader,
+        BlockNumber(0),
+        missing_contract_address,
+        selector_from_name("return_result"),
+        calldata![felt!(123_u128)],
+    );
+    assert_matches!(
+        result,
+        Err(ExecutionError::ContractNotFound { contract_address, .. })
+            if contract_address == missing_contract_address
+    );
+}
+
 // TODO(yair): Compare to the expected fee instead of asserting that it is not zero (all
 // estimate_fee tests).
 #[test]
@@ -233,6 +318,34 @@ fn estimate_fee_combination() {
     }
 }
 
+#[test]
+fn estimate_fee_in_the_denomination_of_the_transaction_version() {
+    let txs = TxsScenarioBuilder::default()
+        .invoke_deprecated(*ACCOUNT_ADDRESS, *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        .declare_class(*ACCOUNT_ADDRESS)
+        .declare(
+            *ACCOUNT_ADDRESS,
+            class_hash!("0x1234"),
+            CompiledClassHash::default(),
+            TransactionVersion::THREE,
+        )
+        .collect();
+
+    let fees = estimate_fees(txs).expect("Fee estimation should succeed.");
+    assert_eq!(fees.len(), 3);
+    // Transactions before V3 are priced in WEI.
+    for fee in &fees[..2] {
+        assert_eq!(fee.unit, PriceUnit::Wei);
+        assert_eq!(fee.gas_price, GAS_PRICE.price_in_wei);
+    }
+    // V3 transactions are priced in FRI.
+    assert_eq!(fees[2].unit, PriceUnit::Fri);
+    assert_eq!(fees[2].gas_price, GAS_PRICE.price_in_fri);
+    for fee in fees {
+        assert_ne!(fee.overall_fee, Fee(0));
+    }
+}
+
 #[test]
 fn estimate_fee_reverted() {
     let non_existing_contract = contract_address!("0x987");
@@ -257,6 +370,7 @@ fn estimate_fees(txs: Vec<ExecutableTransactionInput>) -> FeeEstimationResult {
         StateNumber::unchecked_right_after_block(BlockNumber(0)),
         BlockNumber(1),
         &get_test_execution_config(),
+        None,
         false,
         // TODO(yair): Add test for blob fee estimation.
         true,
@@ -273,6 +387,49 @@ fn serialization_precision() {
     assert_eq!(input, deserialized);
 }
 
+#[test]
+fn simulate_with_hot_state_cache() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    prepare_storage(storage_writer);
+
+    let tx = TxsScenarioBuilder::default()
+        .invoke_deprecated(*ACCOUNT_ADDRESS, *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        .collect();
+    let uncached_results =
+        execute_simulate_transactions(storage_reader.clone(), None, tx.clone(), None, true, true);
+
+    let execution_config = get_test_execution_config();
+    let hot_state_cache = HotStateCache::new(
+        execution_config.hot_state_blocks,
+        execution_config.hot_state_max_entries,
+    );
+    let simulate_with_cache = || {
+        simulate_transactions(
+            tx.clone(),
+            None,
+            &ChainId::Other(CHAIN_ID.to_string()),
+            storage_reader.clone(),
+            None,
+            StateNumber::unchecked_right_after_block(BlockNumber(0)),
+            BlockNumber(1),
+            BlockContextOverrides::default(),
+            None,
+            &get_test_execution_config(),
+            Some(&hot_state_cache),
+            true,
+            true,
+            true,
+        )
+        .unwrap()
+    };
+
+    // The first simulation fills the cache and the second one reads from it.
+    assert_eq!(simulate_with_cache(), uncached_results);
+    let hits_after_first_simulation = hot_state_cache.hits();
+    assert_eq!(simulate_with_cache(), uncached_results);
+    assert!(hot_state_cache.hits() > hits_after_first_simulation);
+}
+
 #[test]
 fn simulate_invoke() {
     let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
@@ -352,6 +509,37 @@ fn simulate_invoke() {
     }
 }
 
+#[test]
+fn simulate_l1_handler() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    prepare_storage(storage_writer);
+
+    let payload = calldata![felt!("0x123"), felt!("0x456")];
+    let txs = TxsScenarioBuilder::default()
+        .l1_handler(*CONTRACT_ADDRESS, selector_from_name("l1_handle"), payload)
+        .collect();
+    let mut results = execute_simulate_transactions(storage_reader, None, txs, None, true, true);
+    let result = results.remove(0);
+
+    let TransactionTrace::L1Handler(L1HandlerTransactionTrace { function_invocation }) =
+        result.transaction_trace
+    else {
+        panic!("Wrong trace type, expected L1HandlerTransactionTrace.")
+    };
+    // The handler is called with the L1 sender followed by the payload.
+    assert_eq!(
+        function_invocation.function_call,
+        FunctionCall {
+            contract_address: *CONTRACT_ADDRESS,
+            entry_point_selector: selector_from_name("l1_handle"),
+            calldata: calldata![*L1_SENDER_ADDRESS, felt!("0x123"), felt!("0x456")],
+        }
+    );
+    assert_eq!(function_invocation.entry_point_type, EntryPointType::L1Handler);
+    // The fee of the message is paid on L1, but its gas is still estimated.
+    assert_ne!(result.fee_estimation.gas_consumed, Felt::ZERO);
+}
+
 #[test]
 fn simulate_declare_deprecated() {
     let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
@@ -474,6 +662,39 @@ fn simulate_declare() {
     }
 }
 
+#[test]
+fn simulate_declare_with_given_hashes() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    prepare_storage(storage_writer);
+
+    let tx = TxsScenarioBuilder::default()
+        .declare(
+            *ACCOUNT_ADDRESS,
+            class_hash!("0x1234"),
+            CompiledClassHash::default(),
+            TransactionVersion::ONE,
+        )
+        .declare(
+            *ACCOUNT_ADDRESS,
+            class_hash!("0x1235"),
+            CompiledClassHash(felt!("0x5678")),
+            TransactionVersion::TWO,
+        )
+        .collect();
+    let results = execute_simulate_transactions(storage_reader, None, tx, None, true, true);
+
+    assert_eq!(results.len(), 2);
+    for result in results {
+        assert_matches!(
+            result.transaction_trace,
+            TransactionTrace::Declare(DeclareTransactionTrace {
+                validate_invocation: Some(_),
+                fee_transfer_invocation: Some(_),
+            })
+        );
+    }
+}
+
 #[test]
 fn simulate_deploy_account() {
     let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
@@ -549,6 +770,49 @@ fn simulate_deploy_account() {
     }
 }
 
+#[test]
+fn simulate_deploy_account_with_salt() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    prepare_storage(storage_writer);
+
+    let salt = ContractAddressSalt(felt!("0x17"));
+    let account_address = calculate_contract_address(
+        salt,
+        *ACCOUNT_CLASS_HASH,
+        &calldata![],
+        ContractAddress::default(),
+    );
+    let txs = TxsScenarioBuilder::default()
+        .deploy_account_with(*ACCOUNT_CLASS_HASH, calldata![], salt)
+        // The nonce of the new account is resolved by the builder.
+        .invoke_deprecated(account_address, *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        .collect();
+
+    let mut result = execute_simulate_transactions(storage_reader, None, txs, None, false, false);
+    assert_eq!(result.len(), 2);
+
+    let Some(TransactionSimulationOutput {
+        transaction_trace: TransactionTrace::Invoke(invoke_trace),
+        ..
+    }) = result.pop()
+    else {
+        panic!("Wrong trace type, expected InvokeTransactionTrace.")
+    };
+    let Some(TransactionSimulationOutput {
+        transaction_trace: TransactionTrace::DeployAccount(deploy_account_trace),
+        ..
+    }) = result.pop()
+    else {
+        panic!("Wrong trace type, expected DeployAccountTransactionTrace.")
+    };
+
+    assert_eq!(
+        deploy_account_trace.constructor_invocation.function_call.contract_address,
+        account_address
+    );
+    assert_matches!(invoke_trace.execute_invocation, FunctionInvocationResult::Ok(_));
+}
+
 #[test]
 fn simulate_invoke_from_new_account() {
     let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
@@ -752,6 +1016,32 @@ fn induced_state_diff() {
     assert_eq!(simulation_results[3].induced_state_diff, expected_deploy_account);
 }
 
+#[test]
+fn simulating_twice_gives_identical_serialized_outputs() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    prepare_storage(storage_writer);
+
+    let tx = TxsScenarioBuilder::default()
+        .invoke_deprecated(*ACCOUNT_ADDRESS, *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        .declare_class(*ACCOUNT_ADDRESS)
+        .deploy_account()
+        .invoke_deprecated(*NEW_ACCOUNT_ADDRESS, *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        .collect();
+    let simulate = || {
+        let simulation_results = execute_simulate_transactions(
+            storage_reader.clone(),
+            None,
+            tx.clone(),
+            None,
+            true,
+            true,
+        );
+        serde_json::to_string(&simulation_results).unwrap()
+    };
+
+    assert_eq!(simulate(), simulate());
+}
+
 #[test]
 fn simulate_with_query_bit_outputs_same_as_no_query_bit() {
     let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
@@ -857,3 +1147,678 @@ fn test_get_versioned_constants() {
     let versioned_constants = get_versioned_constants(Some(&starknet_version_13_1)).unwrap();
     assert_eq!(versioned_constants.invoke_tx_max_n_steps, 4_000_000);
 }
+
+// Appends a block that deploys Blockifier's Cairo 0 test contract and returns its address.
+fn append_block_with_block_info_contract(
+    storage_writer: &mut StorageWriter,
+    block_number: BlockNumber,
+) -> ContractAddress {
+    let block_info_class_hash = class_hash!("0x4");
+    let block_info_contract_address = contract_address!("0x4");
+    storage_writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_header(
+            block_number,
+            &BlockHeader {
+                l1_gas_price: *GAS_PRICE,
+                sequencer: *SEQUENCER_ADDRESS,
+                timestamp: *BLOCK_TIMESTAMP,
+                block_hash: BlockHash(felt!(2_u8)),
+                parent_hash: BlockHash(felt!(1_u8)),
+                block_number,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .append_body(block_number, BlockBody::default())
+        .unwrap()
+        .append_state_diff(
+            block_number,
+            ThinStateDiff {
+                deployed_contracts: indexmap!(block_info_contract_address => block_info_class_hash),
+                deprecated_declared_classes: vec![block_info_class_hash],
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .append_classes(
+            block_number,
+            &[],
+            &[(block_info_class_hash, &get_test_block_info_contract_class())],
+        )
+        .unwrap()
+        .commit()
+        .unwrap();
+    block_info_contract_address
+}
+
+#[test]
+fn simulate_with_block_context_overrides() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    let mut storage_writer = prepare_storage(storage_writer);
+    let block_number = BlockNumber(2);
+    let block_info_contract_address =
+        append_block_with_block_info_contract(&mut storage_writer, block_number);
+
+    let timestamp = BlockTimestamp(5678);
+    let overridden_block_number = BlockNumber(1000);
+    let sequencer_address = SequencerContractAddress(contract_address!("0xb"));
+    // Returns for each of the block timestamp, block number and sequencer address whether the
+    // contract saw the expected value.
+    let simulate_block_info_calls = |block_context_overrides| {
+        let txs = [
+            ("test_get_block_timestamp", felt!(timestamp.0)),
+            ("test_get_block_number", felt!(overridden_block_number.0)),
+            ("test_get_sequencer_address", *sequencer_address.0.key()),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(nonce, (entry_point, expected_value))| {
+            ExecutableTransactionInput::Invoke(
+                InvokeTransaction::V1(InvokeTransactionV1 {
+                    calldata: calldata![
+                        *block_info_contract_address.0.key(),
+                        selector_from_name(entry_point).0,
+                        felt!(1_u8),
+                        expected_value
+                    ],
+                    max_fee: *MAX_FEE,
+                    sender_address: *ACCOUNT_ADDRESS,
+                    nonce: Nonce(felt!(nonce as u64)),
+                    ..Default::default()
+                }),
+                false,
+            )
+        })
+        .collect();
+        simulate_transactions(
+            txs,
+            None,
+            &ChainId::Other(CHAIN_ID.to_string()),
+            storage_reader.clone(),
+            None,
+            StateNumber::unchecked_right_after_block(block_number),
+            block_number,
+            block_context_overrides,
+            None,
+            &get_test_execution_config(),
+            None,
+            false,
+            false,
+            true,
+        )
+        .unwrap()
+        .into_iter()
+        .map(|simulation_output| {
+            let TransactionTrace::Invoke(invoke_trace) = simulation_output.transaction_trace else {
+                panic!("Expected an invoke transaction trace.");
+            };
+            matches!(invoke_trace.execute_invocation, FunctionInvocationResult::Ok(_))
+        })
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        simulate_block_info_calls(BlockContextOverrides::default()),
+        vec![false, false, false]
+    );
+    assert_eq!(
+        simulate_block_info_calls(BlockContextOverrides {
+            timestamp: Some(timestamp),
+            ..Default::default()
+        }),
+        vec![true, false, false]
+    );
+    assert_eq!(
+        simulate_block_info_calls(BlockContextOverrides {
+            block_number: Some(overridden_block_number),
+            timestamp: Some(timestamp),
+            sequencer_address: Some(sequencer_address),
+            ..Default::default()
+        }),
+        vec![true, true, true]
+    );
+
+    // The overrides don't change the stored block.
+    let header =
+        storage_reader.begin_ro_txn().unwrap().get_block_header(block_number).unwrap().unwrap();
+    assert_eq!(header.block_number, block_number);
+    assert_eq!(header.timestamp, *BLOCK_TIMESTAMP);
+}
+
+#[test]
+fn simulate_with_overridden_gas_price() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    prepare_storage(storage_writer);
+
+    let tx = TxsScenarioBuilder::default()
+        .invoke_deprecated(*ACCOUNT_ADDRESS, *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        .collect();
+    let l1_gas_price = GasPricePerToken {
+        price_in_wei: GasPrice(GAS_PRICE.price_in_wei.0 / 2),
+        price_in_fri: GAS_PRICE.price_in_fri,
+    };
+    let block_context_overrides =
+        BlockContextOverrides { l1_gas_price: Some(l1_gas_price), ..Default::default() };
+
+    let stored_price_result =
+        execute_simulate_transactions(storage_reader.clone(), None, tx.clone(), None, true, false)
+            .remove(0);
+    let overridden_price_result = execute_simulate_transactions_with_overrides(
+        storage_reader,
+        None,
+        tx,
+        None,
+        block_context_overrides,
+        true,
+        false,
+    )
+    .remove(0);
+
+    assert_eq!(stored_price_result.fee_estimation.gas_price, GAS_PRICE.price_in_wei);
+    assert_eq!(overridden_price_result.fee_estimation.gas_price, l1_gas_price.price_in_wei);
+    assert_eq!(
+        overridden_price_result.fee_estimation.gas_consumed,
+        stored_price_result.fee_estimation.gas_consumed
+    );
+    assert!(
+        overridden_price_result.fee_estimation.overall_fee
+            < stored_price_result.fee_estimation.overall_fee
+    );
+}
+
+#[test]
+fn simulate_invoke_v3_with_l1_gas_bounds() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    prepare_storage(storage_writer);
+
+    let simulate_with_max_l1_gas = |max_l1_gas_amount| {
+        let txs = TxsScenarioBuilder::default()
+            .invoke_v3(
+                *ACCOUNT_ADDRESS,
+                *DEPRECATED_CONTRACT_ADDRESS,
+                l1_gas_resource_bounds(max_l1_gas_amount),
+                Tip::default(),
+            )
+            .collect();
+        simulate_transactions(
+            txs,
+            None,
+            &ChainId::Other(CHAIN_ID.to_string()),
+            storage_reader.clone(),
+            None,
+            StateNumber::unchecked_right_after_block(BlockNumber(0)),
+            BlockNumber(1),
+            BlockContextOverrides::default(),
+            None,
+            &get_test_execution_config(),
+            None,
+            true,
+            true,
+            true,
+        )
+    };
+
+    let output = simulate_with_max_l1_gas(MAX_L1_GAS_AMOUNT).unwrap().remove(0);
+    assert_matches!(
+        output.transaction_trace,
+        TransactionTrace::Invoke(InvokeTransactionTrace {
+            execute_invocation: FunctionInvocationResult::Ok(_),
+            fee_transfer_invocation: Some(_),
+            ..
+        })
+    );
+    assert_eq!(output.fee_estimation.unit, PriceUnit::Fri);
+    assert_eq!(output.fee_estimation.gas_price, GAS_PRICE.price_in_fri);
+
+    // The bound is lower than the gas the transaction needs before it's even executed.
+    assert_matches!(
+        simulate_with_max_l1_gas(1),
+        Err(ExecutionError::TransactionExecutionError { transaction_index: 0, execution_error })
+        if execution_error.contains("Max L1 gas amount (1) is lower than the minimal gas amount")
+    );
+}
+
+#[test]
+fn deploy_with_constructor_writes_the_constructor_storage() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    let mut storage_writer = prepare_storage(storage_writer);
+    // The constructor of the deprecated test contract writes the value to the given key.
+    let key = patricia_key!("0x25");
+    let value = felt!("0x99");
+
+    let contract_address = deploy_with_constructor(
+        storage_reader.clone(),
+        &mut storage_writer,
+        class_hash!("0x1"),
+        calldata![*key.key(), value],
+    );
+
+    let txn = storage_reader.begin_ro_txn().unwrap();
+    let block_number = txn.get_state_marker().unwrap().prev().unwrap();
+    let state_reader = txn.get_state_reader().unwrap();
+    let state_number = StateNumber::unchecked_right_after_block(block_number);
+    assert_eq!(
+        state_reader.get_storage_at(state_number, &contract_address, &StorageKey(key)).unwrap(),
+        value
+    );
+    // The deploying account can still send transactions with its original nonce.
+    assert_eq!(
+        state_reader.get_nonce_at(state_number, &ACCOUNT_ADDRESS).unwrap(),
+        Some(Nonce::default())
+    );
+
+    // The deployed contract can be called.
+    let retdata = call_contract(
+        storage_reader,
+        block_number,
+        contract_address,
+        selector_from_name("return_result"),
+        calldata![felt!(5_u8)],
+    )
+    .unwrap();
+    assert_eq!(retdata, vec![felt!(5_u8)]);
+}
+
+#[test]
+fn simulate_with_resource_bounds() {
+    const MAX_N_STEPS: u32 = 10000;
+    // Every level of the recursion takes a few steps, so the recursion needs more steps than the
+    // bound allows.
+    const RECURSION_DEPTH: u32 = MAX_N_STEPS;
+
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    let mut storage_writer = prepare_storage(storage_writer);
+    let block_number = BlockNumber(2);
+    let block_info_contract_address =
+        append_block_with_block_info_contract(&mut storage_writer, block_number);
+
+    let simulate_recursion = |resource_bounds| {
+        let tx = ExecutableTransactionInput::Invoke(
+            InvokeTransaction::V1(InvokeTransactionV1 {
+                calldata: calldata![
+                    *block_info_contract_address.0.key(),
+                    selector_from_name("recurse").0,
+                    felt!(1_u8),
+                    felt!(RECURSION_DEPTH)
+                ],
+                max_fee: *MAX_FEE,
+                sender_address: *ACCOUNT_ADDRESS,
+                nonce: Nonce(felt!(0_u8)),
+                ..Default::default()
+            }),
+            false,
+        );
+        let simulation_output = simulate_transactions(
+            vec![tx],
+            None,
+            &ChainId::Other(CHAIN_ID.to_string()),
+            storage_reader.clone(),
+            None,
+            StateNumber::unchecked_right_after_block(block_number),
+            block_number,
+            BlockContextOverrides::default(),
+            resource_bounds,
+            &get_test_execution_config(),
+            None,
+            false,
+            false,
+            true,
+        )
+        .unwrap()
+        .remove(0);
+        let TransactionTrace::Invoke(invoke_trace) = simulation_output.transaction_trace else {
+            panic!("Expected an invoke transaction trace.");
+        };
+        invoke_trace.execute_invocation
+    };
+
+    assert_matches!(simulate_recursion(None), FunctionInvocationResult::Ok(_));
+    assert_eq!(
+        simulate_recursion(Some(ExecutionResourceBounds { max_n_steps: MAX_N_STEPS })),
+        FunctionInvocationResult::Err(RevertReason::OutOfResources { max_n_steps: MAX_N_STEPS })
+    );
+}
+
+// Deploys funded accounts that don't share any state, so that the transactions they send are
+// independent. Returns their addresses and the number of the block in which they were deployed.
+fn deploy_independent_accounts(
+    storage_writer: &mut StorageWriter,
+) -> (Vec<ContractAddress>, BlockNumber) {
+    let account_addresses =
+        (0x445_u128..0x449).map(ContractAddress::from).collect::<Vec<ContractAddress>>();
+    let block_number = BlockNumber(2);
+    storage_writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_header(
+            block_number,
+            &BlockHeader {
+                l1_gas_price: *GAS_PRICE,
+                sequencer: *SEQUENCER_ADDRESS,
+                timestamp: *BLOCK_TIMESTAMP,
+                block_hash: BlockHash(felt!(2_u8)),
+                parent_hash: BlockHash(felt!(1_u8)),
+                block_number,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .append_body(block_number, BlockBody::default())
+        .unwrap()
+        .append_state_diff(
+            block_number,
+            ThinStateDiff {
+                deployed_contracts: account_addresses
+                    .iter()
+                    .map(|account_address| (*account_address, *ACCOUNT_CLASS_HASH))
+                    .collect(),
+                storage_diffs: indexmap! {
+                    *TEST_ERC20_CONTRACT_ADDRESS => account_addresses
+                        .iter()
+                        .map(|account_address| {
+                            let balance_key = get_storage_var_address(
+                                "ERC20_balances",
+                                &[*account_address.0.key()],
+                            );
+                            (balance_key, *ACCOUNT_INITIAL_BALANCE)
+                        })
+                        .collect(),
+                },
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .append_classes(block_number, &[], &[])
+        .unwrap()
+        .commit()
+        .unwrap();
+    (account_addresses, block_number)
+}
+
+#[test]
+fn parallel_simulation_matches_sequential_simulation() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    let mut storage_writer = prepare_storage(storage_writer);
+    let (account_addresses, block_number) = deploy_independent_accounts(&mut storage_writer);
+
+    let simulate = |txs: Vec<ExecutableTransactionInput>, execution_threads| {
+        simulate_transactions(
+            txs,
+            None,
+            &ChainId::Other(CHAIN_ID.to_string()),
+            storage_reader.clone(),
+            None,
+            StateNumber::unchecked_right_after_block(block_number),
+            block_number,
+            BlockContextOverrides::default(),
+            None,
+            &ExecutionConfig { execution_threads, ..get_test_execution_config() },
+            None,
+            false,
+            true,
+            true,
+        )
+        .unwrap()
+    };
+
+    let independent_txs = account_addresses
+        .iter()
+        .fold(TxsScenarioBuilder::default(), |builder, account_address| {
+            builder.invoke_deprecated(*account_address, *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        })
+        .collect();
+    assert_eq!(simulate(independent_txs.clone(), 3), simulate(independent_txs, 1));
+
+    // Transactions of the same account depend on each other through the account's nonce.
+    let dependent_txs = TxsScenarioBuilder::default()
+        .invoke_deprecated(account_addresses[0], *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        .invoke_deprecated(account_addresses[1], *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        .invoke_deprecated(account_addresses[0], *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        .collect();
+    assert_eq!(simulate(dependent_txs.clone(), 3), simulate(dependent_txs, 1));
+}
+
+#[test]
+fn parallel_execution_with_fee_charge() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    let mut storage_writer = prepare_storage(storage_writer);
+    let (account_addresses, block_number) = deploy_independent_accounts(&mut storage_writer);
+
+    let chain_id = ChainId::Other(CHAIN_ID.to_string());
+    let mut cached_state = CachedState::new(ExecutionStateReader {
+        storage_reader: storage_reader.clone(),
+        state_number: StateNumber::unchecked_right_after_block(block_number),
+        maybe_pending_data: None,
+        missing_compiled_class: Cell::new(None),
+        hot_state: None,
+        read_keys: None,
+    });
+    let block_context = create_block_context(
+        &mut cached_state,
+        block_number,
+        chain_id.clone(),
+        &storage_reader,
+        None,
+        &get_test_execution_config(),
+        BlockContextOverrides::default(),
+        None,
+        true,
+    )
+    .unwrap();
+
+    let txs = account_addresses
+        .iter()
+        .fold(TxsScenarioBuilder::default(), |builder, account_address| {
+            builder.invoke_deprecated(*account_address, *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        })
+        .collect::<Vec<_>>();
+    let tx_hashes = (0_u8..4).map(|i| TransactionHash(felt!(i))).collect::<Vec<_>>();
+
+    // All the transactions pay their fee to the sequencer, yet they don't depend on each other.
+    let parallel_outputs = execute_transactions_speculatively(
+        &mut cached_state,
+        &txs,
+        &tx_hashes,
+        &chain_id,
+        &block_context,
+        true,
+        true,
+        3,
+    )
+    .unwrap()
+    .expect("The transactions should be executed in parallel.");
+    let sequential_outputs = execute_transactions_on_state(
+        &mut cached_state,
+        txs,
+        Some(tx_hashes),
+        &chain_id,
+        &block_context,
+        true,
+        true,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(parallel_outputs.len(), sequential_outputs.len());
+    for (parallel_output, sequential_output) in parallel_outputs.into_iter().zip(sequential_outputs)
+    {
+        assert_ne!(parallel_output.execution_info.transaction_receipt.fee, Fee(0));
+        assert_eq!(parallel_output.execution_info, sequential_output.execution_info);
+        assert_eq!(parallel_output.induced_state_diff, sequential_output.induced_state_diff);
+    }
+}
+
+#[test]
+fn replay_block_reproduces_trace() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    let mut storage_writer = prepare_storage(storage_writer);
+
+    let txs = TxsScenarioBuilder::default()
+        .invoke_deprecated(*ACCOUNT_ADDRESS, *DEPRECATED_CONTRACT_ADDRESS, None, false)
+        .collect();
+    let [ExecutableTransactionInput::Invoke(invoke_tx, _)] = txs.as_slice() else {
+        panic!("Expected a single invoke transaction.");
+    };
+    let tx_hash = TransactionHash(felt!(1_u8));
+    let block_number = BlockNumber(2);
+    storage_writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_header(
+            block_number,
+            &BlockHeader {
+                l1_gas_price: *GAS_PRICE,
+                sequencer: *SEQUENCER_ADDRESS,
+                timestamp: *BLOCK_TIMESTAMP,
+                block_hash: BlockHash(felt!(2_u8)),
+                parent_hash: BlockHash(felt!(1_u8)),
+                block_number,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .append_body(
+            block_number,
+            BlockBody {
+                transactions: vec![Transaction::Invoke(invoke_tx.clone())],
+                transaction_outputs: vec![TransactionOutput::Invoke(
+                    InvokeTransactionOutput::default(),
+                )],
+                transaction_hashes: vec![tx_hash],
+            },
+        )
+        .unwrap()
+        .commit()
+        .unwrap();
+
+    let chain_id = ChainId::Other(CHAIN_ID.to_string());
+    let expected_traces = simulate_transactions(
+        txs,
+        Some(vec![tx_hash]),
+        &chain_id,
+        storage_reader.clone(),
+        None,
+        StateNumber::right_before_block(block_number),
+        block_number,
+        BlockContextOverrides::default(),
+        None,
+        &get_test_execution_config(),
+        None,
+        true,
+        true,
+        false,
+    )
+    .unwrap()
+    .into_iter()
+    .map(|simulation_output| simulation_output.transaction_trace)
+    .collect::<Vec<_>>();
+
+    let traces =
+        replay_block(&chain_id, storage_reader.clone(), block_number, &get_test_execution_config())
+            .unwrap();
+    assert_matches!(traces.as_slice(), [TransactionTrace::Invoke(_)]);
+    assert_eq!(traces, expected_traces);
+
+    assert_matches!(
+        replay_block(&chain_id, storage_reader, BlockNumber(3), &get_test_execution_config()),
+        Err(ExecutionError::BlockNotFound { block_number: BlockNumber(3) })
+    );
+}
+
+#[test]
+fn simulate_blocks_on_top_of_each_other() {
+    let ((storage_reader, storage_writer), _temp_dir) = get_test_storage();
+    prepare_storage(storage_writer);
+
+    let mut txs = TxsScenarioBuilder::default()
+        .deploy_account()
+        .invoke_deprecated(
+            *NEW_ACCOUNT_ADDRESS,
+            *DEPRECATED_CONTRACT_ADDRESS,
+            // the deploy account make the next nonce be 1.
+            Some(Nonce(felt!(1_u128))),
+            false,
+        )
+        .collect();
+    let invoke_tx = txs.pop().unwrap();
+    let deploy_account_tx = txs.pop().unwrap();
+    let proposed_block = |txs| ProposedBlock {
+        timestamp: *BLOCK_TIMESTAMP,
+        l1_gas_price: *GAS_PRICE,
+        l1_data_gas_price: *GAS_PRICE,
+        l1_da_mode: L1DataAvailabilityMode::Calldata,
+        sequencer: *SEQUENCER_ADDRESS,
+        txs,
+        tx_hashes: None,
+    };
+    let chain_id = ChainId::Other(CHAIN_ID.to_string());
+    let simulate_blocks = |blocks| {
+        execute_simulate_blocks(
+            &chain_id,
+            storage_reader.clone(),
+            BlockNumber(1),
+            blocks,
+            &get_test_execution_config(),
+            false,
+            false,
+        )
+    };
+
+    // The account is deployed only in the state of the first block.
+    assert_matches!(
+        simulate_blocks(vec![proposed_block(vec![invoke_tx.clone()])]),
+        Err(ExecutionError::TransactionExecutionError { transaction_index: 0, .. })
+    );
+
+    let summaries = simulate_blocks(vec![
+        proposed_block(vec![deploy_account_tx]),
+        proposed_block(vec![invoke_tx]),
+    ])
+    .unwrap();
+    assert_eq!(
+        summaries.iter().map(|summary| summary.block_number).collect::<Vec<_>>(),
+        vec![BlockNumber(2), BlockNumber(3)]
+    );
+    assert_matches!(
+        summaries[1].simulation_outputs.as_slice(),
+        [TransactionSimulationOutput {
+            transaction_trace: TransactionTrace::Invoke(InvokeTransactionTrace {
+                execute_invocation: FunctionInvocationResult::Ok(_),
+                ..
+            }),
+            ..
+        }]
+    );
+
+    // Nothing was written to the storage.
+    assert_eq!(storage_reader.begin_ro_txn().unwrap().get_state_marker().unwrap(), BlockNumber(2));
+}
+
+// A deploy transaction from Starknet mainnet.
+#[test]
+fn calculate_contract_address_of_deployed_contract() {
+    let salt = ContractAddressSalt(felt!(
+        "0x4c40ae2941a804d6941b1794c00d14bed375d19aab9c477500abbacfa58e7bf"
+    ));
+    let class_hash =
+        class_hash!("0x25ec026985a3bf9d0cc1fe17326b245dfdc3ff89b8fde106542a3ea56c5a918");
+    let constructor_calldata = calldata![
+        felt!("0x3e327de1c40540b98d05cbcb13552008e36f0ec8d61d46956d2f9752c294328"),
+        felt!("0x79dc0da7c54b95f10aa182ad0a46400db63156920adb65eca2654c0945a463"),
+        felt!("0x2"),
+        felt!("0x4c40ae2941a804d6941b1794c00d14bed375d19aab9c477500abbacfa58e7bf"),
+        felt!("0x0")
+    ];
+
+    assert_eq!(
+        calculate_contract_address(
+            salt,
+            class_hash,
+            &constructor_calldata,
+            ContractAddress::default()
+        ),
+        contract_address!("0x264266d63d373b5287aaa0f62eb1a31a297024bf9572339c15cb84b7fb51939")
+    );
+}
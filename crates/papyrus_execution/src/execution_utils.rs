@@ -21,6 +21,7 @@ use papyrus_storage::state::StateStorageReader;
 use papyrus_storage::{StorageError, StorageResult, StorageTxn};
 use starknet_api::core::{ClassHash, ContractAddress, Nonce};
 use starknet_api::state::{StateNumber, StorageKey, ThinStateDiff};
+use starknet_api::transaction::{Calldata, ContractAddressSalt};
 use starknet_types_core::felt::Felt;
 use thiserror::Error;
 
@@ -135,7 +136,7 @@ pub fn induced_state_diff(
             replaced_classes.insert(*address, *class_hash);
         }
     }
-    Ok(ThinStateDiff {
+    let mut state_diff = ThinStateDiff {
         deployed_contracts,
         storage_diffs: blockifier_state_diff.storage_updates,
         declared_classes: blockifier_state_diff.class_hash_to_compiled_class_hash,
@@ -143,7 +144,23 @@ pub fn induced_state_diff(
             .map_or_else(Vec::new, |class_hash| vec![class_hash]),
         nonces: blockifier_state_diff.address_to_nonce,
         replaced_classes,
-    })
+    };
+    // The blockifier collects the state changes in hash maps, so the order of the entries differs
+    // between executions. Sort them so that simulating the same transactions twice gives identical
+    // outputs.
+    sort_thin_state_diff(&mut state_diff);
+    Ok(state_diff)
+}
+
+fn sort_thin_state_diff(diff: &mut ThinStateDiff) {
+    diff.deployed_contracts.sort_unstable_keys();
+    diff.declared_classes.sort_unstable_keys();
+    diff.nonces.sort_unstable_keys();
+    diff.replaced_classes.sort_unstable_keys();
+    diff.storage_diffs.sort_unstable_keys();
+    for storage_entries in diff.storage_diffs.values_mut() {
+        storage_entries.sort_unstable_keys();
+    }
 }
 
 /// Get the storage at the given contract and key in the given state. If there's a given pending
@@ -216,3 +233,17 @@ pub fn get_class_hash_at<Mode: TransactionKind>(
     }
     txn.get_state_reader()?.get_class_hash_at(state_number, &contract_address)
 }
+
+/// Calculates the address of a contract deployed with the given parameters, using the Starknet
+/// formula:
+/// `pedersen("STARKNET_CONTRACT_ADDRESS", deployer, salt, class_hash, pedersen(calldata))`, reduced
+/// modulo `2**251 - 256`. The deployer of a deploy account transaction is the zero address.
+pub fn calculate_contract_address(
+    salt: ContractAddressSalt,
+    class_hash: ClassHash,
+    constructor_calldata: &Calldata,
+    deployer: ContractAddress,
+) -> ContractAddress {
+    starknet_api::core::calculate_contract_address(salt, class_hash, constructor_calldata, deployer)
+        .expect("The reduced address should be a valid contract address")
+}
@@ -0,0 +1,160 @@
+//! A cache of the state of the most recent blocks, shared between executions.
+//!
+//! Simulations and fee estimations usually run against one of the latest blocks, so many of them
+//! read the same storage values. The cache keeps the values that were read at each of the last
+//! blocks and serves them without accessing the storage. The number of values kept for each block
+//! is bounded, and once a block's state reaches the bound its values are dropped.
+#[cfg(test)]
+#[path = "hot_state_test.rs"]
+mod hot_state_test;
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use papyrus_storage::db::RO;
+use papyrus_storage::header::HeaderStorageReader;
+use papyrus_storage::state::StateStorageReader;
+use papyrus_storage::{StorageResult, StorageTxn};
+use starknet_api::block::{BlockHash, BlockNumber};
+use starknet_api::core::{ClassHash, CompiledClassHash, ContractAddress, Nonce};
+use starknet_api::state::{StateNumber, StorageKey};
+use starknet_types_core::felt::Felt;
+
+/// A cache of the values read from the state at each of the last blocks.
+///
+/// Cloning the cache is cheap and the clones share their content.
+#[derive(Clone, Debug, Default)]
+pub struct HotStateCache {
+    max_blocks: usize,
+    max_entries_per_state: usize,
+    states: Arc<Mutex<BTreeMap<StateNumber, HotState>>>,
+    hits: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct HotState {
+    // The hash of the last block applied to the state. Used to detect that the block was reverted.
+    last_block_hash: Option<BlockHash>,
+    pub(crate) storage: HashMap<(ContractAddress, StorageKey), Felt>,
+    pub(crate) nonces: HashMap<ContractAddress, Nonce>,
+    pub(crate) class_hashes: HashMap<ContractAddress, ClassHash>,
+    pub(crate) compiled_class_hashes: HashMap<ClassHash, CompiledClassHash>,
+}
+
+impl HotState {
+    fn n_entries(&self) -> usize {
+        self.storage.len()
+            + self.nonces.len()
+            + self.class_hashes.len()
+            + self.compiled_class_hashes.len()
+    }
+
+    fn clear_entries(&mut self) {
+        self.storage.clear();
+        self.nonces.clear();
+        self.class_hashes.clear();
+        self.compiled_class_hashes.clear();
+    }
+}
+
+impl HotStateCache {
+    /// Creates a cache for the states of the last `max_blocks` blocks, each holding up to
+    /// `max_entries_per_state` values. A cache for 0 blocks or 0 values never caches anything.
+    pub fn new(max_blocks: usize, max_entries_per_state: usize) -> Self {
+        Self { max_blocks, max_entries_per_state, ..Default::default() }
+    }
+
+    /// Returns the number of values that were served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns a view into the cached state at the given state number, or None if the state isn't
+    /// one of the last blocks' states.
+    ///
+    /// Cached states whose last block was reverted are dropped together with the states on top of
+    /// them.
+    pub(crate) fn view(
+        &self,
+        txn: &StorageTxn<'_, RO>,
+        state_number: StateNumber,
+    ) -> StorageResult<Option<HotStateView>> {
+        if self.max_blocks == 0 || self.max_entries_per_state == 0 {
+            return Ok(None);
+        }
+        let latest_state_number = StateNumber(txn.get_state_marker()?);
+        if state_number > latest_state_number
+            || latest_state_number.0 .0 - state_number.0 .0 >= self.max_blocks as u64
+        {
+            return Ok(None);
+        }
+        let last_block_hash = match state_number.0.prev() {
+            Some(block_number) => {
+                txn.get_block_header(block_number)?.map(|header| header.block_hash)
+            }
+            None => None,
+        };
+
+        let mut states = self.states.lock().expect("Hot state cache lock is poisoned");
+        if states.get(&state_number).is_some_and(|state| state.last_block_hash != last_block_hash) {
+            states.split_off(&state_number);
+        }
+        states
+            .entry(state_number)
+            .or_insert_with(|| HotState { last_block_hash, ..Default::default() });
+        let oldest_state_number = StateNumber(BlockNumber(
+            latest_state_number.0 .0.saturating_sub(self.max_blocks as u64 - 1),
+        ));
+        *states = states.split_off(&oldest_state_number);
+
+        Ok(Some(HotStateView { cache: self.clone(), state_number, last_block_hash }))
+    }
+}
+
+/// A view into the cached state at a specific state number.
+#[derive(Clone, Debug)]
+pub struct HotStateView {
+    cache: HotStateCache,
+    state_number: StateNumber,
+    // The hash of the last block applied to the state when the view was created.
+    last_block_hash: Option<BlockHash>,
+}
+
+impl HotStateView {
+    /// Returns the cached value of the key in the given table, or reads it and caches the result.
+    pub(crate) fn get_or_read<K: Eq + Hash, V: Copy, E>(
+        &self,
+        table: fn(&mut HotState) -> &mut HashMap<K, V>,
+        key: K,
+        read: impl FnOnce() -> Result<V, E>,
+    ) -> Result<V, E> {
+        if let Some(value) = self.with_state(|state| table(state).get(&key).copied()) {
+            self.cache.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+        // The storage is read without holding the lock.
+        let value = read()?;
+        self.with_state(|state| {
+            // Evicting all the values of a full state keeps the cache bounded without tracking the
+            // order in which the values were used.
+            if state.n_entries() >= self.cache.max_entries_per_state {
+                state.clear_entries();
+            }
+            table(state).insert(key, value)
+        });
+        Ok(value)
+    }
+
+    // Applies f to the cached state, if it wasn't evicted since the view was created. If the last
+    // block of the state was reverted since then, the cached state belongs to the new block and f
+    // isn't applied, so that values read before the revert aren't mixed with it.
+    fn with_state<T>(&self, f: impl FnOnce(&mut HotState) -> Option<T>) -> Option<T> {
+        let mut states = self.cache.states.lock().expect("Hot state cache lock is poisoned");
+        states
+            .get_mut(&self.state_number)
+            .filter(|state| state.last_block_hash == self.last_block_hash)
+            .and_then(f)
+    }
+}
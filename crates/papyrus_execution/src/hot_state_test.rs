@@ -0,0 +1,171 @@
+use std::convert::Infallible;
+
+use papyrus_storage::header::HeaderStorageWriter;
+use papyrus_storage::state::StateStorageWriter;
+use papyrus_storage::test_utils::get_test_storage;
+use papyrus_storage::StorageWriter;
+use starknet_api::block::{BlockHash, BlockHeader, BlockNumber};
+use starknet_api::core::{ContractAddress, Nonce, PatriciaKey};
+use starknet_api::state::{StateNumber, ThinStateDiff};
+use starknet_api::{contract_address, felt, patricia_key};
+
+use crate::hot_state::HotStateCache;
+
+fn append_block(storage_writer: &mut StorageWriter, block_number: BlockNumber, block_hash: u64) {
+    storage_writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_header(
+            block_number,
+            &BlockHeader { block_hash: BlockHash(felt!(block_hash)), ..Default::default() },
+        )
+        .unwrap()
+        .append_state_diff(block_number, ThinStateDiff::default())
+        .unwrap()
+        .commit()
+        .unwrap();
+}
+
+fn revert_block(storage_writer: &mut StorageWriter, block_number: BlockNumber) {
+    let (txn, _, _) = storage_writer.begin_rw_txn().unwrap().revert_header(block_number).unwrap();
+    let (txn, _) = txn.revert_state_diff(block_number).unwrap();
+    txn.commit().unwrap();
+}
+
+#[test]
+fn cached_values_are_served_until_their_block_is_reverted() {
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    append_block(&mut storage_writer, BlockNumber(0), 1);
+    let hot_state_cache = HotStateCache::new(10, 10);
+    let state_number = StateNumber::unchecked_right_after_block(BlockNumber(0));
+    let address = contract_address!("0x1");
+    let nonce = Nonce(felt!(5_u8));
+    let read_nonce = |hot_state_cache: &HotStateCache| {
+        let view = hot_state_cache
+            .view(&storage_reader.begin_ro_txn().unwrap(), state_number)
+            .unwrap()
+            .unwrap();
+        view.get_or_read(|state| &mut state.nonces, address, || Ok::<_, Infallible>(nonce)).unwrap()
+    };
+
+    read_nonce(&hot_state_cache);
+    assert_eq!(hot_state_cache.hits(), 0);
+    read_nonce(&hot_state_cache);
+    assert_eq!(hot_state_cache.hits(), 1);
+
+    // Replacing the block drops the state after it from the cache.
+    revert_block(&mut storage_writer, BlockNumber(0));
+    append_block(&mut storage_writer, BlockNumber(0), 2);
+    read_nonce(&hot_state_cache);
+    assert_eq!(hot_state_cache.hits(), 1);
+    read_nonce(&hot_state_cache);
+    assert_eq!(hot_state_cache.hits(), 2);
+}
+
+#[test]
+fn values_read_before_a_revert_are_not_cached() {
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    append_block(&mut storage_writer, BlockNumber(0), 1);
+    let hot_state_cache = HotStateCache::new(10, 10);
+    let state_number = StateNumber::unchecked_right_after_block(BlockNumber(0));
+    let address = contract_address!("0x1");
+    let old_nonce = Nonce(felt!(5_u8));
+    let new_nonce = Nonce(felt!(6_u8));
+    let view = |hot_state_cache: &HotStateCache| {
+        hot_state_cache
+            .view(&storage_reader.begin_ro_txn().unwrap(), state_number)
+            .unwrap()
+            .unwrap()
+    };
+
+    // The block is replaced and a view of the new block is created after the old view read its
+    // value and before it caches it.
+    let old_view = view(&hot_state_cache);
+    let mut new_view = None;
+    let nonce = old_view
+        .get_or_read(
+            |state| &mut state.nonces,
+            address,
+            || {
+                revert_block(&mut storage_writer, BlockNumber(0));
+                append_block(&mut storage_writer, BlockNumber(0), 2);
+                new_view = Some(view(&hot_state_cache));
+                Ok::<_, Infallible>(old_nonce)
+            },
+        )
+        .unwrap();
+    assert_eq!(nonce, old_nonce);
+
+    // The value of the old view isn't served for the new block.
+    let new_view = new_view.unwrap();
+    let read_new_nonce = || {
+        new_view
+            .get_or_read(|state| &mut state.nonces, address, || Ok::<_, Infallible>(new_nonce))
+            .unwrap()
+    };
+    assert_eq!(read_new_nonce(), new_nonce);
+    assert_eq!(hot_state_cache.hits(), 0);
+    assert_eq!(read_new_nonce(), new_nonce);
+    assert_eq!(hot_state_cache.hits(), 1);
+
+    // The value of the new block isn't served to the old view either.
+    let nonce = old_view
+        .get_or_read(|state| &mut state.nonces, address, || Ok::<_, Infallible>(old_nonce))
+        .unwrap();
+    assert_eq!(nonce, old_nonce);
+    assert_eq!(hot_state_cache.hits(), 1);
+}
+
+#[test]
+fn only_states_of_the_last_blocks_are_cached() {
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    append_block(&mut storage_writer, BlockNumber(0), 1);
+    append_block(&mut storage_writer, BlockNumber(1), 2);
+    let txn = storage_reader.begin_ro_txn().unwrap();
+
+    let hot_state_cache = HotStateCache::new(2, 10);
+    assert!(hot_state_cache.view(&txn, StateNumber(BlockNumber(2))).unwrap().is_some());
+    assert!(hot_state_cache.view(&txn, StateNumber(BlockNumber(1))).unwrap().is_some());
+    assert!(hot_state_cache.view(&txn, StateNumber(BlockNumber(0))).unwrap().is_none());
+
+    let disabled_hot_state_cache = HotStateCache::new(0, 10);
+    assert!(disabled_hot_state_cache.view(&txn, StateNumber(BlockNumber(2))).unwrap().is_none());
+    let disabled_hot_state_cache = HotStateCache::new(2, 0);
+    assert!(disabled_hot_state_cache.view(&txn, StateNumber(BlockNumber(2))).unwrap().is_none());
+}
+
+#[test]
+fn full_state_is_evicted() {
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    append_block(&mut storage_writer, BlockNumber(0), 1);
+    let hot_state_cache = HotStateCache::new(10, 2);
+    let view = hot_state_cache
+        .view(
+            &storage_reader.begin_ro_txn().unwrap(),
+            StateNumber::unchecked_right_after_block(BlockNumber(0)),
+        )
+        .unwrap()
+        .unwrap();
+    let read_nonce = |address: ContractAddress| {
+        view.get_or_read(
+            |state| &mut state.nonces,
+            address,
+            || Ok::<_, Infallible>(Nonce::default()),
+        )
+        .unwrap()
+    };
+
+    read_nonce(contract_address!("0x1"));
+    read_nonce(contract_address!("0x2"));
+    read_nonce(contract_address!("0x1"));
+    read_nonce(contract_address!("0x2"));
+    assert_eq!(hot_state_cache.hits(), 2);
+
+    // Caching a third value drops the values that were cached before it.
+    read_nonce(contract_address!("0x3"));
+    read_nonce(contract_address!("0x1"));
+    read_nonce(contract_address!("0x2"));
+    assert_eq!(hot_state_cache.hits(), 2);
+    read_nonce(contract_address!("0x2"));
+    assert_eq!(hot_state_cache.hits(), 3);
+}
@@ -11,6 +11,7 @@
 #[cfg(test)]
 mod execution_test;
 pub mod execution_utils;
+pub mod hot_state;
 mod state_reader;
 
 #[cfg(test)]
@@ -19,14 +20,18 @@ mod test_utils;
 pub mod testing_instances;
 
 pub mod objects;
-use std::cell::Cell;
-use std::collections::BTreeMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::num::NonZeroU128;
 use std::path::Path;
 use std::sync::Arc;
 
 use blockifier::blockifier::block::{pre_process_block, BlockInfo, BlockNumberHashPair, GasPrices};
 use blockifier::bouncer::BouncerConfig;
+use blockifier::concurrency::fee_utils::{
+    add_fee_to_sequencer_balance,
+    fill_sequencer_balance_reads,
+};
 use blockifier::context::{BlockContext, ChainInfo, FeeTokenAddresses, TransactionContext};
 use blockifier::execution::call_info::CallExecution;
 use blockifier::execution::contract_class::{ClassInfo, ContractClass as BlockifierContractClass};
@@ -35,31 +40,54 @@ use blockifier::execution::entry_point::{
     CallType as BlockifierCallType,
     EntryPointExecutionContext,
 };
-use blockifier::state::cached_state::CachedState;
+use blockifier::fee::fee_utils::get_sequencer_balance_keys;
+use blockifier::state::cached_state::{CachedState, MutRefState};
+use blockifier::state::state_api::StateReader as BlockifierStateReader;
 use blockifier::transaction::errors::TransactionExecutionError as BlockifierTransactionExecutionError;
 use blockifier::transaction::objects::{
     DeprecatedTransactionInfo,
+    FeeType,
     TransactionExecutionInfo,
     TransactionInfo,
 };
 use blockifier::transaction::transaction_execution::Transaction as BlockifierTransaction;
-use blockifier::transaction::transactions::ExecutableTransaction;
+use blockifier::transaction::transactions::{ExecutableTransaction, ExecutionFlags};
 use blockifier::versioned_constants::VersionedConstants;
 use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
 use cairo_vm::types::builtin_name::BuiltinName;
+use cairo_vm::vm::errors::vm_errors::VirtualMachineError;
 use cairo_vm::vm::runners::cairo_runner::ExecutionResources;
 use execution_utils::{get_trace_constructor, induced_state_diff};
-use objects::{PriceUnit, TransactionSimulationOutput};
+use objects::{
+    FunctionInvocationResult,
+    InvokeTransactionTrace,
+    PriceUnit,
+    RevertReason,
+    TransactionSimulationOutput,
+    TransactionTrace,
+};
 use once_cell::sync::Lazy;
+use papyrus_common::deprecated_class_abi::calculate_deprecated_class_abi_length;
 use papyrus_common::transaction_hash::get_transaction_hash;
 use papyrus_common::TransactionOptions;
 use papyrus_config::dumping::{ser_param, SerializeConfig};
 use papyrus_config::{ParamPath, ParamPrivacyInput, SerializedParam};
+use papyrus_storage::body::BodyStorageReader;
+use papyrus_storage::compiled_class::CasmStorageReader;
+use papyrus_storage::db::RO;
 use papyrus_storage::header::HeaderStorageReader;
-use papyrus_storage::{StorageError, StorageReader};
+use papyrus_storage::state::StateStorageReader;
+use papyrus_storage::{StorageError, StorageReader, StorageTxn};
 use serde::{Deserialize, Serialize};
-use starknet_api::block::{BlockNumber, StarknetVersion};
-use starknet_api::core::{ChainId, ClassHash, ContractAddress, EntryPointSelector, PatriciaKey};
+use starknet_api::block::{BlockNumber, BlockTimestamp, GasPricePerToken, StarknetVersion};
+use starknet_api::core::{
+    ChainId,
+    ClassHash,
+    ContractAddress,
+    EntryPointSelector,
+    PatriciaKey,
+    SequencerContractAddress,
+};
 use starknet_api::data_availability::L1DataAvailabilityMode;
 // TODO: merge multiple EntryPointType structs in SN_API into one.
 use starknet_api::deprecated_contract_class::{
@@ -82,10 +110,17 @@ use starknet_api::transaction::{
     TransactionVersion,
 };
 use starknet_api::{contract_address, felt, patricia_key, StarknetApiError};
-use state_reader::ExecutionStateReader;
+use state_reader::{ExecutionStateReader, StateKeys};
 use tracing::trace;
 
-use crate::objects::{tx_execution_output_to_fee_estimation, FeeEstimation, PendingData};
+use crate::hot_state::HotStateCache;
+use crate::objects::{
+    tx_execution_output_to_fee_estimation,
+    BlockContextOverrides,
+    ExecutionResourceBounds,
+    FeeEstimation,
+    PendingData,
+};
 
 const STARKNET_VERSION_O_13_0: &str = "0.13.0";
 const STARKNET_VERSION_O_13_1: &str = "0.13.1";
@@ -94,6 +129,9 @@ const STRK_FEE_CONTRACT_ADDRESS: &str =
 const ETH_FEE_CONTRACT_ADDRESS: &str =
     "0x49d36570d4e46f48e99674bd3fcc84644ddd6b96f7c741b1562b82f9e004dc7";
 const INITIAL_GAS_COST: u64 = 10000000000;
+const HOT_STATE_BLOCKS: usize = 10;
+const HOT_STATE_MAX_ENTRIES: usize = 100_000;
+const EXECUTION_THREADS: usize = 1;
 
 /// Result type for execution functions.
 pub type ExecutionResult<T> = Result<T, ExecutionError>;
@@ -116,6 +154,12 @@ pub struct ExecutionConfig {
     pub eth_fee_contract_address: ContractAddress,
     /// The initial gas cost for a transaction
     pub initial_gas_cost: u64,
+    /// The number of last blocks whose state is kept in the hot state cache
+    pub hot_state_blocks: usize,
+    /// The maximal number of values cached for the state of each of the hot state blocks
+    pub hot_state_max_entries: usize,
+    /// The number of threads that execute the transactions of a simulation or fee estimation
+    pub execution_threads: usize,
 }
 
 impl Default for ExecutionConfig {
@@ -124,6 +168,9 @@ impl Default for ExecutionConfig {
             strk_fee_contract_address: contract_address!(STRK_FEE_CONTRACT_ADDRESS),
             eth_fee_contract_address: contract_address!(ETH_FEE_CONTRACT_ADDRESS),
             initial_gas_cost: INITIAL_GAS_COST,
+            hot_state_blocks: HOT_STATE_BLOCKS,
+            hot_state_max_entries: HOT_STATE_MAX_ENTRIES,
+            execution_threads: EXECUTION_THREADS,
         }
     }
 }
@@ -149,6 +196,28 @@ impl SerializeConfig for ExecutionConfig {
                 "The initial gas cost for a transaction",
                 ParamPrivacyInput::Public,
             ),
+            ser_param(
+                "hot_state_blocks",
+                &self.hot_state_blocks,
+                "The number of last blocks whose state is cached for simulations and fee \
+                 estimations",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "hot_state_max_entries",
+                &self.hot_state_max_entries,
+                "The maximal number of values cached for the state of each of the hot state \
+                 blocks. Once a state reaches it, its cached values are dropped",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "execution_threads",
+                &self.execution_threads,
+                "The number of threads that execute the transactions of a simulation or fee \
+                 estimation. With more than 1, the transactions are executed in parallel and are \
+                 re-executed one after the other if any of them depends on an earlier one",
+                ParamPrivacyInput::Public,
+            ),
         ])
     }
 }
@@ -163,6 +232,8 @@ pub enum ExecutionError {
         #[source]
         err: blockifier::execution::errors::ContractClassError,
     },
+    #[error("Block {block_number} is not in the storage.")]
+    BlockNotFound { block_number: BlockNumber },
     #[error("Execution config file does not contain a configuration for all blocks")]
     ConfigContentError,
     #[error(transparent)]
@@ -178,10 +249,14 @@ pub enum ExecutionError {
     ContractNotFound { contract_address: ContractAddress, state_number: StateNumber },
     #[error("Gas consumed should fit into u64")]
     GasConsumedOutOfRange,
+    #[error("Missing class definition with hash {class_hash}")]
+    MissingClassDefinition { class_hash: ClassHash },
     #[error("Missing class hash in call info")]
     MissingClassHash,
     #[error("Missing compiled class with hash {class_hash} (The CASM table isn't synced)")]
     MissingCompiledClass { class_hash: ClassHash },
+    #[error("Transaction {transaction_index} ran out of its {max_n_steps} steps.")]
+    OutOfResources { transaction_index: usize, max_n_steps: u32 },
     #[error(transparent)]
     StateError(#[from] blockifier::state::errors::StateError),
     #[error(transparent)]
@@ -194,6 +269,8 @@ pub enum ExecutionError {
     TransactionExecutionError { transaction_index: usize, execution_error: String },
     #[error("Failed to calculate transaction hash.")]
     TransactionHashCalculationFailed(StarknetApiError),
+    #[error("Transaction {transaction_index} is a deploy transaction, which can't be executed.")]
+    UnsupportedDeployTransaction { transaction_index: usize },
     #[error("Unknown builtin name: {builtin_name}")]
     UnknownBuiltin { builtin_name: BuiltinName },
 }
@@ -243,6 +320,8 @@ pub fn execute_call(
         state_number,
         maybe_pending_data: maybe_pending_data.clone(),
         missing_compiled_class: Cell::new(None),
+        hot_state: None,
+        read_keys: None,
     });
 
     let block_context = create_block_context(
@@ -252,6 +331,8 @@ pub fn execute_call(
         &storage_reader,
         maybe_pending_data.as_ref(),
         execution_config,
+        BlockContextOverrides::default(),
+        None,
         override_kzg_da_to_false,
     )?;
 
@@ -296,6 +377,7 @@ fn verify_contract_exists(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_block_context(
     cached_state: &mut CachedState<ExecutionStateReader>,
     block_context_number: BlockNumber,
@@ -303,6 +385,8 @@ fn create_block_context(
     storage_reader: &StorageReader,
     maybe_pending_data: Option<&PendingData>,
     execution_config: &ExecutionConfig,
+    block_context_overrides: BlockContextOverrides,
+    resource_bounds: Option<ExecutionResourceBounds>,
     // TODO(shahak): Remove this once we stop supporting rpc v0.6.
     override_kzg_da_to_false: bool,
 ) -> ExecutionResult<BlockContext> {
@@ -337,6 +421,8 @@ fn create_block_context(
             )
         }
     };
+    let sequencer_address = block_context_overrides.sequencer_address.unwrap_or(sequencer_address);
+    let l1_gas_price = block_context_overrides.l1_gas_price.unwrap_or(l1_gas_price);
     let ten_blocks_ago = get_10_blocks_ago(&block_context_number, cached_state)?;
 
     let use_kzg_da = if override_kzg_da_to_false {
@@ -349,10 +435,10 @@ fn create_block_context(
     };
 
     let block_info = BlockInfo {
-        block_timestamp,
+        block_timestamp: block_context_overrides.timestamp.unwrap_or(block_timestamp),
         sequencer_address: sequencer_address.0,
         use_kzg_da,
-        block_number,
+        block_number: block_context_overrides.block_number.unwrap_or(block_number),
         // TODO(yair): What to do about blocks pre 0.13.1 where the data gas price were 0?
         gas_prices: GasPrices {
             eth_l1_gas_price: NonZeroU128::new(l1_gas_price.price_in_wei.0)
@@ -374,18 +460,20 @@ fn create_block_context(
     };
     let starknet_version: Option<StarknetVersion> =
         storage_reader.begin_ro_txn()?.get_starknet_version(block_number)?;
-    let versioned_constants: &VersionedConstants =
-        get_versioned_constants(starknet_version.as_ref())?;
-
-    let block_context = BlockContext::new(
-        block_info,
-        chain_info,
-        versioned_constants.clone(),
-        BouncerConfig::max(),
-    );
-    let next_block_number = block_context.block_info().block_number;
+    let mut versioned_constants: VersionedConstants =
+        get_versioned_constants(starknet_version.as_ref())?.clone();
+    if let Some(resource_bounds) = resource_bounds {
+        versioned_constants.invoke_tx_max_n_steps =
+            versioned_constants.invoke_tx_max_n_steps.min(resource_bounds.max_n_steps);
+        versioned_constants.validate_max_n_steps =
+            versioned_constants.validate_max_n_steps.min(resource_bounds.max_n_steps);
+    }
 
-    pre_process_block(cached_state, ten_blocks_ago, next_block_number)?;
+    let block_context =
+        BlockContext::new(block_info, chain_info, versioned_constants, BouncerConfig::max());
+    // The block hashes are written according to the real block number, also when the block number
+    // is overridden.
+    pre_process_block(cached_state, ten_blocks_ago, block_number)?;
     Ok(block_context)
 }
 
@@ -552,6 +640,7 @@ pub fn estimate_fee(
     state_number: StateNumber,
     block_context_block_number: BlockNumber,
     execution_config: &ExecutionConfig,
+    hot_state_cache: Option<&HotStateCache>,
     validate: bool,
     override_kzg_da_to_false: bool,
 ) -> ExecutionResult<FeeEstimationResult> {
@@ -563,7 +652,10 @@ pub fn estimate_fee(
         maybe_pending_data,
         state_number,
         block_context_block_number,
+        BlockContextOverrides::default(),
+        None,
         execution_config,
+        hot_state_cache,
         false,
         validate,
         override_kzg_da_to_false,
@@ -598,17 +690,29 @@ fn execute_transactions(
     maybe_pending_data: Option<PendingData>,
     state_number: StateNumber,
     block_context_block_number: BlockNumber,
+    block_context_overrides: BlockContextOverrides,
+    resource_bounds: Option<ExecutionResourceBounds>,
     execution_config: &ExecutionConfig,
+    hot_state_cache: Option<&HotStateCache>,
     charge_fee: bool,
     validate: bool,
     override_kzg_da_to_false: bool,
 ) -> ExecutionResult<(Vec<TransactionExecutionOutput>, BlockContext)> {
+    // The pending data isn't part of the cached states, so the hot state is used only without it.
+    let hot_state = match (hot_state_cache, &maybe_pending_data) {
+        (Some(hot_state_cache), None) => {
+            hot_state_cache.view(&storage_reader.begin_ro_txn()?, state_number)?
+        }
+        _ => None,
+    };
     // The starknet state will be from right before the block in which the transactions should run.
     let mut cached_state = CachedState::new(ExecutionStateReader {
         storage_reader: storage_reader.clone(),
         state_number,
         maybe_pending_data: maybe_pending_data.clone(),
         missing_compiled_class: Cell::new(None),
+        hot_state,
+        read_keys: None,
     });
 
     let block_context = create_block_context(
@@ -618,9 +722,241 @@ fn execute_transactions(
         &storage_reader,
         maybe_pending_data.as_ref(),
         execution_config,
+        block_context_overrides,
+        resource_bounds,
         override_kzg_da_to_false,
     )?;
 
+    let res = if execution_config.execution_threads > 1 && txs.len() > 1 {
+        execute_transactions_in_parallel(
+            cached_state,
+            txs,
+            tx_hashes,
+            chain_id,
+            &block_context,
+            charge_fee,
+            validate,
+            execution_config.execution_threads,
+        )?
+    } else {
+        execute_transactions_on_state(
+            &mut cached_state,
+            txs,
+            tx_hashes,
+            chain_id,
+            &block_context,
+            charge_fee,
+            validate,
+        )?
+    };
+    Ok((res, block_context))
+}
+
+// Executes the transactions speculatively in parallel, spreading them between the given number of
+// threads. If they turn out to depend on each other, executes them again one after the other.
+#[allow(clippy::too_many_arguments)]
+fn execute_transactions_in_parallel(
+    mut cached_state: CachedState<ExecutionStateReader>,
+    txs: Vec<ExecutableTransactionInput>,
+    tx_hashes: Option<Vec<TransactionHash>>,
+    chain_id: &ChainId,
+    block_context: &BlockContext,
+    charge_fee: bool,
+    validate: bool,
+    n_threads: usize,
+) -> ExecutionResult<Vec<TransactionExecutionOutput>> {
+    let (txs, tx_hashes) = match tx_hashes {
+        Some(tx_hashes) => (txs, tx_hashes),
+        None => calc_tx_hashes(txs, chain_id)?,
+    };
+
+    if let Some(outputs) = execute_transactions_speculatively(
+        &mut cached_state,
+        &txs,
+        &tx_hashes,
+        chain_id,
+        block_context,
+        charge_fee,
+        validate,
+        n_threads,
+    )? {
+        return Ok(outputs);
+    }
+    trace!("The transactions depend on each other, executing them one after the other.");
+    execute_transactions_on_state(
+        &mut cached_state,
+        txs,
+        Some(tx_hashes),
+        chain_id,
+        block_context,
+        charge_fee,
+        validate,
+        false,
+    )
+}
+
+// Executes each transaction on its own copy of the given state, spreading them between the given
+// number of threads. If none of the transactions read a key of the state that an earlier one wrote,
+// returns the same results as of executing them one after the other. Otherwise, or if any of the
+// executions failed, returns None.
+//
+// The fee transfers are executed in concurrency mode, so that the transactions don't depend on each
+// other through the sequencer balance. Their fees are added to it here, in the order of the
+// transactions.
+#[allow(clippy::too_many_arguments)]
+fn execute_transactions_speculatively(
+    cached_state: &mut CachedState<ExecutionStateReader>,
+    txs: &[ExecutableTransactionInput],
+    tx_hashes: &[TransactionHash],
+    chain_id: &ChainId,
+    block_context: &BlockContext,
+    charge_fee: bool,
+    validate: bool,
+    n_threads: usize,
+) -> ExecutionResult<Option<Vec<TransactionExecutionOutput>>> {
+    // The copies of the state start with the writes made while creating the block context.
+    let block_context_writes = cached_state.to_state_diff()?;
+    // The state readers aren't shared between threads, so each execution gets its own one.
+    let txs_per_thread = txs.len().div_ceil(n_threads);
+    let mut thread_jobs = (0..n_threads).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (transaction_index, (tx, tx_hash)) in txs.iter().zip(tx_hashes).enumerate() {
+        let state_reader = ExecutionStateReader {
+            storage_reader: cached_state.state.storage_reader.clone(),
+            state_number: cached_state.state.state_number,
+            maybe_pending_data: cached_state.state.maybe_pending_data.clone(),
+            missing_compiled_class: Cell::new(None),
+            hot_state: cached_state.state.hot_state.clone(),
+            read_keys: Some(RefCell::default()),
+        };
+        thread_jobs[transaction_index / txs_per_thread].push((tx.clone(), *tx_hash, state_reader));
+    }
+
+    let block_context_writes = &block_context_writes;
+    let speculative_results = std::thread::scope(|scope| {
+        let threads = thread_jobs
+            .into_iter()
+            .map(|jobs| {
+                scope.spawn(move || {
+                    jobs.into_iter()
+                        .map(|(tx, tx_hash, state_reader)| {
+                            let mut state = CachedState::new(state_reader);
+                            state.update_cache(block_context_writes, HashMap::new());
+                            let mut outputs = execute_transactions_on_state(
+                                &mut state,
+                                vec![tx],
+                                Some(vec![tx_hash]),
+                                chain_id,
+                                block_context,
+                                charge_fee,
+                                validate,
+                                true,
+                            )
+                            .ok()?;
+                            let read_keys = state.state.read_keys.take()?.into_inner();
+                            Some((outputs.pop()?, read_keys))
+                        })
+                        .collect::<Option<Vec<_>>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        threads
+            .into_iter()
+            .map(|thread| {
+                thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect::<Option<Vec<_>>>()
+    });
+    let Some(speculative_results) = speculative_results else {
+        return Ok(None);
+    };
+
+    // The sequencer balances are accumulated on top of the given state without changing it, since
+    // the transactions are executed on it again if they depend on each other.
+    let mut fees_state = CachedState::create_transactional(cached_state);
+    let mut written_keys = StateKeys::default();
+    let mut outputs = Vec::with_capacity(txs.len());
+    for (mut output, read_keys) in speculative_results.into_iter().flatten() {
+        // A transaction that reads the sequencer balance, such as one sent by the sequencer, sees
+        // it without the fees of the earlier transactions.
+        if read_keys.intersects(&written_keys)
+            || (charge_fee && reads_sequencer_balance(&read_keys, block_context))
+        {
+            return Ok(None);
+        }
+        add_fee_to_induced_state_diff(&mut output, &mut fees_state, block_context)?;
+        written_keys.extend(StateKeys::from(&output.induced_state_diff));
+        outputs.push(output);
+    }
+    Ok(Some(outputs))
+}
+
+fn reads_sequencer_balance(read_keys: &StateKeys, block_context: &BlockContext) -> bool {
+    let (balance_key_low, balance_key_high) = get_sequencer_balance_keys(block_context);
+    [FeeType::Eth, FeeType::Strk].iter().any(|fee_type| {
+        let fee_token_address = block_context.chain_info.fee_token_address(fee_type);
+        read_keys.contains_storage(fee_token_address, balance_key_low)
+            || read_keys.contains_storage(fee_token_address, balance_key_high)
+    })
+}
+
+// Adds the fee of a transaction whose fee transfer was executed in concurrency mode to the
+// sequencer balance in the given state, and writes the new balance to the induced state diff of the
+// transaction.
+fn add_fee_to_induced_state_diff(
+    output: &mut TransactionExecutionOutput,
+    fees_state: &mut CachedState<MutRefState<'_, CachedState<ExecutionStateReader>>>,
+    block_context: &BlockContext,
+) -> ExecutionResult<()> {
+    let Some(fee_transfer_call_info) = output.execution_info.fee_transfer_call_info.as_mut() else {
+        return Ok(());
+    };
+    let fee_type = match output.price_unit {
+        PriceUnit::Wei => FeeType::Eth,
+        PriceUnit::Fri => FeeType::Strk,
+    };
+    let fee_token_address = block_context.chain_info.fee_token_address(&fee_type);
+    let sequencer_address = block_context.block_info.sequencer_address;
+    let balance = fees_state.get_fee_token_balance(sequencer_address, fee_token_address)?;
+    fill_sequencer_balance_reads(fee_transfer_call_info, balance);
+    add_fee_to_sequencer_balance(
+        fee_token_address,
+        fees_state,
+        output.execution_info.transaction_receipt.fee,
+        block_context,
+        balance,
+    );
+
+    let new_balance = fees_state.get_fee_token_balance(sequencer_address, fee_token_address)?;
+    let (balance_key_low, balance_key_high) = get_sequencer_balance_keys(block_context);
+    // Like the rest of the induced state diff, only the values that changed are included.
+    for (key, value, new_value) in
+        [(balance_key_low, balance.0, new_balance.0), (balance_key_high, balance.1, new_balance.1)]
+    {
+        if new_value != value {
+            output
+                .induced_state_diff
+                .storage_diffs
+                .entry(fee_token_address)
+                .or_default()
+                .insert(key, new_value);
+        }
+    }
+    Ok(())
+}
+
+// Executes a series of transactions on top of the given state, which accumulates their writes. In
+// concurrency mode, the fees aren't added to the sequencer balance.
+#[allow(clippy::too_many_arguments)]
+fn execute_transactions_on_state(
+    cached_state: &mut CachedState<ExecutionStateReader>,
+    txs: Vec<ExecutableTransactionInput>,
+    tx_hashes: Option<Vec<TransactionHash>>,
+    chain_id: &ChainId,
+    block_context: &BlockContext,
+    charge_fee: bool,
+    validate: bool,
+    concurrency_mode: bool,
+) -> ExecutionResult<Vec<TransactionExecutionOutput>> {
     let (txs, tx_hashes) = match tx_hashes {
         Some(tx_hashes) => (txs, tx_hashes),
         None => {
@@ -643,7 +979,7 @@ fn execute_transactions(
         } else {
             PriceUnit::Fri
         };
-        let mut transactional_state = CachedState::create_transactional(&mut cached_state);
+        let mut transactional_state = CachedState::create_transactional(cached_state);
         let deprecated_declared_class_hash = match &tx {
             ExecutableTransactionInput::DeclareV0(
                 DeclareTransactionV0V1 { class_hash, .. },
@@ -660,8 +996,16 @@ fn execute_transactions(
             _ => None,
         };
         let blockifier_tx = to_blockifier_tx(tx, tx_hash, transaction_index)?;
+        // The same as `ExecutableTransaction::execute`, which doesn't support the concurrency mode.
+        let mut execution_state = CachedState::create_transactional(&mut transactional_state);
+        let execution_flags = ExecutionFlags { charge_fee, validate, concurrency_mode };
         let tx_execution_info_result =
-            blockifier_tx.execute(&mut transactional_state, &block_context, charge_fee, validate);
+            blockifier_tx.execute_raw(&mut execution_state, block_context, execution_flags);
+        if tx_execution_info_result.is_ok() {
+            execution_state.commit();
+        } else {
+            execution_state.abort();
+        }
         let state_diff =
             induced_state_diff(&mut transactional_state, deprecated_declared_class_hash)?;
         transactional_state.commit();
@@ -679,7 +1023,7 @@ fn execute_transactions(
         });
     }
 
-    Ok((res, block_context))
+    Ok(res)
 }
 
 /// Converts a transaction index and [BlockifierTransactionExecutionError] to an [ExecutionError].
@@ -883,6 +1227,9 @@ fn get_versioned_constants(
 }
 
 /// Simulates a series of transactions and returns the transaction traces and the fee estimations.
+/// If resource bounds are given, a transaction that exceeds them is aborted. If it reverted, its
+/// trace reports [RevertReason::OutOfResources], and otherwise the simulation fails with
+/// [ExecutionError::OutOfResources].
 // TODO(yair): Return structs instead of tuples.
 #[allow(clippy::too_many_arguments)]
 pub fn simulate_transactions(
@@ -893,13 +1240,16 @@ pub fn simulate_transactions(
     maybe_pending_data: Option<PendingData>,
     state_number: StateNumber,
     block_context_block_number: BlockNumber,
+    block_context_overrides: BlockContextOverrides,
+    resource_bounds: Option<ExecutionResourceBounds>,
     execution_config: &ExecutionConfig,
+    hot_state_cache: Option<&HotStateCache>,
     charge_fee: bool,
     validate: bool,
     override_kzg_da_to_false: bool,
 ) -> ExecutionResult<Vec<TransactionSimulationOutput>> {
     let trace_constructors = txs.iter().map(get_trace_constructor).collect::<Vec<_>>();
-    let (execution_results, block_context) = execute_transactions(
+    let execution_result = execute_transactions(
         txs,
         tx_hashes,
         chain_id,
@@ -907,17 +1257,64 @@ pub fn simulate_transactions(
         maybe_pending_data,
         state_number,
         block_context_block_number,
+        block_context_overrides,
+        resource_bounds,
         execution_config,
+        hot_state_cache,
         charge_fee,
         validate,
         override_kzg_da_to_false,
-    )?;
+    );
+    let Some(ExecutionResourceBounds { max_n_steps }) = resource_bounds else {
+        let (execution_results, block_context) = execution_result?;
+        return to_simulation_outputs(execution_results, trace_constructors, &block_context);
+    };
+
+    let (execution_results, block_context) = execution_result.map_err(|error| match error {
+        ExecutionError::TransactionExecutionError { transaction_index, execution_error }
+            if is_out_of_steps_error(&execution_error) =>
+        {
+            ExecutionError::OutOfResources { transaction_index, max_n_steps }
+        }
+        error => error,
+    })?;
+    let mut simulation_outputs =
+        to_simulation_outputs(execution_results, trace_constructors, &block_context)?;
+    // Only the execution of invoke transactions can revert.
+    for simulation_output in &mut simulation_outputs {
+        let TransactionTrace::Invoke(InvokeTransactionTrace { execute_invocation, .. }) =
+            &mut simulation_output.transaction_trace
+        else {
+            continue;
+        };
+        if matches!(
+            execute_invocation,
+            FunctionInvocationResult::Err(RevertReason::RevertReason(revert_reason))
+                if is_out_of_steps_error(revert_reason)
+        ) {
+            *execute_invocation =
+                FunctionInvocationResult::Err(RevertReason::OutOfResources { max_n_steps });
+        }
+    }
+    Ok(simulation_outputs)
+}
+
+// Whether the given execution error is of a Cairo run that stopped because it had no steps left.
+fn is_out_of_steps_error(execution_error: &str) -> bool {
+    execution_error.contains(&VirtualMachineError::UnfinishedExecution.to_string())
+}
+
+fn to_simulation_outputs(
+    execution_results: Vec<TransactionExecutionOutput>,
+    trace_constructors: Vec<fn(TransactionExecutionInfo) -> ExecutionResult<TransactionTrace>>,
+    block_context: &BlockContext,
+) -> ExecutionResult<Vec<TransactionSimulationOutput>> {
     execution_results
         .into_iter()
         .zip(trace_constructors)
         .map(|(tx_execution_output, trace_constructor)| {
             let fee_estimation =
-                tx_execution_output_to_fee_estimation(&tx_execution_output, &block_context)?;
+                tx_execution_output_to_fee_estimation(&tx_execution_output, block_context)?;
             match trace_constructor(tx_execution_output.execution_info) {
                 Ok(transaction_trace) => Ok(TransactionSimulationOutput {
                     transaction_trace,
@@ -929,3 +1326,203 @@ pub fn simulate_transactions(
         })
         .collect()
 }
+
+/// A block that isn't in the storage, whose transactions should be simulated.
+#[allow(missing_docs)]
+#[derive(Clone, Debug)]
+pub struct ProposedBlock {
+    pub timestamp: BlockTimestamp,
+    pub l1_gas_price: GasPricePerToken,
+    pub l1_data_gas_price: GasPricePerToken,
+    pub l1_da_mode: L1DataAvailabilityMode,
+    pub sequencer: SequencerContractAddress,
+    pub txs: Vec<ExecutableTransactionInput>,
+    /// If not given, the hashes are calculated from the transactions.
+    pub tx_hashes: Option<Vec<TransactionHash>>,
+}
+
+/// The result of simulating a [`ProposedBlock`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BlockExecutionSummary {
+    /// The number the block gets on top of the previous blocks.
+    pub block_number: BlockNumber,
+    /// The simulation results of the transactions of the block.
+    pub simulation_outputs: Vec<TransactionSimulationOutput>,
+}
+
+/// Simulates a sequence of blocks on top of the state right after `parent_block_number`. Each
+/// block is executed against the state left by the blocks before it. Nothing is written to the
+/// storage.
+pub fn execute_simulate_blocks(
+    chain_id: &ChainId,
+    storage_reader: StorageReader,
+    parent_block_number: BlockNumber,
+    blocks: Vec<ProposedBlock>,
+    execution_config: &ExecutionConfig,
+    charge_fee: bool,
+    validate: bool,
+) -> ExecutionResult<Vec<BlockExecutionSummary>> {
+    // The same cached state is used for all the blocks, so the writes of each block are visible
+    // to the ones after it.
+    let mut cached_state = CachedState::new(ExecutionStateReader {
+        storage_reader: storage_reader.clone(),
+        state_number: StateNumber::unchecked_right_after_block(parent_block_number),
+        maybe_pending_data: None,
+        missing_compiled_class: Cell::new(None),
+        hot_state: None,
+        read_keys: None,
+    });
+
+    let mut summaries = Vec::with_capacity(blocks.len());
+    let mut block_context_block_number = parent_block_number;
+    for block in blocks {
+        // The block context of a block that isn't stored is created like the one of a pending
+        // block on top of the block before it.
+        let block_header_data = PendingData {
+            timestamp: block.timestamp,
+            l1_gas_price: block.l1_gas_price,
+            l1_data_gas_price: block.l1_data_gas_price,
+            l1_da_mode: block.l1_da_mode,
+            sequencer: block.sequencer,
+            ..Default::default()
+        };
+        let block_context = create_block_context(
+            &mut cached_state,
+            block_context_block_number,
+            chain_id.clone(),
+            &storage_reader,
+            Some(&block_header_data),
+            execution_config,
+            BlockContextOverrides::default(),
+            None,
+            false,
+        )?;
+        let trace_constructors = block.txs.iter().map(get_trace_constructor).collect::<Vec<_>>();
+        let execution_results = execute_transactions_on_state(
+            &mut cached_state,
+            block.txs,
+            block.tx_hashes,
+            chain_id,
+            &block_context,
+            charge_fee,
+            validate,
+            false,
+        )?;
+        block_context_block_number = block_context_block_number.unchecked_next();
+        summaries.push(BlockExecutionSummary {
+            block_number: block_context_block_number,
+            simulation_outputs: to_simulation_outputs(
+                execution_results,
+                trace_constructors,
+                &block_context,
+            )?,
+        });
+    }
+    Ok(summaries)
+}
+
+/// Re-executes the transactions of a block that is already in the storage against the state right
+/// before that block and returns their traces.
+pub fn replay_block(
+    chain_id: &ChainId,
+    storage_reader: StorageReader,
+    block_number: BlockNumber,
+    execution_config: &ExecutionConfig,
+) -> ExecutionResult<Vec<TransactionTrace>> {
+    let state_number = StateNumber::right_before_block(block_number);
+    let txn = storage_reader.begin_ro_txn()?;
+    let (Some(block_transactions), Some(tx_hashes)) = (
+        txn.get_block_transactions(block_number)?,
+        txn.get_block_transaction_hashes(block_number)?,
+    ) else {
+        return Err(ExecutionError::BlockNotFound { block_number });
+    };
+    let txs = block_transactions
+        .into_iter()
+        .enumerate()
+        .map(|(transaction_index, tx)| {
+            stored_tx_to_executable_tx(tx, transaction_index, &txn, block_number)
+        })
+        .collect::<ExecutionResult<Vec<_>>>()?;
+    drop(txn);
+
+    Ok(simulate_transactions(
+        txs,
+        Some(tx_hashes),
+        chain_id,
+        storage_reader,
+        None,
+        state_number,
+        block_number,
+        BlockContextOverrides::default(),
+        None,
+        execution_config,
+        None,
+        true,
+        true,
+        false,
+    )?
+    .into_iter()
+    .map(|simulation_output| simulation_output.transaction_trace)
+    .collect())
+}
+
+// Converts a transaction of the given block to an executable transaction. The classes declared in
+// the block are only in the state after it, so they're read from there.
+fn stored_tx_to_executable_tx(
+    tx: Transaction,
+    transaction_index: usize,
+    txn: &StorageTxn<'_, RO>,
+    block_number: BlockNumber,
+) -> ExecutionResult<ExecutableTransactionInput> {
+    let state_number_after_block = StateNumber::unchecked_right_after_block(block_number);
+    match tx {
+        Transaction::Declare(DeclareTransaction::V0(tx)) => {
+            let class = txn
+                .get_state_reader()?
+                .get_deprecated_class_definition_at(state_number_after_block, &tx.class_hash)?
+                .ok_or(ExecutionError::MissingClassDefinition { class_hash: tx.class_hash })?;
+            let abi_length = calculate_deprecated_class_abi_length(&class)?;
+            Ok(ExecutableTransactionInput::DeclareV0(tx, class, abi_length, false))
+        }
+        Transaction::Declare(DeclareTransaction::V1(tx)) => {
+            let class = txn
+                .get_state_reader()?
+                .get_deprecated_class_definition_at(state_number_after_block, &tx.class_hash)?
+                .ok_or(ExecutionError::MissingClassDefinition { class_hash: tx.class_hash })?;
+            let abi_length = calculate_deprecated_class_abi_length(&class)?;
+            Ok(ExecutableTransactionInput::DeclareV1(tx, class, abi_length, false))
+        }
+        Transaction::Declare(DeclareTransaction::V2(tx)) => {
+            let (casm, sierra_size, abi_size) =
+                get_casm_and_class_lengths(txn, state_number_after_block, tx.class_hash)?;
+            Ok(ExecutableTransactionInput::DeclareV2(tx, casm, sierra_size, abi_size, false))
+        }
+        Transaction::Declare(DeclareTransaction::V3(tx)) => {
+            let (casm, sierra_size, abi_size) =
+                get_casm_and_class_lengths(txn, state_number_after_block, tx.class_hash)?;
+            Ok(ExecutableTransactionInput::DeclareV3(tx, casm, sierra_size, abi_size, false))
+        }
+        Transaction::Deploy(_) => {
+            Err(ExecutionError::UnsupportedDeployTransaction { transaction_index })
+        }
+        Transaction::DeployAccount(tx) => Ok(ExecutableTransactionInput::DeployAccount(tx, false)),
+        Transaction::Invoke(tx) => Ok(ExecutableTransactionInput::Invoke(tx, false)),
+        // TODO(yair): Use the fee that was paid on L1 once it's available.
+        Transaction::L1Handler(tx) => Ok(ExecutableTransactionInput::L1Handler(tx, Fee(1), false)),
+    }
+}
+
+fn get_casm_and_class_lengths(
+    txn: &StorageTxn<'_, RO>,
+    state_number: StateNumber,
+    class_hash: ClassHash,
+) -> ExecutionResult<(CasmContractClass, SierraSize, AbiSize)> {
+    let casm =
+        txn.get_casm(&class_hash)?.ok_or(ExecutionError::MissingCompiledClass { class_hash })?;
+    let class = txn
+        .get_state_reader()?
+        .get_class_definition_at(state_number, &class_hash)?
+        .ok_or(ExecutionError::MissingClassDefinition { class_hash })?;
+    Ok((casm, class.sierra_program.len(), class.abi.len()))
+}
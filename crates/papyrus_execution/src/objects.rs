@@ -22,7 +22,7 @@ use papyrus_common::state::{
     StorageEntry,
 };
 use serde::{Deserialize, Serialize};
-use starknet_api::block::{BlockTimestamp, GasPrice, GasPricePerToken};
+use starknet_api::block::{BlockNumber, BlockTimestamp, GasPrice, GasPricePerToken};
 use starknet_api::core::{
     ClassHash,
     ContractAddress,
@@ -113,6 +113,10 @@ pub struct FeeEstimation {
 pub enum RevertReason {
     #[serde(rename = "revert_reason")]
     RevertReason(String),
+    /// The transaction ran out of the steps allowed by the [ExecutionResourceBounds] of the
+    /// simulation.
+    #[serde(rename = "out_of_resources")]
+    OutOfResources { max_n_steps: u32 },
 }
 
 impl TryFrom<TransactionExecutionInfo> for InvokeTransactionTrace {
@@ -518,6 +522,29 @@ pub struct PendingData {
     pub classes: PendingClasses,
 }
 
+/// Values to use in the block context instead of the ones of the block the transactions run in.
+/// The overrides only affect the execution and are not written to the storage.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct BlockContextOverrides {
+    /// The block number that the transactions see.
+    pub block_number: Option<BlockNumber>,
+    /// The block timestamp that the transactions see.
+    pub timestamp: Option<BlockTimestamp>,
+    /// The sequencer address that the transactions see and that receives the fees.
+    pub sequencer_address: Option<SequencerContractAddress>,
+    /// The L1 gas price that the transactions are charged by.
+    pub l1_gas_price: Option<GasPricePerToken>,
+}
+
+/// Limits on the resources that each simulated transaction can use. A transaction that exceeds
+/// them is aborted instead of running until the limits of the Starknet version.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ExecutionResourceBounds {
+    /// The maximal number of Cairo steps in each of the validation and the execution of a
+    /// transaction.
+    pub max_n_steps: u32,
+}
+
 /// The unit of the fee.
 #[derive(
     Debug, Default, Clone, Copy, Eq, Hash, PartialEq, Deserialize, Serialize, PartialOrd, Ord,
@@ -2,7 +2,9 @@
 #[path = "state_reader_test.rs"]
 mod state_reader_test;
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 
 use blockifier::execution::contract_class::{
     ContractClass as BlockifierContractClass,
@@ -16,11 +18,12 @@ use papyrus_common::state::DeclaredClassHashEntry;
 use papyrus_storage::state::StateStorageReader;
 use papyrus_storage::{StorageError, StorageReader};
 use starknet_api::core::{ClassHash, CompiledClassHash, ContractAddress, Nonce};
-use starknet_api::state::{StateNumber, StorageKey};
+use starknet_api::state::{StateNumber, StorageKey, ThinStateDiff};
 use starknet_types_core::felt::Felt;
 
 use crate::execution_utils;
 use crate::execution_utils::{get_contract_class, ExecutionUtilsError};
+use crate::hot_state::{HotState, HotStateView};
 use crate::objects::PendingData;
 
 /// A view into the state at a specific state number.
@@ -31,6 +34,71 @@ pub struct ExecutionStateReader {
     // We want to return a custom error when missing a compiled class, but we need to return
     // Blockifier's error, so we store the missing class's hash in case of error.
     pub missing_compiled_class: Cell<Option<ClassHash>>,
+    // Values of the state that were already read by other executions. Only used when there's no
+    // pending data on top of the state.
+    pub hot_state: Option<HotStateView>,
+    // The keys that were read from the state. Only recorded when transactions are executed in
+    // parallel, to find the ones that depend on each other.
+    pub read_keys: Option<RefCell<StateKeys>>,
+}
+
+/// Keys of the state that were read or written by an execution.
+#[derive(Debug, Default)]
+pub struct StateKeys {
+    storage: HashSet<(ContractAddress, StorageKey)>,
+    nonces: HashSet<ContractAddress>,
+    class_hashes: HashSet<ContractAddress>,
+    // The classes whose compiled class or compiled class hash were accessed.
+    classes: HashSet<ClassHash>,
+}
+
+impl StateKeys {
+    /// Returns whether both contain the same key.
+    pub fn intersects(&self, other: &Self) -> bool {
+        !self.storage.is_disjoint(&other.storage)
+            || !self.nonces.is_disjoint(&other.nonces)
+            || !self.class_hashes.is_disjoint(&other.class_hashes)
+            || !self.classes.is_disjoint(&other.classes)
+    }
+
+    /// Returns whether the given storage key of the given contract is contained.
+    pub fn contains_storage(&self, contract_address: ContractAddress, key: StorageKey) -> bool {
+        self.storage.contains(&(contract_address, key))
+    }
+
+    pub fn extend(&mut self, other: Self) {
+        self.storage.extend(other.storage);
+        self.nonces.extend(other.nonces);
+        self.class_hashes.extend(other.class_hashes);
+        self.classes.extend(other.classes);
+    }
+}
+
+impl From<&ThinStateDiff> for StateKeys {
+    fn from(state_diff: &ThinStateDiff) -> Self {
+        Self {
+            storage: state_diff
+                .storage_diffs
+                .iter()
+                .flat_map(|(address, storage_entries)| {
+                    storage_entries.keys().map(|key| (*address, *key))
+                })
+                .collect(),
+            nonces: state_diff.nonces.keys().copied().collect(),
+            class_hashes: state_diff
+                .deployed_contracts
+                .keys()
+                .chain(state_diff.replaced_classes.keys())
+                .copied()
+                .collect(),
+            classes: state_diff
+                .declared_classes
+                .keys()
+                .chain(&state_diff.deprecated_declared_classes)
+                .copied()
+                .collect(),
+        }
+    }
 }
 
 impl BlockifierStateReader for ExecutionStateReader {
@@ -39,46 +107,67 @@ impl BlockifierStateReader for ExecutionStateReader {
         contract_address: ContractAddress,
         key: StorageKey,
     ) -> StateResult<Felt> {
-        execution_utils::get_storage_at(
-            &self.storage_reader.begin_ro_txn().map_err(storage_err_to_state_err)?,
-            self.state_number,
-            self.maybe_pending_data.as_ref().map(|pending_data| &pending_data.storage_diffs),
-            contract_address,
-            key,
-        )
-        .map_err(storage_err_to_state_err)
+        self.record_read(|read_keys| {
+            read_keys.storage.insert((contract_address, key));
+        });
+        let read = || {
+            execution_utils::get_storage_at(
+                &self.storage_reader.begin_ro_txn().map_err(storage_err_to_state_err)?,
+                self.state_number,
+                self.maybe_pending_data.as_ref().map(|pending_data| &pending_data.storage_diffs),
+                contract_address,
+                key,
+            )
+            .map_err(storage_err_to_state_err)
+        };
+        self.read_through_hot_state(|state| &mut state.storage, (contract_address, key), read)
     }
 
     // Returns the default value if the contract address is not found.
     fn get_nonce_at(&self, contract_address: ContractAddress) -> StateResult<Nonce> {
-        Ok(execution_utils::get_nonce_at(
-            &self.storage_reader.begin_ro_txn().map_err(storage_err_to_state_err)?,
-            self.state_number,
-            self.maybe_pending_data.as_ref().map(|pending_data| &pending_data.nonces),
-            contract_address,
-        )
-        .map_err(storage_err_to_state_err)?
-        .unwrap_or_default())
+        self.record_read(|read_keys| {
+            read_keys.nonces.insert(contract_address);
+        });
+        let read = || {
+            Ok(execution_utils::get_nonce_at(
+                &self.storage_reader.begin_ro_txn().map_err(storage_err_to_state_err)?,
+                self.state_number,
+                self.maybe_pending_data.as_ref().map(|pending_data| &pending_data.nonces),
+                contract_address,
+            )
+            .map_err(storage_err_to_state_err)?
+            .unwrap_or_default())
+        };
+        self.read_through_hot_state(|state| &mut state.nonces, contract_address, read)
     }
 
     // Returns the default value if the contract address is not found.
     fn get_class_hash_at(&self, contract_address: ContractAddress) -> StateResult<ClassHash> {
-        Ok(execution_utils::get_class_hash_at(
-            &self.storage_reader.begin_ro_txn().map_err(storage_err_to_state_err)?,
-            self.state_number,
-            self.maybe_pending_data.as_ref().map(|pending_data| {
-                (&pending_data.deployed_contracts, &pending_data.replaced_classes)
-            }),
-            contract_address,
-        )
-        .map_err(storage_err_to_state_err)?
-        .unwrap_or_default())
+        self.record_read(|read_keys| {
+            read_keys.class_hashes.insert(contract_address);
+        });
+        let read = || {
+            Ok(execution_utils::get_class_hash_at(
+                &self.storage_reader.begin_ro_txn().map_err(storage_err_to_state_err)?,
+                self.state_number,
+                self.maybe_pending_data.as_ref().map(|pending_data| {
+                    (&pending_data.deployed_contracts, &pending_data.replaced_classes)
+                }),
+                contract_address,
+            )
+            .map_err(storage_err_to_state_err)?
+            .unwrap_or_default())
+        };
+        self.read_through_hot_state(|state| &mut state.class_hashes, contract_address, read)
     }
 
     fn get_compiled_contract_class(
         &self,
         class_hash: ClassHash,
     ) -> StateResult<BlockifierContractClass> {
+        self.record_read(|read_keys| {
+            read_keys.classes.insert(class_hash);
+        });
         if let Some(pending_casm) = self
             .maybe_pending_data
             .as_ref()
@@ -115,6 +204,39 @@ impl BlockifierStateReader for ExecutionStateReader {
     }
 
     fn get_compiled_class_hash(&self, class_hash: ClassHash) -> StateResult<CompiledClassHash> {
+        self.record_read(|read_keys| {
+            read_keys.classes.insert(class_hash);
+        });
+        self.read_through_hot_state(
+            |state| &mut state.compiled_class_hashes,
+            class_hash,
+            || self.read_compiled_class_hash(class_hash),
+        )
+    }
+}
+
+impl ExecutionStateReader {
+    // Records the key of a read, if reads are recorded.
+    fn record_read(&self, record: impl FnOnce(&mut StateKeys)) {
+        if let Some(read_keys) = &self.read_keys {
+            record(&mut read_keys.borrow_mut());
+        }
+    }
+
+    // Reads a value through the hot state, if there is one.
+    fn read_through_hot_state<K: Eq + Hash, V: Copy>(
+        &self,
+        table: fn(&mut HotState) -> &mut HashMap<K, V>,
+        key: K,
+        read: impl FnOnce() -> StateResult<V>,
+    ) -> StateResult<V> {
+        match &self.hot_state {
+            Some(hot_state) => hot_state.get_or_read(table, key, read),
+            None => read(),
+        }
+    }
+
+    fn read_compiled_class_hash(&self, class_hash: ClassHash) -> StateResult<CompiledClassHash> {
         if let Some(pending_data) = &self.maybe_pending_data {
             for DeclaredClassHashEntry { class_hash: other_class_hash, compiled_class_hash } in
                 &pending_data.declared_classes
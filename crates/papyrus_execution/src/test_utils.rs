@@ -1,14 +1,16 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
+use assert_matches::assert_matches;
 use blockifier::abi::abi_utils::get_storage_var_address;
 use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
-use indexmap::indexmap;
+use indexmap::{indexmap, IndexMap};
 use lazy_static::lazy_static;
 use papyrus_storage::body::BodyStorageWriter;
 use papyrus_storage::class::ClassStorageWriter;
 use papyrus_storage::compiled_class::CasmStorageWriter;
-use papyrus_storage::header::HeaderStorageWriter;
-use papyrus_storage::state::StateStorageWriter;
+use papyrus_storage::header::{HeaderStorageReader, HeaderStorageWriter};
+use papyrus_storage::state::{StateStorageReader, StateStorageWriter};
 use papyrus_storage::{StorageReader, StorageWriter};
 use serde::de::DeserializeOwned;
 use starknet_api::block::{
@@ -25,38 +27,64 @@ use starknet_api::core::{
     ClassHash,
     CompiledClassHash,
     ContractAddress,
+    EntryPointSelector,
     Nonce,
     PatriciaKey,
     SequencerContractAddress,
 };
+use starknet_api::data_availability::DataAvailabilityMode;
 use starknet_api::deprecated_contract_class::ContractClass as DeprecatedContractClass;
 use starknet_api::state::{ContractClass, StateNumber, ThinStateDiff};
 use starknet_api::transaction::{
+    AccountDeploymentData,
     Calldata,
+    ContractAddressSalt,
     DeclareTransactionV0V1,
     DeclareTransactionV2,
+    DeclareTransactionV3,
     DeployAccountTransaction,
     DeployAccountTransactionV1,
     Fee,
     InvokeTransaction,
     InvokeTransactionV1,
+    InvokeTransactionV3,
+    L1HandlerTransaction,
+    PaymasterData,
+    Resource,
+    ResourceBounds,
+    ResourceBoundsMapping,
+    Tip,
     TransactionHash,
+    TransactionSignature,
+    TransactionVersion,
 };
 use starknet_api::{calldata, class_hash, contract_address, felt, patricia_key};
 use starknet_types_core::felt::Felt;
 use test_utils::read_json_file;
 
-use crate::execution_utils::selector_from_name;
-use crate::objects::{PendingData, TransactionSimulationOutput};
+use crate::execution_utils::{calculate_contract_address, selector_from_name};
+use crate::objects::{
+    BlockContextOverrides,
+    FunctionInvocationResult,
+    PendingData,
+    TransactionSimulationOutput,
+    TransactionTrace,
+};
 use crate::testing_instances::get_test_execution_config;
-use crate::{simulate_transactions, ExecutableTransactionInput, OnlyQuery, SierraSize};
+use crate::{
+    execute_call,
+    simulate_transactions,
+    ExecutableTransactionInput,
+    ExecutionResult,
+    OnlyQuery,
+    SierraSize,
+};
 
 lazy_static! {
     pub static ref CHAIN_ID: ChainId = ChainId::Other(String::from("TEST_CHAIN_ID"));
     pub static ref GAS_PRICE: GasPricePerToken = GasPricePerToken{
         price_in_wei: GasPrice(100 * u128::pow(10, 9)),
-        // TODO(yair): add value and tests.
-        price_in_fri: GasPrice::default(),
+        price_in_fri: GasPrice(200 * u128::pow(10, 9)),
     };
     pub static ref MAX_FEE: Fee = Fee(1000000 * GAS_PRICE.price_in_wei.0);
     pub static ref BLOCK_TIMESTAMP: BlockTimestamp = BlockTimestamp(1234);
@@ -72,10 +100,14 @@ lazy_static! {
     pub static ref TEST_ERC20_CONTRACT_CLASS_HASH: ClassHash = class_hash!("0x1010");
     pub static ref TEST_ERC20_CONTRACT_ADDRESS: ContractAddress = contract_address!("0x1001");
     pub static ref ACCOUNT_INITIAL_BALANCE: Felt = felt!(2 * MAX_FEE.0);
+    // The L1 address that the messages of L1 handler transactions are sent from.
+    pub static ref L1_SENDER_ADDRESS: Felt = felt!("0x987");
 }
 
 // Sierra size must be > 0.
 const DUMMY_SIERRA_SIZE: SierraSize = 1;
+// The L1 gas bound of V3 transactions. It's the amount of gas that MAX_FEE covers in WEI.
+pub const MAX_L1_GAS_AMOUNT: u64 = 1000000;
 
 fn get_test_instance<T: DeserializeOwned>(path_in_resource_dir: &str) -> T {
     serde_json::from_value(read_json_file(path_in_resource_dir)).unwrap()
@@ -95,8 +127,14 @@ pub fn get_test_erc20_fee_contract_class() -> DeprecatedContractClass {
 pub fn get_test_account_class() -> DeprecatedContractClass {
     get_test_instance("account_class.json")
 }
+// A deprecated class with entry points that fail unless the block has the expected info, taken
+// from the test contract of Blockifier.
+pub fn get_test_block_info_contract_class() -> DeprecatedContractClass {
+    get_test_instance("deprecated_block_info_contract_class.json")
+}
 
-pub fn prepare_storage(mut storage_writer: StorageWriter) {
+// Returns the writer so that tests can append more blocks on top of the prepared ones.
+pub fn prepare_storage(mut storage_writer: StorageWriter) -> StorageWriter {
     let class_hash0 = class_hash!("0x2");
     let class_hash1 = class_hash!("0x1");
 
@@ -191,6 +229,121 @@ pub fn prepare_storage(mut storage_writer: StorageWriter) {
         .unwrap()
         .commit()
         .unwrap();
+    storage_writer
+}
+
+// Deploys a contract of an already declared class in a new block, running its constructor with
+// the execution engine, and returns the address of the contract. Only the effects of the
+// deployment are written to the storage; the nonce of the deploying account doesn't change.
+pub fn deploy_with_constructor(
+    storage_reader: StorageReader,
+    storage_writer: &mut StorageWriter,
+    class_hash: ClassHash,
+    constructor_calldata: Calldata,
+) -> ContractAddress {
+    let txn = storage_reader.begin_ro_txn().unwrap();
+    let block_number = txn.get_state_marker().unwrap();
+    let parent_block_number =
+        block_number.prev().expect("The storage should be prepared before deploying contracts.");
+    let parent_hash = txn.get_block_header(parent_block_number).unwrap().unwrap().block_hash;
+    let state_number = StateNumber::unchecked_right_after_block(parent_block_number);
+    let account_nonce = txn
+        .get_state_reader()
+        .unwrap()
+        .get_nonce_at(state_number, &ACCOUNT_ADDRESS)
+        .unwrap()
+        .unwrap_or_default();
+
+    // A salt per block lets tests deploy the same class with the same calldata more than once.
+    let contract_address_salt = ContractAddressSalt(felt!(block_number.0));
+    let contract_address = calculate_contract_address(
+        contract_address_salt,
+        class_hash,
+        &constructor_calldata,
+        ContractAddress::default(),
+    );
+    // The account calls the deploy syscall through the test_deploy entry point of the deprecated
+    // test contract, deploying from the zero address.
+    let test_deploy_calldata = [
+        vec![class_hash.0, contract_address_salt.0, felt!(constructor_calldata.0.len() as u64)],
+        constructor_calldata.0.to_vec(),
+        vec![felt!(1_u8)],
+    ]
+    .concat();
+    let calldata = [
+        vec![
+            *DEPRECATED_CONTRACT_ADDRESS.0.key(),
+            selector_from_name("test_deploy").0,
+            felt!(test_deploy_calldata.len() as u64),
+        ],
+        test_deploy_calldata,
+    ]
+    .concat();
+    let tx = ExecutableTransactionInput::Invoke(
+        InvokeTransaction::V1(InvokeTransactionV1 {
+            calldata: Calldata(Arc::new(calldata)),
+            max_fee: *MAX_FEE,
+            sender_address: *ACCOUNT_ADDRESS,
+            nonce: account_nonce,
+            ..Default::default()
+        }),
+        false,
+    );
+    let simulation_output = simulate_transactions(
+        vec![tx],
+        None,
+        &CHAIN_ID,
+        storage_reader,
+        None,
+        state_number,
+        parent_block_number,
+        BlockContextOverrides::default(),
+        None,
+        &get_test_execution_config(),
+        None,
+        false,
+        false,
+        true,
+    )
+    .unwrap()
+    .remove(0);
+    let TransactionTrace::Invoke(invoke_trace) = simulation_output.transaction_trace else {
+        panic!("Expected an invoke transaction trace.");
+    };
+    assert_matches!(
+        invoke_trace.execute_invocation,
+        FunctionInvocationResult::Ok(_),
+        "The deployment of a contract of class {class_hash} failed."
+    );
+
+    let state_diff =
+        ThinStateDiff { nonces: IndexMap::new(), ..simulation_output.induced_state_diff };
+    assert_eq!(state_diff.deployed_contracts.get(&contract_address), Some(&class_hash));
+    storage_writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_header(
+            block_number,
+            &BlockHeader {
+                l1_gas_price: *GAS_PRICE,
+                sequencer: *SEQUENCER_ADDRESS,
+                timestamp: *BLOCK_TIMESTAMP,
+                block_hash: BlockHash(felt!(block_number.0)),
+                parent_hash,
+                block_number,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .append_body(block_number, BlockBody::default())
+        .unwrap()
+        .append_state_diff(block_number, state_diff)
+        .unwrap()
+        .append_classes(block_number, &[], &[])
+        .unwrap()
+        .commit()
+        .unwrap();
+    contract_address
 }
 
 pub fn execute_simulate_transactions(
@@ -200,6 +353,26 @@ pub fn execute_simulate_transactions(
     tx_hashes: Option<Vec<TransactionHash>>,
     charge_fee: bool,
     validate: bool,
+) -> Vec<TransactionSimulationOutput> {
+    execute_simulate_transactions_with_overrides(
+        storage_reader,
+        maybe_pending_data,
+        txs,
+        tx_hashes,
+        BlockContextOverrides::default(),
+        charge_fee,
+        validate,
+    )
+}
+
+pub fn execute_simulate_transactions_with_overrides(
+    storage_reader: StorageReader,
+    maybe_pending_data: Option<PendingData>,
+    txs: Vec<ExecutableTransactionInput>,
+    tx_hashes: Option<Vec<TransactionHash>>,
+    block_context_overrides: BlockContextOverrides,
+    charge_fee: bool,
+    validate: bool,
 ) -> Vec<TransactionSimulationOutput> {
     let chain_id = ChainId::Other(CHAIN_ID.to_string());
 
@@ -211,7 +384,10 @@ pub fn execute_simulate_transactions(
         maybe_pending_data,
         StateNumber::unchecked_right_after_block(BlockNumber(0)),
         BlockNumber(1),
+        block_context_overrides,
+        None,
         &get_test_execution_config(),
+        None,
         charge_fee,
         validate,
         // TODO: Consider testing without overriding DA (It's already tested in the RPC)
@@ -220,19 +396,74 @@ pub fn execute_simulate_transactions(
     .unwrap()
 }
 
+// Calls an entry point of a contract in the state after the given block and returns its retdata.
+// Unlike invoking the entry point in a transaction, no account, nonce or fee is involved.
+pub fn call_contract(
+    storage_reader: StorageReader,
+    block_number: BlockNumber,
+    contract_address: ContractAddress,
+    entry_point_selector: EntryPointSelector,
+    calldata: Calldata,
+) -> ExecutionResult<Vec<Felt>> {
+    let chain_id = ChainId::Other(CHAIN_ID.to_string());
+
+    let call_execution = execute_call(
+        storage_reader,
+        None,
+        &chain_id,
+        StateNumber::unchecked_right_after_block(block_number),
+        block_number,
+        &contract_address,
+        entry_point_selector,
+        calldata,
+        &get_test_execution_config(),
+        true,
+    )?;
+    Ok(call_execution.retdata.0)
+}
+
+// Returns resource bounds that allow the given amount of L1 gas at the test price in FRI, and no L2
+// gas.
+pub fn l1_gas_resource_bounds(max_amount: u64) -> ResourceBoundsMapping {
+    ResourceBoundsMapping(BTreeMap::from([
+        (
+            Resource::L1Gas,
+            ResourceBounds { max_amount, max_price_per_unit: GAS_PRICE.price_in_fri.0 },
+        ),
+        (Resource::L2Gas, ResourceBounds::default()),
+    ]))
+}
+
+// Returns the calldata of an account call to the return_result function of the given contract.
+fn return_result_calldata(contract_address: ContractAddress) -> Calldata {
+    calldata![
+        *contract_address.0.key(),             // Contract address.
+        selector_from_name("return_result").0, // EP selector.
+        felt!(1_u8),                           // Calldata length.
+        felt!(2_u8)                            // Calldata: num.
+    ]
+}
+
 // Creates transactions for testing while resolving nonces and class hashes uniqueness.
 pub struct TxsScenarioBuilder {
     // Each transaction by the same sender needs a unique nonce.
     sender_to_nonce: HashMap<ContractAddress, u128>,
     // Each declare class needs a unique class hash.
     next_class_hash: u128,
+    // Each message from L1 needs a unique nonce.
+    next_l1_message_nonce: u128,
     // the result.
     txs: Vec<ExecutableTransactionInput>,
 }
 
 impl Default for TxsScenarioBuilder {
     fn default() -> Self {
-        Self { sender_to_nonce: HashMap::new(), next_class_hash: 100_u128, txs: Vec::new() }
+        Self {
+            sender_to_nonce: HashMap::new(),
+            next_class_hash: 100_u128,
+            next_l1_message_nonce: 0,
+            txs: Vec::new(),
+        }
     }
 }
 
@@ -248,12 +479,7 @@ impl TxsScenarioBuilder {
         nonce: Option<Nonce>,
         only_query: OnlyQuery,
     ) -> Self {
-        let calldata = calldata![
-            *contract_address.0.key(),             // Contract address.
-            selector_from_name("return_result").0, // EP selector.
-            felt!(1_u8),                           // Calldata length.
-            felt!(2_u8)                            // Calldata: num.
-        ];
+        let calldata = return_result_calldata(contract_address);
         let nonce = match nonce {
             None => self.next_nonce(sender_address),
             Some(nonce) => {
@@ -277,47 +503,145 @@ impl TxsScenarioBuilder {
         self
     }
 
-    pub fn declare_deprecated_class(mut self, sender_address: ContractAddress) -> Self {
-        let tx = ExecutableTransactionInput::DeclareV1(
-            DeclareTransactionV0V1 {
-                max_fee: *MAX_FEE,
-                sender_address,
+    // Invokes the same function as invoke_deprecated, with a V3 transaction. V3 transactions are
+    // priced in FRI and bounded by their resource bounds instead of a max fee.
+    pub fn invoke_v3(
+        mut self,
+        sender_address: ContractAddress,
+        contract_address: ContractAddress,
+        resource_bounds: ResourceBoundsMapping,
+        tip: Tip,
+    ) -> Self {
+        let tx = ExecutableTransactionInput::Invoke(
+            InvokeTransaction::V3(InvokeTransactionV3 {
+                resource_bounds,
+                tip,
+                signature: TransactionSignature::default(),
                 nonce: self.next_nonce(sender_address),
-                class_hash: self.next_class_hash(),
-                ..Default::default()
-            },
-            get_test_deprecated_contract_class(),
-            0,
+                sender_address,
+                calldata: return_result_calldata(contract_address),
+                nonce_data_availability_mode: DataAvailabilityMode::L1,
+                fee_data_availability_mode: DataAvailabilityMode::L1,
+                paymaster_data: PaymasterData::default(),
+                account_deployment_data: AccountDeploymentData::default(),
+            }),
             false,
         );
         self.txs.push(tx);
         self
     }
 
+    pub fn declare_deprecated_class(mut self, sender_address: ContractAddress) -> Self {
+        let class_hash = self.next_class_hash();
+        self.declare(
+            sender_address,
+            class_hash,
+            CompiledClassHash::default(),
+            TransactionVersion::ONE,
+        )
+    }
+
     pub fn declare_class(mut self, sender_address: ContractAddress) -> TxsScenarioBuilder {
-        let tx = ExecutableTransactionInput::DeclareV2(
-            DeclareTransactionV2 {
-                max_fee: *MAX_FEE,
-                sender_address,
-                nonce: self.next_nonce(sender_address),
-                class_hash: self.next_class_hash(),
-                ..Default::default()
-            },
-            get_test_casm(),
-            DUMMY_SIERRA_SIZE,
-            0,
-            false,
-        );
+        let class_hash = self.next_class_hash();
+        self.declare(
+            sender_address,
+            class_hash,
+            CompiledClassHash::default(),
+            TransactionVersion::TWO,
+        )
+    }
+
+    // Declares the test class with the given hashes. Version 1 declares a deprecated class, in
+    // which case the compiled class hash is ignored, and versions 2 and 3 declare a Sierra class.
+    pub fn declare(
+        mut self,
+        sender_address: ContractAddress,
+        class_hash: ClassHash,
+        compiled_class_hash: CompiledClassHash,
+        version: TransactionVersion,
+    ) -> Self {
+        let nonce = self.next_nonce(sender_address);
+        let tx = if version == TransactionVersion::ONE {
+            ExecutableTransactionInput::DeclareV1(
+                DeclareTransactionV0V1 {
+                    max_fee: *MAX_FEE,
+                    sender_address,
+                    nonce,
+                    class_hash,
+                    ..Default::default()
+                },
+                get_test_deprecated_contract_class(),
+                0,
+                false,
+            )
+        } else if version == TransactionVersion::TWO {
+            ExecutableTransactionInput::DeclareV2(
+                DeclareTransactionV2 {
+                    max_fee: *MAX_FEE,
+                    sender_address,
+                    nonce,
+                    class_hash,
+                    compiled_class_hash,
+                    ..Default::default()
+                },
+                get_test_casm(),
+                DUMMY_SIERRA_SIZE,
+                0,
+                false,
+            )
+        } else if version == TransactionVersion::THREE {
+            ExecutableTransactionInput::DeclareV3(
+                DeclareTransactionV3 {
+                    resource_bounds: l1_gas_resource_bounds(MAX_L1_GAS_AMOUNT),
+                    tip: Tip::default(),
+                    signature: TransactionSignature::default(),
+                    nonce,
+                    class_hash,
+                    compiled_class_hash,
+                    sender_address,
+                    nonce_data_availability_mode: DataAvailabilityMode::L1,
+                    fee_data_availability_mode: DataAvailabilityMode::L1,
+                    paymaster_data: PaymasterData::default(),
+                    account_deployment_data: AccountDeploymentData::default(),
+                },
+                get_test_casm(),
+                DUMMY_SIERRA_SIZE,
+                0,
+                false,
+            )
+        } else {
+            panic!("Unsupported declare transaction version {version:?}.");
+        };
         self.txs.push(tx);
         self
     }
 
-    pub fn deploy_account(mut self) -> TxsScenarioBuilder {
+    pub fn deploy_account(self) -> TxsScenarioBuilder {
+        self.deploy_account_with(*ACCOUNT_CLASS_HASH, calldata![], ContractAddressSalt::default())
+    }
+
+    // Deploys an account of the given class. The address of the account is derived from the
+    // class hash, salt and calldata, so that following transactions of the account get the
+    // right nonces.
+    pub fn deploy_account_with(
+        mut self,
+        class_hash: ClassHash,
+        constructor_calldata: Calldata,
+        contract_address_salt: ContractAddressSalt,
+    ) -> Self {
+        let account_address = calculate_contract_address(
+            contract_address_salt,
+            class_hash,
+            &constructor_calldata,
+            ContractAddress::default(),
+        );
         let tx = ExecutableTransactionInput::DeployAccount(
             DeployAccountTransaction::V1(DeployAccountTransactionV1 {
                 max_fee: *MAX_FEE,
-                nonce: Nonce(felt!(0_u128)),
-                class_hash: *ACCOUNT_CLASS_HASH,
+                nonce: self.next_nonce(account_address),
+                class_hash,
+                constructor_calldata,
+                contract_address_salt,
                 ..Default::default()
             }),
             false,
@@ -326,7 +650,34 @@ impl TxsScenarioBuilder {
         self
     }
 
-    // TODO(yair): add l1 handler transaction.
+    // Delivers a message from L1 with the given payload to an L1 handler of the contract. The nonce
+    // of an L1 handler is the nonce of the message on L1, so it doesn't affect the contract's
+    // nonce.
+    pub fn l1_handler(
+        mut self,
+        contract_address: ContractAddress,
+        entry_point_selector: EntryPointSelector,
+        payload: Calldata,
+    ) -> Self {
+        // The first item of the calldata is the L1 address that sent the message.
+        let calldata = Calldata(Arc::new([vec![*L1_SENDER_ADDRESS], payload.0.to_vec()].concat()));
+        let nonce = Nonce(felt!(self.next_l1_message_nonce));
+        self.next_l1_message_nonce += 1;
+        let tx = ExecutableTransactionInput::L1Handler(
+            L1HandlerTransaction {
+                version: TransactionVersion::ONE,
+                nonce,
+                contract_address,
+                entry_point_selector,
+                calldata,
+            },
+            // The fee paid on L1 isn't charged on L2, the blockifier only checks that it isn't 0.
+            Fee(1),
+            false,
+        );
+        self.txs.push(tx);
+        self
+    }
 
     fn next_nonce(&mut self, sender_address: ContractAddress) -> Nonce {
         match self.sender_to_nonce.get_mut(&sender_address) {
@@ -36,6 +36,9 @@ pub fn get_test_execution_config() -> ExecutionConfig {
         strk_fee_contract_address: contract_address!("0x1001"),
         eth_fee_contract_address: contract_address!("0x1001"),
         initial_gas_cost: 10_u64.pow(10),
+        hot_state_blocks: 10,
+        hot_state_max_entries: 100_000,
+        execution_threads: 1,
     }
 }
 
@@ -0,0 +1,213 @@
+//! Benchmarks for the throughput and latency of SQMR sessions between two peers connected over the
+//! memory transport.
+//!
+//! Run with `cargo bench -p papyrus_network`.
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::StreamExt;
+use libp2p::swarm::SwarmEvent;
+use libp2p::{StreamProtocol, Swarm};
+use libp2p_swarm_test::SwarmExt;
+use papyrus_network::sqmr::behaviour::{Behaviour, Event, ExternalEvent};
+use papyrus_network::sqmr::{Config, InboundSessionId, SessionId, DEFAULT_MAX_MESSAGE_SIZE};
+use tokio::runtime::Runtime;
+
+const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/papyrus/bench/1");
+const FRAME_SIZES: [usize; 3] = [64, 1024, 16 * 1024];
+const NUM_SESSIONS: [usize; 2] = [1, 10];
+const NUM_FRAMES_PER_SESSION: usize = 50;
+const NUM_CONCURRENT_SESSIONS_UNDER_STRESS: usize = 100;
+const FRAME_SIZE_UNDER_STRESS: usize = 1024;
+const CONST_BYTE: u8 = 1;
+
+enum PeerEvent {
+    Outbound(ExternalEvent),
+    Inbound(ExternalEvent),
+}
+
+async fn create_connected_swarms() -> (Swarm<Behaviour>, Swarm<Behaviour>) {
+    let create_behaviour = || {
+        let mut behaviour = Behaviour::new(Config {
+            session_timeout: Duration::from_secs(60),
+            deterministic_event_order: false,
+            max_concurrent_inbound_sessions_per_peer: None,
+            inbound_session_rate_limit_per_peer: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        });
+        behaviour.add_new_supported_inbound_protocol(PROTOCOL_NAME);
+        behaviour
+    };
+    let mut outbound_swarm = Swarm::new_ephemeral(|_| create_behaviour());
+    let mut inbound_swarm = Swarm::new_ephemeral(|_| create_behaviour());
+    outbound_swarm.listen().with_memory_addr_external().await;
+    inbound_swarm.listen().with_memory_addr_external().await;
+    outbound_swarm.connect(&mut inbound_swarm).await;
+    (outbound_swarm, inbound_swarm)
+}
+
+/// Poll both swarms until one of them emits an external event of the SQMR behaviour.
+async fn next_event(
+    outbound_swarm: &mut Swarm<Behaviour>,
+    inbound_swarm: &mut Swarm<Behaviour>,
+) -> PeerEvent {
+    loop {
+        tokio::select! {
+            event = outbound_swarm.select_next_some() => {
+                if let SwarmEvent::Behaviour(Event::External(event)) = event {
+                    return PeerEvent::Outbound(event);
+                }
+            }
+            event = inbound_swarm.select_next_some() => {
+                if let SwarmEvent::Behaviour(Event::External(event)) = event {
+                    return PeerEvent::Inbound(event);
+                }
+            }
+        }
+    }
+}
+
+fn send_query(outbound_swarm: &mut Swarm<Behaviour>, inbound_swarm: &Swarm<Behaviour>) {
+    outbound_swarm
+        .behaviour_mut()
+        .send_query(vec![], *inbound_swarm.local_peer_id(), vec![PROTOCOL_NAME])
+        .expect("The benchmarked peers should be connected");
+}
+
+fn send_frame(
+    inbound_swarm: &mut Swarm<Behaviour>,
+    inbound_session_id: InboundSessionId,
+    frame_size: usize,
+) {
+    inbound_swarm
+        .behaviour_mut()
+        .send_response(vec![CONST_BYTE; frame_size], inbound_session_id)
+        .expect("The benchmarked session should be open");
+}
+
+/// Open `num_sessions` concurrent sessions, where the inbound peer answers each one with
+/// `NUM_FRAMES_PER_SESSION` frames of `frame_size` bytes, and wait until all of them finished.
+async fn run_sessions(
+    outbound_swarm: &mut Swarm<Behaviour>,
+    inbound_swarm: &mut Swarm<Behaviour>,
+    num_sessions: usize,
+    frame_size: usize,
+) {
+    for _ in 0..num_sessions {
+        send_query(outbound_swarm, inbound_swarm);
+    }
+    let mut num_finished_sessions = 0;
+    while num_finished_sessions < num_sessions {
+        match next_event(outbound_swarm, inbound_swarm).await {
+            PeerEvent::Inbound(ExternalEvent::NewInboundSession { inbound_session_id, .. }) => {
+                for _ in 0..NUM_FRAMES_PER_SESSION {
+                    send_frame(inbound_swarm, inbound_session_id, frame_size);
+                }
+                inbound_swarm
+                    .behaviour_mut()
+                    .close_inbound_session(inbound_session_id)
+                    .expect("The benchmarked session should be open");
+            }
+            PeerEvent::Outbound(ExternalEvent::SessionFinishedSuccessfully {
+                session_id: SessionId::OutboundSessionId(_),
+            }) => num_finished_sessions += 1,
+            PeerEvent::Outbound(ExternalEvent::SessionFailed { error, .. })
+            | PeerEvent::Inbound(ExternalEvent::SessionFailed { error, .. }) => {
+                panic!("Benchmarked session failed: {error:?}")
+            }
+            _ => {}
+        }
+    }
+}
+
+fn bench_throughput(
+    criterion: &mut Criterion,
+    group_name: &str,
+    num_sessions_and_frame_sizes: impl IntoIterator<Item = (usize, usize)>,
+) {
+    let runtime = Runtime::new().expect("Failed creating a tokio runtime");
+    let mut group = criterion.benchmark_group(group_name);
+    group.sample_size(10);
+    for (num_sessions, frame_size) in num_sessions_and_frame_sizes {
+        let (mut outbound_swarm, mut inbound_swarm) = runtime.block_on(create_connected_swarms());
+        group.throughput(Throughput::Elements((num_sessions * NUM_FRAMES_PER_SESSION) as u64));
+        group.bench_function(
+            BenchmarkId::new(format!("{num_sessions}_sessions"), format!("{frame_size}_bytes")),
+            |bencher| {
+                bencher.iter(|| {
+                    runtime.block_on(run_sessions(
+                        &mut outbound_swarm,
+                        &mut inbound_swarm,
+                        num_sessions,
+                        frame_size,
+                    ))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Measure the amount of frames received per second for different amounts of sessions and frame
+/// sizes.
+fn frames_throughput(criterion: &mut Criterion) {
+    bench_throughput(
+        criterion,
+        "sqmr_frames_throughput",
+        NUM_SESSIONS.into_iter().flat_map(|num_sessions| {
+            FRAME_SIZES.into_iter().map(move |frame_size| (num_sessions, frame_size))
+        }),
+    );
+}
+
+/// Measure the aggregate throughput when many sessions run concurrently over the same connection.
+fn concurrent_sessions_throughput(criterion: &mut Criterion) {
+    bench_throughput(
+        criterion,
+        "sqmr_concurrent_sessions_throughput",
+        [(NUM_CONCURRENT_SESSIONS_UNDER_STRESS, FRAME_SIZE_UNDER_STRESS)],
+    );
+}
+
+/// Measure the time from sending a frame in an open session until the other peer receives it.
+fn frame_latency(criterion: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed creating a tokio runtime");
+    let mut group = criterion.benchmark_group("sqmr_frame_latency");
+    for frame_size in FRAME_SIZES {
+        let (mut outbound_swarm, mut inbound_swarm) = runtime.block_on(create_connected_swarms());
+        send_query(&mut outbound_swarm, &inbound_swarm);
+        let inbound_session_id = runtime.block_on(async {
+            loop {
+                if let PeerEvent::Inbound(ExternalEvent::NewInboundSession {
+                    inbound_session_id,
+                    ..
+                }) = next_event(&mut outbound_swarm, &mut inbound_swarm).await
+                {
+                    return inbound_session_id;
+                }
+            }
+        });
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{frame_size}_bytes")),
+            |bencher| {
+                bencher.iter_custom(|num_iterations| {
+                    runtime.block_on(async {
+                        let start_time = Instant::now();
+                        for _ in 0..num_iterations {
+                            send_frame(&mut inbound_swarm, inbound_session_id, frame_size);
+                            while !matches!(
+                                next_event(&mut outbound_swarm, &mut inbound_swarm).await,
+                                PeerEvent::Outbound(ExternalEvent::ReceivedResponse { .. })
+                            ) {}
+                        }
+                        start_time.elapsed()
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, frames_throughput, concurrent_sessions_throughput, frame_latency);
+criterion_main!(benches);
@@ -7,7 +7,15 @@ use libp2p::swarm::SwarmEvent;
 use libp2p::{PeerId, StreamProtocol, Swarm};
 use papyrus_network::bin_utils::{build_swarm, dial};
 use papyrus_network::sqmr::behaviour::{Behaviour, Event, ExternalEvent, SessionError};
-use papyrus_network::sqmr::{Bytes, Config, InboundSessionId, OutboundSessionId, SessionId};
+use papyrus_network::sqmr::{
+    Bytes,
+    Config,
+    InboundSessionId,
+    OutboundSessionId,
+    SessionId,
+    DEFAULT_MAX_MESSAGE_SIZE,
+};
+use papyrus_network::upgrade_limit::InboundUpgradeLimit;
 
 const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/papyrus/bench/1");
 const CONST_BYTE: u8 = 1;
@@ -78,6 +86,10 @@ struct Args {
     /// Amount of time (in seconds) to wait until closing an unactive connection.
     #[arg(short = 't', long, default_value_t = 10)]
     idle_connection_timeout: u64,
+
+    /// Maximal number of inbound connections to upgrade at the same time.
+    #[arg(short = 'u', long, default_value_t = 32)]
+    max_concurrent_inbound_upgrades: usize,
 }
 
 fn create_outbound_sessions_if_all_peers_connected(
@@ -92,7 +104,7 @@ fn create_outbound_sessions_if_all_peers_connected(
         for peer_id in peers_pending_outbound_session {
             for _ in 0..args.num_queries_per_connection {
                 let outbound_session_id =
-                    swarm.behaviour_mut().send_query(vec![], *peer_id, PROTOCOL_NAME).expect(
+                    swarm.behaviour_mut().send_query(vec![], *peer_id, vec![PROTOCOL_NAME]).expect(
                         "There's no connection to a peer immediately after we got a \
                          ConnectionEstablished event",
                     );
@@ -219,9 +231,15 @@ async fn main() {
         vec![args.listen_address.clone()],
         Duration::from_secs(args.idle_connection_timeout),
         None,
+        InboundUpgradeLimit::new(args.max_concurrent_inbound_upgrades),
         |_| {
-            let mut behaviour =
-                Behaviour::new(Config { session_timeout: Duration::from_secs(3600) });
+            let mut behaviour = Behaviour::new(Config {
+                session_timeout: Duration::from_secs(3600),
+                deterministic_event_order: false,
+                max_concurrent_inbound_sessions_per_peer: None,
+                inbound_session_rate_limit_per_peer: None,
+                max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            });
             behaviour.add_new_supported_inbound_protocol(PROTOCOL_NAME);
             behaviour
         },
@@ -285,6 +303,7 @@ async fn main() {
                 outbound_session_id,
                 response,
                 peer_id: _,
+                protocol_name: _,
             })) => {
                 if response[0] != CONST_BYTE {
                     outbound_session_measurements
@@ -1,16 +1,23 @@
+#[cfg(test)]
+mod test;
+
 use std::str::FromStr;
 use std::time::Duration;
 
+use libp2p::core::upgrade::Version;
 use libp2p::identity::Keypair;
 use libp2p::swarm::dial_opts::DialOpts;
 use libp2p::swarm::NetworkBehaviour;
-use libp2p::{noise, yamux, Multiaddr, Swarm, SwarmBuilder};
+use libp2p::{noise, tcp, yamux, Multiaddr, Swarm, SwarmBuilder, Transport};
 use tracing::debug;
 
+use crate::upgrade_limit::InboundUpgradeLimit;
+
 pub fn build_swarm<Behaviour: NetworkBehaviour>(
     listen_addresses: Vec<String>,
     idle_connection_timeout: Duration,
     secret_key: Option<Vec<u8>>,
+    inbound_upgrade_limit: InboundUpgradeLimit,
     behaviour: impl Fn(Keypair) -> Behaviour,
 ) -> Swarm<Behaviour>
 where
@@ -29,7 +36,16 @@ where
     };
     let mut swarm = SwarmBuilder::with_existing_identity(key_pair)
         .with_tokio()
-        .with_tcp(Default::default(), noise::Config::new, yamux::Config::default)
+        .with_other_transport(|key| {
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(
+                inbound_upgrade_limit.apply(
+                    tcp::tokio::Transport::new(Default::default())
+                        .upgrade(Version::V1Lazy)
+                        .authenticate(noise::Config::new(key)?)
+                        .multiplex(yamux::Config::default()),
+                ),
+            )
+        })
         .expect("Error building TCP transport")
         // TODO: quic transpot does not work (failure appears in the command line when running in debug mode)
         // .with_quic()
@@ -0,0 +1,138 @@
+use std::time::{Duration, Instant};
+
+use assert_matches::assert_matches;
+use futures::StreamExt;
+use libp2p::swarm::{Swarm, SwarmEvent};
+use libp2p::StreamProtocol;
+
+use super::build_swarm;
+use crate::sqmr::behaviour::{Behaviour, Event, ExternalEvent};
+use crate::sqmr::Config;
+use crate::upgrade_limit::InboundUpgradeLimit;
+
+const IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_millis(500);
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/papyrus/test/1");
+
+enum Peer {
+    Dialer,
+    Listener,
+}
+
+fn create_swarm() -> Swarm<Behaviour> {
+    build_swarm(
+        vec!["/ip4/127.0.0.1/tcp/0".to_owned()],
+        IDLE_CONNECTION_TIMEOUT,
+        None,
+        InboundUpgradeLimit::new(1),
+        |_| {
+            let mut behaviour = Behaviour::new(Config {
+                session_timeout: SESSION_TIMEOUT,
+                ..Config::get_test_config()
+            });
+            behaviour.add_new_supported_inbound_protocol(PROTOCOL_NAME);
+            behaviour
+        },
+    )
+}
+
+// Polls both swarms and returns the next event of either of them.
+async fn next_event(
+    dialer: &mut Swarm<Behaviour>,
+    listener: &mut Swarm<Behaviour>,
+) -> (Peer, SwarmEvent<Event>) {
+    tokio::select! {
+        event = dialer.select_next_some() => (Peer::Dialer, event),
+        event = listener.select_next_some() => (Peer::Listener, event),
+    }
+}
+
+async fn connect(dialer: &mut Swarm<Behaviour>, listener: &mut Swarm<Behaviour>) {
+    let listen_address = loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = listener.select_next_some().await {
+            break address;
+        }
+    };
+    dialer.dial(listen_address).unwrap();
+    let mut n_established_connections = 0;
+    while n_established_connections < 2 {
+        if let (_, SwarmEvent::ConnectionEstablished { .. }) = next_event(dialer, listener).await {
+            n_established_connections += 1;
+        }
+    }
+}
+
+async fn wait_for_dialer_connection_closed(
+    dialer: &mut Swarm<Behaviour>,
+    listener: &mut Swarm<Behaviour>,
+) {
+    loop {
+        if let (Peer::Dialer, SwarmEvent::ConnectionClosed { .. }) =
+            next_event(dialer, listener).await
+        {
+            return;
+        }
+    }
+}
+
+#[tokio::test]
+async fn idle_connection_is_closed_after_idle_timeout() {
+    let mut dialer = create_swarm();
+    let mut listener = create_swarm();
+    connect(&mut dialer, &mut listener).await;
+
+    let start = Instant::now();
+    tokio::time::timeout(
+        IDLE_CONNECTION_TIMEOUT * 10,
+        wait_for_dialer_connection_closed(&mut dialer, &mut listener),
+    )
+    .await
+    .expect("Idle connection wasn't closed.");
+    // The idle timer may start slightly before the connection is reported as established.
+    assert!(start.elapsed() >= IDLE_CONNECTION_TIMEOUT / 2);
+}
+
+#[tokio::test]
+async fn connection_with_active_session_is_kept_alive() {
+    let mut dialer = create_swarm();
+    let mut listener = create_swarm();
+    connect(&mut dialer, &mut listener).await;
+
+    let listener_peer_id = *listener.local_peer_id();
+    dialer.behaviour_mut().send_query(vec![], listener_peer_id, vec![PROTOCOL_NAME]).unwrap();
+    let inbound_session_id = loop {
+        if let (
+            Peer::Listener,
+            SwarmEvent::Behaviour(Event::External(ExternalEvent::NewInboundSession {
+                inbound_session_id,
+                ..
+            })),
+        ) = next_event(&mut dialer, &mut listener).await
+        {
+            break inbound_session_id;
+        }
+    };
+
+    // The session is open, so the connection outlives the idle timeout.
+    tokio::time::timeout(IDLE_CONNECTION_TIMEOUT * 3, async {
+        loop {
+            let (_, event) = next_event(&mut dialer, &mut listener).await;
+            assert_matches!(
+                event,
+                SwarmEvent::NewListenAddr { .. } | SwarmEvent::Behaviour(_),
+                "Unexpected event while the session is open."
+            );
+        }
+    })
+    .await
+    .unwrap_err();
+
+    // Once the session is closed, the connection becomes idle.
+    listener.behaviour_mut().close_inbound_session(inbound_session_id).unwrap();
+    tokio::time::timeout(
+        IDLE_CONNECTION_TIMEOUT * 10,
+        wait_for_dialer_connection_closed(&mut dialer, &mut listener),
+    )
+    .await
+    .expect("Connection wasn't closed after its session was closed.");
+}
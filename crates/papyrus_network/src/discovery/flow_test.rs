@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::iter;
 
 use futures::StreamExt;
@@ -7,9 +7,10 @@ use libp2p::identity::Keypair;
 use libp2p::kad::store::MemoryStore;
 use libp2p::swarm::behaviour::toggle::Toggle;
 use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
-use libp2p::{identify, kad, Multiaddr, Swarm};
+use libp2p::{identify, kad, Multiaddr, PeerId, Swarm};
 use libp2p_swarm_test::SwarmExt;
 
+use super::kad_impl::KadExternalEvent;
 use super::Behaviour;
 use crate::mixed_behaviour;
 use crate::mixed_behaviour::{BridgedBehaviour, MixedBehaviour};
@@ -34,10 +35,11 @@ impl DiscoveryMixedBehaviour {
     }
 }
 
-#[tokio::test]
-async fn all_nodes_have_same_bootstrap_peer() {
-    const NUM_NODES: usize = 2;
-
+// Creates a bootstrap swarm and the given number of swarms that have it as their bootstrap peer.
+// Returns the bootstrap peer id and a stream of the events of all the swarms, which are listening.
+async fn create_swarms_with_bootstrap_peer(
+    num_nodes: usize,
+) -> (PeerId, StreamHashMap<PeerId, Swarm<DiscoveryMixedBehaviour>>) {
     let mut bootstrap_swarm =
         Swarm::new_ephemeral(|keypair| DiscoveryMixedBehaviour::new(keypair, None));
     bootstrap_swarm.listen().with_memory_addr_external().await;
@@ -51,7 +53,7 @@ async fn all_nodes_have_same_bootstrap_peer() {
         .with_p2p(bootstrap_peer_id)
         .unwrap();
 
-    let swarms = (0..NUM_NODES).map(|_| {
+    let swarms = (0..num_nodes).map(|_| {
         Swarm::new_ephemeral(|keypair| {
             DiscoveryMixedBehaviour::new(keypair, Some(bootstrap_peer_multiaddr.clone()))
         })
@@ -66,36 +68,77 @@ async fn all_nodes_have_same_bootstrap_peer() {
         // Can't use libp2p's listen function since it assumes no other events are emitted.
         swarm.listen_on(Protocol::Memory(0).into()).unwrap();
     }
+    (bootstrap_peer_id, swarms_stream)
+}
+
+// Passes the event to the other behaviours of the swarm that emitted it, the same way the network
+// manager does. Returns the event if it's an external event.
+fn route_event(
+    swarms_stream: &mut StreamHashMap<PeerId, Swarm<DiscoveryMixedBehaviour>>,
+    peer_id: PeerId,
+    event: SwarmEvent<DiscoveryMixedBehaviourEvent>,
+) -> Option<mixed_behaviour::ExternalEvent> {
+    let mixed_event: mixed_behaviour::Event = match event {
+        SwarmEvent::Behaviour(DiscoveryMixedBehaviourEvent::Discovery(event)) => event.into(),
+        SwarmEvent::Behaviour(DiscoveryMixedBehaviourEvent::Kademlia(event)) => event.into(),
+        SwarmEvent::Behaviour(DiscoveryMixedBehaviourEvent::Identify(event)) => event.into(),
+        _ => return None,
+    };
+
+    let event = match mixed_event {
+        mixed_behaviour::Event::ExternalEvent(event) => return Some(event),
+        mixed_behaviour::Event::ToOtherBehaviourEvent(event) => event,
+    };
+    if let mixed_behaviour::ToOtherBehaviourEvent::NoOp = event {
+        return None;
+    };
+    let behaviour_ref = swarms_stream.get_mut(&peer_id).unwrap().behaviour_mut();
+    behaviour_ref.identify.on_other_behaviour_event(&event);
+    behaviour_ref.kademlia.on_other_behaviour_event(&event);
+    if let Some(discovery) = behaviour_ref.discovery.as_mut() {
+        discovery.on_other_behaviour_event(&event);
+    }
+    None
+}
+
+#[tokio::test]
+async fn all_nodes_have_same_bootstrap_peer() {
+    const NUM_NODES: usize = 2;
 
+    let (bootstrap_peer_id, mut swarms_stream) = create_swarms_with_bootstrap_peer(NUM_NODES).await;
     let mut connected_peers = HashSet::new();
 
     while connected_peers.len() < NUM_NODES * (NUM_NODES - 1) {
         let (peer_id, event) = swarms_stream.next().await.unwrap();
-
-        let mixed_event: mixed_behaviour::Event = match event {
-            SwarmEvent::Behaviour(DiscoveryMixedBehaviourEvent::Discovery(event)) => event.into(),
-            SwarmEvent::Behaviour(DiscoveryMixedBehaviourEvent::Kademlia(event)) => event.into(),
-            SwarmEvent::Behaviour(DiscoveryMixedBehaviourEvent::Identify(event)) => event.into(),
-            SwarmEvent::ConnectionEstablished { peer_id: other_peer_id, .. } => {
-                if peer_id != bootstrap_peer_id && bootstrap_peer_id != other_peer_id {
-                    connected_peers.insert((peer_id, other_peer_id));
-                }
-                continue;
+        if let SwarmEvent::ConnectionEstablished { peer_id: other_peer_id, .. } = event {
+            if peer_id != bootstrap_peer_id && bootstrap_peer_id != other_peer_id {
+                connected_peers.insert((peer_id, other_peer_id));
             }
-            _ => continue,
-        };
-
-        let mixed_behaviour::Event::ToOtherBehaviourEvent(event) = mixed_event else {
-            continue;
-        };
-        if let mixed_behaviour::ToOtherBehaviourEvent::NoOp = event {
             continue;
-        };
-        let behaviour_ref = swarms_stream.get_mut(&peer_id).unwrap().behaviour_mut();
-        behaviour_ref.identify.on_other_behaviour_event(&event);
-        behaviour_ref.kademlia.on_other_behaviour_event(&event);
-        if let Some(discovery) = behaviour_ref.discovery.as_mut() {
-            discovery.on_other_behaviour_event(&event);
+        }
+        route_event(&mut swarms_stream, peer_id, event);
+    }
+}
+
+#[tokio::test]
+async fn nodes_discover_the_other_peers_through_the_bootstrap_peer() {
+    const NUM_NODES: usize = 2;
+
+    let (bootstrap_peer_id, mut swarms_stream) = create_swarms_with_bootstrap_peer(NUM_NODES).await;
+    let all_peer_ids = swarms_stream.keys().copied().collect::<HashSet<_>>();
+    let mut discovered_peers = HashMap::<PeerId, HashSet<PeerId>>::new();
+
+    // Each node other than the bootstrap peer discovers all the other nodes.
+    while all_peer_ids.iter().filter(|peer_id| **peer_id != bootstrap_peer_id).any(|peer_id| {
+        discovered_peers.get(peer_id).map_or(0, HashSet::len) < all_peer_ids.len() - 1
+    }) {
+        let (peer_id, event) = swarms_stream.next().await.unwrap();
+        if let Some(mixed_behaviour::ExternalEvent::Kad(KadExternalEvent::PeerDiscovered(
+            discovered_peer_id,
+        ))) = route_event(&mut swarms_stream, peer_id, event)
+        {
+            assert_ne!(discovered_peer_id, peer_id);
+            discovered_peers.entry(peer_id).or_default().insert(discovered_peer_id);
         }
     }
 }
@@ -1,4 +1,4 @@
-use libp2p::kad;
+use libp2p::{kad, PeerId};
 use tracing::error;
 
 use super::identify_impl::IdentifyToOtherBehaviourEvent;
@@ -10,6 +10,12 @@ pub enum KadToOtherBehaviourEvent {
     KadQueryFinished,
 }
 
+#[derive(Debug)]
+pub enum KadExternalEvent {
+    /// A peer that wasn't known before was added to the routing table.
+    PeerDiscovered(PeerId),
+}
+
 impl From<kad::Event> for mixed_behaviour::Event {
     fn from(event: kad::Event) -> Self {
         match event {
@@ -27,6 +33,11 @@ impl From<kad::Event> for mixed_behaviour::Event {
                     ),
                 )
             }
+            kad::Event::RoutingUpdated { peer, is_new_peer: true, .. } => {
+                mixed_behaviour::Event::ExternalEvent(mixed_behaviour::ExternalEvent::Kad(
+                    KadExternalEvent::PeerDiscovered(peer),
+                ))
+            }
             _ => mixed_behaviour::Event::ToOtherBehaviourEvent(
                 mixed_behaviour::ToOtherBehaviourEvent::NoOp,
             ),
@@ -5,6 +5,7 @@ use libp2p::core::multiaddr::Protocol;
 use libp2p::swarm::SwarmEvent;
 use libp2p::{Multiaddr, Swarm};
 use libp2p_swarm_test::SwarmExt;
+use starknet_api::core::ChainId;
 
 use crate::gossipsub_impl::Topic;
 use crate::mixed_behaviour::MixedBehaviour;
@@ -38,7 +39,7 @@ async fn create_swarm(bootstrap_peer_multiaddr: Option<Multiaddr>) -> Swarm<Mixe
 fn create_network_manager(
     swarm: Swarm<MixedBehaviour>,
 ) -> GenericNetworkManager<Swarm<MixedBehaviour>> {
-    GenericNetworkManager::generic_new(swarm)
+    GenericNetworkManager::generic_new(swarm, ChainId::Mainnet)
 }
 
 const BUFFER_SIZE: usize = 100;
@@ -119,3 +120,56 @@ async fn broadcast_subscriber_end_to_end_test() {
         }
     }
 }
+
+#[tokio::test]
+async fn broadcast_reaches_all_subscribers_except_the_publisher() {
+    let topic = Topic::new("TOPIC");
+    let bootstrap_swarm = create_swarm(None).await;
+    let bootstrap_peer_multiaddr = bootstrap_swarm.external_addresses().next().unwrap().clone();
+    let bootstrap_peer_multiaddr =
+        bootstrap_peer_multiaddr.with_p2p(*bootstrap_swarm.local_peer_id()).unwrap();
+    let mut bootstrap_network_manager = create_network_manager(bootstrap_swarm);
+    let mut network_manager1 =
+        create_network_manager(create_swarm(Some(bootstrap_peer_multiaddr.clone())).await);
+    let mut network_manager2 =
+        create_network_manager(create_swarm(Some(bootstrap_peer_multiaddr)).await);
+
+    let bootstrap_subscriber_channels = bootstrap_network_manager
+        .register_broadcast_topic::<Number>(topic.clone(), BUFFER_SIZE)
+        .unwrap();
+    let mut subscriber_channels1 =
+        network_manager1.register_broadcast_topic::<Number>(topic.clone(), BUFFER_SIZE).unwrap();
+    let subscriber_channels2 =
+        network_manager2.register_broadcast_topic::<Number>(topic, BUFFER_SIZE).unwrap();
+
+    tokio::select! {
+        _ = network_manager1.run() => panic!("network manager ended"),
+        _ = network_manager2.run() => panic!("network manager ended"),
+        _ = bootstrap_network_manager.run() => panic!("network manager ended"),
+        result = tokio::time::timeout(
+            TIMEOUT, async move {
+                // TODO(shahak): Remove this sleep once we fix the bug of broadcasting while there
+                // are no peers.
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let number = Number(1);
+                let mut broadcasted_messages_receiver1 =
+                    subscriber_channels1.broadcasted_messages_receiver;
+                subscriber_channels1.messages_to_broadcast_sender.send(number).await.unwrap();
+                for mut broadcasted_messages_receiver in [
+                    bootstrap_subscriber_channels.broadcasted_messages_receiver,
+                    subscriber_channels2.broadcasted_messages_receiver,
+                ] {
+                    let (received_number, _report_callback) =
+                        broadcasted_messages_receiver.next().await.unwrap();
+                    assert_eq!(received_number.unwrap(), number);
+                    assert!(broadcasted_messages_receiver.next().now_or_never().is_none());
+                }
+                // The publisher doesn't receive its own message back from the other peers.
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                assert!(broadcasted_messages_receiver1.next().now_or_never().is_none());
+            }
+        ) => {
+            result.unwrap()
+        }
+    }
+}
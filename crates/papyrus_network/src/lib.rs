@@ -13,6 +13,7 @@ mod peer_manager;
 pub mod sqmr;
 #[cfg(test)]
 mod test_utils;
+pub mod upgrade_limit;
 mod utils;
 
 use std::collections::BTreeMap;
@@ -28,6 +29,7 @@ use papyrus_config::dumping::{ser_optional_param, ser_param, SerializeConfig};
 use papyrus_config::validators::validate_vec_u256;
 use papyrus_config::{ParamPath, ParamPrivacyInput, SerializedParam};
 use serde::{Deserialize, Serialize};
+use starknet_api::core::ChainId;
 use validator::Validate;
 
 // TODO: add peer manager config to the network config
@@ -43,6 +45,17 @@ pub struct NetworkConfig {
     #[validate(custom = "validate_vec_u256")]
     #[serde(deserialize_with = "deserialize_optional_vec_u8")]
     pub(crate) secret_key: Option<Vec<u8>>,
+    pub chain_id: ChainId,
+    #[validate(range(min = 1))]
+    pub max_concurrent_inbound_upgrades: usize,
+    #[validate(range(min = 1))]
+    pub max_concurrent_inbound_sessions_per_peer: usize,
+    #[validate(range(min = 1))]
+    pub inbound_sessions_per_second_per_peer: u32,
+    #[validate(range(min = 1))]
+    pub inbound_sessions_burst_per_peer: u32,
+    #[validate(range(min = 1))]
+    pub max_message_size: usize,
 }
 
 impl SerializeConfig for NetworkConfig {
@@ -73,6 +86,48 @@ impl SerializeConfig for NetworkConfig {
                  alive.",
                 ParamPrivacyInput::Public,
             ),
+            ser_param(
+                "chain_id",
+                &self.chain_id,
+                "The chain to follow. The names of the protocols are prefixed with it, so nodes \
+                 of different chains don't communicate with each other.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "max_concurrent_inbound_upgrades",
+                &self.max_concurrent_inbound_upgrades,
+                "Maximal number of inbound connections that are secured and multiplexed at the \
+                 same time. Excess connections wait until an upgrade finishes.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "max_concurrent_inbound_sessions_per_peer",
+                &self.max_concurrent_inbound_sessions_per_peer,
+                "Maximal number of inbound sessions a single peer can have open at the same time. \
+                 Excess sessions are rejected without closing the connection.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "inbound_sessions_per_second_per_peer",
+                &self.inbound_sessions_per_second_per_peer,
+                "Number of inbound sessions per second a single peer can open in the long run. \
+                 Excess sessions are rejected without closing the connection.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "inbound_sessions_burst_per_peer",
+                &self.inbound_sessions_burst_per_peer,
+                "Number of inbound sessions a single peer can open at once before being limited \
+                 to inbound_sessions_per_second_per_peer.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "max_message_size",
+                &self.max_message_size,
+                "Maximal size in bytes of a message a peer can send in a session. A session in \
+                 which the peer sends a longer message fails.",
+                ParamPrivacyInput::Public,
+            ),
         ]);
         config.extend(ser_optional_param(
             &self.bootstrap_peer_multiaddr,
@@ -101,6 +156,12 @@ impl Default for NetworkConfig {
             idle_connection_timeout: Duration::from_secs(120),
             bootstrap_peer_multiaddr: None,
             secret_key: None,
+            chain_id: ChainId::Mainnet,
+            max_concurrent_inbound_upgrades: 32,
+            max_concurrent_inbound_sessions_per_peer: 100,
+            inbound_sessions_per_second_per_peer: 50,
+            inbound_sessions_burst_per_peer: 100,
+            max_message_size: sqmr::DEFAULT_MAX_MESSAGE_SIZE,
         }
     }
 }
@@ -8,7 +8,7 @@ use libp2p::swarm::NetworkBehaviour;
 use libp2p::{gossipsub, identify, kad, Multiaddr, PeerId};
 
 use crate::discovery::identify_impl::{IdentifyToOtherBehaviourEvent, IDENTIFY_PROTOCOL_VERSION};
-use crate::discovery::kad_impl::KadToOtherBehaviourEvent;
+use crate::discovery::kad_impl::{KadExternalEvent, KadToOtherBehaviourEvent};
 use crate::peer_manager::PeerManagerConfig;
 use crate::{discovery, gossipsub_impl, peer_manager, sqmr};
 
@@ -37,6 +37,7 @@ pub enum Event {
 pub enum ExternalEvent {
     Sqmr(sqmr::behaviour::ExternalEvent),
     GossipSub(gossipsub_impl::ExternalEvent),
+    Kad(KadExternalEvent),
 }
 
 #[derive(Debug)]
@@ -4,8 +4,17 @@ mod swarm_trait;
 mod test;
 
 use std::collections::HashMap;
-
-use futures::channel::mpsc::{Receiver, SendError, Sender};
+use std::io;
+use std::time::Duration;
+
+use futures::channel::mpsc::{
+    unbounded,
+    Receiver,
+    SendError,
+    Sender,
+    UnboundedReceiver,
+    UnboundedSender,
+};
 use futures::channel::oneshot;
 use futures::future::{ready, BoxFuture, Ready};
 use futures::sink::With;
@@ -13,17 +22,21 @@ use futures::stream::{self, BoxStream, FuturesUnordered, Map, Stream};
 use futures::{pin_mut, FutureExt, Sink, SinkExt, StreamExt};
 use libp2p::gossipsub::{SubscriptionError, TopicHash};
 use libp2p::swarm::SwarmEvent;
-use libp2p::{PeerId, StreamProtocol, Swarm};
+use libp2p::{Multiaddr, PeerId, StreamProtocol, Swarm};
 use metrics::gauge;
 use papyrus_common::metrics as papyrus_metrics;
 use sqmr::Bytes;
+use starknet_api::core::ChainId;
+use tokio::sync::watch;
 use tracing::{debug, error, info, trace};
 
 use self::swarm_trait::SwarmTrait;
 use crate::bin_utils::build_swarm;
+use crate::discovery::kad_impl::KadExternalEvent;
 use crate::gossipsub_impl::Topic;
 use crate::mixed_behaviour::{self, BridgedBehaviour};
 use crate::sqmr::{self, InboundSessionId, OutboundSessionId, SessionId};
+use crate::upgrade_limit::InboundUpgradeLimit;
 use crate::utils::StreamHashMap;
 use crate::{gossipsub_impl, NetworkConfig};
 
@@ -31,10 +44,60 @@ use crate::{gossipsub_impl, NetworkConfig};
 pub enum NetworkError {
     #[error(transparent)]
     DialError(#[from] libp2p::swarm::DialError),
+    #[error("Failed to decode a response received from the network: {0}")]
+    DecodeFailure(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Session timed out after {} seconds.", session_timeout.as_secs())]
+    Timeout { session_timeout: Duration },
+    #[error("The remote peer reset the session.")]
+    PeerReset,
+    #[error("Remote peer doesn't support the given protocol.")]
+    RemoteDoesntSupportProtocol,
+    #[error("Remote peer sent a malformed message: {0}")]
+    MalformedMessage(io::Error),
+    #[error("Remote peer has too many concurrent inbound sessions.")]
+    TooManySessions,
+    #[error("Remote peer opens inbound sessions too fast.")]
+    RateLimited,
+    #[error(transparent)]
+    IOError(io::Error),
+}
+
+/// An event about the network that is reported to the subscribers of
+/// [`GenericNetworkManager::subscribe_to_network_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// A new peer was found and added to the routing table.
+    PeerDiscovered(PeerId),
+}
+
+impl From<sqmr::behaviour::SessionError> for NetworkError {
+    fn from(error: sqmr::behaviour::SessionError) -> Self {
+        match error {
+            sqmr::behaviour::SessionError::Timeout { session_timeout } => {
+                Self::Timeout { session_timeout }
+            }
+            sqmr::behaviour::SessionError::IOError(error) => match error.kind() {
+                io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof => Self::PeerReset,
+                _ => Self::IOError(error),
+            },
+            sqmr::behaviour::SessionError::RemoteDoesntSupportProtocol => {
+                Self::RemoteDoesntSupportProtocol
+            }
+            sqmr::behaviour::SessionError::MalformedMessage(error) => Self::MalformedMessage(error),
+            sqmr::behaviour::SessionError::RemoteClosed
+            | sqmr::behaviour::SessionError::ConnectionClosed => Self::PeerReset,
+            sqmr::behaviour::SessionError::TooManySessions => Self::TooManySessions,
+            sqmr::behaviour::SessionError::RateLimited => Self::RateLimited,
+        }
+    }
 }
 
 pub struct GenericNetworkManager<SwarmT: SwarmTrait> {
     swarm: SwarmT,
+    chain_id: ChainId,
     inbound_protocol_to_buffer_size: HashMap<StreamProtocol, usize>,
     sqmr_inbound_response_receivers:
         StreamHashMap<InboundSessionId, BoxStream<'static, Option<Bytes>>>,
@@ -49,6 +112,9 @@ pub struct GenericNetworkManager<SwarmT: SwarmTrait> {
     messages_to_broadcast_receivers: StreamHashMap<TopicHash, Receiver<Bytes>>,
     broadcasted_messages_senders: HashMap<TopicHash, Sender<(Bytes, ReportSender)>>,
     reported_peer_receivers: FuturesUnordered<BoxFuture<'static, Option<PeerId>>>,
+    // The addresses the swarm is listening on, as they were bound by the OS.
+    listen_addresses_sender: watch::Sender<Vec<Multiaddr>>,
+    network_event_senders: Vec<UnboundedSender<NetworkEvent>>,
     // Fields for metrics
     num_active_inbound_sessions: usize,
     num_active_outbound_sessions: usize,
@@ -71,12 +137,13 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
         }
     }
 
-    pub(crate) fn generic_new(swarm: SwarmT) -> Self {
+    pub(crate) fn generic_new(swarm: SwarmT, chain_id: ChainId) -> Self {
         gauge!(papyrus_metrics::PAPYRUS_NUM_CONNECTED_PEERS, 0f64);
         let reported_peer_receivers = FuturesUnordered::new();
         reported_peer_receivers.push(futures::future::pending().boxed());
         Self {
             swarm,
+            chain_id,
             inbound_protocol_to_buffer_size: HashMap::new(),
             sqmr_inbound_response_receivers: StreamHashMap::new(HashMap::new()),
             sqmr_inbound_query_senders: HashMap::new(),
@@ -86,11 +153,26 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
             messages_to_broadcast_receivers: StreamHashMap::new(HashMap::new()),
             broadcasted_messages_senders: HashMap::new(),
             reported_peer_receivers,
+            listen_addresses_sender: watch::channel(Vec::new()).0,
+            network_event_senders: Vec::new(),
             num_active_inbound_sessions: 0,
             num_active_outbound_sessions: 0,
         }
     }
 
+    /// Returns a receiver of the addresses the node is listening on. The addresses are reported
+    /// after they were bound, so listening on port 0 is reported with the port the OS assigned.
+    pub fn get_listen_addresses_receiver(&self) -> watch::Receiver<Vec<Multiaddr>> {
+        self.listen_addresses_sender.subscribe()
+    }
+
+    /// Returns a receiver of the network events that happen from now on.
+    pub fn subscribe_to_network_events(&mut self) -> UnboundedReceiver<NetworkEvent> {
+        let (sender, receiver) = unbounded();
+        self.network_event_senders.push(sender);
+        receiver
+    }
+
     /// TODO: Support multiple protocols where they're all different versions of the same protocol
     pub fn register_sqmr_protocol_server<Query, Response>(
         &mut self,
@@ -101,8 +183,7 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
         Bytes: From<Response>,
         Query: TryFrom<Bytes>,
     {
-        let protocol = StreamProtocol::try_from_owned(protocol)
-            .expect("Could not parse protocol into StreamProtocol.");
+        let protocol = protocol_name_for_chain(&self.chain_id, &protocol);
         self.swarm.add_new_supported_inbound_protocol(protocol.clone());
         if let Some(_old_buffer_size) =
             self.inbound_protocol_to_buffer_size.insert(protocol.clone(), buffer_size)
@@ -135,11 +216,10 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
     where
         Bytes: From<Query>,
         Response: TryFrom<Bytes> + 'static + Send,
-        <Response as TryFrom<Bytes>>::Error: 'static + Send,
+        <Response as TryFrom<Bytes>>::Error: std::error::Error + 'static + Send + Sync,
         Query: 'static,
     {
-        let protocol = StreamProtocol::try_from_owned(protocol)
-            .expect("Could not parse protocol into StreamProtocol.");
+        let protocol = protocol_name_for_chain(&self.chain_id, &protocol);
         self.swarm.add_new_supported_inbound_protocol(protocol.clone());
         let (payload_sender, payload_receiver) = futures::channel::mpsc::channel(buffer_size);
 
@@ -254,10 +334,18 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
                 );
             }
             SwarmEvent::NewListenAddr { address, .. } => {
+                info!("Listening on {address}.");
+                self.listen_addresses_sender
+                    .send_modify(|addresses| addresses.push(address.clone()));
                 // TODO(shahak): Once we support nodes behind a NAT, fix this to only add external
                 // addresses.
                 self.swarm.add_external_address(address);
             }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                info!("Stopped listening on {address}.");
+                self.listen_addresses_sender
+                    .send_modify(|addresses| addresses.retain(|other| *other != address));
+            }
             SwarmEvent::IncomingConnection { .. }
             | SwarmEvent::Dialing { .. }
             | SwarmEvent::NewExternalAddrCandidate { .. } => {}
@@ -286,6 +374,9 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
             mixed_behaviour::ExternalEvent::GossipSub(event) => {
                 self.handle_gossipsub_behaviour_event(event);
             }
+            mixed_behaviour::ExternalEvent::Kad(event) => {
+                self.handle_kad_behaviour_event(event);
+            }
         }
     }
 
@@ -350,6 +441,7 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
                 outbound_session_id,
                 response,
                 peer_id: _peer_id,
+                protocol_name: _protocol_name,
             } => {
                 trace!(
                     "Received response from peer for session id: {outbound_session_id:?}. sending \
@@ -362,7 +454,7 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
                     // TODO(shahak): Close the channel if the buffer is full.
                     network_send_now(
                         response_sender,
-                        response,
+                        Ok(response),
                         format!(
                             "Received response for an outbound query while the buffer is full. \
                              Dropping it. Session: {outbound_session_id:?}"
@@ -372,10 +464,28 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
             }
             sqmr::behaviour::ExternalEvent::SessionFailed { session_id, error } => {
                 error!("Session {session_id:?} failed on {error:?}");
-                self.report_session_removed_to_metrics(session_id);
+                // Sessions rejected for exceeding the limits were never reported as new sessions.
+                if !matches!(
+                    error,
+                    sqmr::behaviour::SessionError::TooManySessions
+                        | sqmr::behaviour::SessionError::RateLimited
+                ) {
+                    self.report_session_removed_to_metrics(session_id);
+                }
                 // TODO: Handle reputation and retry.
                 if let SessionId::OutboundSessionId(outbound_session_id) = session_id {
-                    self.sqmr_outbound_response_senders.remove(&outbound_session_id);
+                    if let Some(mut response_sender) =
+                        self.sqmr_outbound_response_senders.remove(&outbound_session_id)
+                    {
+                        network_send_now(
+                            &mut response_sender,
+                            Err(error.into()),
+                            format!(
+                                "Outbound session failed while the responses buffer is full. \
+                                 Dropping the error. Session: {outbound_session_id:?}"
+                            ),
+                        );
+                    }
                     // TODO: check if the report receiver was already removed when session was
                     // assigned
                     self.sqmr_outbound_report_receivers.remove(&outbound_session_id);
@@ -421,6 +531,20 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
         }
     }
 
+    fn handle_kad_behaviour_event(&mut self, event: KadExternalEvent) {
+        match event {
+            KadExternalEvent::PeerDiscovered(peer_id) => {
+                debug!("Discovered peer {peer_id:?}.");
+                self.report_network_event(NetworkEvent::PeerDiscovered(peer_id));
+            }
+        }
+    }
+
+    fn report_network_event(&mut self, event: NetworkEvent) {
+        // Subscribers that dropped their receiver are removed.
+        self.network_event_senders.retain(|sender| sender.unbounded_send(event.clone()).is_ok());
+    }
+
     fn handle_response_for_inbound_query(&mut self, res: (InboundSessionId, Option<Bytes>)) {
         let (inbound_session_id, maybe_response) = res;
         match maybe_response {
@@ -506,6 +630,13 @@ impl<SwarmT: SwarmTrait> GenericNetworkManager<SwarmT> {
     }
 }
 
+/// Returns the name under which the given protocol is negotiated on the given chain. The chain id
+/// is added as a prefix so that nodes of different chains fail to negotiate the protocol.
+pub(crate) fn protocol_name_for_chain(chain_id: &ChainId, protocol: &str) -> StreamProtocol {
+    StreamProtocol::try_from_owned(format!("/{chain_id}{protocol}"))
+        .expect("Could not parse protocol into StreamProtocol.")
+}
+
 fn network_send_now<Item>(
     sender: &mut GenericSender<Item>,
     item: Item,
@@ -545,6 +676,12 @@ impl NetworkManager {
             idle_connection_timeout,
             bootstrap_peer_multiaddr,
             secret_key,
+            chain_id,
+            max_concurrent_inbound_upgrades,
+            max_concurrent_inbound_sessions_per_peer,
+            inbound_sessions_per_second_per_peer,
+            inbound_sessions_burst_per_peer,
+            max_message_size,
         } = config;
 
         let listen_addresses = vec![
@@ -552,14 +689,31 @@ impl NetworkManager {
             // format!("/ip4/0.0.0.0/udp/{quic_port}/quic-v1"),
             format!("/ip4/0.0.0.0/tcp/{tcp_port}"),
         ];
-        let swarm = build_swarm(listen_addresses, idle_connection_timeout, secret_key, |key| {
-            mixed_behaviour::MixedBehaviour::new(
-                key,
-                bootstrap_peer_multiaddr.clone(),
-                sqmr::Config { session_timeout },
-            )
-        });
-        Self::generic_new(swarm)
+        let swarm = build_swarm(
+            listen_addresses,
+            idle_connection_timeout,
+            secret_key,
+            InboundUpgradeLimit::new(max_concurrent_inbound_upgrades),
+            |key| {
+                mixed_behaviour::MixedBehaviour::new(
+                    key,
+                    bootstrap_peer_multiaddr.clone(),
+                    sqmr::Config {
+                        session_timeout,
+                        deterministic_event_order: false,
+                        max_concurrent_inbound_sessions_per_peer: Some(
+                            max_concurrent_inbound_sessions_per_peer,
+                        ),
+                        inbound_session_rate_limit_per_peer: Some(sqmr::InboundSessionRateLimit {
+                            sessions_per_second: inbound_sessions_per_second_per_peer,
+                            burst: inbound_sessions_burst_per_peer,
+                        }),
+                        max_message_size,
+                    },
+                )
+            },
+        );
+        Self::generic_new(swarm, chain_id)
     }
 
     pub fn get_local_peer_id(&self) -> String {
@@ -624,9 +778,8 @@ type GenericSender<T> = Box<dyn Sink<T, Error = SendError> + Unpin + Send>;
 // Box<S> implements Stream only if S: Stream + Unpin
 type GenericReceiver<T> = Box<dyn Stream<Item = T> + Unpin + Send>;
 
-type ResponsesSenderForNetwork = GenericSender<Bytes>;
-type ResponsesSender<Response> =
-    GenericSender<Result<Response, <Response as TryFrom<Bytes>>::Error>>;
+type ResponsesSenderForNetwork = GenericSender<Result<Bytes, NetworkError>>;
+type ResponsesSender<Response> = GenericSender<Result<Response, NetworkError>>;
 
 type ReportSender = oneshot::Sender<()>;
 type ReportReceiver = oneshot::Receiver<()>;
@@ -660,7 +813,7 @@ type SqmrClientReceiver = GenericReceiver<SqmrClientPayloadForNetwork>;
 struct SqmrServerPayloadForNetwork {
     query: Bytes,
     report_sender: ReportSender,
-    responses_sender: ResponsesSenderForNetwork,
+    responses_sender: GenericSender<Bytes>,
 }
 
 #[allow(dead_code)]
@@ -670,13 +823,18 @@ impl<Query, Response> From<SqmrClientPayload<Query, Response>> for SqmrClientPay
 where
     Bytes: From<Query>,
     Response: TryFrom<Bytes> + 'static + Send,
-    <Response as TryFrom<Bytes>>::Error: 'static + Send,
+    <Response as TryFrom<Bytes>>::Error: std::error::Error + 'static + Send + Sync,
 {
     fn from(payload: SqmrClientPayload<Query, Response>) -> Self {
         let SqmrClientPayload { query, report_receiver, responses_sender } = payload;
         let query = Bytes::from(query);
         let responses_sender =
-            Box::new(responses_sender.with(|response| ready(Ok(Response::try_from(response)))));
+            Box::new(responses_sender.with(|response: Result<Bytes, NetworkError>| {
+                ready(Ok(response.and_then(|response| {
+                    Response::try_from(response)
+                        .map_err(|error| NetworkError::DecodeFailure(Box::new(error)))
+                })))
+            }));
         Self { query, report_receiver, responses_sender }
     }
 }
@@ -70,7 +70,7 @@ impl SwarmTrait for Swarm<mixed_behaviour::MixedBehaviour> {
         _peer_id: PeerId,
         protocol: StreamProtocol,
     ) -> Result<OutboundSessionId, PeerNotConnected> {
-        Ok(self.behaviour_mut().sqmr.start_query(query, protocol))
+        Ok(self.behaviour_mut().sqmr.start_query(query, vec![protocol]))
     }
 
     fn dial(&mut self, peer_multiaddr: Multiaddr) -> Result<(), DialError> {
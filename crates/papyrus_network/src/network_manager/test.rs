@@ -1,11 +1,11 @@
 use std::collections::{HashMap, HashSet};
-use std::convert::Infallible;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
-use std::vec;
+use std::{io, vec};
 
+use assert_matches::assert_matches;
 use deadqueue::unlimited::Queue;
 use futures::channel::mpsc::{unbounded, UnboundedSender};
 use futures::channel::oneshot;
@@ -13,21 +13,27 @@ use futures::future::{poll_fn, FutureExt};
 use futures::stream::Stream;
 use futures::{pin_mut, Future, SinkExt, StreamExt};
 use lazy_static::lazy_static;
+use libp2p::core::multiaddr::Protocol;
 use libp2p::core::ConnectedPoint;
 use libp2p::gossipsub::{SubscriptionError, TopicHash};
-use libp2p::swarm::ConnectionId;
+use libp2p::swarm::{ConnectionId, SwarmEvent};
 use libp2p::{Multiaddr, PeerId, StreamProtocol};
+use starknet_api::core::ChainId;
 use tokio::select;
 use tokio::sync::Mutex;
 use tokio::time::sleep;
 
 use super::swarm_trait::{Event, SwarmTrait};
-use super::GenericNetworkManager;
+use super::{protocol_name_for_chain, GenericNetworkManager, NetworkError, NetworkEvent};
+use crate::bin_utils::build_swarm;
+use crate::discovery::kad_impl::KadExternalEvent;
 use crate::gossipsub_impl::{self, Topic};
 use crate::mixed_behaviour;
 use crate::network_manager::SqmrClientPayload;
-use crate::sqmr::behaviour::{PeerNotConnected, SessionIdNotFoundError};
-use crate::sqmr::{Bytes, GenericEvent, InboundSessionId, OutboundSessionId};
+use crate::sqmr::behaviour::{PeerNotConnected, SessionError, SessionIdNotFoundError};
+use crate::sqmr::{self, Bytes, GenericEvent, InboundSessionId, OutboundSessionId, SessionId};
+use crate::test_utils::create_fully_connected_swarms_stream;
+use crate::upgrade_limit::InboundUpgradeLimit;
 
 const TIMEOUT: Duration = Duration::from_secs(1);
 
@@ -47,6 +53,8 @@ struct MockSwarm {
     inbound_session_id_to_response_sender: HashMap<InboundSessionId, UnboundedSender<Bytes>>,
     next_outbound_session_id: usize,
     first_polled_event_notifier: Option<oneshot::Sender<()>>,
+    // If set, every outbound session fails with this error after sending its responses.
+    outbound_session_error_fn: Option<fn() -> SessionError>,
 }
 
 impl Stream for MockSwarm {
@@ -107,6 +115,7 @@ impl MockSwarm {
         query: Vec<u8>,
         outbound_session_id: OutboundSessionId,
         peer_id: PeerId,
+        protocol_name: StreamProtocol,
     ) {
         for response in query {
             self.pending_events.push(Event::Behaviour(mixed_behaviour::Event::ExternalEvent(
@@ -114,6 +123,7 @@ impl MockSwarm {
                     response: vec![response],
                     outbound_session_id,
                     peer_id,
+                    protocol_name: protocol_name.clone(),
                 }),
             )));
         }
@@ -138,14 +148,23 @@ impl SwarmTrait for MockSwarm {
         &mut self,
         query: Vec<u8>,
         peer_id: PeerId,
-        _protocol: StreamProtocol,
+        protocol: StreamProtocol,
     ) -> Result<OutboundSessionId, PeerNotConnected> {
         let outbound_session_id = OutboundSessionId { value: self.next_outbound_session_id };
         self.create_response_events_for_query_each_num_becomes_response(
             query,
             outbound_session_id,
             peer_id,
+            protocol,
         );
+        if let Some(outbound_session_error_fn) = self.outbound_session_error_fn {
+            self.pending_events.push(Event::Behaviour(mixed_behaviour::Event::ExternalEvent(
+                mixed_behaviour::ExternalEvent::Sqmr(GenericEvent::SessionFailed {
+                    session_id: SessionId::OutboundSessionId(outbound_session_id),
+                    error: outbound_session_error_fn(),
+                }),
+            )));
+        }
         self.next_outbound_session_id += 1;
         Ok(outbound_session_id)
     }
@@ -218,7 +237,7 @@ async fn register_sqmr_protocol_client_and_use_channels() {
     mock_swarm.first_polled_event_notifier = Some(event_notifier);
 
     // network manager to register subscriber
-    let mut network_manager = GenericNetworkManager::generic_new(mock_swarm);
+    let mut network_manager = GenericNetworkManager::generic_new(mock_swarm, ChainId::Mainnet);
 
     // register subscriber and send payload
     let mut payload_sender = network_manager.register_sqmr_protocol_client::<Vec<u8>, Vec<u8>>(
@@ -229,7 +248,7 @@ async fn register_sqmr_protocol_client_and_use_channels() {
     let response_receiver_length = Arc::new(Mutex::new(0));
     let cloned_response_receiver_length = Arc::clone(&response_receiver_length);
     let (responses_sender, response_receiver) =
-        futures::channel::mpsc::channel::<Result<Vec<u8>, Infallible>>(BUFFER_SIZE);
+        futures::channel::mpsc::channel::<Result<Vec<u8>, NetworkError>>(BUFFER_SIZE);
     let responses_sender = Box::new(responses_sender);
     let response_receiver_collector = response_receiver
         .enumerate()
@@ -256,6 +275,114 @@ async fn register_sqmr_protocol_client_and_use_channels() {
     assert_eq!(*response_receiver_length.lock().await, VEC1.len());
 }
 
+#[derive(Debug)]
+struct NonZeroByte(u8);
+
+#[derive(thiserror::Error, Debug)]
+#[error("Received a zero byte.")]
+struct ZeroByteError;
+
+impl TryFrom<Bytes> for NonZeroByte {
+    type Error = ZeroByteError;
+
+    fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
+        match bytes[..] {
+            [0] => Err(ZeroByteError),
+            [byte] => Ok(Self(byte)),
+            _ => panic!("Expected a single byte, got {bytes:?}"),
+        }
+    }
+}
+
+async fn get_sqmr_client_responses(
+    mock_swarm: MockSwarm,
+    query: Vec<u8>,
+    num_responses: usize,
+) -> Vec<Result<NonZeroByte, NetworkError>> {
+    let mut network_manager = GenericNetworkManager::generic_new(mock_swarm, ChainId::Mainnet);
+    let mut payload_sender = network_manager.register_sqmr_protocol_client::<Vec<u8>, NonZeroByte>(
+        SIGNED_BLOCK_HEADER_PROTOCOL.to_string(),
+        BUFFER_SIZE,
+    );
+    let (responses_sender, responses_receiver) = futures::channel::mpsc::channel(BUFFER_SIZE);
+    let (_report_sender, report_receiver) = oneshot::channel::<()>();
+    payload_sender
+        .send(SqmrClientPayload {
+            query,
+            report_receiver,
+            responses_sender: Box::new(responses_sender),
+        })
+        .await
+        .unwrap();
+
+    select! {
+        _ = network_manager.run() => panic!("network manager ended"),
+        responses = responses_receiver.take(num_responses).collect::<Vec<_>>() => responses,
+        _ = sleep(Duration::from_secs(5)) => panic!("Test timed out"),
+    }
+}
+
+#[tokio::test]
+async fn sqmr_client_receives_decode_failure() {
+    let responses = get_sqmr_client_responses(MockSwarm::default(), vec![1, 0, 2], 3).await;
+
+    assert_matches!(responses[0], Ok(NonZeroByte(1)));
+    assert_matches!(&responses[1], Err(NetworkError::DecodeFailure(_)));
+    assert_matches!(responses[2], Ok(NonZeroByte(2)));
+}
+
+#[tokio::test]
+async fn sqmr_client_receives_session_failure() {
+    let mock_swarm = MockSwarm {
+        outbound_session_error_fn: Some(|| SessionError::Timeout { session_timeout: TIMEOUT }),
+        ..Default::default()
+    };
+    // The response channel is closed after the error, so we ask for more responses than we expect
+    // to get.
+    let responses = get_sqmr_client_responses(mock_swarm, vec![1], 3).await;
+
+    assert_eq!(responses.len(), 2);
+    assert_matches!(responses[0], Ok(NonZeroByte(1)));
+    assert_matches!(
+        responses[1],
+        Err(NetworkError::Timeout { session_timeout }) if session_timeout == TIMEOUT
+    );
+}
+
+#[test]
+fn session_errors_map_to_network_errors() {
+    assert_matches!(
+        NetworkError::from(SessionError::Timeout { session_timeout: TIMEOUT }),
+        NetworkError::Timeout { session_timeout } if session_timeout == TIMEOUT
+    );
+    assert_matches!(NetworkError::from(SessionError::ConnectionClosed), NetworkError::PeerReset);
+    assert_matches!(
+        NetworkError::from(SessionError::TooManySessions),
+        NetworkError::TooManySessions
+    );
+    assert_matches!(
+        NetworkError::from(SessionError::IOError(io::ErrorKind::ConnectionReset.into())),
+        NetworkError::PeerReset
+    );
+    assert_matches!(
+        NetworkError::from(SessionError::IOError(io::ErrorKind::UnexpectedEof.into())),
+        NetworkError::PeerReset
+    );
+    assert_matches!(
+        NetworkError::from(SessionError::IOError(io::ErrorKind::InvalidData.into())),
+        NetworkError::IOError(error) if error.kind() == io::ErrorKind::InvalidData
+    );
+    assert_matches!(
+        NetworkError::from(SessionError::RemoteDoesntSupportProtocol),
+        NetworkError::RemoteDoesntSupportProtocol
+    );
+    assert_matches!(
+        NetworkError::from(SessionError::MalformedMessage(io::ErrorKind::InvalidData.into())),
+        NetworkError::MalformedMessage(error) if error.kind() == io::ErrorKind::InvalidData
+    );
+    assert_matches!(NetworkError::from(SessionError::RemoteClosed), NetworkError::PeerReset);
+}
+
 // TODO(shahak): Add multiple protocols and multiple queries in the test.
 #[tokio::test]
 async fn process_incoming_query() {
@@ -263,6 +390,7 @@ async fn process_incoming_query() {
     let query = VEC1.clone();
     let responses = vec![VEC1.clone(), VEC2.clone(), VEC3.clone()];
     let protocol: StreamProtocol = SIGNED_BLOCK_HEADER_PROTOCOL;
+    let protocol_name = protocol_name_for_chain(&ChainId::Mainnet, protocol.as_ref());
 
     // Setup mock swarm and tell it to return an event of new inbound query.
     let mut mock_swarm = MockSwarm::default();
@@ -272,7 +400,7 @@ async fn process_incoming_query() {
             query: query.clone(),
             inbound_session_id,
             peer_id: PeerId::random(),
-            protocol_name: protocol.clone(),
+            protocol_name: protocol_name.clone(),
         }),
     )));
 
@@ -281,13 +409,13 @@ async fn process_incoming_query() {
     let get_responses_fut = mock_swarm.get_responses_sent_to_inbound_session(inbound_session_id);
     let mut get_supported_inbound_protocol_fut = mock_swarm.get_supported_inbound_protocol();
 
-    let mut network_manager = GenericNetworkManager::generic_new(mock_swarm);
+    let mut network_manager = GenericNetworkManager::generic_new(mock_swarm, ChainId::Mainnet);
 
     let mut inbound_query_receiver = network_manager
         .register_sqmr_protocol_server::<Vec<u8>, Vec<u8>>(protocol.to_string(), BUFFER_SIZE);
 
     let actual_protocol = get_supported_inbound_protocol_fut.next().await.unwrap();
-    assert_eq!(protocol, actual_protocol);
+    assert_eq!(protocol_name, actual_protocol);
 
     let responses_clone = responses.clone();
     select! {
@@ -317,7 +445,7 @@ async fn broadcast_message() {
     let mut mock_swarm = MockSwarm::default();
     let mut messages_we_broadcasted_stream = mock_swarm.stream_messages_we_broadcasted();
 
-    let mut network_manager = GenericNetworkManager::generic_new(mock_swarm);
+    let mut network_manager = GenericNetworkManager::generic_new(mock_swarm, ChainId::Mainnet);
 
     let mut messages_to_broadcast_sender = network_manager
         .register_broadcast_topic(topic.clone(), BUFFER_SIZE)
@@ -337,6 +465,25 @@ async fn broadcast_message() {
     }
 }
 
+#[tokio::test]
+async fn discovered_peers_are_reported() {
+    let peer_id = PeerId::random();
+    let mock_swarm = MockSwarm::default();
+    mock_swarm.pending_events.push(Event::Behaviour(mixed_behaviour::Event::ExternalEvent(
+        mixed_behaviour::ExternalEvent::Kad(KadExternalEvent::PeerDiscovered(peer_id)),
+    )));
+
+    let mut network_manager = GenericNetworkManager::generic_new(mock_swarm, ChainId::Mainnet);
+    let mut network_events_receiver = network_manager.subscribe_to_network_events();
+
+    tokio::select! {
+        _ = network_manager.run() => panic!("network manager ended"),
+        result = tokio::time::timeout(TIMEOUT, network_events_receiver.next()) => {
+            assert_eq!(result.unwrap().unwrap(), NetworkEvent::PeerDiscovered(peer_id));
+        }
+    }
+}
+
 #[tokio::test]
 async fn receive_broadcasted_message_and_report_it() {
     let topic = Topic::new("TOPIC");
@@ -353,7 +500,7 @@ async fn receive_broadcasted_message_and_report_it() {
     )));
     let mut reported_peer_receiver = mock_swarm.get_reported_peers_stream();
 
-    let mut network_manager = GenericNetworkManager::generic_new(mock_swarm);
+    let mut network_manager = GenericNetworkManager::generic_new(mock_swarm, ChainId::Mainnet);
 
     let mut broadcasted_messages_receiver = network_manager
         .register_broadcast_topic::<Bytes>(topic.clone(), BUFFER_SIZE)
@@ -389,3 +536,100 @@ fn get_test_connection_established_event(mock_peer_id: PeerId) -> Event {
         established_in: Duration::from_secs(0),
     }
 }
+
+#[tokio::test]
+async fn nodes_of_different_chains_fail_to_negotiate_protocol() {
+    const PROTOCOL: &str = "/starknet/headers/1";
+    let mut swarms_stream = create_fully_connected_swarms_stream(2, || {
+        sqmr::Behaviour::new(sqmr::Config {
+            session_timeout: Duration::from_secs(10),
+            deterministic_event_order: true,
+            max_concurrent_inbound_sessions_per_peer: None,
+            inbound_session_rate_limit_per_peer: None,
+            max_message_size: sqmr::DEFAULT_MAX_MESSAGE_SIZE,
+        })
+    })
+    .await;
+    let peer_ids = swarms_stream.keys().cloned().collect::<Vec<_>>();
+    let (outbound_peer_id, inbound_peer_id) = (peer_ids[0], peer_ids[1]);
+
+    swarms_stream
+        .get_mut(&inbound_peer_id)
+        .unwrap()
+        .behaviour_mut()
+        .add_new_supported_inbound_protocol(protocol_name_for_chain(&ChainId::Mainnet, PROTOCOL));
+    let outbound_session_id = swarms_stream
+        .get_mut(&outbound_peer_id)
+        .unwrap()
+        .behaviour_mut()
+        .send_query(
+            VEC1.clone(),
+            inbound_peer_id,
+            vec![protocol_name_for_chain(&ChainId::Sepolia, PROTOCOL)],
+        )
+        .unwrap();
+
+    loop {
+        let (peer_id, event) = swarms_stream.next().await.unwrap();
+        let SwarmEvent::Behaviour(sqmr::behaviour::Event::External(event)) = event else {
+            continue;
+        };
+        assert_eq!(peer_id, outbound_peer_id, "Inbound peer got unexpected event {event:?}");
+        assert_matches!(
+            event,
+            GenericEvent::SessionFailed {
+                session_id: SessionId::OutboundSessionId(session_id),
+                error: SessionError::RemoteDoesntSupportProtocol,
+            } if session_id == outbound_session_id
+        );
+        break;
+    }
+}
+
+#[tokio::test]
+async fn bound_listen_addresses_are_reported() {
+    let listen_address = "/ip4/127.0.0.1/tcp/0".to_owned();
+    let swarm = build_swarm(
+        vec![listen_address.clone(), listen_address],
+        Duration::from_secs(10),
+        None,
+        InboundUpgradeLimit::new(10),
+        |key| {
+            mixed_behaviour::MixedBehaviour::new(
+                key,
+                None,
+                sqmr::Config {
+                    session_timeout: Duration::from_secs(10),
+                    deterministic_event_order: false,
+                    max_concurrent_inbound_sessions_per_peer: None,
+                    inbound_session_rate_limit_per_peer: None,
+                    max_message_size: sqmr::DEFAULT_MAX_MESSAGE_SIZE,
+                },
+            )
+        },
+    );
+    let network_manager = GenericNetworkManager::generic_new(swarm, ChainId::Mainnet);
+    let mut listen_addresses_receiver = network_manager.get_listen_addresses_receiver();
+    assert!(listen_addresses_receiver.borrow().is_empty());
+
+    tokio::select! {
+        _ = network_manager.run() => panic!("Network manager ended"),
+        result = tokio::time::timeout(
+            TIMEOUT, listen_addresses_receiver.wait_for(|addresses| addresses.len() == 2)
+        ) => {
+            let listen_addresses = result.unwrap().unwrap().clone();
+            let ports = listen_addresses
+                .iter()
+                .map(|address| {
+                    assert_matches!(
+                        address.iter().collect::<Vec<_>>()[..],
+                        [Protocol::Ip4(_), Protocol::Tcp(port)] => port
+                    )
+                })
+                .collect::<HashSet<_>>();
+            // Each address was bound to a different port assigned by the OS.
+            assert_eq!(ports.len(), 2);
+            assert!(!ports.contains(&0));
+        }
+    }
+}
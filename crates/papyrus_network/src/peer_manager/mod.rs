@@ -6,12 +6,13 @@ use futures::FutureExt;
 use libp2p::swarm::dial_opts::DialOpts;
 use libp2p::swarm::ToSwarm;
 use libp2p::PeerId;
-use tracing::info;
+use tracing::{debug, info};
 
 pub use self::behaviour_impl::ToOtherBehaviourEvent;
 use self::peer::PeerTrait;
 use crate::discovery::identify_impl::IdentifyToOtherBehaviourEvent;
 use crate::mixed_behaviour::BridgedBehaviour;
+use crate::sqmr::behaviour::SessionCloseReason;
 use crate::sqmr::OutboundSessionId;
 use crate::{discovery, mixed_behaviour, sqmr};
 
@@ -22,13 +23,22 @@ mod test;
 
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum ReputationModifier {
-    // TODO: Implement this enum
+    /// The peer misbehaved in a way that should get it blacklisted right away, e.g. it sent data
+    /// that failed validation.
     Bad,
+    /// An outbound session with the peer failed or timed out.
+    SessionFailed,
+    /// An outbound session with the peer finished successfully.
+    SessionSucceeded,
 }
 
+const MAX_PEER_SCORE: i32 = 100;
+const SESSION_FAILED_PENALTY: i32 = 10;
+const SESSION_SUCCEEDED_REWARD: i32 = 1;
+
 pub struct PeerManager<P: PeerTrait + 'static> {
     peers: HashMap<PeerId, P>,
-    // TODO: consider implementing a cleanup mechanism to not store all queries forever
+    peer_scores: HashMap<PeerId, i32>,
     session_to_peer_map: HashMap<OutboundSessionId, PeerId>,
     config: PeerManagerConfig,
     last_peer_index: usize,
@@ -43,6 +53,9 @@ pub struct PeerManager<P: PeerTrait + 'static> {
 pub struct PeerManagerConfig {
     target_num_for_peers: usize,
     blacklist_timeout: Duration,
+    /// A peer whose score drops to this value is blacklisted for `blacklist_timeout`. Its score is
+    /// reset once it's blacklisted.
+    ban_score_threshold: i32,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -61,6 +74,7 @@ impl Default for PeerManagerConfig {
             target_num_for_peers: 100,
             // 1 year.
             blacklist_timeout: Duration::from_secs(3600 * 24 * 365),
+            ban_score_threshold: -50,
         }
     }
 }
@@ -74,6 +88,7 @@ where
         let peers = HashMap::new();
         Self {
             peers,
+            peer_scores: HashMap::new(),
             session_to_peer_map: HashMap::new(),
             config,
             last_peer_index: 0,
@@ -185,12 +200,7 @@ where
     ) -> Result<(), PeerManagerError> {
         // TODO(shahak): Add time blacklisted to log.
         info!("Peer {:?} reported as misbehaving.", peer_id);
-        if let Some(peer) = self.peers.get_mut(&peer_id) {
-            peer.update_reputation(reason);
-            Ok(())
-        } else {
-            Err(PeerManagerError::NoSuchPeer(peer_id))
-        }
+        self.update_peer_score(peer_id, reason)
     }
 
     fn report_session(
@@ -199,17 +209,42 @@ where
         reason: ReputationModifier,
     ) -> Result<(), PeerManagerError> {
         if let Some(peer_id) = self.session_to_peer_map.get(&outbound_session_id) {
-            if let Some(peer) = self.peers.get_mut(peer_id) {
-                peer.update_reputation(reason);
-                Ok(())
-            } else {
-                Err(PeerManagerError::NoSuchPeer(*peer_id))
-            }
+            self.update_peer_score(*peer_id, reason)
         } else {
             Err(PeerManagerError::NoSuchSession(outbound_session_id))
         }
     }
 
+    fn update_peer_score(
+        &mut self,
+        peer_id: PeerId,
+        reason: ReputationModifier,
+    ) -> Result<(), PeerManagerError> {
+        let Some(peer) = self.peers.get_mut(&peer_id) else {
+            return Err(PeerManagerError::NoSuchPeer(peer_id));
+        };
+        let score = self.peer_scores.entry(peer_id).or_default();
+        *score = match reason {
+            ReputationModifier::Bad => self.config.ban_score_threshold,
+            ReputationModifier::SessionFailed => score.saturating_sub(SESSION_FAILED_PENALTY),
+            ReputationModifier::SessionSucceeded => {
+                (*score + SESSION_SUCCEEDED_REWARD).min(MAX_PEER_SCORE)
+            }
+        };
+        if *score <= self.config.ban_score_threshold {
+            info!("Peer {:?} reached the ban score threshold. Blacklisting it.", peer_id);
+            // The peer gets a fresh start once it's no longer blacklisted.
+            *score = 0;
+            peer.update_reputation(reason);
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn peer_score(&self, peer_id: PeerId) -> i32 {
+        self.peer_scores.get(&peer_id).copied().unwrap_or_default()
+    }
+
     fn more_peers_needed(&self) -> bool {
         // TODO: consider if we should count blocked peers (and in what cases? what if they are
         // blocked temporarily?)
@@ -231,6 +266,26 @@ impl<P: PeerTrait + 'static> BridgedBehaviour for PeerManager<P> {
             ) => {
                 self.assign_peer_to_session(*outbound_session_id);
             }
+            mixed_behaviour::ToOtherBehaviourEvent::Sqmr(
+                sqmr::ToOtherBehaviourEvent::OutboundSessionClosed { outbound_session_id, reason },
+            ) => {
+                let reputation_modifier = match reason {
+                    SessionCloseReason::FinishedSuccessfully => {
+                        Some(ReputationModifier::SessionSucceeded)
+                    }
+                    // We closed the session ourselves, so it's not the peer's fault.
+                    SessionCloseReason::Dropped => None,
+                    _ => Some(ReputationModifier::SessionFailed),
+                };
+                if let Some(reputation_modifier) = reputation_modifier {
+                    if let Err(error) =
+                        self.report_session(*outbound_session_id, reputation_modifier)
+                    {
+                        debug!("Failed to update the score of a closed session's peer: {error}");
+                    }
+                }
+                self.session_to_peer_map.remove(outbound_session_id);
+            }
             mixed_behaviour::ToOtherBehaviourEvent::Identify(
                 IdentifyToOtherBehaviourEvent::FoundListenAddresses { peer_id, listen_addresses },
             )
@@ -20,7 +20,16 @@ use crate::discovery::identify_impl::IdentifyToOtherBehaviourEvent;
 use crate::mixed_behaviour;
 use crate::mixed_behaviour::BridgedBehaviour;
 use crate::peer_manager::peer::{MockPeerTrait, Peer, PeerTrait};
-use crate::peer_manager::{PeerManager, PeerManagerConfig, ReputationModifier};
+use crate::peer_manager::{
+    PeerManager,
+    PeerManagerConfig,
+    ReputationModifier,
+    MAX_PEER_SCORE,
+    SESSION_FAILED_PENALTY,
+    SESSION_SUCCEEDED_REWARD,
+};
+use crate::sqmr;
+use crate::sqmr::behaviour::SessionCloseReason;
 use crate::sqmr::OutboundSessionId;
 
 impl<P: PeerTrait> Unpin for PeerManager<P> {}
@@ -544,3 +553,82 @@ fn no_more_peers_needed_stops_discovery() {
     }
     panic!("Discovery pause event not emitted");
 }
+
+fn simulate_outbound_session_closed(
+    peer_manager: &mut PeerManager<Peer>,
+    outbound_session_id: OutboundSessionId,
+    reason: SessionCloseReason,
+) {
+    peer_manager.on_other_behaviour_event(&mixed_behaviour::ToOtherBehaviourEvent::Sqmr(
+        sqmr::ToOtherBehaviourEvent::OutboundSessionClosed { outbound_session_id, reason },
+    ));
+}
+
+#[tokio::test]
+async fn failed_sessions_lower_score_until_peer_is_blacklisted() {
+    let config = PeerManagerConfig { ban_score_threshold: -30, ..Default::default() };
+    let mut peer_manager: PeerManager<Peer> = PeerManager::new(config);
+    let peer_id = PeerId::random();
+    peer_manager.add_peer(Peer::new(peer_id, Multiaddr::empty()));
+
+    let failure_reasons = [
+        SessionCloseReason::Timeout { session_timeout: Duration::from_secs(1) },
+        SessionCloseReason::ConnectionClosed,
+        SessionCloseReason::RemoteDoesntSupportProtocol,
+    ];
+    for (i, reason) in failure_reasons.into_iter().enumerate() {
+        assert!(!peer_manager.get_mut_peer(peer_id).unwrap().is_blocked());
+        let outbound_session_id = OutboundSessionId { value: i };
+        assert_eq!(peer_manager.assign_peer_to_session(outbound_session_id), Some(peer_id));
+        simulate_outbound_session_closed(&mut peer_manager, outbound_session_id, reason);
+    }
+
+    assert!(peer_manager.get_mut_peer(peer_id).unwrap().is_blocked());
+    // The score is reset once the peer is blacklisted.
+    assert_eq!(peer_manager.peer_score(peer_id), 0);
+    assert_eq!(peer_manager.assign_peer_to_session(OutboundSessionId { value: 3 }), None);
+}
+
+#[test]
+fn successful_sessions_raise_score() {
+    let mut peer_manager: PeerManager<Peer> = PeerManager::new(PeerManagerConfig::default());
+    let peer_id = PeerId::random();
+    peer_manager.add_peer(Peer::new(peer_id, Multiaddr::empty()));
+
+    let run_session = |peer_manager: &mut PeerManager<Peer>, reason| {
+        let outbound_session_id = OutboundSessionId::default();
+        peer_manager.assign_peer_to_session(outbound_session_id);
+        simulate_outbound_session_closed(peer_manager, outbound_session_id, reason);
+    };
+
+    run_session(&mut peer_manager, SessionCloseReason::ConnectionClosed);
+    assert_eq!(peer_manager.peer_score(peer_id), -SESSION_FAILED_PENALTY);
+    run_session(&mut peer_manager, SessionCloseReason::FinishedSuccessfully);
+    assert_eq!(peer_manager.peer_score(peer_id), SESSION_SUCCEEDED_REWARD - SESSION_FAILED_PENALTY);
+
+    // Sessions we dropped ourselves don't affect the score.
+    run_session(&mut peer_manager, SessionCloseReason::Dropped);
+    assert_eq!(peer_manager.peer_score(peer_id), SESSION_SUCCEEDED_REWARD - SESSION_FAILED_PENALTY);
+
+    // The score is capped so that a long history of good behaviour doesn't hide misbehaviour.
+    for _ in 0..2 * MAX_PEER_SCORE {
+        run_session(&mut peer_manager, SessionCloseReason::FinishedSuccessfully);
+    }
+    assert_eq!(peer_manager.peer_score(peer_id), MAX_PEER_SCORE);
+}
+
+#[tokio::test]
+async fn blacklisted_peer_is_assignable_after_cooldown() {
+    const BLACKLIST_TIMEOUT: Duration = Duration::from_millis(100);
+    let config = PeerManagerConfig { blacklist_timeout: BLACKLIST_TIMEOUT, ..Default::default() };
+    let mut peer_manager: PeerManager<Peer> = PeerManager::new(config);
+    let peer_id = PeerId::random();
+    peer_manager.add_peer(Peer::new(peer_id, Multiaddr::empty()));
+
+    peer_manager.report_peer(peer_id, ReputationModifier::Bad).unwrap();
+    assert_eq!(peer_manager.assign_peer_to_session(OutboundSessionId { value: 1 }), None);
+
+    sleep(BLACKLIST_TIMEOUT).await;
+    assert!(!peer_manager.get_mut_peer(peer_id).unwrap().is_blocked());
+    assert_eq!(peer_manager.assign_peer_to_session(OutboundSessionId { value: 2 }), Some(peer_id));
+}
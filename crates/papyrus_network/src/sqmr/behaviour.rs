@@ -23,7 +23,7 @@ use libp2p::swarm::{
     ToSwarm,
 };
 use libp2p::{Multiaddr, PeerId, StreamProtocol};
-use tracing::{error, info};
+use tracing::{debug, error, info};
 
 use super::handler::{
     Handler,
@@ -31,6 +31,7 @@ use super::handler::{
     RequestToBehaviourEvent,
     SessionError as HandlerSessionError,
 };
+use super::token_bucket::TokenBucket;
 use super::{Bytes, Config, GenericEvent, InboundSessionId, OutboundSessionId, SessionId};
 use crate::mixed_behaviour::{self, BridgedBehaviour};
 use crate::peer_manager;
@@ -43,12 +44,20 @@ pub enum SessionError {
     IOError(#[from] io::Error),
     #[error("Remote peer doesn't support the given protocol.")]
     RemoteDoesntSupportProtocol,
+    #[error("Remote peer sent a malformed message: {0}")]
+    MalformedMessage(io::Error),
+    #[error("Remote peer closed the session in the middle of a message.")]
+    RemoteClosed,
     // If there's a connection with a single session and it was closed because of another reason,
     // we might get ConnectionClosed instead of that reason because the swarm automatically closes
     // a connection that has no sessions. If this is a problem, set the swarm's
     // idle_connection_timeout to a non-zero number.
     #[error("Connection to remote peer closed.")]
     ConnectionClosed,
+    #[error("Remote peer has too many concurrent inbound sessions.")]
+    TooManySessions,
+    #[error("Remote peer opens inbound sessions too fast.")]
+    RateLimited,
 }
 
 impl From<GenericEvent<HandlerSessionError>> for GenericEvent<SessionError> {
@@ -60,9 +69,12 @@ impl From<GenericEvent<HandlerSessionError>> for GenericEvent<SessionError> {
                 peer_id,
                 protocol_name,
             } => Self::NewInboundSession { query, inbound_session_id, peer_id, protocol_name },
-            GenericEvent::ReceivedResponse { outbound_session_id, response, peer_id } => {
-                Self::ReceivedResponse { outbound_session_id, response, peer_id }
-            }
+            GenericEvent::ReceivedResponse {
+                outbound_session_id,
+                response,
+                peer_id,
+                protocol_name,
+            } => Self::ReceivedResponse { outbound_session_id, response, peer_id, protocol_name },
             GenericEvent::SessionFailed {
                 session_id,
                 error: HandlerSessionError::Timeout { session_timeout },
@@ -79,6 +91,14 @@ impl From<GenericEvent<HandlerSessionError>> for GenericEvent<SessionError> {
             } => {
                 Self::SessionFailed { session_id, error: SessionError::RemoteDoesntSupportProtocol }
             }
+            GenericEvent::SessionFailed {
+                session_id,
+                error: HandlerSessionError::MalformedMessage(error),
+            } => Self::SessionFailed { session_id, error: SessionError::MalformedMessage(error) },
+            GenericEvent::SessionFailed {
+                session_id,
+                error: HandlerSessionError::RemoteClosed,
+            } => Self::SessionFailed { session_id, error: SessionError::RemoteClosed },
             GenericEvent::SessionFinishedSuccessfully { session_id } => {
                 Self::SessionFinishedSuccessfully { session_id }
             }
@@ -88,9 +108,57 @@ impl From<GenericEvent<HandlerSessionError>> for GenericEvent<SessionError> {
 
 pub type ExternalEvent = GenericEvent<SessionError>;
 
+/// The amount of closed sessions the behaviour remembers by default.
+pub const DEFAULT_SESSION_HISTORY_SIZE: usize = 100;
+
+/// The reason a session was closed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SessionCloseReason {
+    FinishedSuccessfully,
+    /// The session was dropped by us, either explicitly or because it exceeded the limit of
+    /// concurrent inbound sessions.
+    Dropped,
+    Timeout {
+        session_timeout: Duration,
+    },
+    IOError(io::ErrorKind),
+    RemoteDoesntSupportProtocol,
+    MalformedMessage,
+    RemoteClosed,
+    ConnectionClosed,
+    TooManySessions,
+    RateLimited,
+}
+
+impl From<&SessionError> for SessionCloseReason {
+    fn from(error: &SessionError) -> Self {
+        match error {
+            SessionError::Timeout { session_timeout } => {
+                Self::Timeout { session_timeout: *session_timeout }
+            }
+            SessionError::IOError(error) => Self::IOError(error.kind()),
+            SessionError::RemoteDoesntSupportProtocol => Self::RemoteDoesntSupportProtocol,
+            SessionError::MalformedMessage(_) => Self::MalformedMessage,
+            SessionError::RemoteClosed => Self::RemoteClosed,
+            SessionError::ConnectionClosed => Self::ConnectionClosed,
+            SessionError::TooManySessions => Self::TooManySessions,
+            SessionError::RateLimited => Self::RateLimited,
+        }
+    }
+}
+
+/// A session that was closed, kept for debugging.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClosedSession {
+    pub session_id: SessionId,
+    pub peer_id: PeerId,
+    pub reason: SessionCloseReason,
+}
+
 #[derive(Debug)]
 pub enum ToOtherBehaviourEvent {
     RequestPeerAssignment { outbound_session_id: OutboundSessionId },
+    OutboundSessionClosed { outbound_session_id: OutboundSessionId, reason: SessionCloseReason },
 }
 
 #[derive(Debug)]
@@ -117,8 +185,15 @@ pub struct Behaviour {
     next_inbound_session_id: Arc<AtomicUsize>,
     dropped_sessions: HashSet<SessionId>,
     wakers_waiting_for_event: Vec<Waker>,
-    outbound_sessions_pending_peer_assignment: HashMap<OutboundSessionId, (Bytes, StreamProtocol)>,
+    outbound_sessions_pending_peer_assignment:
+        HashMap<OutboundSessionId, (Bytes, Vec<StreamProtocol>)>,
     supported_inbound_protocols: HashSet<StreamProtocol>,
+    max_concurrent_inbound_sessions: HashMap<StreamProtocol, usize>,
+    inbound_session_id_to_protocol: HashMap<InboundSessionId, StreamProtocol>,
+    session_history: VecDeque<ClosedSession>,
+    session_history_size: usize,
+    // Removed when the last connection to the peer closes.
+    inbound_session_rate_limiters: HashMap<PeerId, TokenBucket>,
 }
 
 impl Behaviour {
@@ -134,17 +209,23 @@ impl Behaviour {
             wakers_waiting_for_event: Default::default(),
             outbound_sessions_pending_peer_assignment: Default::default(),
             supported_inbound_protocols: Default::default(),
+            max_concurrent_inbound_sessions: Default::default(),
+            inbound_session_id_to_protocol: Default::default(),
+            session_history: Default::default(),
+            session_history_size: DEFAULT_SESSION_HISTORY_SIZE,
+            inbound_session_rate_limiters: Default::default(),
         }
     }
 
     /// Send query to the given peer and start a new outbound session with it. Return the id of the
-    /// new session.
+    /// new session. The session uses the first of the given protocol versions that the peer
+    /// supports, so they should be ordered from the most preferred to the least preferred.
     // TODO(shahak) Remove this function once Network manager uses start_query.
     pub fn send_query(
         &mut self,
         query: Bytes,
         peer_id: PeerId,
-        protocol_name: StreamProtocol,
+        protocol_names: Vec<StreamProtocol>,
     ) -> Result<OutboundSessionId, PeerNotConnected> {
         let connection_id =
             *self.connection_ids_map.get(peer_id).iter().next().ok_or(PeerNotConnected)?;
@@ -161,24 +242,25 @@ impl Behaviour {
             event: RequestFromBehaviourEvent::CreateOutboundSession {
                 query,
                 outbound_session_id,
-                protocol_name,
+                protocol_names,
             },
         });
 
         Ok(outbound_session_id)
     }
 
-    /// Assign some peer and start a query. Return the id of the new session.
+    /// Assign some peer and start a query. Return the id of the new session. See
+    /// [`send_query`](Self::send_query) for the order of the protocol versions.
     pub fn start_query(
         &mut self,
         query: Bytes,
-        protocol_name: StreamProtocol,
+        protocol_names: Vec<StreamProtocol>,
     ) -> OutboundSessionId {
         let outbound_session_id = self.next_outbound_session_id;
         self.next_outbound_session_id.value += 1;
 
         self.outbound_sessions_pending_peer_assignment
-            .insert(outbound_session_id, (query, protocol_name));
+            .insert(outbound_session_id, (query, protocol_names));
         info!("Requesting peer assignment for outbound session: {:?}.", outbound_session_id);
         self.add_event_to_queue(ToSwarm::GenerateEvent(Event::ToOtherBehaviourEvent(
             ToOtherBehaviourEvent::RequestPeerAssignment { outbound_session_id },
@@ -224,6 +306,9 @@ impl Behaviour {
     pub fn drop_session(&mut self, session_id: SessionId) -> Result<(), SessionIdNotFoundError> {
         let (peer_id, connection_id) =
             self.get_peer_id_and_connection_id_from_session_id(session_id)?;
+        if let SessionId::InboundSessionId(inbound_session_id) = session_id {
+            self.inbound_session_id_to_protocol.remove(&inbound_session_id);
+        }
         if self.dropped_sessions.insert(session_id) {
             self.add_event_to_queue(ToSwarm::NotifyHandler {
                 peer_id,
@@ -255,6 +340,128 @@ impl Behaviour {
             self.supported_inbound_protocols.insert(protocol);
         }
     }
+
+    /// Limit the amount of inbound sessions of the given protocol that can be open at the same
+    /// time. Inbound sessions exceeding the limit are rejected and reported as failed with
+    /// [`SessionError::TooManySessions`].
+    pub fn set_max_concurrent_inbound_sessions(
+        &mut self,
+        protocol: StreamProtocol,
+        max_concurrent_inbound: usize,
+    ) {
+        self.max_concurrent_inbound_sessions.insert(protocol, max_concurrent_inbound);
+    }
+
+    /// Set the amount of closed sessions that are kept in the session history.
+    pub fn set_session_history_size(&mut self, session_history_size: usize) {
+        self.session_history_size = session_history_size;
+        while self.session_history.len() > session_history_size {
+            self.session_history.pop_front();
+        }
+    }
+
+    /// Returns the last closed sessions and the reason each of them was closed, from the oldest to
+    /// the newest.
+    pub fn recent_session_history(&self) -> impl Iterator<Item = &ClosedSession> {
+        self.session_history.iter()
+    }
+
+    fn record_closed_session(
+        &mut self,
+        session_id: SessionId,
+        peer_id: PeerId,
+        reason: SessionCloseReason,
+    ) {
+        // Let the peer manager score the peer by the outcome of the sessions we opened to it.
+        if let SessionId::OutboundSessionId(outbound_session_id) = session_id {
+            self.add_event_to_queue(ToSwarm::GenerateEvent(Event::ToOtherBehaviourEvent(
+                ToOtherBehaviourEvent::OutboundSessionClosed {
+                    outbound_session_id,
+                    reason: reason.clone(),
+                },
+            )));
+        }
+        if self.session_history_size == 0 {
+            return;
+        }
+        if self.session_history.len() == self.session_history_size {
+            self.session_history.pop_front();
+        }
+        self.session_history.push_back(ClosedSession { session_id, peer_id, reason });
+    }
+
+    fn is_inbound_sessions_limit_reached(&self, protocol: &StreamProtocol) -> bool {
+        let Some(max_concurrent_inbound) = self.max_concurrent_inbound_sessions.get(protocol)
+        else {
+            return false;
+        };
+        let num_active_inbound_sessions = self
+            .inbound_session_id_to_protocol
+            .values()
+            .filter(|session_protocol| *session_protocol == protocol)
+            .count();
+        num_active_inbound_sessions >= *max_concurrent_inbound
+    }
+
+    fn is_inbound_sessions_limit_per_peer_reached(&self, peer_id: PeerId) -> bool {
+        let Some(max_concurrent_inbound) = self.config.max_concurrent_inbound_sessions_per_peer
+        else {
+            return false;
+        };
+        let num_active_inbound_sessions = self
+            .session_id_to_peer_id_and_connection_id
+            .iter()
+            .filter(|(session_id, (session_peer_id, _))| {
+                matches!(session_id, SessionId::InboundSessionId(_))
+                    && *session_peer_id == peer_id
+                    && !self.dropped_sessions.contains(session_id)
+            })
+            .count();
+        num_active_inbound_sessions >= max_concurrent_inbound
+    }
+
+    // Takes a token from the rate limiter of the peer. Returns true if the peer has no tokens left.
+    fn is_inbound_session_rate_limit_per_peer_reached(&mut self, peer_id: PeerId) -> bool {
+        let Some(rate_limit) = self.config.inbound_session_rate_limit_per_peer else {
+            return false;
+        };
+        !self
+            .inbound_session_rate_limiters
+            .entry(peer_id)
+            .or_insert_with(|| TokenBucket::new(rate_limit.burst, rate_limit.sessions_per_second))
+            .try_take()
+    }
+
+    // Rejects an inbound session that was never reported and reports it as failed.
+    fn fail_inbound_session(
+        &mut self,
+        inbound_session_id: InboundSessionId,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        error: SessionError,
+    ) {
+        self.reject_inbound_session(inbound_session_id, peer_id, connection_id);
+        let session_id = inbound_session_id.into();
+        self.record_closed_session(session_id, peer_id, (&error).into());
+        self.add_event_to_queue(ToSwarm::GenerateEvent(Event::External(
+            ExternalEvent::SessionFailed { session_id, error },
+        )));
+    }
+
+    fn reject_inbound_session(
+        &mut self,
+        inbound_session_id: InboundSessionId,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+    ) {
+        let session_id = inbound_session_id.into();
+        self.dropped_sessions.insert(session_id);
+        self.add_event_to_queue(ToSwarm::NotifyHandler {
+            peer_id,
+            handler: NotifyHandler::One(connection_id),
+            event: RequestFromBehaviourEvent::DropSession { session_id },
+        });
+    }
 }
 
 impl NetworkBehaviour for Behaviour {
@@ -300,7 +507,15 @@ impl NetworkBehaviour for Behaviour {
             }) => {
                 self.connection_ids_map.get_mut(peer_id).insert(connection_id);
             }
-            FromSwarm::ConnectionClosed(ConnectionClosed { peer_id, connection_id, .. }) => {
+            FromSwarm::ConnectionClosed(ConnectionClosed {
+                peer_id,
+                connection_id,
+                remaining_established,
+                ..
+            }) => {
+                if remaining_established == 0 {
+                    self.inbound_session_rate_limiters.remove(&peer_id);
+                }
                 let mut session_ids = Vec::new();
                 self.session_id_to_peer_id_and_connection_id.retain(
                     |session_id, (session_peer_id, session_connection_id)| {
@@ -313,6 +528,14 @@ impl NetworkBehaviour for Behaviour {
                     },
                 );
                 for session_id in session_ids {
+                    if let SessionId::InboundSessionId(inbound_session_id) = session_id {
+                        self.inbound_session_id_to_protocol.remove(&inbound_session_id);
+                    }
+                    self.record_closed_session(
+                        session_id,
+                        peer_id,
+                        SessionCloseReason::ConnectionClosed,
+                    );
                     self.add_event_to_queue(ToSwarm::GenerateEvent(Event::External(
                         ExternalEvent::SessionFailed {
                             session_id,
@@ -336,17 +559,71 @@ impl NetworkBehaviour for Behaviour {
                 let converted_event = event.into();
                 let mut is_event_muted = false;
                 match converted_event {
-                    ExternalEvent::NewInboundSession { inbound_session_id, .. } => {
+                    ExternalEvent::NewInboundSession {
+                        inbound_session_id,
+                        ref protocol_name,
+                        ..
+                    } => {
+                        if self.is_inbound_sessions_limit_reached(protocol_name) {
+                            debug!(
+                                "Reached the limit of concurrent inbound sessions for protocol \
+                                 {protocol_name}. Rejecting inbound session {inbound_session_id}."
+                            );
+                            self.fail_inbound_session(
+                                inbound_session_id,
+                                peer_id,
+                                connection_id,
+                                SessionError::TooManySessions,
+                            );
+                            return;
+                        }
+                        if self.is_inbound_sessions_limit_per_peer_reached(peer_id) {
+                            debug!(
+                                "Reached the limit of concurrent inbound sessions for peer \
+                                 {peer_id}. Rejecting inbound session {inbound_session_id}."
+                            );
+                            self.fail_inbound_session(
+                                inbound_session_id,
+                                peer_id,
+                                connection_id,
+                                SessionError::TooManySessions,
+                            );
+                            return;
+                        }
+                        if self.is_inbound_session_rate_limit_per_peer_reached(peer_id) {
+                            debug!(
+                                "Peer {peer_id} exceeded the rate limit of inbound sessions. \
+                                 Rejecting inbound session {inbound_session_id}."
+                            );
+                            self.fail_inbound_session(
+                                inbound_session_id,
+                                peer_id,
+                                connection_id,
+                                SessionError::RateLimited,
+                            );
+                            return;
+                        }
+                        self.inbound_session_id_to_protocol
+                            .insert(inbound_session_id, protocol_name.clone());
                         self.session_id_to_peer_id_and_connection_id
                             .insert(inbound_session_id.into(), (peer_id, connection_id));
                     }
                     ExternalEvent::SessionFailed { session_id, .. }
                     | ExternalEvent::SessionFinishedSuccessfully { session_id, .. } => {
                         self.session_id_to_peer_id_and_connection_id.remove(&session_id);
+                        if let SessionId::InboundSessionId(inbound_session_id) = session_id {
+                            self.inbound_session_id_to_protocol.remove(&inbound_session_id);
+                        }
                         let is_dropped = self.dropped_sessions.remove(&session_id);
                         if is_dropped {
                             is_event_muted = true;
                         }
+                        let reason = match &converted_event {
+                            _ if is_dropped => SessionCloseReason::Dropped,
+                            ExternalEvent::SessionFailed { error, .. } => error.into(),
+                            _ => SessionCloseReason::FinishedSuccessfully,
+                        };
+                        self.record_closed_session(session_id, peer_id, reason);
                     }
                     ExternalEvent::ReceivedResponse { outbound_session_id, .. } => {
                         if self.dropped_sessions.contains(&outbound_session_id.into()) {
@@ -395,7 +672,7 @@ impl BridgedBehaviour for Behaviour {
         self.session_id_to_peer_id_and_connection_id
             .insert((*outbound_session_id).into(), (*peer_id, *connection_id));
 
-        let Some((query, protocol_name)) =
+        let Some((query, protocol_names)) =
             self.outbound_sessions_pending_peer_assignment.remove(outbound_session_id)
         else {
             error!(
@@ -411,7 +688,7 @@ impl BridgedBehaviour for Behaviour {
             event: RequestFromBehaviourEvent::CreateOutboundSession {
                 query,
                 outbound_session_id: *outbound_session_id,
-                protocol_name,
+                protocol_names,
             },
         });
     }
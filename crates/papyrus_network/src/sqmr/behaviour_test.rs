@@ -1,7 +1,9 @@
 // TODO(shahak): Use start_query in all tests instead of send_query
 
+use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use assert_matches::assert_matches;
 use futures::{FutureExt, Stream, StreamExt};
@@ -11,9 +13,29 @@ use libp2p::swarm::behaviour::ConnectionEstablished;
 use libp2p::swarm::{ConnectionClosed, ConnectionId, FromSwarm, NetworkBehaviour, ToSwarm};
 use libp2p::{Multiaddr, PeerId, StreamProtocol};
 
-use super::super::handler::{RequestFromBehaviourEvent, RequestToBehaviourEvent};
-use super::super::{Bytes, Config, GenericEvent, InboundSessionId, OutboundSessionId, SessionId};
-use super::{Behaviour, Event, ExternalEvent, SessionError};
+use super::super::handler::{
+    RequestFromBehaviourEvent,
+    RequestToBehaviourEvent,
+    SessionError as HandlerSessionError,
+};
+use super::super::{
+    Bytes,
+    Config,
+    GenericEvent,
+    InboundSessionId,
+    InboundSessionRateLimit,
+    OutboundSessionId,
+    SessionId,
+};
+use super::{
+    Behaviour,
+    ClosedSession,
+    Event,
+    ExternalEvent,
+    SessionCloseReason,
+    SessionError,
+    ToOtherBehaviourEvent,
+};
 use crate::test_utils::dummy_data;
 
 impl Unpin for Behaviour {}
@@ -72,6 +94,22 @@ fn simulate_new_inbound_session(
     peer_id: PeerId,
     inbound_session_id: InboundSessionId,
     query: Bytes,
+) {
+    simulate_new_inbound_session_with_protocol(
+        behaviour,
+        peer_id,
+        inbound_session_id,
+        query,
+        PROTOCOL_NAME.clone(),
+    );
+}
+
+fn simulate_new_inbound_session_with_protocol(
+    behaviour: &mut Behaviour,
+    peer_id: PeerId,
+    inbound_session_id: InboundSessionId,
+    query: Bytes,
+    protocol_name: StreamProtocol,
 ) {
     behaviour.on_connection_handler_event(
         peer_id,
@@ -80,7 +118,7 @@ fn simulate_new_inbound_session(
             query,
             inbound_session_id,
             peer_id,
-            protocol_name: PROTOCOL_NAME.clone(),
+            protocol_name,
         }),
     );
 }
@@ -98,6 +136,7 @@ fn simulate_received_response(
             response,
             outbound_session_id,
             peer_id,
+            protocol_name: PROTOCOL_NAME.clone(),
         }),
     );
 }
@@ -116,14 +155,26 @@ fn simulate_session_finished_successfully(
     );
 }
 
+fn simulate_session_failed(
+    behaviour: &mut Behaviour,
+    peer_id: PeerId,
+    session_id: SessionId,
+    error: HandlerSessionError,
+) {
+    behaviour.on_connection_handler_event(
+        peer_id,
+        ConnectionId::new_unchecked(0),
+        RequestToBehaviourEvent::GenerateEvent(GenericEvent::SessionFailed { session_id, error }),
+    );
+}
+
 fn simulate_connection_closed(behaviour: &mut Behaviour, peer_id: PeerId) {
     // This is the same connection_id from simulate_connection_established
     let connection_id = ConnectionId::new_unchecked(0);
     behaviour.on_swarm_event(FromSwarm::ConnectionClosed(ConnectionClosed {
         peer_id,
         connection_id,
-        // Filling these fields with arbitrary values since the behaviour doesn't look at these
-        // fields.
+        // Filling this field with an arbitrary value since the behaviour doesn't look at it.
         endpoint: &ConnectedPoint::Dialer {
             address: Multiaddr::empty(),
             role_override: Endpoint::Dialer,
@@ -151,12 +202,12 @@ async fn validate_create_outbound_session_event(
         event,
         ToSwarm::NotifyHandler {
             peer_id: event_peer_id,
-            event: RequestFromBehaviourEvent::CreateOutboundSession { query: event_query, outbound_session_id: event_outbound_session_id, protocol_name },
+            event: RequestFromBehaviourEvent::CreateOutboundSession { query: event_query, outbound_session_id: event_outbound_session_id, protocol_names },
             ..
         } if *peer_id == event_peer_id
             && *outbound_session_id == event_outbound_session_id
             && *query == event_query
-            && protocol_name == PROTOCOL_NAME.clone()
+            && protocol_names == vec![PROTOCOL_NAME.clone()]
     );
 }
 
@@ -192,8 +243,9 @@ async fn validate_received_response_event(
         event,
         ToSwarm::GenerateEvent(Event::External(ExternalEvent::ReceivedResponse {
             response: event_response, outbound_session_id: event_outbound_session_id,
-            peer_id: event_peer_id,
+            peer_id: event_peer_id, protocol_name,
         })) if event_response == *response && event_outbound_session_id == outbound_session_id && peer_id == event_peer_id
+            && protocol_name == PROTOCOL_NAME.clone()
     );
 }
 
@@ -268,6 +320,23 @@ async fn validate_session_finished_successfully_event(
     );
 }
 
+async fn validate_outbound_session_closed_event(
+    behaviour: &mut Behaviour,
+    outbound_session_id: OutboundSessionId,
+    reason: SessionCloseReason,
+) {
+    let event = behaviour.next().await.unwrap();
+    assert_matches!(
+        event,
+        ToSwarm::GenerateEvent(Event::ToOtherBehaviourEvent(
+            ToOtherBehaviourEvent::OutboundSessionClosed {
+                outbound_session_id: event_outbound_session_id,
+                reason: event_reason,
+            }
+        )) if event_outbound_session_id == outbound_session_id && event_reason == reason
+    );
+}
+
 // TODO(shahak): Fix code duplication with handler test.
 fn validate_no_events(behaviour: &mut Behaviour) {
     assert!(behaviour.next().now_or_never().is_none());
@@ -321,7 +390,7 @@ async fn create_and_process_outbound_session() {
 
     simulate_connection_established(&mut behaviour, peer_id);
     let outbound_session_id =
-        behaviour.send_query(QUERY.clone(), peer_id, PROTOCOL_NAME.clone()).unwrap();
+        behaviour.send_query(QUERY.clone(), peer_id, vec![PROTOCOL_NAME.clone()]).unwrap();
 
     validate_create_outbound_session_event(&mut behaviour, &peer_id, &QUERY, &outbound_session_id)
         .await;
@@ -340,6 +409,12 @@ async fn create_and_process_outbound_session() {
 
     let session_id = outbound_session_id.into();
     simulate_session_finished_successfully(&mut behaviour, peer_id, session_id);
+    validate_outbound_session_closed_event(
+        &mut behaviour,
+        outbound_session_id,
+        SessionCloseReason::FinishedSuccessfully,
+    )
+    .await;
     validate_session_finished_successfully_event(&mut behaviour, session_id).await;
     validate_no_events(&mut behaviour);
 }
@@ -354,7 +429,7 @@ async fn connection_closed() {
     simulate_connection_established(&mut behaviour, peer_id);
 
     let outbound_session_id =
-        behaviour.send_query(QUERY.clone(), peer_id, PROTOCOL_NAME.clone()).unwrap();
+        behaviour.send_query(QUERY.clone(), peer_id, vec![PROTOCOL_NAME.clone()]).unwrap();
 
     // Consume the event to create an outbound session.
     behaviour.next().await.unwrap();
@@ -367,9 +442,27 @@ async fn connection_closed() {
 
     simulate_connection_closed(&mut behaviour, peer_id);
 
-    let event1 = behaviour.next().await.unwrap();
-    let event2 = behaviour.next().await.unwrap();
-    let failed_session_ids = [event1, event2]
+    let mut events = Vec::new();
+    for _ in 0..3 {
+        events.push(behaviour.next().await.unwrap());
+    }
+    // The peer manager is notified about the outbound session right before the user is.
+    let closed_event_index = events
+        .iter()
+        .position(|event| {
+            matches!(
+                event,
+                ToSwarm::GenerateEvent(Event::ToOtherBehaviourEvent(
+                    ToOtherBehaviourEvent::OutboundSessionClosed {
+                        outbound_session_id: event_outbound_session_id,
+                        reason: SessionCloseReason::ConnectionClosed,
+                    }
+                )) if *event_outbound_session_id == outbound_session_id
+            )
+        })
+        .expect("The peer manager wasn't notified that the outbound session was closed.");
+    events.remove(closed_event_index);
+    let failed_session_ids = events
         .iter()
         .map(|event| {
             let ToSwarm::GenerateEvent(Event::External(ExternalEvent::SessionFailed {
@@ -402,7 +495,7 @@ async fn drop_outbound_session() {
     simulate_connection_established(&mut behaviour, peer_id);
 
     let outbound_session_id =
-        behaviour.send_query(QUERY.clone(), peer_id, PROTOCOL_NAME.clone()).unwrap();
+        behaviour.send_query(QUERY.clone(), peer_id, vec![PROTOCOL_NAME.clone()]).unwrap();
 
     // Consume the event to create an outbound session.
     behaviour.next().await.unwrap();
@@ -418,6 +511,14 @@ async fn drop_outbound_session() {
 
     simulate_session_finished_successfully(&mut behaviour, peer_id, outbound_session_id.into());
 
+    // The user isn't notified about a dropped session, but the peer manager is so it could stop
+    // tracking it.
+    validate_outbound_session_closed_event(
+        &mut behaviour,
+        outbound_session_id,
+        SessionCloseReason::Dropped,
+    )
+    .await;
     validate_no_events(&mut behaviour);
 
     simulate_session_dropped(&mut behaviour, peer_id, outbound_session_id.into());
@@ -453,6 +554,299 @@ async fn drop_inbound_session() {
     // so if it will the behaviour might output them.
 }
 
+#[tokio::test]
+async fn max_concurrent_inbound_sessions_enforced_per_protocol() {
+    const MAX_CONCURRENT_INBOUND_CLASS_SESSIONS: usize = 1;
+    const MAX_CONCURRENT_INBOUND_HEADER_SESSIONS: usize = 3;
+    let class_protocol = StreamProtocol::new("/classes");
+    let header_protocol = StreamProtocol::new("/headers");
+
+    let mut behaviour = Behaviour::new(Config::get_test_config());
+    behaviour.set_max_concurrent_inbound_sessions(
+        class_protocol.clone(),
+        MAX_CONCURRENT_INBOUND_CLASS_SESSIONS,
+    );
+    behaviour.set_max_concurrent_inbound_sessions(
+        header_protocol.clone(),
+        MAX_CONCURRENT_INBOUND_HEADER_SESSIONS,
+    );
+
+    let peer_id = PeerId::random();
+    simulate_listener_connection(&mut behaviour, peer_id);
+
+    let mut next_inbound_session_id = (0..).map(|value| InboundSessionId { value });
+    for (protocol, max_concurrent_inbound) in [
+        (class_protocol.clone(), MAX_CONCURRENT_INBOUND_CLASS_SESSIONS),
+        (header_protocol.clone(), MAX_CONCURRENT_INBOUND_HEADER_SESSIONS),
+    ] {
+        for _ in 0..max_concurrent_inbound {
+            let inbound_session_id = next_inbound_session_id.next().unwrap();
+            simulate_new_inbound_session_with_protocol(
+                &mut behaviour,
+                peer_id,
+                inbound_session_id,
+                QUERY.clone(),
+                protocol.clone(),
+            );
+            let event = behaviour.next().await.unwrap();
+            assert_matches!(
+                event,
+                ToSwarm::GenerateEvent(Event::External(ExternalEvent::NewInboundSession {
+                    inbound_session_id: event_inbound_session_id,
+                    protocol_name,
+                    ..
+                })) if event_inbound_session_id == inbound_session_id && protocol_name == protocol
+            );
+        }
+
+        let rejected_inbound_session_id = next_inbound_session_id.next().unwrap();
+        simulate_new_inbound_session_with_protocol(
+            &mut behaviour,
+            peer_id,
+            rejected_inbound_session_id,
+            QUERY.clone(),
+            protocol.clone(),
+        );
+        validate_request_drop_session_event(
+            &mut behaviour,
+            &peer_id,
+            rejected_inbound_session_id.into(),
+        )
+        .await;
+        let event = behaviour.next().await.unwrap();
+        assert_matches!(
+            event,
+            ToSwarm::GenerateEvent(Event::External(ExternalEvent::SessionFailed {
+                session_id,
+                error: SessionError::TooManySessions,
+            })) if session_id == rejected_inbound_session_id.into()
+        );
+        validate_no_events(&mut behaviour);
+        simulate_session_dropped(&mut behaviour, peer_id, rejected_inbound_session_id.into());
+    }
+
+    // Finishing a class session frees a slot for a new class session.
+    let finished_session_id = InboundSessionId { value: 0 }.into();
+    simulate_session_finished_successfully(&mut behaviour, peer_id, finished_session_id);
+    validate_session_finished_successfully_event(&mut behaviour, finished_session_id).await;
+
+    let inbound_session_id = next_inbound_session_id.next().unwrap();
+    simulate_new_inbound_session_with_protocol(
+        &mut behaviour,
+        peer_id,
+        inbound_session_id,
+        QUERY.clone(),
+        class_protocol,
+    );
+    let event = behaviour.next().await.unwrap();
+    assert_matches!(
+        event,
+        ToSwarm::GenerateEvent(Event::External(ExternalEvent::NewInboundSession {
+            inbound_session_id: event_inbound_session_id,
+            ..
+        })) if event_inbound_session_id == inbound_session_id
+    );
+    validate_no_events(&mut behaviour);
+}
+
+#[tokio::test]
+async fn max_concurrent_inbound_sessions_enforced_per_peer() {
+    const MAX_CONCURRENT_INBOUND_SESSIONS_PER_PEER: usize = 3;
+
+    let mut behaviour = Behaviour::new(Config {
+        max_concurrent_inbound_sessions_per_peer: Some(MAX_CONCURRENT_INBOUND_SESSIONS_PER_PEER),
+        ..Config::get_test_config()
+    });
+
+    let peer_id = PeerId::random();
+    simulate_listener_connection(&mut behaviour, peer_id);
+
+    let inbound_session_ids = (0..MAX_CONCURRENT_INBOUND_SESSIONS_PER_PEER)
+        .map(|value| InboundSessionId { value })
+        .collect::<Vec<_>>();
+    for inbound_session_id in &inbound_session_ids {
+        simulate_new_inbound_session(&mut behaviour, peer_id, *inbound_session_id, QUERY.clone());
+        validate_new_inbound_session_event(&mut behaviour, &peer_id, *inbound_session_id, &QUERY)
+            .await;
+    }
+
+    let rejected_inbound_session_id =
+        InboundSessionId { value: MAX_CONCURRENT_INBOUND_SESSIONS_PER_PEER };
+    simulate_new_inbound_session(
+        &mut behaviour,
+        peer_id,
+        rejected_inbound_session_id,
+        QUERY.clone(),
+    );
+    validate_request_drop_session_event(
+        &mut behaviour,
+        &peer_id,
+        rejected_inbound_session_id.into(),
+    )
+    .await;
+    let event = behaviour.next().await.unwrap();
+    assert_matches!(
+        event,
+        ToSwarm::GenerateEvent(Event::External(ExternalEvent::SessionFailed {
+            session_id,
+            error: SessionError::TooManySessions,
+        })) if session_id == rejected_inbound_session_id.into()
+    );
+    validate_no_events(&mut behaviour);
+    simulate_session_dropped(&mut behaviour, peer_id, rejected_inbound_session_id.into());
+
+    // The sessions that were opened before the limit was reached keep working.
+    let response = dummy_data().remove(0);
+    for inbound_session_id in inbound_session_ids {
+        behaviour.send_response(response.clone(), inbound_session_id).unwrap();
+        validate_request_send_response_event(
+            &mut behaviour,
+            &peer_id,
+            &response,
+            inbound_session_id,
+        )
+        .await;
+    }
+    validate_no_events(&mut behaviour);
+}
+
+// Opens an inbound session and returns whether the behaviour accepted it or rejected it for
+// exceeding the rate limit.
+async fn open_inbound_session_under_rate_limit(
+    behaviour: &mut Behaviour,
+    peer_id: PeerId,
+    inbound_session_id: InboundSessionId,
+) -> bool {
+    simulate_new_inbound_session(behaviour, peer_id, inbound_session_id, QUERY.clone());
+    let event = behaviour.next().await.unwrap();
+    if let ToSwarm::GenerateEvent(Event::External(ExternalEvent::NewInboundSession {
+        inbound_session_id: event_inbound_session_id,
+        ..
+    })) = event
+    {
+        assert_eq!(event_inbound_session_id, inbound_session_id);
+        return true;
+    }
+    let session_id = inbound_session_id.into();
+    assert_matches!(
+        event,
+        ToSwarm::NotifyHandler {
+            event: RequestFromBehaviourEvent::DropSession { session_id: event_session_id },
+            ..
+        } if event_session_id == session_id
+    );
+    let event = behaviour.next().await.unwrap();
+    assert_matches!(
+        event,
+        ToSwarm::GenerateEvent(Event::External(ExternalEvent::SessionFailed {
+            session_id: event_session_id,
+            error: SessionError::RateLimited,
+        })) if event_session_id == session_id
+    );
+    simulate_session_dropped(behaviour, peer_id, session_id);
+    false
+}
+
+#[tokio::test(start_paused = true)]
+async fn inbound_sessions_rate_limited_per_peer() {
+    const RATE_LIMIT: InboundSessionRateLimit =
+        InboundSessionRateLimit { sessions_per_second: 2, burst: 5 };
+    const N_SESSIONS: usize = 100;
+    // Twice the rate limit.
+    const INTERVAL: Duration = Duration::from_millis(250);
+
+    let mut behaviour = Behaviour::new(Config {
+        inbound_session_rate_limit_per_peer: Some(RATE_LIMIT),
+        ..Config::get_test_config()
+    });
+    let peer_id = PeerId::random();
+    simulate_listener_connection(&mut behaviour, peer_id);
+
+    let mut n_accepted = 0;
+    for value in 0..N_SESSIONS {
+        if open_inbound_session_under_rate_limit(
+            &mut behaviour,
+            peer_id,
+            InboundSessionId { value },
+        )
+        .await
+        {
+            n_accepted += 1;
+        }
+        tokio::time::advance(INTERVAL).await;
+    }
+    // The first sessions use up the burst, except for the half token that is refilled between
+    // each two sessions. From then on, every other session is accepted.
+    assert_eq!(n_accepted, 54);
+
+    // Another peer has its own bucket.
+    let other_peer_id = PeerId::random();
+    simulate_listener_connection(&mut behaviour, other_peer_id);
+    for value in N_SESSIONS..N_SESSIONS + 5 {
+        assert!(
+            open_inbound_session_under_rate_limit(
+                &mut behaviour,
+                other_peer_id,
+                InboundSessionId { value }
+            )
+            .await
+        );
+    }
+    validate_no_events(&mut behaviour);
+}
+
+#[tokio::test(start_paused = true)]
+async fn inbound_session_rate_limit_refills_and_resets_on_disconnect() {
+    const RATE_LIMIT: InboundSessionRateLimit =
+        InboundSessionRateLimit { sessions_per_second: 1, burst: 2 };
+
+    let mut behaviour = Behaviour::new(Config {
+        inbound_session_rate_limit_per_peer: Some(RATE_LIMIT),
+        ..Config::get_test_config()
+    });
+    let peer_id = PeerId::random();
+    simulate_listener_connection(&mut behaviour, peer_id);
+
+    let mut inbound_session_ids = (0..).map(|value| InboundSessionId { value });
+    let mut next_inbound_session_ids =
+        |n_sessions: usize| inbound_session_ids.by_ref().take(n_sessions).collect::<Vec<_>>();
+    for inbound_session_id in next_inbound_session_ids(2) {
+        assert!(
+            open_inbound_session_under_rate_limit(&mut behaviour, peer_id, inbound_session_id)
+                .await
+        );
+    }
+    let [inbound_session_id] = next_inbound_session_ids(1)[..] else { unreachable!() };
+    assert!(
+        !open_inbound_session_under_rate_limit(&mut behaviour, peer_id, inbound_session_id).await
+    );
+
+    // The bucket refills over time, up to the burst.
+    tokio::time::advance(Duration::from_secs(10)).await;
+    for inbound_session_id in next_inbound_session_ids(2) {
+        assert!(
+            open_inbound_session_under_rate_limit(&mut behaviour, peer_id, inbound_session_id)
+                .await
+        );
+    }
+    let [inbound_session_id] = next_inbound_session_ids(1)[..] else { unreachable!() };
+    assert!(
+        !open_inbound_session_under_rate_limit(&mut behaviour, peer_id, inbound_session_id).await
+    );
+
+    // The bucket of a disconnected peer is removed, so it starts full when the peer reconnects.
+    simulate_connection_closed(&mut behaviour, peer_id);
+    while behaviour.next().now_or_never().is_some() {}
+    simulate_listener_connection(&mut behaviour, peer_id);
+    for inbound_session_id in next_inbound_session_ids(2) {
+        assert!(
+            open_inbound_session_under_rate_limit(&mut behaviour, peer_id, inbound_session_id)
+                .await
+        );
+    }
+    validate_no_events(&mut behaviour);
+}
+
 #[test]
 fn close_non_existing_session_fails() {
     let mut behaviour = Behaviour::new(Config::get_test_config());
@@ -473,5 +867,71 @@ fn send_query_peer_not_connected_fails() {
 
     let peer_id = PeerId::random();
 
-    behaviour.send_query(QUERY.clone(), peer_id, PROTOCOL_NAME.clone()).unwrap_err();
+    behaviour.send_query(QUERY.clone(), peer_id, vec![PROTOCOL_NAME.clone()]).unwrap_err();
+}
+
+#[tokio::test]
+async fn closed_sessions_are_recorded_in_history() {
+    let mut behaviour = Behaviour::new(Config::get_test_config());
+
+    let peer_id = PeerId::random();
+    simulate_connection_established(&mut behaviour, peer_id);
+
+    let mut outbound_session_ids = Vec::new();
+    for _ in 0..3 {
+        outbound_session_ids.push(
+            behaviour
+                .send_query(QUERY.clone(), peer_id, vec![PROTOCOL_NAME.clone()])
+                .unwrap()
+                .into(),
+        );
+    }
+    let inbound_session_ids: Vec<SessionId> =
+        vec![InboundSessionId { value: 0 }.into(), InboundSessionId { value: 1 }.into()];
+    for session_id in &inbound_session_ids {
+        let SessionId::InboundSessionId(inbound_session_id) = session_id else {
+            unreachable!();
+        };
+        simulate_new_inbound_session(&mut behaviour, peer_id, *inbound_session_id, QUERY.clone());
+    }
+
+    let session_timeout = Duration::from_secs(1);
+    simulate_session_finished_successfully(&mut behaviour, peer_id, outbound_session_ids[0]);
+    simulate_session_failed(
+        &mut behaviour,
+        peer_id,
+        outbound_session_ids[1],
+        HandlerSessionError::Timeout { session_timeout },
+    );
+    behaviour.drop_session(outbound_session_ids[2]).unwrap();
+    simulate_session_failed(
+        &mut behaviour,
+        peer_id,
+        outbound_session_ids[2],
+        HandlerSessionError::IOError(io::ErrorKind::BrokenPipe.into()),
+    );
+    simulate_session_failed(
+        &mut behaviour,
+        peer_id,
+        inbound_session_ids[0],
+        HandlerSessionError::IOError(io::ErrorKind::ConnectionReset.into()),
+    );
+    simulate_connection_closed(&mut behaviour, peer_id);
+
+    let expected_history = [
+        (outbound_session_ids[0], SessionCloseReason::FinishedSuccessfully),
+        (outbound_session_ids[1], SessionCloseReason::Timeout { session_timeout }),
+        (outbound_session_ids[2], SessionCloseReason::Dropped),
+        (inbound_session_ids[0], SessionCloseReason::IOError(io::ErrorKind::ConnectionReset)),
+        (inbound_session_ids[1], SessionCloseReason::ConnectionClosed),
+    ]
+    .map(|(session_id, reason)| ClosedSession { session_id, peer_id, reason });
+    assert_eq!(behaviour.recent_session_history().cloned().collect::<Vec<_>>(), expected_history);
+
+    // Only the newest sessions are kept when the history is full.
+    behaviour.set_session_history_size(2);
+    assert_eq!(
+        behaviour.recent_session_history().cloned().collect::<Vec<_>>(),
+        expected_history[3..]
+    );
 }
@@ -9,7 +9,14 @@ use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
 use libp2p::{PeerId, StreamProtocol, Swarm};
 
 use super::behaviour::{Behaviour, Event, ExternalEvent};
-use super::{Bytes, Config, InboundSessionId, OutboundSessionId, SessionId};
+use super::{
+    Bytes,
+    Config,
+    InboundSessionId,
+    OutboundSessionId,
+    SessionId,
+    DEFAULT_MAX_MESSAGE_SIZE,
+};
 use crate::test_utils::create_fully_connected_swarms_stream;
 use crate::utils::StreamHashMap;
 
@@ -18,6 +25,8 @@ const NUM_MESSAGES_PER_SESSION: usize = 5;
 
 pub const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/example");
 pub const OTHER_PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/other");
+pub const PROTOCOL_NAME_V1: StreamProtocol = StreamProtocol::new("/example/1");
+pub const PROTOCOL_NAME_V2: StreamProtocol = StreamProtocol::new("/example/2");
 
 type SwarmEventAlias<BehaviourTrait> = SwarmEvent<<BehaviourTrait as NetworkBehaviour>::ToSwarm>;
 
@@ -70,7 +79,7 @@ fn send_query_and_update_map(
         .send_query(
             get_bytes_from_query_indices(outbound_peer_id, inbound_peer_id),
             inbound_peer_id,
-            PROTOCOL_NAME,
+            vec![PROTOCOL_NAME],
         )
         .unwrap();
     outbound_session_id_to_peer_id.insert((outbound_peer_id, outbound_session_id), inbound_peer_id);
@@ -139,10 +148,12 @@ fn check_received_response_event(
         outbound_session_id: _outbound_session_id,
         response,
         peer_id: inbound_peer_id,
+        protocol_name,
     }) = event
     else {
         panic!("Got unexpected event {:?} when expecting ReceivedResponse", event);
     };
+    assert_eq!(protocol_name, PROTOCOL_NAME);
     assert_eq!(
         outbound_session_id_to_peer_id[&(outbound_peer_id, _outbound_session_id)],
         inbound_peer_id
@@ -189,7 +200,13 @@ fn get_response_from_indices(peer_id1: PeerId, peer_id2: PeerId, message_index:
 #[tokio::test]
 async fn everyone_sends_to_everyone() {
     let mut swarms_stream = create_fully_connected_swarms_stream(NUM_PEERS, || {
-        let mut behaviour = Behaviour::new(Config { session_timeout: Duration::from_secs(5) });
+        let mut behaviour = Behaviour::new(Config {
+            session_timeout: Duration::from_secs(5),
+            deterministic_event_order: true,
+            max_concurrent_inbound_sessions_per_peer: None,
+            inbound_session_rate_limit_per_peer: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        });
         let supported_inbound_protocols = vec![PROTOCOL_NAME, OTHER_PROTOCOL_NAME];
         for protocol in supported_inbound_protocols {
             behaviour.add_new_supported_inbound_protocol(protocol);
@@ -260,3 +277,65 @@ async fn everyone_sends_to_everyone() {
     )
     .await;
 }
+
+#[tokio::test]
+async fn peers_negotiate_down_to_a_common_protocol_version() {
+    // Both peers serve only v1. The peer that sends the query prefers v2 but also offers v1.
+    let mut swarms_stream = create_fully_connected_swarms_stream(2, || {
+        let mut behaviour = Behaviour::new(Config {
+            session_timeout: Duration::from_secs(5),
+            deterministic_event_order: true,
+            max_concurrent_inbound_sessions_per_peer: None,
+            inbound_session_rate_limit_per_peer: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        });
+        behaviour.add_new_supported_inbound_protocol(PROTOCOL_NAME_V1);
+        behaviour
+    })
+    .await;
+    let peer_ids = swarms_stream.keys().copied().collect::<Vec<_>>();
+    let (v1_peer_id, v1_and_v2_peer_id) = (peer_ids[0], peer_ids[1]);
+
+    let query = get_bytes_from_query_indices(v1_and_v2_peer_id, v1_peer_id);
+    let outbound_session_id = swarms_stream
+        .get_mut(&v1_and_v2_peer_id)
+        .unwrap()
+        .behaviour_mut()
+        .send_query(query.clone(), v1_peer_id, vec![PROTOCOL_NAME_V2, PROTOCOL_NAME_V1])
+        .unwrap();
+
+    let response = get_response_from_indices(v1_peer_id, v1_and_v2_peer_id, 0);
+    loop {
+        let (peer_id, event) = swarms_stream.next().await.unwrap();
+        let SwarmEvent::Behaviour(Event::External(event)) = event else {
+            continue;
+        };
+        match event {
+            ExternalEvent::NewInboundSession {
+                query: received_query,
+                inbound_session_id,
+                protocol_name,
+                ..
+            } => {
+                assert_eq!(peer_id, v1_peer_id);
+                assert_eq!(received_query, query);
+                assert_eq!(protocol_name, PROTOCOL_NAME_V1);
+                let behaviour = swarms_stream.get_mut(&v1_peer_id).unwrap().behaviour_mut();
+                behaviour.send_response(response.clone(), inbound_session_id).unwrap();
+            }
+            ExternalEvent::ReceivedResponse {
+                outbound_session_id: received_outbound_session_id,
+                response: received_response,
+                protocol_name,
+                ..
+            } => {
+                assert_eq!(peer_id, v1_and_v2_peer_id);
+                assert_eq!(received_outbound_session_id, outbound_session_id);
+                assert_eq!(received_response, response);
+                assert_eq!(protocol_name, PROTOCOL_NAME_V1);
+                break;
+            }
+            _ => panic!("Got unexpected event {event:?}"),
+        }
+    }
+}
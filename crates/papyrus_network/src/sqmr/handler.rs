@@ -4,15 +4,19 @@ mod handler_test;
 mod inbound_session;
 
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::io;
+use std::pin::pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_stream::stream;
+use futures::future::Either;
 use futures::stream::BoxStream;
 use futures::{FutureExt, StreamExt};
+use futures_timer::Delay;
 use libp2p::swarm::handler::{
     ConnectionEvent,
     DialUpgradeError,
@@ -38,7 +42,7 @@ pub enum RequestFromBehaviourEvent {
     CreateOutboundSession {
         query: Bytes,
         outbound_session_id: OutboundSessionId,
-        protocol_name: StreamProtocol,
+        protocol_names: Vec<StreamProtocol>,
     },
     SendResponse {
         response: Bytes,
@@ -66,6 +70,24 @@ pub enum SessionError {
     IOError(#[from] io::Error),
     #[error("Remote peer doesn't support the given protocol.")]
     RemoteDoesntSupportProtocol,
+    #[error("Remote peer sent a malformed message: {0}")]
+    MalformedMessage(io::Error),
+    #[error("Remote peer closed the session in the middle of a message.")]
+    RemoteClosed,
+}
+
+impl SessionError {
+    // Tells apart the errors of reading a message that were caused by what the remote peer sent.
+    fn from_read_error(error: Self) -> Self {
+        let Self::IOError(error) = error else {
+            return error;
+        };
+        match error.kind() {
+            io::ErrorKind::InvalidData => Self::MalformedMessage(error),
+            io::ErrorKind::UnexpectedEof => Self::RemoteClosed,
+            _ => Self::IOError(error),
+        }
+    }
 }
 
 type HandlerEvent<H> = ConnectionHandlerEvent<
@@ -74,14 +96,16 @@ type HandlerEvent<H> = ConnectionHandlerEvent<
     <H as ConnectionHandler>::ToBehaviour,
 >;
 
+// The responses of an outbound session, each with the protocol version negotiated for the session.
+type OutboundSession = BoxStream<'static, Result<(Bytes, StreamProtocol), SessionError>>;
+
 pub struct Handler {
     // TODO(shahak): Consider changing to Arc<Config> if the config becomes heavy to clone.
     config: Config,
     next_inbound_session_id: Arc<AtomicUsize>,
     peer_id: PeerId,
     id_to_inbound_session: HashMap<InboundSessionId, InboundSession>,
-    id_to_outbound_session:
-        HashMap<OutboundSessionId, BoxStream<'static, Result<Bytes, io::Error>>>,
+    id_to_outbound_session: HashMap<OutboundSessionId, OutboundSession>,
     // TODO(shahak): Use deadqueue if using a VecDeque is a bug (libp2p uses VecDeque, so we opened
     // an issue on it https://github.com/libp2p/rust-libp2p/issues/5147)
     pending_events: VecDeque<HandlerEvent<Self>>,
@@ -120,13 +144,13 @@ impl Handler {
         cx: &mut Context<'_>,
     ) -> bool {
         match inbound_session.poll_unpin(cx) {
-            Poll::Ready(Err(io_error)) => {
+            Poll::Ready(Err(session_error)) => {
                 // No need to wake those waiting for pending events because this function is called
                 // inside `poll`.
                 pending_events.push_back(ConnectionHandlerEvent::NotifyBehaviour(
                     RequestToBehaviourEvent::GenerateEvent(GenericEvent::SessionFailed {
                         session_id: inbound_session_id.into(),
-                        error: SessionError::IOError(io_error),
+                        error: session_error,
                     }),
                 ));
                 true
@@ -146,6 +170,75 @@ impl Handler {
             Poll::Pending => false,
         }
     }
+
+    /// Poll an inbound session, closing it if the behaviour asked to, and return whether the
+    /// inbound session is still alive.
+    fn poll_inbound_session_and_check_alive(
+        inbound_session: &mut InboundSession,
+        inbound_session_id: InboundSessionId,
+        inbound_sessions_marked_to_end: &HashSet<InboundSessionId>,
+        pending_events: &mut VecDeque<HandlerEvent<Self>>,
+        cx: &mut Context<'_>,
+    ) -> bool {
+        if Self::poll_inbound_session(inbound_session, inbound_session_id, pending_events, cx) {
+            let is_session_alive = false;
+            return is_session_alive;
+        }
+        if inbound_sessions_marked_to_end.contains(&inbound_session_id)
+            && inbound_session.is_waiting()
+        {
+            inbound_session.start_closing();
+            if Self::poll_inbound_session(inbound_session, inbound_session_id, pending_events, cx) {
+                let is_session_alive = false;
+                return is_session_alive;
+            }
+        }
+        true
+    }
+
+    /// Poll an outbound session, inserting any events needed to pending_events, and return whether
+    /// the outbound session is still alive.
+    fn poll_outbound_session_and_check_alive(
+        outbound_session: &mut OutboundSession,
+        outbound_session_id: OutboundSessionId,
+        peer_id: PeerId,
+        pending_events: &mut VecDeque<HandlerEvent<Self>>,
+        cx: &mut Context<'_>,
+    ) -> bool {
+        match outbound_session.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok((response, protocol_name)))) => {
+                pending_events.push_back(ConnectionHandlerEvent::NotifyBehaviour(
+                    RequestToBehaviourEvent::GenerateEvent(GenericEvent::ReceivedResponse {
+                        outbound_session_id,
+                        response,
+                        peer_id,
+                        protocol_name,
+                    }),
+                ));
+                true
+            }
+            Poll::Ready(Some(Err(session_error))) => {
+                pending_events.push_back(ConnectionHandlerEvent::NotifyBehaviour(
+                    RequestToBehaviourEvent::GenerateEvent(GenericEvent::SessionFailed {
+                        session_id: SessionId::OutboundSessionId(outbound_session_id),
+                        error: session_error,
+                    }),
+                ));
+                false
+            }
+            Poll::Ready(None) => {
+                pending_events.push_back(ConnectionHandlerEvent::NotifyBehaviour(
+                    RequestToBehaviourEvent::GenerateEvent(
+                        GenericEvent::SessionFinishedSuccessfully {
+                            session_id: SessionId::OutboundSessionId(outbound_session_id),
+                        },
+                    ),
+                ));
+                false
+            }
+            Poll::Pending => true,
+        }
+    }
 }
 
 impl ConnectionHandler for Handler {
@@ -173,68 +266,42 @@ impl ConnectionHandler for Handler {
         ConnectionHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::ToBehaviour>,
     > {
         // Handle inbound sessions.
-        self.id_to_inbound_session.retain(|inbound_session_id, inbound_session| {
-            if Self::poll_inbound_session(
+        for inbound_session_id in
+            session_ids_to_poll(&self.id_to_inbound_session, self.config.deterministic_event_order)
+        {
+            let inbound_session = self
+                .id_to_inbound_session
+                .get_mut(&inbound_session_id)
+                .expect("Inbound session was removed while iterating over the sessions.");
+            if !Self::poll_inbound_session_and_check_alive(
                 inbound_session,
-                *inbound_session_id,
+                inbound_session_id,
+                &self.inbound_sessions_marked_to_end,
                 &mut self.pending_events,
                 cx,
             ) {
-                let is_session_alive = false;
-                return is_session_alive;
-            }
-            if self.inbound_sessions_marked_to_end.contains(inbound_session_id)
-                && inbound_session.is_waiting()
-            {
-                inbound_session.start_closing();
-                if Self::poll_inbound_session(
-                    inbound_session,
-                    *inbound_session_id,
-                    &mut self.pending_events,
-                    cx,
-                ) {
-                    let is_session_alive = false;
-                    return is_session_alive;
-                }
+                self.id_to_inbound_session.remove(&inbound_session_id);
             }
-            true
-        });
+        }
 
         // Handle outbound sessions.
-        self.id_to_outbound_session.retain(|outbound_session_id, outbound_session| {
-            match outbound_session.poll_next_unpin(cx) {
-                Poll::Ready(Some(Ok(response))) => {
-                    self.pending_events.push_back(ConnectionHandlerEvent::NotifyBehaviour(
-                        RequestToBehaviourEvent::GenerateEvent(GenericEvent::ReceivedResponse {
-                            outbound_session_id: *outbound_session_id,
-                            response,
-                            peer_id: self.peer_id,
-                        }),
-                    ));
-                    true
-                }
-                Poll::Ready(Some(Err(io_error))) => {
-                    self.pending_events.push_back(ConnectionHandlerEvent::NotifyBehaviour(
-                        RequestToBehaviourEvent::GenerateEvent(GenericEvent::SessionFailed {
-                            session_id: SessionId::OutboundSessionId(*outbound_session_id),
-                            error: SessionError::IOError(io_error),
-                        }),
-                    ));
-                    false
-                }
-                Poll::Ready(None) => {
-                    self.pending_events.push_back(ConnectionHandlerEvent::NotifyBehaviour(
-                        RequestToBehaviourEvent::GenerateEvent(
-                            GenericEvent::SessionFinishedSuccessfully {
-                                session_id: SessionId::OutboundSessionId(*outbound_session_id),
-                            },
-                        ),
-                    ));
-                    false
-                }
-                Poll::Pending => true,
+        for outbound_session_id in
+            session_ids_to_poll(&self.id_to_outbound_session, self.config.deterministic_event_order)
+        {
+            let outbound_session = self
+                .id_to_outbound_session
+                .get_mut(&outbound_session_id)
+                .expect("Outbound session was removed while iterating over the sessions.");
+            if !Self::poll_outbound_session_and_check_alive(
+                outbound_session,
+                outbound_session_id,
+                self.peer_id,
+                &mut self.pending_events,
+                cx,
+            ) {
+                self.id_to_outbound_session.remove(&outbound_session_id);
             }
-        });
+        }
 
         // Handling pending_events at the end of the function to avoid starvation and to make sure
         // we don't return Pending if the code above created an event.
@@ -249,7 +316,7 @@ impl ConnectionHandler for Handler {
             RequestFromBehaviourEvent::CreateOutboundSession {
                 query,
                 outbound_session_id,
-                protocol_name,
+                protocol_names,
             } => {
                 // TODO(shahak) Consider extracting to a utility function to prevent forgetfulness
                 // of the timeout.
@@ -258,7 +325,7 @@ impl ConnectionHandler for Handler {
                 // on_behaviour_event. See https://github.com/libp2p/rust-libp2p/issues/5147
                 self.pending_events.push_back(ConnectionHandlerEvent::OutboundSubstreamRequest {
                     protocol: SubstreamProtocol::new(
-                        OutboundProtocol { query, protocol_name },
+                        OutboundProtocol { query, protocol_names },
                         outbound_session_id,
                     )
                     .with_timeout(self.config.session_timeout),
@@ -331,21 +398,29 @@ impl ConnectionHandler for Handler {
     ) {
         match event {
             ConnectionEvent::FullyNegotiatedOutbound(FullyNegotiatedOutbound {
-                protocol: mut read_stream,
+                protocol: (mut read_stream, protocol_name),
                 info: outbound_session_id,
             }) => {
                 if self.dropped_outbound_sessions_non_negotiated.remove(&outbound_session_id) {
                     return;
                 }
+                let session_timeout = self.config.session_timeout;
+                let max_message_size = self.config.max_message_size;
                 self.id_to_outbound_session.insert(
                     outbound_session_id,
                     stream! {
                         loop {
-                            let result_opt = read_message(&mut read_stream).await;
+                            // The timeout restarts on each message, so that only a session that
+                            // stopped progressing times out.
+                            let result_opt = with_timeout(
+                                session_timeout,
+                                read_message(&mut read_stream, max_message_size),
+                            )
+                            .await;
                             let result = match result_opt {
-                                Ok(Some(response)) => Ok(response),
+                                Ok(Some(response)) => Ok((response, protocol_name.clone())),
                                 Ok(None) => break,
-                                Err(error) => Err(error),
+                                Err(error) => Err(SessionError::from_read_error(error)),
                             };
                             let is_err = result.is_err();
                             yield result;
@@ -371,8 +446,10 @@ impl ConnectionHandler for Handler {
                         protocol_name,
                     }),
                 ));
-                self.id_to_inbound_session
-                    .insert(inbound_session_id, InboundSession::new(write_stream));
+                self.id_to_inbound_session.insert(
+                    inbound_session_id,
+                    InboundSession::new(write_stream, self.config.session_timeout),
+                );
             }
             ConnectionEvent::DialUpgradeError(DialUpgradeError {
                 info: outbound_session_id,
@@ -405,3 +482,33 @@ impl ConnectionHandler for Handler {
         }
     }
 }
+
+/// Returns the ids of the given sessions in the order they should be polled. With a deterministic
+/// event order, the sessions are polled in the order they were opened so that their events are
+/// emitted in a stable order.
+fn session_ids_to_poll<Id: Copy + Ord, Session>(
+    id_to_session: &HashMap<Id, Session>,
+    deterministic_event_order: bool,
+) -> Vec<Id> {
+    let mut session_ids = id_to_session.keys().copied().collect::<Vec<_>>();
+    if deterministic_event_order {
+        session_ids.sort();
+    }
+    session_ids
+}
+
+/// Fails with a timeout error if the given future doesn't finish within the session timeout.
+// Handlers may be polled outside of a tokio runtime, so a runtime agnostic timer is used.
+async fn with_timeout<T>(
+    session_timeout: Duration,
+    future: impl Future<Output = Result<T, io::Error>>,
+) -> Result<T, SessionError> {
+    // A timeout too long to be represented as a deadline (e.g Duration::MAX) never elapses.
+    if Instant::now().checked_add(session_timeout).is_none() {
+        return Ok(future.await?);
+    }
+    match futures::future::select(pin!(future), Delay::new(session_timeout)).await {
+        Either::Left((result, _)) => Ok(result?),
+        Either::Right(_) => Err(SessionError::Timeout { session_timeout }),
+    }
+}
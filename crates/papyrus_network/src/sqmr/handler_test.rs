@@ -3,6 +3,7 @@ use std::io;
 use std::pin::Pin;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
+use std::time::Duration;
 
 use assert_matches::assert_matches;
 use futures::task::{Context, Poll};
@@ -18,7 +19,15 @@ use libp2p::swarm::{ConnectionHandler, ConnectionHandlerEvent, Stream, StreamUpg
 use libp2p::{PeerId, StreamProtocol};
 
 use super::super::messages::{read_message, write_message};
-use super::super::{Bytes, Config, GenericEvent, InboundSessionId, OutboundSessionId, SessionId};
+use super::super::{
+    Bytes,
+    Config,
+    GenericEvent,
+    InboundSessionId,
+    OutboundSessionId,
+    SessionId,
+    DEFAULT_MAX_MESSAGE_SIZE,
+};
 use super::{
     Handler,
     HandlerEvent,
@@ -67,7 +76,7 @@ fn simulate_request_to_send_query_from_swarm(
     handler.on_behaviour_event(RequestFromBehaviourEvent::CreateOutboundSession {
         query,
         outbound_session_id,
-        protocol_name: PROTOCOL_NAME.clone(),
+        protocol_names: vec![PROTOCOL_NAME.clone()],
     });
 }
 
@@ -101,7 +110,10 @@ fn simulate_negotiated_outbound_session_from_swarm(
     outbound_session_id: OutboundSessionId,
 ) {
     handler.on_connection_event(ConnectionEvent::FullyNegotiatedOutbound(
-        FullyNegotiatedOutbound { protocol: outbound_stream.split().0, info: outbound_session_id },
+        FullyNegotiatedOutbound {
+            protocol: (outbound_stream.split().0, PROTOCOL_NAME.clone()),
+            info: outbound_session_id,
+        },
     ));
 }
 
@@ -151,11 +163,12 @@ async fn validate_received_response_event(
         ConnectionHandlerEvent::NotifyBehaviour(
             RequestToBehaviourEvent::GenerateEvent(
                 GenericEvent::ReceivedResponse {
-                    response: event_response, outbound_session_id: event_outbound_session_id, peer_id : event_peer_id
-
+                    response: event_response, outbound_session_id: event_outbound_session_id, peer_id : event_peer_id,
+                    protocol_name
                 }
             )
         ) if event_response == *response &&  event_outbound_session_id == outbound_session_id && event_peer_id == handler.peer_id
+            && protocol_name == PROTOCOL_NAME.clone()
     );
 }
 
@@ -222,7 +235,7 @@ async fn read_messages(handler: Handler, stream: &mut Stream, num_messages: usiz
     async fn read_messages_inner(stream: &mut Stream, num_messages: usize) -> Vec<Bytes> {
         let mut result = Vec::new();
         for _ in 0..num_messages {
-            match read_message(&mut *stream).await.unwrap() {
+            match read_message(&mut *stream, DEFAULT_MAX_MESSAGE_SIZE).await.unwrap() {
                 Some(message) => result.push(message),
                 None => return result,
             }
@@ -375,6 +388,53 @@ async fn process_outbound_session() {
     validate_session_finished_successfully_event(&mut handler, outbound_session_id.into()).await;
 }
 
+#[tokio::test]
+async fn outbound_session_without_progress_times_out() {
+    const SESSION_TIMEOUT: Duration = Duration::from_millis(500);
+    let mut handler = Handler::new(
+        Config { session_timeout: SESSION_TIMEOUT, ..Config::get_test_config() },
+        Arc::new(Default::default()),
+        PeerId::random(),
+        Handler::get_test_supported_protocols(),
+    );
+
+    let (mut inbound_stream, outbound_stream, _) = get_connected_streams().await;
+    let outbound_session_id = OutboundSessionId { value: 1 };
+    simulate_negotiated_outbound_session_from_swarm(
+        &mut handler,
+        outbound_stream,
+        outbound_session_id,
+    );
+
+    // The responses take longer than the timeout in total, but the session shouldn't time out
+    // because there's progress within each timeout.
+    let dummy_data_vec = dummy_data();
+    let dummy_data_vec_clone = dummy_data_vec.clone();
+    let _stalled_stream = tokio::spawn(async move {
+        for response in &dummy_data_vec_clone {
+            tokio::time::sleep(SESSION_TIMEOUT / 2).await;
+            write_message(response, &mut inbound_stream).await.unwrap();
+        }
+        // Returning the stream so that it won't be closed.
+        inbound_stream
+    });
+
+    tokio::time::timeout(SESSION_TIMEOUT * 10, async {
+        for response in &dummy_data_vec {
+            validate_received_response_event(&mut handler, response, outbound_session_id).await;
+        }
+        validate_session_failed_event(&mut handler, outbound_session_id.into(), |error| {
+            matches!(
+                error,
+                SessionError::Timeout { session_timeout } if *session_timeout == SESSION_TIMEOUT
+            )
+        })
+        .await;
+    })
+    .await
+    .unwrap();
+}
+
 // Extracting to a function because two closures have different types.
 async fn test_outbound_session_negotiation_failure(
     upgrade_error: StreamUpgradeError<io::Error>,
@@ -394,7 +454,6 @@ async fn test_outbound_session_negotiation_failure(
     validate_no_events(&mut handler);
 }
 
-// TODO(shahak): Add tests where session fails after negotiation.
 #[tokio::test]
 async fn outbound_session_negotiation_failure() {
     let error_kind = io::ErrorKind::UnexpectedEof;
@@ -443,6 +502,94 @@ async fn outbound_session_negotiation_failure() {
     .await;
 }
 
+// Writes the given bytes as the response of an outbound session, and checks that the session
+// fails with an error that matches the given matcher.
+async fn test_outbound_session_failure_on_response(
+    response: &[u8],
+    session_error_matcher: impl FnOnce(&SessionError) -> bool,
+    config: Config,
+) {
+    let mut handler = Handler::new(
+        config,
+        Arc::new(Default::default()),
+        PeerId::random(),
+        Handler::get_test_supported_protocols(),
+    );
+
+    let (mut inbound_stream, outbound_stream, _) = get_connected_streams().await;
+    let outbound_session_id = OutboundSessionId { value: 1 };
+    simulate_negotiated_outbound_session_from_swarm(
+        &mut handler,
+        outbound_stream,
+        outbound_session_id,
+    );
+
+    inbound_stream.write_all(response).await.unwrap();
+    inbound_stream.close().await.unwrap();
+    validate_session_failed_event(&mut handler, outbound_session_id.into(), session_error_matcher)
+        .await;
+    validate_no_events(&mut handler);
+}
+
+#[tokio::test]
+async fn outbound_session_fails_on_malformed_message() {
+    // Allocating a message of this length would abort the test.
+    let mut length_prefix = unsigned_varint::encode::usize_buffer();
+    let length_prefix = unsigned_varint::encode::usize(usize::MAX, &mut length_prefix);
+    test_outbound_session_failure_on_response(
+        length_prefix,
+        |session_error| {
+            matches!(
+                session_error,
+                SessionError::MalformedMessage(error) if error.kind() == io::ErrorKind::InvalidData
+            )
+        },
+        Config::get_test_config(),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn outbound_session_fails_on_message_longer_than_max_message_size() {
+    const MAX_MESSAGE_SIZE: usize = 3;
+    let mut handler = Handler::new(
+        Config { max_message_size: MAX_MESSAGE_SIZE, ..Config::get_test_config() },
+        Arc::new(Default::default()),
+        PeerId::random(),
+        Handler::get_test_supported_protocols(),
+    );
+    let (mut inbound_stream, outbound_stream, _) = get_connected_streams().await;
+    let outbound_session_id = OutboundSessionId { value: 1 };
+    simulate_negotiated_outbound_session_from_swarm(
+        &mut handler,
+        outbound_stream,
+        outbound_session_id,
+    );
+
+    // A message of the maximal size is received.
+    let response = vec![1u8; MAX_MESSAGE_SIZE];
+    write_message(&response, &mut inbound_stream).await.unwrap();
+    validate_received_response_event(&mut handler, &response, outbound_session_id).await;
+
+    write_message(&vec![1u8; MAX_MESSAGE_SIZE + 1], &mut inbound_stream).await.unwrap();
+    validate_session_failed_event(&mut handler, outbound_session_id.into(), |session_error| {
+        matches!(session_error, SessionError::MalformedMessage(_))
+    })
+    .await;
+    validate_no_events(&mut handler);
+}
+
+#[tokio::test]
+async fn outbound_session_fails_when_remote_closes_in_the_middle_of_a_message() {
+    // A length prefix of 10 bytes, followed by only 3 bytes.
+    test_outbound_session_failure_on_response(
+        &[10, 1, 2, 3],
+        |session_error| matches!(session_error, SessionError::RemoteClosed),
+        Config::get_test_config(),
+    )
+    .await;
+}
+
 #[tokio::test]
 async fn outbound_session_dropped_after_negotiation() {
     let mut handler = Handler::new(
@@ -545,7 +692,7 @@ async fn inbound_session_dropped() {
     tokio::time::sleep(std::time::Duration::from_millis(10)).await;
 
     // A dropped inbound session will return EOF.
-    assert!(read_message(&mut outbound_stream).await.unwrap().is_none());
+    assert!(read_message(&mut outbound_stream, DEFAULT_MAX_MESSAGE_SIZE).await.unwrap().is_none());
 
     // Need to sleep to make sure that if we did send a message the stream inside the handle will
     // receive it
@@ -553,3 +700,44 @@ async fn inbound_session_dropped() {
 
     validate_no_events(&mut handler);
 }
+
+#[tokio::test]
+async fn deterministic_event_order_emits_events_in_session_open_order() {
+    const NUM_SESSIONS: usize = 10;
+    let mut handler = Handler::new(
+        Config { deterministic_event_order: true, ..Config::get_test_config() },
+        Arc::new(Default::default()),
+        PeerId::random(),
+        Handler::get_test_supported_protocols(),
+    );
+
+    // Insert the sessions directly so that all of their responses are ready at the same poll.
+    // Inserting them in reverse order makes sure the order doesn't come from the insertion order.
+    for value in (0..NUM_SESSIONS).rev() {
+        handler.id_to_outbound_session.insert(
+            OutboundSessionId { value },
+            futures::stream::iter([Ok((
+                vec![u8::try_from(value).unwrap()],
+                PROTOCOL_NAME.clone(),
+            ))])
+            .boxed(),
+        );
+    }
+
+    for value in 0..NUM_SESSIONS {
+        validate_received_response_event(
+            &mut handler,
+            &vec![u8::try_from(value).unwrap()],
+            OutboundSessionId { value },
+        )
+        .await;
+    }
+    for value in 0..NUM_SESSIONS {
+        validate_session_finished_successfully_event(
+            &mut handler,
+            OutboundSessionId { value }.into(),
+        )
+        .await;
+    }
+    validate_no_events(&mut handler);
+}
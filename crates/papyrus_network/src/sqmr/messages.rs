@@ -10,8 +10,6 @@ use unsigned_varint::encode::usize_buffer;
 
 use super::Bytes;
 
-pub const MAX_MESSAGE_SIZE: usize = 1 << 20;
-
 pub async fn write_message<Stream: AsyncWrite + Unpin>(
     message: &Bytes,
     io: &mut Stream,
@@ -33,17 +31,20 @@ pub async fn write_message_without_length_prefix<Stream: AsyncWrite + Unpin>(
     Ok(())
 }
 
+/// Reads a length prefixed message, failing with [`io::ErrorKind::InvalidData`] if its length
+/// prefix exceeds `max_message_size`. The length is checked before the message is allocated.
 pub async fn read_message<Stream: AsyncRead + Unpin>(
     io: &mut Stream,
+    max_message_size: usize,
 ) -> Result<Option<Bytes>, io::Error> {
     // This code is based on read_length_prefixed from libp2p v0.52 which was erased in v0.53.
     let Some(message_len) = read_usize(io).await? else { return Ok(None) };
-    if message_len > MAX_MESSAGE_SIZE {
+    if message_len > max_message_size {
         return Err(io::Error::new(
             io::ErrorKind::InvalidData,
             format!(
                 "Received message of size ({message_len} bytes), which exceeds maximum \
-                 ({MAX_MESSAGE_SIZE} bytes)"
+                 ({max_message_size} bytes)"
             ),
         ));
     }
@@ -9,6 +9,7 @@ use super::{
     write_message,
     write_message_without_length_prefix,
 };
+use crate::sqmr::DEFAULT_MAX_MESSAGE_SIZE;
 use crate::test_utils::{dummy_data, get_connected_streams};
 
 #[tokio::test]
@@ -19,7 +20,10 @@ async fn read_write_positive_flow() {
         write_message(message, &mut stream1).await.unwrap();
     }
     for expected_message in &messages {
-        assert_eq!(*expected_message, read_message(&mut stream2).await.unwrap().unwrap());
+        assert_eq!(
+            *expected_message,
+            read_message(&mut stream2, DEFAULT_MAX_MESSAGE_SIZE).await.unwrap().unwrap()
+        );
     }
 }
 
@@ -37,13 +41,18 @@ async fn read_write_without_length_prefix_positive_flow() {
 async fn read_message_returns_none_when_other_stream_is_closed() {
     let (mut stream1, mut stream2, _) = get_connected_streams().await;
     stream1.close().await.unwrap();
-    assert!(read_message(&mut stream2).await.unwrap().is_none());
+    assert!(read_message(&mut stream2, DEFAULT_MAX_MESSAGE_SIZE).await.unwrap().is_none());
 }
 
 #[tokio::test]
 async fn read_message_is_pending_when_other_stream_didnt_send() {
     let (_stream1, mut stream2, _) = get_connected_streams().await;
     assert!(
-        tokio::time::timeout(Duration::from_millis(10), read_message(&mut stream2)).await.is_err()
+        tokio::time::timeout(
+            Duration::from_millis(10),
+            read_message(&mut stream2, DEFAULT_MAX_MESSAGE_SIZE)
+        )
+        .await
+        .is_err()
     );
 }
@@ -2,6 +2,7 @@ pub mod behaviour;
 pub mod handler;
 mod messages;
 pub mod protocol;
+mod token_bucket;
 
 #[cfg(test)]
 mod flow_test;
@@ -14,12 +15,15 @@ use libp2p::{PeerId, StreamProtocol};
 
 pub type Bytes = Vec<u8>;
 
-#[derive(Clone, Copy, Debug, Default, Display, Eq, Hash, PartialEq)]
+/// The default of [`Config::max_message_size`]. It's large enough for any block header.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, Default, Display, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct OutboundSessionId {
     pub value: usize,
 }
 
-#[derive(Clone, Copy, Debug, Default, Display, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Display, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct InboundSessionId {
     pub value: usize,
 }
@@ -54,6 +58,8 @@ pub enum GenericEvent<SessionError> {
         outbound_session_id: OutboundSessionId,
         response: Bytes,
         peer_id: PeerId,
+        /// The version of the protocol that was negotiated for the session.
+        protocol_name: StreamProtocol,
     },
     SessionFailed {
         session_id: SessionId,
@@ -64,7 +70,46 @@ pub enum GenericEvent<SessionError> {
     },
 }
 
-#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Config {
     pub session_timeout: Duration,
+    /// Poll the sessions of each connection in the order they were opened, so that events are
+    /// emitted in a stable order. Meant for tests.
+    pub deterministic_event_order: bool,
+    /// Maximal number of inbound sessions a single peer can have open at the same time. Inbound
+    /// sessions beyond it are dropped and reported as failed with
+    /// [`SessionError::TooManySessions`](behaviour::SessionError::TooManySessions). None means
+    /// there's no limit.
+    pub max_concurrent_inbound_sessions_per_peer: Option<usize>,
+    /// Limit on the rate at which a single peer can open inbound sessions. Inbound sessions beyond
+    /// it are dropped and reported as failed with
+    /// [`SessionError::RateLimited`](behaviour::SessionError::RateLimited). None means there's no
+    /// limit.
+    pub inbound_session_rate_limit_per_peer: Option<InboundSessionRateLimit>,
+    /// Maximal size in bytes of a message received in a session. A session in which the remote
+    /// peer sends a longer length prefix fails with
+    /// [`SessionError::MalformedMessage`](behaviour::SessionError::MalformedMessage), without
+    /// allocating the message.
+    pub max_message_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            session_timeout: Duration::default(),
+            deterministic_event_order: false,
+            max_concurrent_inbound_sessions_per_peer: None,
+            inbound_session_rate_limit_per_peer: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+/// A limit on the rate of inbound sessions, enforced with a token bucket for each peer.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct InboundSessionRateLimit {
+    /// The number of sessions a peer regains the right to open every second.
+    pub sessions_per_second: u32,
+    /// The number of sessions a peer that was idle for long enough can open at once.
+    pub burst: u32,
 }
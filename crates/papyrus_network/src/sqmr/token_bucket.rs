@@ -0,0 +1,38 @@
+use tokio::time::Instant;
+
+/// A token bucket that holds up to `capacity` tokens and is refilled at a constant rate. Taking a
+/// token from it allows a burst of `capacity` actions and then `tokens_per_second` actions per
+/// second.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    capacity: f64,
+    tokens_per_second: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    pub fn new(capacity: u32, tokens_per_second: u32) -> Self {
+        Self {
+            capacity: capacity.into(),
+            tokens_per_second: tokens_per_second.into(),
+            tokens: capacity.into(),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Refills the bucket for the time that passed since the last call and takes a token from it.
+    /// Returns false if the bucket is empty.
+    pub fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed_seconds = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed_seconds * self.tokens_per_second).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
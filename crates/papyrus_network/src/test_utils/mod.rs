@@ -47,7 +47,13 @@ pub(crate) fn dummy_data() -> Vec<Bytes> {
 
 impl crate::sqmr::Config {
     pub fn get_test_config() -> Self {
-        Self { session_timeout: Duration::MAX }
+        Self {
+            session_timeout: Duration::MAX,
+            deterministic_event_order: true,
+            max_concurrent_inbound_sessions_per_peer: None,
+            inbound_session_rate_limit_per_peer: None,
+            max_message_size: crate::sqmr::DEFAULT_MAX_MESSAGE_SIZE,
+        }
     }
 }
 // TODO(eitan): create a lazy static constant of SUPPORTED_PROTOCOLS which is this vec
@@ -0,0 +1,146 @@
+#[cfg(test)]
+#[path = "upgrade_limit_test.rs"]
+mod upgrade_limit_test;
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use libp2p::core::transport::{ListenerId, TransportError, TransportEvent};
+use libp2p::{Multiaddr, Transport};
+use metrics::gauge;
+use papyrus_common::metrics as papyrus_metrics;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits the number of inbound connections that are upgraded (secured and multiplexed) at the
+/// same time. Connections that arrive while the limit is reached wait until a running upgrade
+/// finishes.
+#[derive(Clone, Debug)]
+pub struct InboundUpgradeLimit {
+    semaphore: Arc<Semaphore>,
+    max_concurrent_upgrades: usize,
+}
+
+impl InboundUpgradeLimit {
+    pub fn new(max_concurrent_upgrades: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent_upgrades)),
+            max_concurrent_upgrades,
+        }
+    }
+
+    /// The number of inbound connections that are currently being upgraded. Connections that wait
+    /// for their upgrade to start aren't counted.
+    pub fn in_progress_upgrades(&self) -> usize {
+        self.max_concurrent_upgrades - self.semaphore.available_permits()
+    }
+
+    /// Wraps a transport so that the upgrades of its inbound connections obey this limit.
+    pub fn apply<T: Transport>(&self, transport: T) -> LimitedUpgradeTransport<T> {
+        LimitedUpgradeTransport { inner: transport, limit: self.clone() }
+    }
+
+    fn limit_upgrade<F>(&self, upgrade: F) -> BoxFuture<'static, F::Output>
+    where
+        F: futures::Future + Send + 'static,
+    {
+        let limit = self.clone();
+        async move {
+            let _in_progress_upgrade = InProgressUpgrade::new(limit).await;
+            upgrade.await
+        }
+        .boxed()
+    }
+}
+
+// Holds a permit of the limit for as long as the upgrade runs, including when the upgrade is
+// dropped before it finishes.
+struct InProgressUpgrade {
+    limit: InboundUpgradeLimit,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl InProgressUpgrade {
+    async fn new(limit: InboundUpgradeLimit) -> Self {
+        let permit = limit
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("The semaphore of the inbound upgrade limit is never closed.");
+        update_in_progress_upgrades_metric(&limit);
+        Self { limit, permit: Some(permit) }
+    }
+}
+
+impl Drop for InProgressUpgrade {
+    fn drop(&mut self) {
+        drop(self.permit.take());
+        update_in_progress_upgrades_metric(&self.limit);
+    }
+}
+
+fn update_in_progress_upgrades_metric(limit: &InboundUpgradeLimit) {
+    gauge!(
+        papyrus_metrics::PAPYRUS_NUM_IN_PROGRESS_INBOUND_UPGRADES,
+        limit.in_progress_upgrades() as f64
+    );
+}
+
+/// A transport whose inbound connection upgrades are limited by an [`InboundUpgradeLimit`].
+/// Outbound connections are passed through as is.
+pub struct LimitedUpgradeTransport<T> {
+    inner: T,
+    limit: InboundUpgradeLimit,
+}
+
+impl<T> Transport for LimitedUpgradeTransport<T>
+where
+    T: Transport + Unpin,
+    T::ListenerUpgrade: Send + 'static,
+{
+    type Output = T::Output;
+    type Error = T::Error;
+    type ListenerUpgrade = BoxFuture<'static, Result<T::Output, T::Error>>;
+    type Dial = T::Dial;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        self.inner.listen_on(id, addr)
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.inner.remove_listener(id)
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.inner.dial(addr)
+    }
+
+    fn dial_as_listener(
+        &mut self,
+        addr: Multiaddr,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.inner.dial_as_listener(addr)
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        let unpinned_self = Pin::into_inner(self);
+        let limit = &unpinned_self.limit;
+        Pin::new(&mut unpinned_self.inner)
+            .poll(cx)
+            .map(|event| event.map_upgrade(|upgrade| limit.limit_upgrade(upgrade)))
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::channel::oneshot;
+use futures::future::{poll_fn, BoxFuture};
+use futures::FutureExt;
+use libp2p::core::transport::{ListenerId, TransportError, TransportEvent};
+use libp2p::{Multiaddr, Transport};
+
+use super::InboundUpgradeLimit;
+
+const MAX_CONCURRENT_UPGRADES: usize = 2;
+const NUM_CONNECTIONS: usize = 5;
+
+// A transport that emits a given number of inbound connections whose upgrades don't finish until
+// the test releases them.
+#[derive(Default)]
+struct SlowUpgradeTransport {
+    pending_upgrades: VecDeque<BoxFuture<'static, Result<(), io::Error>>>,
+    running_upgrades: Arc<AtomicUsize>,
+    max_running_upgrades: Arc<AtomicUsize>,
+}
+
+impl SlowUpgradeTransport {
+    fn add_inbound_connection(&mut self) -> oneshot::Sender<()> {
+        let (release_sender, release_receiver) = oneshot::channel();
+        let running_upgrades = self.running_upgrades.clone();
+        let max_running_upgrades = self.max_running_upgrades.clone();
+        self.pending_upgrades.push_back(
+            async move {
+                let running = running_upgrades.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running_upgrades.fetch_max(running, Ordering::SeqCst);
+                release_receiver.await.expect("The test dropped the release sender.");
+                running_upgrades.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            }
+            .boxed(),
+        );
+        release_sender
+    }
+}
+
+impl Transport for SlowUpgradeTransport {
+    type Output = ();
+    type Error = io::Error;
+    type ListenerUpgrade = BoxFuture<'static, Result<(), io::Error>>;
+    type Dial = BoxFuture<'static, Result<(), io::Error>>;
+
+    fn listen_on(
+        &mut self,
+        _: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<io::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn remove_listener(&mut self, _: ListenerId) -> bool {
+        false
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<io::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn dial_as_listener(
+        &mut self,
+        addr: Multiaddr,
+    ) -> Result<Self::Dial, TransportError<io::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, io::Error>> {
+        match self.pending_upgrades.pop_front() {
+            Some(upgrade) => Poll::Ready(TransportEvent::Incoming {
+                listener_id: ListenerId::next(),
+                upgrade,
+                local_addr: Multiaddr::empty(),
+                send_back_addr: Multiaddr::empty(),
+            }),
+            None => Poll::Pending,
+        }
+    }
+
+    fn address_translation(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
+
+async fn let_upgrades_run() {
+    for _ in 0..NUM_CONNECTIONS * 2 {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[tokio::test]
+async fn inbound_upgrades_above_limit_wait_for_running_ones() {
+    let mut slow_transport = SlowUpgradeTransport::default();
+    let release_senders =
+        (0..NUM_CONNECTIONS).map(|_| slow_transport.add_inbound_connection()).collect::<Vec<_>>();
+    let running_upgrades = slow_transport.running_upgrades.clone();
+    let max_running_upgrades = slow_transport.max_running_upgrades.clone();
+
+    let limit = InboundUpgradeLimit::new(MAX_CONCURRENT_UPGRADES);
+    let mut transport = limit.apply(slow_transport);
+
+    let mut upgrade_handles = Vec::new();
+    for _ in 0..NUM_CONNECTIONS {
+        let TransportEvent::Incoming { upgrade, .. } =
+            poll_fn(|cx| Pin::new(&mut transport).poll(cx)).await
+        else {
+            panic!("Expected an inbound connection");
+        };
+        upgrade_handles.push(tokio::spawn(upgrade));
+    }
+    let_upgrades_run().await;
+    assert_eq!(limit.in_progress_upgrades(), MAX_CONCURRENT_UPGRADES);
+    assert_eq!(running_upgrades.load(Ordering::SeqCst), MAX_CONCURRENT_UPGRADES);
+
+    // Releasing an upgrade that didn't start yet makes it finish immediately once it starts.
+    for (i, release_sender) in release_senders.into_iter().enumerate() {
+        release_sender.send(()).unwrap();
+        let_upgrades_run().await;
+        let remaining_connections = NUM_CONNECTIONS - i - 1;
+        assert!(limit.in_progress_upgrades() <= remaining_connections.min(MAX_CONCURRENT_UPGRADES));
+    }
+    for upgrade_handle in upgrade_handles {
+        upgrade_handle.await.unwrap().unwrap();
+    }
+
+    assert_eq!(limit.in_progress_upgrades(), 0);
+    assert_eq!(max_running_upgrades.load(Ordering::SeqCst), MAX_CONCURRENT_UPGRADES);
+}
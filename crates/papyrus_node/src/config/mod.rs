@@ -29,6 +29,7 @@ use papyrus_consensus::config::ConsensusConfig;
 use papyrus_monitoring_gateway::MonitoringGatewayConfig;
 use papyrus_network::NetworkConfig;
 use papyrus_p2p_sync::client::{P2PSyncClient, P2PSyncClientConfig};
+use papyrus_p2p_sync::server::P2PSyncServerConfig;
 #[cfg(feature = "rpc")]
 use papyrus_rpc::RpcConfig;
 use papyrus_storage::db::DbConfig;
@@ -58,11 +59,13 @@ pub struct NodeConfig {
     #[validate]
     pub storage: StorageConfig,
     /// None if the syncing should be disabled.
+    #[validate]
     pub sync: Option<SyncConfig>,
     /// One of p2p_sync or sync must be None.
     /// If P2P sync is active, then network must be active too.
     // TODO(yair): Change NodeConfig to have an option of enum of SyncConfig or P2PSyncConfig.
     pub p2p_sync: Option<P2PSyncClientConfig>,
+    pub p2p_sync_server: P2PSyncServerConfig,
     pub consensus: Option<ConsensusConfig>,
     // TODO(shahak): Make network non-optional once it's developed enough.
     pub network: Option<NetworkConfig>,
@@ -81,6 +84,7 @@ impl Default for NodeConfig {
             storage: StorageConfig::default(),
             sync: Some(SyncConfig::default()),
             p2p_sync: None,
+            p2p_sync_server: P2PSyncServerConfig::default(),
             consensus: None,
             network: None,
             collect_profiling_metrics: false,
@@ -98,6 +102,7 @@ impl SerializeConfig for NodeConfig {
             append_sub_config_name(self.storage.dump(), "storage"),
             ser_optional_sub_config(&self.sync, "sync"),
             ser_optional_sub_config(&self.p2p_sync, "p2p_sync"),
+            append_sub_config_name(self.p2p_sync_server.dump(), "p2p_sync_server"),
             ser_optional_sub_config(&self.consensus, "consensus"),
             ser_optional_sub_config(&self.network, "network"),
             BTreeMap::from_iter([ser_param(
@@ -51,7 +51,12 @@ lazy_static! {
             &ChainId::Mainnet,
             "The chain to follow. For more details see https://docs.starknet.io/documentation/architecture_and_concepts/Blocks/transactions/#chain-id.",
         ),
-        vec!["storage.db_config.chain_id".to_owned(), "rpc.chain_id".to_owned()],
+        vec![
+            "storage.db_config.chain_id".to_owned(),
+            "rpc.chain_id".to_owned(),
+            "network.chain_id".to_owned(),
+            "p2p_sync_server.chain_id".to_owned(),
+        ],
     ),
     (
         ser_pointer_target_param(
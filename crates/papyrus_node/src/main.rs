@@ -195,6 +195,7 @@ async fn run_threads(config: NodeConfig) -> anyhow::Result<()> {
             event_server_channel,
         )) => {
             let p2p_sync_server = P2PSyncServer::new(
+                config.p2p_sync_server.clone(),
                 storage_reader.clone(),
                 header_server_channel,
                 state_diff_server_channel,
@@ -1,12 +1,33 @@
+use async_stream::stream;
+use futures::channel::oneshot;
 use futures::future::BoxFuture;
-use futures::{FutureExt, StreamExt};
-use papyrus_protobuf::sync::SignedBlockHeader;
+use futures::stream::BoxStream;
+use futures::{FutureExt, SinkExt, StreamExt};
+use papyrus_network::network_manager::SqmrClientPayload;
+use papyrus_protobuf::sync::{
+    BlockHashOrNumber,
+    DataOrFin,
+    Direction,
+    HeaderQuery,
+    Query,
+    SignedBlockHeader,
+};
 use papyrus_storage::header::{HeaderStorageReader, HeaderStorageWriter};
 use papyrus_storage::{StorageError, StorageReader, StorageWriter};
 use starknet_api::block::BlockNumber;
+use tracing::debug;
 
 use super::stream_builder::{BlockData, BlockNumberLimit, DataStreamBuilder};
-use super::{P2PSyncError, ResponseReceiver, ALLOWED_SIGNATURES_LENGTH, NETWORK_DATA_TIMEOUT};
+use super::{
+    HeaderPayloadSender,
+    P2PSyncError,
+    ResponseReceiver,
+    SyncResponse,
+    ALLOWED_SIGNATURES_LENGTH,
+    NETWORK_DATA_TIMEOUT,
+    STEP,
+};
+use crate::BUFFER_SIZE;
 
 impl BlockData for SignedBlockHeader {
     fn write_to_storage(
@@ -43,35 +64,123 @@ impl DataStreamBuilder<SignedBlockHeader> for HeaderStreamBuilder {
         block_number: BlockNumber,
         _storage_reader: &'a StorageReader,
     ) -> BoxFuture<'a, Result<Option<Self::Output>, P2PSyncError>> {
-        async move {
-            let maybe_signed_header =
-                tokio::time::timeout(NETWORK_DATA_TIMEOUT, signed_headers_receiver.next())
-                    .await?
-                    .ok_or(P2PSyncError::ReceiverChannelTerminated {
-                    type_description: Self::TYPE_DESCRIPTION,
-                })?;
-            let Some(signed_block_header) = maybe_signed_header?.0 else {
-                return Ok(None);
-            };
-            // TODO(shahak): Check that parent_hash is the same as the previous block's hash
-            // and handle reverts.
-            if block_number != signed_block_header.block_header.block_number {
-                return Err(P2PSyncError::HeadersUnordered {
-                    expected_block_number: block_number,
-                    actual_block_number: signed_block_header.block_header.block_number,
-                });
-            }
-            if signed_block_header.signatures.len() != ALLOWED_SIGNATURES_LENGTH {
-                return Err(P2PSyncError::WrongSignaturesLength {
-                    signatures: signed_block_header.signatures,
-                });
-            }
-            Ok(Some(signed_block_header))
-        }
-        .boxed()
+        parse_signed_header(signed_headers_receiver, block_number).boxed()
     }
 
     fn get_start_block_number(storage_reader: &StorageReader) -> Result<BlockNumber, StorageError> {
         storage_reader.begin_ro_txn()?.get_header_marker()
     }
 }
+
+async fn parse_signed_header(
+    signed_headers_receiver: &mut ResponseReceiver<SignedBlockHeader>,
+    block_number: BlockNumber,
+) -> Result<Option<SignedBlockHeader>, P2PSyncError> {
+    let maybe_signed_header =
+        tokio::time::timeout(NETWORK_DATA_TIMEOUT, signed_headers_receiver.next()).await?.ok_or(
+            P2PSyncError::ReceiverChannelTerminated {
+                type_description: HeaderStreamBuilder::TYPE_DESCRIPTION,
+            },
+        )?;
+    let Some(signed_block_header) = maybe_signed_header?.0 else {
+        return Ok(None);
+    };
+    // TODO(shahak): Check that parent_hash is the same as the previous block's hash
+    // and handle reverts.
+    if block_number != signed_block_header.block_header.block_number {
+        return Err(P2PSyncError::HeadersUnordered {
+            expected_block_number: block_number,
+            actual_block_number: signed_block_header.block_header.block_number,
+        });
+    }
+    if signed_block_header.signatures.len() != ALLOWED_SIGNATURES_LENGTH {
+        return Err(P2PSyncError::WrongSignaturesLength {
+            signatures: signed_block_header.signatures,
+        });
+    }
+    Ok(Some(signed_block_header))
+}
+
+/// A client for fetching signed block headers from peers without writing them to the storage.
+pub struct BlockHeaderClient {
+    payload_sender: HeaderPayloadSender,
+    num_headers_per_query: u64,
+}
+
+impl BlockHeaderClient {
+    pub fn new(payload_sender: HeaderPayloadSender, num_headers_per_query: u64) -> Self {
+        Self { payload_sender, num_headers_per_query }
+    }
+
+    /// Streams every header from `start` up to the tip of the chain.
+    ///
+    /// The tip is the point where a query returns fewer headers than requested. Since the tip may
+    /// advance while streaming, the client keeps re-querying from the next missing block and only
+    /// ends the stream once a query returns no new headers.
+    pub fn stream_from(
+        mut self,
+        start: BlockNumber,
+    ) -> BoxStream<'static, Result<SignedBlockHeader, P2PSyncError>> {
+        stream! {
+            let mut current_block_number = start;
+            loop {
+                let query_start_block_number = current_block_number;
+                let end_block_number = current_block_number.0 + self.num_headers_per_query;
+                debug!(
+                    "Requesting headers for blocks [{}, {})",
+                    current_block_number.0,
+                    end_block_number,
+                );
+                // TODO(shahak): Use the report callback.
+                let (_report_sender, report_receiver) = oneshot::channel::<()>();
+                let (responses_sender, responses_receiver) =
+                    futures::channel::mpsc::channel::<SyncResponse<SignedBlockHeader>>(BUFFER_SIZE);
+                let mut responses_receiver: ResponseReceiver<SignedBlockHeader> =
+                    Box::new(responses_receiver);
+                self.payload_sender
+                    .send(SqmrClientPayload {
+                        query: HeaderQuery(Query {
+                            start_block: BlockHashOrNumber::Number(current_block_number),
+                            direction: Direction::Forward,
+                            limit: self.num_headers_per_query,
+                            step: STEP,
+                        }),
+                        report_receiver,
+                        responses_sender: Box::new(responses_sender),
+                    })
+                    .await?;
+
+                let mut reached_tip = false;
+                while current_block_number.0 < end_block_number {
+                    let Some(signed_header) =
+                        parse_signed_header(&mut responses_receiver, current_block_number).await?
+                    else {
+                        reached_tip = true;
+                        break;
+                    };
+                    yield Ok(signed_header);
+                    current_block_number = current_block_number.unchecked_next();
+                }
+
+                if reached_tip {
+                    if current_block_number == query_start_block_number {
+                        debug!("Finished streaming headers at the tip {}.", current_block_number);
+                        return;
+                    }
+                    // The tip may have advanced since this query was answered.
+                    continue;
+                }
+
+                // Consume the None message signaling the end of the query.
+                match responses_receiver.next().await {
+                    Some(Ok(DataOrFin(None))) => {}
+                    Some(_) => Err(P2PSyncError::TooManyResponses)?,
+                    None => Err(P2PSyncError::ReceiverChannelTerminated {
+                        type_description: HeaderStreamBuilder::TYPE_DESCRIPTION,
+                    })?,
+                }
+            }
+        }
+        .boxed()
+    }
+}
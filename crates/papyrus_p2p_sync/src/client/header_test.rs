@@ -12,6 +12,7 @@ use papyrus_storage::header::HeaderStorageReader;
 use starknet_api::block::{BlockHeader, BlockNumber};
 use tokio::time::timeout;
 
+use super::header::BlockHeaderClient;
 use super::test_utils::{
     create_block_hashes_and_signatures,
     setup,
@@ -174,4 +175,71 @@ async fn sync_sends_new_header_query_if_it_got_partial_responses() {
     }
 }
 
+#[tokio::test]
+async fn stream_from_delivers_headers_when_tip_advances_mid_stream() {
+    const START_BLOCK_NUMBER: u64 = 1;
+    // The first query reaches the tip at this block and the second one catches up with the tip
+    // that advanced in the meantime. Both queries return less than HEADER_QUERY_LENGTH headers.
+    const INITIAL_TIP: u64 = 3;
+    const ADVANCED_TIP: u64 = 6;
+
+    let (header_payload_sender, mut header_payload_receiver) = futures::channel::mpsc::channel(1);
+    let header_client =
+        BlockHeaderClient::new(Box::new(header_payload_sender), HEADER_QUERY_LENGTH);
+    let block_hashes_and_signatures =
+        create_block_hashes_and_signatures(ADVANCED_TIP.try_into().unwrap());
+
+    let respond_to_queries_future = async move {
+        for (query_start, tip) in [
+            (START_BLOCK_NUMBER, INITIAL_TIP),
+            (INITIAL_TIP, ADVANCED_TIP),
+            (ADVANCED_TIP, ADVANCED_TIP),
+        ] {
+            let SqmrClientPayload {
+                query,
+                report_receiver: _report_receiver,
+                responses_sender: mut headers_sender,
+            } = header_payload_receiver.next().await.unwrap();
+            assert_eq!(
+                query,
+                HeaderQuery(Query {
+                    start_block: BlockHashOrNumber::Number(BlockNumber(query_start)),
+                    direction: Direction::Forward,
+                    limit: HEADER_QUERY_LENGTH,
+                    step: 1,
+                })
+            );
+            for block_number in query_start..tip {
+                let (block_hash, block_signature) =
+                    block_hashes_and_signatures[usize::try_from(block_number).unwrap()];
+                headers_sender
+                    .send(Ok(DataOrFin(Some(SignedBlockHeader {
+                        block_header: BlockHeader {
+                            block_number: BlockNumber(block_number),
+                            block_hash,
+                            ..Default::default()
+                        },
+                        signatures: vec![block_signature],
+                    }))))
+                    .await
+                    .unwrap();
+            }
+            headers_sender.send(Ok(DataOrFin(None))).await.unwrap();
+        }
+    };
+
+    let (_, headers) = tokio::join!(
+        respond_to_queries_future,
+        header_client.stream_from(BlockNumber(START_BLOCK_NUMBER)).collect::<Vec<_>>()
+    );
+    let block_numbers = headers
+        .into_iter()
+        .map(|header| header.unwrap().block_header.block_number)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        block_numbers,
+        (START_BLOCK_NUMBER..ADVANCED_TIP).map(BlockNumber).collect::<Vec<_>>()
+    );
+}
+
 // TODO(shahak): Add negative tests.
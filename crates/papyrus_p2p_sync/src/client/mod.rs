@@ -15,12 +15,12 @@ use futures::channel::mpsc::SendError;
 use futures::future::{ready, Ready};
 use futures::sink::With;
 use futures::{SinkExt, Stream};
+pub use header::BlockHeaderClient;
 use header::HeaderStreamBuilder;
 use papyrus_config::converters::deserialize_seconds_to_duration;
 use papyrus_config::dumping::{ser_optional_param, ser_param, SerializeConfig};
 use papyrus_config::{ParamPath, ParamPrivacyInput, SerializedParam};
-use papyrus_network::network_manager::{SqmrClientPayload, SqmrClientSender};
-use papyrus_protobuf::converters::ProtobufConversionError;
+use papyrus_network::network_manager::{NetworkError, SqmrClientPayload, SqmrClientSender};
 use papyrus_protobuf::sync::{
     DataOrFin,
     HeaderQuery,
@@ -33,6 +33,7 @@ use papyrus_protobuf::sync::{
 use papyrus_storage::{StorageError, StorageReader, StorageWriter};
 use serde::{Deserialize, Serialize};
 use starknet_api::block::{BlockNumber, BlockSignature};
+use starknet_api::core::StateDiffCommitment;
 use starknet_api::transaction::{Transaction, TransactionOutput};
 use state_diff::StateDiffStreamBuilder;
 use stream_builder::{DataStreamBuilder, DataStreamResult};
@@ -130,6 +131,16 @@ pub enum P2PSyncError {
     )]
     WrongStateDiffLength { expected_length: usize, possible_lengths: Vec<usize> },
     // TODO(shahak): Remove this and report to network on invalid data once that's possible.
+    #[error(
+        "The state diff of block {block_number} doesn't match the state diff commitment in its \
+         header. Expected {expected_commitment:?}, got {actual_commitment:?}."
+    )]
+    WrongStateDiffCommitment {
+        block_number: BlockNumber,
+        expected_commitment: StateDiffCommitment,
+        actual_commitment: StateDiffCommitment,
+    },
+    // TODO(shahak): Remove this and report to network on invalid data once that's possible.
     #[error("Two state diff parts for the same state diff are conflicting.")]
     ConflictingStateDiffParts,
     // TODO(shahak): Remove this and report to network on invalid data once that's possible.
@@ -142,7 +153,7 @@ pub enum P2PSyncError {
     TooManyResponses,
     // TODO(shahak): Remove this and report to network on invalid data once that's possible.
     #[error(transparent)]
-    ProtobufConversionError(#[from] ProtobufConversionError),
+    NetworkError(#[from] NetworkError),
     #[error(
         "Encountered an old header in the storage at {block_number:?} that's missing the field \
          {missing_field}. Re-sync the node from {block_number:?} from a node that provides this \
@@ -170,7 +181,7 @@ type WithPayloadSender<TQuery, Response> = With<
         SqmrClientPayload<Query, Response>,
     ) -> Ready<Result<SqmrClientPayload<TQuery, Response>, SendError>>,
 >;
-type SyncResponse<T> = Result<DataOrFin<T>, ProtobufConversionError>;
+type SyncResponse<T> = Result<DataOrFin<T>, NetworkError>;
 type ResponseReceiver<T> = Box<dyn Stream<Item = SyncResponse<T>> + Unpin + Send>;
 
 type HeaderPayloadSender = SqmrClientSender<HeaderQuery, DataOrFin<SignedBlockHeader>>;
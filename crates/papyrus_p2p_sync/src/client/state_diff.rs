@@ -2,12 +2,13 @@ use std::collections::HashSet;
 
 use futures::future::BoxFuture;
 use futures::{FutureExt, StreamExt};
+use papyrus_common::state_diff_commitment::{calculate_state_diff_commitment, StateDiffVersion};
 use papyrus_proc_macros::latency_histogram;
 use papyrus_protobuf::sync::StateDiffChunk;
 use papyrus_storage::header::HeaderStorageReader;
 use papyrus_storage::state::{StateStorageReader, StateStorageWriter};
 use papyrus_storage::{StorageError, StorageReader, StorageWriter};
-use starknet_api::block::BlockNumber;
+use starknet_api::block::{BlockHeader, BlockNumber};
 use starknet_api::state::ThinStateDiff;
 
 use super::ResponseReceiver;
@@ -42,12 +43,12 @@ impl DataStreamBuilder<StateDiffChunk> for StateDiffStreamBuilder {
             let mut result = ThinStateDiff::default();
             let mut prev_result_len = 0;
             let mut current_state_diff_len = 0;
-            let target_state_diff_len = storage_reader
+            let header = storage_reader
                 .begin_ro_txn()?
                 .get_block_header(block_number)?
-                .expect("A header with number lower than the header marker is missing")
-                .state_diff_length
-                .ok_or(P2PSyncError::OldHeaderInStorage {
+                .expect("A header with number lower than the header marker is missing");
+            let target_state_diff_len =
+                header.state_diff_length.ok_or(P2PSyncError::OldHeaderInStorage {
                     block_number,
                     missing_field: "state_diff_length",
                 })?;
@@ -87,6 +88,7 @@ impl DataStreamBuilder<StateDiffChunk> for StateDiffStreamBuilder {
             }
 
             validate_deprecated_declared_classes_non_conflicting(&result)?;
+            verify_state_diff_commitment(&result, &header)?;
             Ok(Some((result, block_number)))
         }
         .boxed()
@@ -169,3 +171,42 @@ fn validate_deprecated_declared_classes_non_conflicting(
         Err(P2PSyncError::ConflictingStateDiffParts)
     }
 }
+
+/// Verifies that the given state diff hashes to the state diff commitment of the given header.
+/// Headers that don't contain a state diff commitment can't be verified against, so any state diff
+/// is accepted for them.
+#[latency_histogram("p2p_sync_state_diff_verify_state_diff_commitment_latency_seconds", true)]
+pub(crate) fn verify_state_diff_commitment(
+    state_diff: &ThinStateDiff,
+    header: &BlockHeader,
+) -> Result<(), P2PSyncError> {
+    let Some(expected_commitment) = &header.state_diff_commitment else {
+        return Ok(());
+    };
+    // The commitment depends on the order of the entries, while the parts of the state diff may
+    // arrive in any order.
+    let mut sorted_state_diff = state_diff.clone();
+    sort_state_diff(&mut sorted_state_diff);
+    let actual_commitment =
+        calculate_state_diff_commitment(&sorted_state_diff, StateDiffVersion::V0);
+    if actual_commitment != *expected_commitment {
+        return Err(P2PSyncError::WrongStateDiffCommitment {
+            block_number: header.block_number,
+            expected_commitment: expected_commitment.clone(),
+            actual_commitment,
+        });
+    }
+    Ok(())
+}
+
+fn sort_state_diff(state_diff: &mut ThinStateDiff) {
+    state_diff.declared_classes.sort_unstable_keys();
+    state_diff.deprecated_declared_classes.sort_unstable();
+    state_diff.deployed_contracts.sort_unstable_keys();
+    state_diff.nonces.sort_unstable_keys();
+    state_diff.replaced_classes.sort_unstable_keys();
+    state_diff.storage_diffs.sort_unstable_keys();
+    for storage_entries in state_diff.storage_diffs.values_mut() {
+        storage_entries.sort_unstable_keys();
+    }
+}
@@ -3,6 +3,7 @@ use std::time::Duration;
 use assert_matches::assert_matches;
 use futures::{FutureExt, SinkExt, StreamExt};
 use indexmap::indexmap;
+use papyrus_common::state_diff_commitment::{calculate_state_diff_commitment, StateDiffVersion};
 use papyrus_network::network_manager::SqmrClientPayload;
 use papyrus_protobuf::sync::{
     BlockHashOrNumber,
@@ -25,6 +26,7 @@ use starknet_types_core::felt::Felt;
 use static_assertions::const_assert;
 use test_utils::{get_rng, GetTestInstance};
 
+use super::state_diff::verify_state_diff_commitment;
 use super::test_utils::{
     create_block_hashes_and_signatures,
     setup,
@@ -304,6 +306,109 @@ async fn state_diff_conflicting() {
     .await;
 }
 
+#[test]
+fn state_diff_matching_commitment_accepted() {
+    let state_diff = ThinStateDiff {
+        deployed_contracts: indexmap! { ContractAddress::from(1u64) => ClassHash(Felt::ONE) },
+        nonces: indexmap! { ContractAddress::from(1u64) => Nonce(Felt::ONE) },
+        ..Default::default()
+    };
+    let header = BlockHeader {
+        state_diff_commitment: Some(calculate_state_diff_commitment(
+            &state_diff,
+            StateDiffVersion::V0,
+        )),
+        ..Default::default()
+    };
+    verify_state_diff_commitment(&state_diff, &header).unwrap();
+}
+
+#[test]
+fn state_diff_in_any_order_matches_commitment() {
+    let sorted_state_diff = ThinStateDiff {
+        deployed_contracts: indexmap! {
+            ContractAddress::from(1u64) => ClassHash(Felt::ONE),
+            ContractAddress::from(2u64) => ClassHash(Felt::TWO),
+        },
+        storage_diffs: indexmap! {
+            ContractAddress::from(1u64) => indexmap! {
+                StorageKey::from(1u128) => Felt::ONE,
+                StorageKey::from(2u128) => Felt::TWO,
+            },
+            ContractAddress::from(2u64) => indexmap! {
+                StorageKey::from(1u128) => Felt::THREE,
+                StorageKey::from(3u128) => Felt::ONE,
+            },
+        },
+        declared_classes: indexmap! {
+            ClassHash(Felt::ONE) => CompiledClassHash(Felt::ONE),
+            ClassHash(Felt::TWO) => CompiledClassHash(Felt::TWO),
+        },
+        deprecated_declared_classes: vec![ClassHash(Felt::THREE), ClassHash(Felt::from(4u64))],
+        nonces: indexmap! {
+            ContractAddress::from(1u64) => Nonce(Felt::ONE),
+            ContractAddress::from(2u64) => Nonce(Felt::TWO),
+        },
+        replaced_classes: indexmap! {},
+    };
+    let header = BlockHeader {
+        state_diff_commitment: Some(calculate_state_diff_commitment(
+            &sorted_state_diff,
+            StateDiffVersion::V0,
+        )),
+        ..Default::default()
+    };
+
+    // The same state diff, with its parts in the reverse order.
+    let state_diff = ThinStateDiff {
+        deployed_contracts: sorted_state_diff.deployed_contracts.into_iter().rev().collect(),
+        storage_diffs: sorted_state_diff
+            .storage_diffs
+            .into_iter()
+            .rev()
+            .map(|(address, storage_entries)| {
+                (address, storage_entries.into_iter().rev().collect())
+            })
+            .collect(),
+        declared_classes: sorted_state_diff.declared_classes.into_iter().rev().collect(),
+        deprecated_declared_classes: sorted_state_diff
+            .deprecated_declared_classes
+            .into_iter()
+            .rev()
+            .collect(),
+        nonces: sorted_state_diff.nonces.into_iter().rev().collect(),
+        replaced_classes: indexmap! {},
+    };
+    verify_state_diff_commitment(&state_diff, &header).unwrap();
+}
+
+#[test]
+fn state_diff_tampered_commitment_rejected() {
+    let state_diff = ThinStateDiff {
+        deployed_contracts: indexmap! { ContractAddress::from(1u64) => ClassHash(Felt::ONE) },
+        nonces: indexmap! { ContractAddress::from(1u64) => Nonce(Felt::ONE) },
+        ..Default::default()
+    };
+    let expected_commitment = calculate_state_diff_commitment(&state_diff, StateDiffVersion::V0);
+    let header = BlockHeader {
+        block_number: BlockNumber(1),
+        state_diff_commitment: Some(expected_commitment.clone()),
+        ..Default::default()
+    };
+    let mut tampered_state_diff = state_diff;
+    tampered_state_diff.nonces.insert(ContractAddress::from(1u64), Nonce(Felt::TWO));
+
+    let error = verify_state_diff_commitment(&tampered_state_diff, &header).unwrap_err();
+    assert_matches!(
+        error,
+        P2PSyncError::WrongStateDiffCommitment {
+            block_number,
+            expected_commitment: error_expected_commitment,
+            ..
+        } if block_number == BlockNumber(1) && error_expected_commitment == expected_commitment
+    );
+}
+
 async fn validate_state_diff_fails(
     state_diff_length_in_header: usize,
     state_diff_chunks: Vec<Option<StateDiffChunk>>,
@@ -1,8 +1,17 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::Duration;
 use std::vec;
 
 use futures::channel::mpsc::SendError;
 use futures::{Sink, SinkExt, Stream, StreamExt};
+use metrics::counter;
+use papyrus_common::block_hash::validate_header;
+use papyrus_common::metrics as papyrus_metrics;
 use papyrus_common::pending_classes::ApiContractClass;
+use papyrus_config::converters::deserialize_seconds_to_duration;
+use papyrus_config::dumping::{ser_param, SerializeConfig};
+use papyrus_config::{ParamPath, ParamPrivacyInput, SerializedParam};
 use papyrus_protobuf::converters::ProtobufConversionError;
 use papyrus_protobuf::sync::{
     BlockHashOrNumber,
@@ -24,17 +33,111 @@ use papyrus_storage::class::ClassStorageReader;
 use papyrus_storage::header::HeaderStorageReader;
 use papyrus_storage::state::StateStorageReader;
 use papyrus_storage::{db, StorageReader, StorageTxn};
+use serde::{Deserialize, Serialize};
 use starknet_api::block::BlockNumber;
-use starknet_api::core::ClassHash;
+use starknet_api::core::{ChainId, ClassHash};
 use starknet_api::state::ThinStateDiff;
 use starknet_api::transaction::{Event, Transaction, TransactionHash, TransactionOutput};
-use tracing::error;
+use tracing::{debug, error};
 
 #[cfg(test)]
 mod test;
 
 mod utils;
 
+pub use utils::continuation_query;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct P2PSyncServerConfig {
+    // Queries for blocks outside this range are rejected. Archive nodes that serve only a window
+    // of the chain can use it to split the chain between them.
+    pub served_range: Range<BlockNumber>,
+    // A session that didn't finish by this time is closed, even if it's slow only because the peer
+    // reads the responses slowly.
+    #[serde(deserialize_with = "deserialize_seconds_to_duration")]
+    pub max_session_duration: Duration,
+    pub chain_id: ChainId,
+    // Served headers whose block number is a multiple of this interval have their hash recomputed
+    // and compared to the stored hash, to detect storage corruption. 0 disables the check.
+    pub header_hash_check_interval: u64,
+    // A query for more blocks is served up to this number of blocks. The requester continues it
+    // with the query returned by `continuation_query`.
+    pub max_blocks_per_session: u64,
+}
+
+impl SerializeConfig for P2PSyncServerConfig {
+    fn dump(&self) -> BTreeMap<ParamPath, SerializedParam> {
+        BTreeMap::from_iter([
+            ser_param(
+                "chain_id",
+                &self.chain_id,
+                "The chain the served headers belong to. Used for recomputing their hashes.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "header_hash_check_interval",
+                &self.header_hash_check_interval,
+                "Served headers whose block number is a multiple of this interval are checked \
+                 against their stored hash, to detect storage corruption. 0 disables the check.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "max_blocks_per_session",
+                &self.max_blocks_per_session,
+                "The maximal number of blocks served in a single session. The rest of the blocks \
+                 of a larger query can be requested in further sessions.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "max_session_duration",
+                &self.max_session_duration.as_secs(),
+                "Time in seconds after which a session that serves a peer's query is closed.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "served_range.start",
+                &self.served_range.start,
+                "The first block number this node serves to other peers.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "served_range.end",
+                &self.served_range.end,
+                "The block number after the last block this node serves to other peers.",
+                ParamPrivacyInput::Public,
+            ),
+        ])
+    }
+}
+
+impl Default for P2PSyncServerConfig {
+    fn default() -> Self {
+        Self {
+            served_range: BlockNumber(0)..BlockNumber(u64::MAX),
+            max_session_duration: Duration::from_secs(300),
+            chain_id: ChainId::Mainnet,
+            header_hash_check_interval: 0,
+            max_blocks_per_session: u64::MAX,
+        }
+    }
+}
+
+/// The reason the server refused to serve a query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionRejectionReason {
+    /// The query asked for a block outside of the range this node serves.
+    OutOfServedRange,
+    /// The query's step is 0, which would serve the same block over and over.
+    ZeroStep,
+}
+
+/// The reason the server closed a session before it finished serving the query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionClosingReason {
+    /// The session exceeded the maximal session duration of the server.
+    ServerDeadline,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum P2PSyncServerError {
     #[error(transparent)]
@@ -55,6 +158,10 @@ pub enum P2PSyncServerError {
     SignatureNotFound { block_number: BlockNumber },
     #[error(transparent)]
     SendError(#[from] futures::channel::mpsc::SendError),
+    #[error("Session rejected. Reason: {reason:?}")]
+    SessionRejected { reason: SessionRejectionReason },
+    #[error("Session closed. Reason: {reason:?}")]
+    SessionClosed { reason: SessionClosingReason },
 }
 
 impl P2PSyncServerError {
@@ -63,7 +170,11 @@ impl P2PSyncServerError {
             Self::JoinError(_) | Self::SignatureNotFound { .. } | Self::SendError { .. }
             // TODO(shahak): Consider returning false for some of the StorageError variants.
             | Self::DBInternalError { .. } => true,
-            Self::BlockNumberOutOfRange { .. } | Self::BlockNotFound { .. } | Self::ClassNotFound { .. } => false,
+            Self::BlockNumberOutOfRange { .. }
+            | Self::BlockNotFound { .. }
+            | Self::ClassNotFound { .. }
+            | Self::SessionRejected { .. }
+            | Self::SessionClosed { .. } => false,
         }
     }
 }
@@ -76,6 +187,7 @@ pub struct P2PSyncServer<
     ClassQueryReceiver,
     EventQueryReceiver,
 > {
+    config: P2PSyncServerConfig,
     storage_reader: StorageReader,
     header_queries_receiver: HeaderQueryReceiver,
     state_diff_queries_receiver: StateDiffQueryReceiver,
@@ -181,6 +293,7 @@ where
     }
 
     pub fn new(
+        config: P2PSyncServerConfig,
         storage_reader: StorageReader,
         header_queries_receiver: HeaderQueryReceiver,
         state_diff_queries_receiver: StateDiffQueryReceiver,
@@ -189,6 +302,7 @@ where
         event_queries_receiver: EventQueryReceiver,
     ) -> Self {
         Self {
+            config,
             storage_reader,
             header_queries_receiver,
             state_diff_queries_receiver,
@@ -205,8 +319,10 @@ where
         P2PSyncServerError: From<<Sender as Sink<DataOrFin<Data>>>::Error>,
     {
         let storage_reader_clone = self.storage_reader.clone();
+        let config = self.config.clone();
         tokio::task::spawn(async move {
-            let result = send_data_for_query(storage_reader_clone, query.clone(), sender).await;
+            let result =
+                send_data_for_query(storage_reader_clone, config, query.clone(), sender).await;
             if let Err(error) = result {
                 if error.should_log_in_error_level() {
                     error!("Running inbound query {query:?} failed on {error:?}");
@@ -224,6 +340,10 @@ pub trait FetchBlockDataFromDb: Sized {
         block_number: BlockNumber,
         txn: &StorageTxn<'_, db::RO>,
     ) -> Result<Vec<Self>, P2PSyncServerError>;
+
+    // Checks invariants of the data that can break only if the storage is corrupted. Violations are
+    // reported and the data is served anyway, since peers verify the data they receive.
+    fn check_served_data(&self, _config: &P2PSyncServerConfig) {}
 }
 
 impl FetchBlockDataFromDb for SignedBlockHeader {
@@ -250,6 +370,29 @@ impl FetchBlockDataFromDb for SignedBlockHeader {
             .ok_or(P2PSyncServerError::SignatureNotFound { block_number })?;
         Ok(vec![SignedBlockHeader { block_header: header, signatures: vec![signature] }])
     }
+
+    fn check_served_data(&self, config: &P2PSyncServerConfig) {
+        let header = &self.block_header;
+        let interval = config.header_hash_check_interval;
+        if interval == 0 || header.block_number.0 % interval != 0 {
+            return;
+        }
+        match validate_header(header, &config.chain_id) {
+            Ok(true) => {}
+            Ok(false) => {
+                error!(
+                    "The stored hash {} of block {} doesn't match its header. The storage might \
+                     be corrupted.",
+                    header.block_hash, header.block_number
+                );
+                counter!(papyrus_metrics::PAPYRUS_NUM_SERVED_HEADER_HASH_MISMATCHES, 1);
+            }
+            // Headers of old blocks don't contain all the data their hash is calculated from.
+            Err(err) => {
+                debug!("Couldn't check the hash of block {}: {err:?}.", header.block_number)
+            }
+        }
+    }
 }
 
 impl FetchBlockDataFromDb for StateDiffChunk {
@@ -391,6 +534,7 @@ pub fn split_thin_state_diff(thin_state_diff: ThinStateDiff) -> Vec<StateDiffChu
 
 async fn send_data_for_query<Data, Sender>(
     storage_reader: StorageReader,
+    config: P2PSyncServerConfig,
     query: Query,
     mut sender: Sender,
 ) -> Result<(), P2PSyncServerError>
@@ -399,14 +543,23 @@ where
     Sender: Sink<DataOrFin<Data>> + Unpin + Send + 'static,
     P2PSyncServerError: From<<Sender as Sink<DataOrFin<Data>>>::Error>,
 {
-    // If this function fails, we still want to send fin before failing.
-    let result = send_data_without_fin_for_query(&storage_reader, query, &mut sender).await;
-    sender.feed(DataOrFin(None)).await?;
-    result
+    let session = async {
+        // If this function fails, we still want to send fin before failing.
+        let result =
+            send_data_without_fin_for_query(&storage_reader, &config, query, &mut sender).await;
+        sender.feed(DataOrFin(None)).await?;
+        result
+    };
+    // On the deadline the sender is dropped without sending fin, since sending it might wait for
+    // the peer as well.
+    tokio::time::timeout(config.max_session_duration, session).await.unwrap_or(Err(
+        P2PSyncServerError::SessionClosed { reason: SessionClosingReason::ServerDeadline },
+    ))
 }
 
 async fn send_data_without_fin_for_query<Data, Sender>(
     storage_reader: &StorageReader,
+    config: &P2PSyncServerConfig,
     query: Query,
     sender: &mut Sender,
 ) -> Result<(), P2PSyncServerError>
@@ -415,6 +568,11 @@ where
     Sender: Sink<DataOrFin<Data>> + Unpin + Send + 'static,
     P2PSyncServerError: From<<Sender as Sink<DataOrFin<Data>>>::Error>,
 {
+    if query.step == 0 {
+        return Err(P2PSyncServerError::SessionRejected {
+            reason: SessionRejectionReason::ZeroStep,
+        });
+    }
     let txn = storage_reader.begin_ro_txn()?;
     let start_block_number = match query.start_block {
         BlockHashOrNumber::Number(BlockNumber(num)) => num,
@@ -426,11 +584,17 @@ where
                 .0
         }
     };
-    for block_counter in 0..query.limit {
+    for block_counter in 0..query.limit.min(config.max_blocks_per_session) {
         let block_number =
             BlockNumber(utils::calculate_block_number(&query, start_block_number, block_counter)?);
+        if !config.served_range.contains(&block_number) {
+            return Err(P2PSyncServerError::SessionRejected {
+                reason: SessionRejectionReason::OutOfServedRange,
+            });
+        }
         let data_vec = Data::fetch_block_data_from_db(block_number, &txn)?;
         for data in data_vec {
+            data.check_served_data(config);
             // TODO: consider implement retry mechanism.
             sender.feed(DataOrFin(Some(data))).await?;
         }
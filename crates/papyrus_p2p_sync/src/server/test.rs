@@ -1,6 +1,11 @@
+use std::time::Duration;
+
+use assert_matches::assert_matches;
 use futures::channel::mpsc::{Receiver, Sender};
 use futures::StreamExt;
 use lazy_static::lazy_static;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use papyrus_common::metrics as papyrus_metrics;
 use papyrus_common::pending_classes::ApiContractClass;
 use papyrus_common::state::create_random_state_diff;
 use papyrus_protobuf::converters::ProtobufConversionError;
@@ -23,14 +28,26 @@ use papyrus_storage::header::{HeaderStorageReader, HeaderStorageWriter};
 use papyrus_storage::state::StateStorageWriter;
 use papyrus_storage::test_utils::get_test_storage;
 use papyrus_storage::{StorageReader, StorageWriter};
+use prometheus_parse::Value::Counter;
 use rand::random;
 use starknet_api::block::{BlockBody, BlockHash, BlockHeader, BlockNumber, BlockSignature};
+use starknet_api::core::{EventCommitment, TransactionCommitment};
 use starknet_api::deprecated_contract_class::ContractClass as DeprecatedContractClass;
 use starknet_api::state::ContractClass;
 use starknet_api::transaction::{Event, Transaction, TransactionHash, TransactionOutput};
-use test_utils::{get_rng, get_test_body, GetTestInstance};
+use test_utils::{get_rng, get_test_body, prometheus_is_contained, GetTestInstance};
 
-use super::{split_thin_state_diff, FetchBlockDataFromDb, P2PSyncServer};
+use super::{
+    continuation_query,
+    send_data_for_query,
+    split_thin_state_diff,
+    FetchBlockDataFromDb,
+    P2PSyncServer,
+    P2PSyncServerConfig,
+    P2PSyncServerError,
+    SessionClosingReason,
+    SessionRejectionReason,
+};
 const BUFFER_SIZE: usize = 10;
 const NUM_OF_BLOCKS: u64 = 10;
 const NUM_TXS_PER_BLOCK: usize = 5;
@@ -237,6 +254,307 @@ async fn class_query_some_blocks_are_missing() {
     run_test(assert_class, NUM_OF_BLOCKS - BLOCKS_DELTA, StartBlockType::Number).await;
 }
 
+#[tokio::test]
+async fn query_outside_served_range_is_rejected() {
+    let ((storage_reader, _storage_writer), _temp_dir) = get_test_storage();
+    let config = P2PSyncServerConfig {
+        served_range: BlockNumber(100)..BlockNumber(200),
+        ..Default::default()
+    };
+    let (sender, receiver) = futures::channel::mpsc::channel(BUFFER_SIZE);
+    let query = Query {
+        start_block: BlockHashOrNumber::Number(BlockNumber(50)),
+        direction: Direction::Forward,
+        limit: 1,
+        step: 1,
+    };
+
+    let result =
+        send_data_for_query::<SignedBlockHeader, _>(storage_reader, config, query, sender).await;
+
+    assert_matches!(
+        result,
+        Err(P2PSyncServerError::SessionRejected {
+            reason: SessionRejectionReason::OutOfServedRange
+        })
+    );
+    assert_eq!(receiver.collect::<Vec<_>>().await, vec![DataOrFin(None)]);
+}
+
+#[tokio::test]
+async fn query_by_hash_starts_at_the_block_of_the_hash() {
+    const START_BLOCK_NUMBER: BlockNumber = BlockNumber(BLOCKS_DELTA);
+    const LIMIT: u64 = 3;
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    insert_to_storage_test_blocks_up_to(&mut storage_writer);
+    let block_hash = storage_reader
+        .begin_ro_txn()
+        .unwrap()
+        .get_block_header(START_BLOCK_NUMBER)
+        .unwrap()
+        .unwrap()
+        .block_hash;
+    let (sender, receiver) = futures::channel::mpsc::channel(BUFFER_SIZE);
+    let query = Query {
+        start_block: BlockHashOrNumber::Hash(block_hash),
+        direction: Direction::Forward,
+        limit: LIMIT,
+        step: 1,
+    };
+
+    send_data_for_query::<SignedBlockHeader, _>(
+        storage_reader,
+        P2PSyncServerConfig::default(),
+        query,
+        sender,
+    )
+    .await
+    .unwrap();
+
+    let mut responses = receiver.collect::<Vec<_>>().await;
+    assert_eq!(responses.pop().unwrap(), DataOrFin(None));
+    let served_block_numbers = responses
+        .into_iter()
+        .map(|data| data.0.unwrap().block_header.block_number)
+        .collect::<Vec<_>>();
+    let expected_block_numbers =
+        (0..LIMIT).map(|i| BlockNumber(START_BLOCK_NUMBER.0 + i)).collect::<Vec<_>>();
+    assert_eq!(served_block_numbers, expected_block_numbers);
+}
+
+#[tokio::test]
+async fn query_by_unknown_hash_is_block_not_found() {
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    insert_to_storage_test_blocks_up_to(&mut storage_writer);
+    let unknown_block_hash = BlockHash(random::<u64>().into());
+    let (sender, receiver) = futures::channel::mpsc::channel(BUFFER_SIZE);
+    let query = Query {
+        start_block: BlockHashOrNumber::Hash(unknown_block_hash),
+        direction: Direction::Forward,
+        limit: 1,
+        step: 1,
+    };
+
+    let result = send_data_for_query::<SignedBlockHeader, _>(
+        storage_reader,
+        P2PSyncServerConfig::default(),
+        query,
+        sender,
+    )
+    .await;
+
+    assert_matches!(
+        result,
+        Err(P2PSyncServerError::BlockNotFound {
+            block_hash_or_number: BlockHashOrNumber::Hash(block_hash)
+        }) if block_hash == unknown_block_hash
+    );
+    assert_eq!(receiver.collect::<Vec<_>>().await, vec![DataOrFin(None)]);
+}
+
+#[tokio::test]
+async fn query_with_step_serves_every_step_th_block() {
+    let forward_query = Query {
+        start_block: BlockHashOrNumber::Number(BlockNumber(1)),
+        direction: Direction::Forward,
+        limit: 4,
+        step: 2,
+    };
+    assert_eq!(
+        served_header_block_numbers(forward_query).await,
+        [1, 3, 5, 7].map(BlockNumber).to_vec()
+    );
+
+    let backward_query = Query {
+        start_block: BlockHashOrNumber::Number(BlockNumber(NUM_OF_BLOCKS - 1)),
+        direction: Direction::Backward,
+        limit: 3,
+        step: 3,
+    };
+    assert_eq!(
+        served_header_block_numbers(backward_query).await,
+        [9, 6, 3].map(BlockNumber).to_vec()
+    );
+}
+
+#[tokio::test]
+async fn query_with_zero_step_is_rejected() {
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    insert_to_storage_test_blocks_up_to(&mut storage_writer);
+    let (sender, receiver) = futures::channel::mpsc::channel(BUFFER_SIZE);
+    let query = Query {
+        start_block: BlockHashOrNumber::Number(BlockNumber(0)),
+        direction: Direction::Forward,
+        limit: NUM_OF_BLOCKS,
+        step: 0,
+    };
+
+    let result = send_data_for_query::<SignedBlockHeader, _>(
+        storage_reader,
+        P2PSyncServerConfig::default(),
+        query,
+        sender,
+    )
+    .await;
+
+    assert_matches!(
+        result,
+        Err(P2PSyncServerError::SessionRejected { reason: SessionRejectionReason::ZeroStep })
+    );
+    assert_eq!(receiver.collect::<Vec<_>>().await, vec![DataOrFin(None)]);
+}
+
+#[tokio::test]
+async fn session_of_slow_reading_client_is_closed_on_deadline() {
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    insert_to_storage_test_blocks_up_to(&mut storage_writer);
+    let config = P2PSyncServerConfig {
+        max_session_duration: Duration::from_millis(10),
+        ..Default::default()
+    };
+    // The channel holds a single response, so the server waits for the client to read it.
+    let (sender, receiver) = futures::channel::mpsc::channel(0);
+    let query = Query {
+        start_block: BlockHashOrNumber::Number(BlockNumber(0)),
+        direction: Direction::Forward,
+        limit: NUM_OF_BLOCKS,
+        step: 1,
+    };
+
+    let result =
+        send_data_for_query::<SignedBlockHeader, _>(storage_reader, config, query, sender).await;
+
+    assert_matches!(
+        result,
+        Err(P2PSyncServerError::SessionClosed { reason: SessionClosingReason::ServerDeadline })
+    );
+    // The client reads only what the server sent before the deadline, without a fin.
+    let responses = receiver.collect::<Vec<_>>().await;
+    assert_eq!(responses.len(), 1);
+    assert_matches!(responses[0], DataOrFin(Some(_)));
+}
+
+#[tokio::test]
+async fn header_with_corrupted_hash_is_detected_while_serving() {
+    let handle = PrometheusBuilder::new().install_recorder().unwrap();
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    // The stored hash isn't the hash of the header's content.
+    let header = BlockHeader {
+        block_hash: BlockHash(random::<u64>().into()),
+        transaction_commitment: Some(TransactionCommitment::default()),
+        event_commitment: Some(EventCommitment::default()),
+        state_diff_length: Some(0),
+        ..Default::default()
+    };
+    storage_writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_header(BlockNumber(0), &header)
+        .unwrap()
+        .append_block_signature(BlockNumber(0), &BlockSignature::default())
+        .unwrap()
+        .commit()
+        .unwrap();
+    let config = P2PSyncServerConfig { header_hash_check_interval: 1, ..Default::default() };
+    let (sender, receiver) = futures::channel::mpsc::channel(BUFFER_SIZE);
+    let query = Query {
+        start_block: BlockHashOrNumber::Number(BlockNumber(0)),
+        direction: Direction::Forward,
+        limit: 1,
+        step: 1,
+    };
+
+    send_data_for_query::<SignedBlockHeader, _>(storage_reader, config, query, sender)
+        .await
+        .unwrap();
+
+    // The header is still served, and the mismatch is reported.
+    let responses = receiver.collect::<Vec<_>>().await;
+    assert_eq!(responses.len(), 2);
+    assert_matches!(
+        &responses[0],
+        DataOrFin(Some(signed_header)) if signed_header.block_header.block_hash == header.block_hash
+    );
+    assert_eq!(
+        prometheus_is_contained(
+            handle.render(),
+            papyrus_metrics::PAPYRUS_NUM_SERVED_HEADER_HASH_MISMATCHES,
+            &[]
+        ),
+        Some(Counter(1f64))
+    );
+}
+
+#[tokio::test]
+async fn query_larger_than_session_is_served_in_continuations() {
+    const MAX_BLOCKS_PER_SESSION: u64 = 3;
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    insert_to_storage_test_blocks_up_to(&mut storage_writer);
+    let config = P2PSyncServerConfig {
+        max_blocks_per_session: MAX_BLOCKS_PER_SESSION,
+        ..Default::default()
+    };
+    let query = Query {
+        start_block: BlockHashOrNumber::Number(BlockNumber(1)),
+        direction: Direction::Forward,
+        limit: NUM_OF_BLOCKS / 2,
+        step: 2,
+    };
+
+    let mut served_block_numbers = vec![];
+    let mut n_sessions = 0;
+    let mut next_query = Some(query.clone());
+    while let Some(query) = next_query {
+        let BlockHashOrNumber::Number(session_start_block_number) = query.start_block else {
+            panic!("A continuation query should start at a block number.");
+        };
+        let (sender, receiver) = futures::channel::mpsc::channel(BUFFER_SIZE);
+        send_data_for_query::<SignedBlockHeader, _>(
+            storage_reader.clone(),
+            config.clone(),
+            query.clone(),
+            sender,
+        )
+        .await
+        .unwrap();
+
+        let mut responses = receiver.collect::<Vec<_>>().await;
+        assert_eq!(responses.pop().unwrap(), DataOrFin(None));
+        assert!(responses.len() <= MAX_BLOCKS_PER_SESSION as usize);
+        let n_served_blocks = responses.len() as u64;
+        served_block_numbers
+            .extend(responses.into_iter().map(|data| data.0.unwrap().block_header.block_number));
+        next_query =
+            continuation_query(&query, session_start_block_number, n_served_blocks).unwrap();
+        n_sessions += 1;
+    }
+
+    assert_eq!(n_sessions, 2);
+    assert_eq!(served_block_numbers, served_header_block_numbers(query).await);
+    assert_eq!(served_block_numbers, [1, 3, 5, 7, 9].map(BlockNumber).to_vec());
+}
+
+// Serves the given header query from a storage with the test blocks and returns the numbers of the
+// served blocks.
+async fn served_header_block_numbers(query: Query) -> Vec<BlockNumber> {
+    let ((storage_reader, mut storage_writer), _temp_dir) = get_test_storage();
+    insert_to_storage_test_blocks_up_to(&mut storage_writer);
+    let (sender, receiver) = futures::channel::mpsc::channel(BUFFER_SIZE);
+
+    send_data_for_query::<SignedBlockHeader, _>(
+        storage_reader,
+        P2PSyncServerConfig::default(),
+        query,
+        sender,
+    )
+    .await
+    .unwrap();
+
+    let mut responses = receiver.collect::<Vec<_>>().await;
+    assert_eq!(responses.pop().unwrap(), DataOrFin(None));
+    responses.into_iter().map(|data| data.0.unwrap().block_header.block_number).collect()
+}
+
 async fn run_test<T, F>(assert_fn: F, start_block_number: u64, start_block_type: StartBlockType)
 where
     T: FetchBlockDataFromDb + std::fmt::Debug + PartialEq + Send + Sync + 'static,
@@ -349,6 +667,7 @@ fn setup() -> (
     )>(BUFFER_SIZE);
 
     let p2p_sync_server = super::P2PSyncServer::new(
+        P2PSyncServerConfig::default(),
         storage_reader.clone(),
         header_queries_receiver,
         state_diff_queries_receiver,
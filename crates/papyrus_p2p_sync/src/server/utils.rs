@@ -1,4 +1,5 @@
-use papyrus_protobuf::sync::{Direction, Query};
+use papyrus_protobuf::sync::{BlockHashOrNumber, Direction, Query};
+use starknet_api::block::BlockNumber;
 
 use super::P2PSyncServerError;
 
@@ -11,14 +12,39 @@ pub(crate) fn calculate_block_number(
         Direction::Forward => 1,
         Direction::Backward => -1,
     };
-    // TODO(shahak): Fix this code.
-    let blocks_delta: i128 = direction_factor * (query.step * read_blocks_counter) as i128;
-    let block_number: i128 = start_block as i128 + blocks_delta;
-    if block_number < 0 || block_number > u64::MAX as i128 {
-        return Err(P2PSyncServerError::BlockNumberOutOfRange {
-            query: query.clone(),
-            counter: read_blocks_counter,
-        });
+    let out_of_range_error = || P2PSyncServerError::BlockNumberOutOfRange {
+        query: query.clone(),
+        counter: read_blocks_counter,
+    };
+    let blocks_delta = i128::from(query.step)
+        .checked_mul(i128::from(read_blocks_counter))
+        .ok_or_else(out_of_range_error)?
+        * direction_factor;
+    let block_number =
+        i128::from(start_block).checked_add(blocks_delta).ok_or_else(out_of_range_error)?;
+    u64::try_from(block_number).map_err(|_| out_of_range_error())
+}
+
+/// Returns the query that continues the given query after the first `n_served_blocks` of its blocks
+/// were served, or `None` if all of its blocks were served. A server serves at most
+/// `max_blocks_per_session` blocks of a query, so a requester of more blocks continues the query
+/// in further sessions.
+///
+/// `start_block_number` is the number of the first block of the given query. For a query that
+/// starts at a block hash, the requester learns it from the first served block. The continuation
+/// always starts at a block number.
+pub fn continuation_query(
+    query: &Query,
+    start_block_number: BlockNumber,
+    n_served_blocks: u64,
+) -> Result<Option<Query>, P2PSyncServerError> {
+    if n_served_blocks >= query.limit {
+        return Ok(None);
     }
-    Ok(block_number as u64)
+    let next_block_number = calculate_block_number(query, start_block_number.0, n_served_blocks)?;
+    Ok(Some(Query {
+        start_block: BlockHashOrNumber::Number(BlockNumber(next_block_number)),
+        limit: query.limit - n_served_blocks,
+        ..query.clone()
+    }))
 }
@@ -21,7 +21,7 @@ use starknet_api::core::{
     TransactionCommitment,
 };
 use starknet_api::crypto::utils::Signature;
-use starknet_api::hash::PoseidonHash;
+use starknet_api::hash::{PoseidonHash, StarkHash};
 
 use super::common::{enum_int_to_l1_data_availability_mode, l1_data_availability_mode_to_enum_int};
 use super::ProtobufConversionError;
@@ -87,53 +87,14 @@ impl TryFrom<protobuf::SignedBlockHeader> for SignedBlockHeader {
             .try_into()
             .map(GlobalRoot)?;
 
-        let n_transactions = value
-            .transactions
-            .as_ref()
-            .ok_or(ProtobufConversionError::MissingField {
-                field_description: "SignedBlockHeader::transactions",
-            })?
-            .n_leaves
-            .try_into()
-            .expect("Failed converting u64 to usize");
-
-        let transaction_commitment = value
-            .transactions
-            .map(|transactions| {
-                Ok::<_, ProtobufConversionError>(TransactionCommitment(
-                    transactions
-                        .root
-                        .ok_or(ProtobufConversionError::MissingField {
-                            field_description: "Merkle::root",
-                        })?
-                        .try_into()?,
-                ))
-            })
-            .transpose()?;
+        // The commitments of a block are optional, so a missing commitment is decoded as an empty
+        // one. This matches the encoding of headers whose commitments aren't known.
+        let (n_transactions, transaction_commitment) =
+            patricia_to_leaves_and_root(value.transactions)?;
+        let transaction_commitment = transaction_commitment.map(TransactionCommitment);
 
-        let n_events = value
-            .events
-            .as_ref()
-            .ok_or(ProtobufConversionError::MissingField {
-                field_description: "SignedBlockHeader::events",
-            })?
-            .n_leaves
-            .try_into()
-            .expect("Failed converting u64 to usize");
-
-        let event_commitment = value
-            .events
-            .map(|events| {
-                Ok::<_, ProtobufConversionError>(EventCommitment(
-                    events
-                        .root
-                        .ok_or(ProtobufConversionError::MissingField {
-                            field_description: "Merkle::root",
-                        })?
-                        .try_into()?,
-                ))
-            })
-            .transpose()?;
+        let (n_events, event_commitment) = patricia_to_leaves_and_root(value.events)?;
+        let event_commitment = event_commitment.map(EventCommitment);
 
         let state_diff_length = value.state_diff_commitment.as_ref().map(|state_diff_commitment| {
             state_diff_commitment
@@ -146,42 +107,15 @@ impl TryFrom<protobuf::SignedBlockHeader> for SignedBlockHeader {
 
         let starknet_version = StarknetVersion(value.protocol_version);
 
+        // Gas prices that weren't sent are zero, as in blocks from before they were introduced.
         let l1_gas_price = GasPricePerToken {
-            price_in_fri: GasPrice(
-                value
-                    .gas_price_fri
-                    .ok_or(ProtobufConversionError::MissingField {
-                        field_description: "SignedBlockHeader::gas_price_fri",
-                    })?
-                    .into(),
-            ),
-            price_in_wei: GasPrice(
-                value
-                    .gas_price_wei
-                    .ok_or(ProtobufConversionError::MissingField {
-                        field_description: "SignedBlockHeader::gas_price_wei",
-                    })?
-                    .into(),
-            ),
+            price_in_fri: uint128_to_gas_price(value.gas_price_fri),
+            price_in_wei: uint128_to_gas_price(value.gas_price_wei),
         };
 
         let l1_data_gas_price = GasPricePerToken {
-            price_in_fri: GasPrice(
-                value
-                    .data_gas_price_fri
-                    .ok_or(ProtobufConversionError::MissingField {
-                        field_description: "SignedBlockHeader::data_gas_price_fri",
-                    })?
-                    .into(),
-            ),
-            price_in_wei: GasPrice(
-                value
-                    .data_gas_price_wei
-                    .ok_or(ProtobufConversionError::MissingField {
-                        field_description: "SignedBlockHeader::data_gas_price_wei",
-                    })?
-                    .into(),
-            ),
+            price_in_fri: uint128_to_gas_price(value.data_gas_price_fri),
+            price_in_wei: uint128_to_gas_price(value.data_gas_price_wei),
         };
 
         let receipt_commitment = value
@@ -233,12 +167,36 @@ impl TryFrom<protobuf::SignedBlockHeader> for SignedBlockHeader {
     }
 }
 
+fn patricia_to_leaves_and_root(
+    patricia: Option<protobuf::Patricia>,
+) -> Result<(usize, Option<StarkHash>), ProtobufConversionError> {
+    let Some(patricia) = patricia else {
+        return Ok((0, None));
+    };
+    let n_leaves = patricia.n_leaves.try_into().expect("Failed converting u64 to usize");
+    let root = patricia
+        .root
+        .ok_or(ProtobufConversionError::MissingField { field_description: "Patricia::root" })?
+        .try_into()?;
+    Ok((n_leaves, Some(root)))
+}
+
+fn uint128_to_gas_price(value: Option<protobuf::Uint128>) -> GasPrice {
+    value.map(|value| GasPrice(value.into())).unwrap_or_default()
+}
+
 impl From<DataOrFin<SignedBlockHeader>> for protobuf::BlockHeadersResponse {
     fn from(value: DataOrFin<SignedBlockHeader>) -> Self {
         value.0.into()
     }
 }
 
+impl From<SignedBlockHeader> for protobuf::SignedBlockHeader {
+    fn from(value: SignedBlockHeader) -> Self {
+        (value.block_header, value.signatures).into()
+    }
+}
+
 impl From<(BlockHeader, Vec<BlockSignature>)> for protobuf::SignedBlockHeader {
     fn from((header, signatures): (BlockHeader, Vec<BlockSignature>)) -> Self {
         let state_diff_commitment = match (header.state_diff_commitment, header.state_diff_length) {
@@ -333,6 +291,8 @@ impl From<Option<SignedBlockHeader>> for protobuf::BlockHeadersResponse {
 }
 
 auto_impl_into_and_try_from_vec_u8!(DataOrFin<SignedBlockHeader>, protobuf::BlockHeadersResponse);
+// Headers propagated with gossipsub are encoded as a bare SignedBlockHeader message.
+auto_impl_into_and_try_from_vec_u8!(SignedBlockHeader, protobuf::SignedBlockHeader);
 
 // TODO(shahak): Erase this once network stops using it.
 impl TryFrom<protobuf::BlockHeadersRequest> for Query {
@@ -1,5 +1,31 @@
+use prost::Message;
+use starknet_api::block::{
+    BlockHash,
+    BlockHeader,
+    BlockNumber,
+    BlockSignature,
+    BlockTimestamp,
+    GasPrice,
+    GasPricePerToken,
+    StarknetVersion,
+};
+use starknet_api::core::{
+    ContractAddress,
+    EventCommitment,
+    GlobalRoot,
+    PatriciaKey,
+    ReceiptCommitment,
+    SequencerContractAddress,
+    StateDiffCommitment,
+    TransactionCommitment,
+};
+use starknet_api::crypto::utils::Signature;
+use starknet_api::data_availability::L1DataAvailabilityMode;
+use starknet_api::hash::PoseidonHash;
+use starknet_api::{contract_address, felt, patricia_key};
 use test_utils::{get_rng, GetTestInstance};
 
+use crate::protobuf;
 use crate::sync::{DataOrFin, HeaderQuery, SignedBlockHeader};
 
 #[test]
@@ -12,6 +38,20 @@ fn block_header_to_bytes_and_back() {
     assert_eq!(res_data, data);
 }
 
+#[test]
+fn broadcasted_block_header_to_bytes_and_back() {
+    let mut rng = get_rng();
+    let signed_block_header = SignedBlockHeader::get_test_instance(&mut rng);
+    let bytes = Vec::<u8>::from(signed_block_header.clone());
+    // The message is the spec's SignedBlockHeader, without a response wrapping it.
+    assert_eq!(
+        SignedBlockHeader::try_from(protobuf::SignedBlockHeader::decode(bytes.as_slice()).unwrap())
+            .unwrap(),
+        signed_block_header
+    );
+    assert_eq!(SignedBlockHeader::try_from(bytes).unwrap(), signed_block_header);
+}
+
 #[test]
 fn fin_to_bytes_and_back() {
     let bytes_data = Vec::<u8>::from(DataOrFin::<SignedBlockHeader>(None));
@@ -28,3 +68,132 @@ fn header_query_to_bytes_and_back() {
     let res_query = HeaderQuery::try_from(bytes).unwrap();
     assert_eq!(header_query, res_query);
 }
+
+// Encodes a Hash, Address or Felt252 message, which holds 32 big-endian bytes in field 1.
+fn felt_message(last_byte: u8) -> Vec<u8> {
+    let mut message = vec![0x0a, 32];
+    message.extend([0; 31]);
+    message.push(last_byte);
+    message
+}
+
+// Encodes a length delimited field given its already encoded key.
+fn length_delimited_field(key: &[u8], value: Vec<u8>) -> Vec<u8> {
+    [key.to_vec(), vec![u8::try_from(value.len()).unwrap()], value].concat()
+}
+
+// A header with only the required fields of the spec's SignedBlockHeader message, encoded by hand
+// with the spec's field numbers so that the test doesn't depend on the generated code.
+fn spec_header_required_fields() -> Vec<u8> {
+    [
+        length_delimited_field(&[0x0a], felt_message(1)), // 1: block_hash
+        length_delimited_field(&[0x12], felt_message(2)), // 2: parent_hash
+        vec![0x18, 0x05],                                 // 3: number
+        vec![0x20, 0xe8, 0x07],                           // 4: time
+        length_delimited_field(&[0x2a], felt_message(3)), // 5: sequencer_address
+        length_delimited_field(&[0x32], felt_message(4)), // 6: state_root
+        length_delimited_field(&[0x5a], b"0.13.1".to_vec()), // 11: protocol_version
+    ]
+    .concat()
+}
+
+#[test]
+fn decode_spec_header() {
+    let bytes = [
+        spec_header_required_fields(),
+        // 7: state_diff_commitment
+        length_delimited_field(
+            &[0x3a],
+            [vec![0x08, 0x07], vec![0x12, 34], felt_message(5)].concat(),
+        ),
+        // 8: transactions
+        length_delimited_field(
+            &[0x42],
+            [vec![0x08, 0x02], vec![0x12, 34], felt_message(6)].concat(),
+        ),
+        // 9: events
+        length_delimited_field(
+            &[0x4a],
+            [vec![0x08, 0x03], vec![0x12, 34], felt_message(7)].concat(),
+        ),
+        length_delimited_field(&[0x52], felt_message(8)), // 10: receipts
+        length_delimited_field(&[0x62], vec![0x08, 10]),  // 12: gas_price_fri
+        length_delimited_field(&[0x6a], vec![0x08, 11]),  // 13: gas_price_wei
+        length_delimited_field(&[0x72], vec![0x08, 12]),  // 14: data_gas_price_fri
+        length_delimited_field(&[0x7a], vec![0x08, 13]),  // 15: data_gas_price_wei
+        vec![0x80, 0x01, 0x01],                           // 16: l1_data_availability_mode
+        // 17: signatures
+        length_delimited_field(
+            &[0x8a, 0x01],
+            [vec![0x0a, 34], felt_message(9), vec![0x12, 34], felt_message(10)].concat(),
+        ),
+    ]
+    .concat();
+
+    let signed_block_header =
+        SignedBlockHeader::try_from(protobuf::SignedBlockHeader::decode(bytes.as_slice()).unwrap())
+            .unwrap();
+
+    let expected_block_header = BlockHeader {
+        block_hash: BlockHash(felt!("0x1")),
+        parent_hash: BlockHash(felt!("0x2")),
+        block_number: BlockNumber(5),
+        timestamp: BlockTimestamp(1000),
+        sequencer: SequencerContractAddress(contract_address!("0x3")),
+        state_root: GlobalRoot(felt!("0x4")),
+        state_diff_commitment: Some(StateDiffCommitment(PoseidonHash(felt!("0x5")))),
+        state_diff_length: Some(7),
+        transaction_commitment: Some(TransactionCommitment(felt!("0x6"))),
+        n_transactions: 2,
+        event_commitment: Some(EventCommitment(felt!("0x7"))),
+        n_events: 3,
+        receipt_commitment: Some(ReceiptCommitment(felt!("0x8"))),
+        starknet_version: StarknetVersion("0.13.1".to_owned()),
+        l1_gas_price: GasPricePerToken { price_in_fri: GasPrice(10), price_in_wei: GasPrice(11) },
+        l1_data_gas_price: GasPricePerToken {
+            price_in_fri: GasPrice(12),
+            price_in_wei: GasPrice(13),
+        },
+        l1_da_mode: L1DataAvailabilityMode::Blob,
+    };
+    assert_eq!(signed_block_header.block_header, expected_block_header);
+    assert_eq!(
+        signed_block_header.signatures,
+        vec![BlockSignature(Signature { r: felt!("0x9"), s: felt!("0xa") })]
+    );
+}
+
+#[test]
+fn decode_spec_header_without_optional_fields() {
+    let signed_block_header = SignedBlockHeader::try_from(
+        protobuf::SignedBlockHeader::decode(spec_header_required_fields().as_slice()).unwrap(),
+    )
+    .unwrap();
+
+    let expected_block_header = BlockHeader {
+        block_hash: BlockHash(felt!("0x1")),
+        parent_hash: BlockHash(felt!("0x2")),
+        block_number: BlockNumber(5),
+        timestamp: BlockTimestamp(1000),
+        sequencer: SequencerContractAddress(contract_address!("0x3")),
+        state_root: GlobalRoot(felt!("0x4")),
+        starknet_version: StarknetVersion("0.13.1".to_owned()),
+        ..BlockHeader::default()
+    };
+    assert_eq!(signed_block_header.block_header, expected_block_header);
+    assert!(signed_block_header.signatures.is_empty());
+}
+
+#[test]
+fn header_without_commitments_to_bytes_and_back() {
+    let mut rng = get_rng();
+    let mut signed_block_header = SignedBlockHeader::get_test_instance(&mut rng);
+    signed_block_header.block_header.transaction_commitment = None;
+    signed_block_header.block_header.n_transactions = 0;
+    signed_block_header.block_header.event_commitment = None;
+    signed_block_header.block_header.n_events = 0;
+    let data = DataOrFin(Some(signed_block_header));
+    let bytes_data = Vec::<u8>::from(data.clone());
+    let res_data = DataOrFin::try_from(bytes_data).unwrap();
+    assert_eq!(res_data, data);
+}
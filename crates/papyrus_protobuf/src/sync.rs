@@ -1,6 +1,11 @@
+#[cfg(test)]
+#[path = "sync_test.rs"]
+mod sync_test;
+
 use std::fmt::Debug;
 
 use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 use starknet_api::block::{BlockHash, BlockHeader, BlockNumber, BlockSignature};
 use starknet_api::core::{ClassHash, CompiledClassHash, ContractAddress, Nonce};
 use starknet_api::state::StorageKey;
@@ -8,7 +13,7 @@ use starknet_types_core::felt::Felt;
 #[cfg(any(feature = "testing", test))]
 use test_utils::{auto_impl_get_test_instance, get_number_of_variants, GetTestInstance};
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash, Deserialize, Serialize)]
 pub enum Direction {
     #[default]
     Forward,
@@ -16,7 +21,7 @@ pub enum Direction {
 }
 
 /// This struct represents a query that can be sent to a peer.
-#[derive(Default, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Query {
     pub start_block: BlockHashOrNumber,
     pub direction: Direction,
@@ -24,7 +29,7 @@ pub struct Query {
     pub step: u64,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Deserialize, Serialize)]
 pub enum BlockHashOrNumber {
     Hash(BlockHash),
     Number(BlockNumber),
@@ -0,0 +1,40 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use starknet_api::block::{BlockHash, BlockNumber};
+use starknet_api::felt;
+
+use crate::sync::{BlockHashOrNumber, Direction, Query};
+
+fn assert_serde_round_trip<T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug>(
+    value: T,
+) {
+    let serialized = serde_json::to_value(&value).unwrap();
+    assert_eq!(serde_json::from_value::<T>(serialized).unwrap(), value);
+}
+
+#[test]
+fn query_serde_round_trip() {
+    for direction in [Direction::Forward, Direction::Backward] {
+        for start_block in [
+            BlockHashOrNumber::Hash(BlockHash(felt!("0x1234"))),
+            BlockHashOrNumber::Number(BlockNumber(5)),
+        ] {
+            assert_serde_round_trip(direction);
+            assert_serde_round_trip(start_block);
+            assert_serde_round_trip(Query { start_block, direction, limit: 10, step: 2 });
+        }
+    }
+}
+
+#[test]
+fn block_hash_or_number_is_serialized_as_a_tagged_enum() {
+    assert_eq!(
+        serde_json::to_value(BlockHashOrNumber::Number(BlockNumber(5))).unwrap(),
+        json!({"Number": 5})
+    );
+    assert_eq!(
+        serde_json::to_value(BlockHashOrNumber::Hash(BlockHash(felt!("0x1234")))).unwrap(),
+        json!({"Hash": "0x1234"})
+    );
+}
@@ -34,6 +34,9 @@ pub fn get_test_rpc_config() -> RpcConfig {
             eth_fee_contract_address: contract_address!("0x1001"),
             strk_fee_contract_address: contract_address!("0x1001"),
             initial_gas_cost: 10000000000,
+            hot_state_blocks: 10,
+            hot_state_max_entries: 100000,
+            execution_threads: 1,
         },
         server_address: String::from("127.0.0.1:0"),
         max_events_chunk_size: 10,
@@ -6,7 +6,9 @@ use jsonrpsee::types::ErrorObjectOwned;
 use jsonrpsee::RpcModule;
 use lazy_static::lazy_static;
 use papyrus_common::pending_classes::{PendingClasses, PendingClassesTrait};
+use papyrus_execution::hot_state::HotStateCache;
 use papyrus_execution::objects::{
+    BlockContextOverrides,
     FeeEstimation as ExecutionFeeEstimate,
     PendingData as ExecutionPendingData,
 };
@@ -153,6 +155,7 @@ pub struct JsonRpcServerImpl {
     pub pending_data: Arc<RwLock<PendingData>>,
     pub pending_classes: Arc<RwLock<PendingClasses>>,
     pub writer_client: Arc<dyn StarknetWriter>,
+    pub hot_state_cache: HotStateCache,
 }
 
 #[async_trait]
@@ -999,6 +1002,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
         drop(storage_txn);
         let state_number = StateNumber::unchecked_right_after_block(block_number);
         let execution_config = self.execution_config;
+        let hot_state_cache = self.hot_state_cache.clone();
 
         let chain_id = self.chain_id.clone();
         let reader = self.storage_reader.clone();
@@ -1012,6 +1016,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
                 state_number,
                 block_number,
                 &execution_config,
+                Some(&hot_state_cache),
                 validate,
                 IGNORE_L1_DA_MODE,
             )
@@ -1068,6 +1073,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
         drop(storage_txn);
         let state_number = StateNumber::unchecked_right_after_block(block_number);
         let execution_config = self.execution_config;
+        let hot_state_cache = self.hot_state_cache.clone();
 
         let chain_id = self.chain_id.clone();
         let reader = self.storage_reader.clone();
@@ -1084,7 +1090,10 @@ impl JsonRpcServer for JsonRpcServerImpl {
                 maybe_pending_data,
                 state_number,
                 block_number,
+                BlockContextOverrides::default(),
+                None,
                 &execution_config,
+                Some(&hot_state_cache),
                 charge_fee,
                 validate,
                 IGNORE_L1_DA_MODE,
@@ -1219,6 +1228,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
         drop(storage_txn);
 
         let execution_config = self.execution_config;
+        let hot_state_cache = self.hot_state_cache.clone();
 
         let chain_id = self.chain_id.clone();
         let reader = self.storage_reader.clone();
@@ -1232,7 +1242,10 @@ impl JsonRpcServer for JsonRpcServerImpl {
                 maybe_pending_data,
                 state_number,
                 block_number,
+                BlockContextOverrides::default(),
+                None,
                 &execution_config,
+                Some(&hot_state_cache),
                 true,
                 true,
                 IGNORE_L1_DA_MODE,
@@ -1335,6 +1348,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
         drop(storage_txn);
 
         let execution_config = self.execution_config;
+        let hot_state_cache = self.hot_state_cache.clone();
 
         let chain_id = self.chain_id.clone();
         let reader = self.storage_reader.clone();
@@ -1349,7 +1363,10 @@ impl JsonRpcServer for JsonRpcServerImpl {
                 maybe_pending_data,
                 state_number,
                 block_number,
+                BlockContextOverrides::default(),
+                None,
                 &execution_config,
+                Some(&hot_state_cache),
                 true,
                 true,
                 IGNORE_L1_DA_MODE,
@@ -1400,6 +1417,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
         drop(storage_txn);
         let state_number = StateNumber::unchecked_right_after_block(block_number);
         let execution_config = self.execution_config;
+        let hot_state_cache = self.hot_state_cache.clone();
 
         let chain_id = self.chain_id.clone();
         let reader = self.storage_reader.clone();
@@ -1413,6 +1431,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
                 state_number,
                 block_number,
                 &execution_config,
+                Some(&hot_state_cache),
                 false,
                 IGNORE_L1_DA_MODE,
             )
@@ -1513,6 +1532,10 @@ impl JsonRpcServerTrait for JsonRpcServerImpl {
             pending_data,
             pending_classes,
             writer_client,
+            hot_state_cache: HotStateCache::new(
+                execution_config.hot_state_blocks,
+                execution_config.hot_state_max_entries,
+            ),
         }
     }
 
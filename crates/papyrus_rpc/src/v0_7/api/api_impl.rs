@@ -6,7 +6,12 @@ use jsonrpsee::types::ErrorObjectOwned;
 use jsonrpsee::RpcModule;
 use lazy_static::lazy_static;
 use papyrus_common::pending_classes::{PendingClasses, PendingClassesTrait};
-use papyrus_execution::objects::{FeeEstimation, PendingData as ExecutionPendingData};
+use papyrus_execution::hot_state::HotStateCache;
+use papyrus_execution::objects::{
+    BlockContextOverrides,
+    FeeEstimation,
+    PendingData as ExecutionPendingData,
+};
 use papyrus_execution::{
     estimate_fee as exec_estimate_fee,
     execute_call,
@@ -159,6 +164,7 @@ pub struct JsonRpcServerImpl {
     pub pending_data: Arc<RwLock<PendingData>>,
     pub pending_classes: Arc<RwLock<PendingClasses>>,
     pub writer_client: Arc<dyn StarknetWriter>,
+    pub hot_state_cache: HotStateCache,
 }
 
 #[async_trait]
@@ -989,6 +995,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
         drop(storage_txn);
         let state_number = StateNumber::unchecked_right_after_block(block_number);
         let execution_config = self.execution_config;
+        let hot_state_cache = self.hot_state_cache.clone();
 
         let chain_id = self.chain_id.clone();
         let reader = self.storage_reader.clone();
@@ -1002,6 +1009,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
                 state_number,
                 block_number,
                 &execution_config,
+                Some(&hot_state_cache),
                 validate,
                 DONT_IGNORE_L1_DA_MODE,
             )
@@ -1053,6 +1061,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
         drop(storage_txn);
         let state_number = StateNumber::unchecked_right_after_block(block_number);
         let execution_config = self.execution_config;
+        let hot_state_cache = self.hot_state_cache.clone();
 
         let chain_id = self.chain_id.clone();
         let reader = self.storage_reader.clone();
@@ -1069,7 +1078,10 @@ impl JsonRpcServer for JsonRpcServerImpl {
                 maybe_pending_data,
                 state_number,
                 block_number,
+                BlockContextOverrides::default(),
+                None,
                 &execution_config,
+                Some(&hot_state_cache),
                 charge_fee,
                 validate,
                 DONT_IGNORE_L1_DA_MODE,
@@ -1204,6 +1216,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
         drop(storage_txn);
 
         let execution_config = self.execution_config;
+        let hot_state_cache = self.hot_state_cache.clone();
 
         let chain_id = self.chain_id.clone();
         let reader = self.storage_reader.clone();
@@ -1217,7 +1230,10 @@ impl JsonRpcServer for JsonRpcServerImpl {
                 maybe_pending_data,
                 state_number,
                 block_number,
+                BlockContextOverrides::default(),
+                None,
                 &execution_config,
+                Some(&hot_state_cache),
                 true,
                 true,
                 DONT_IGNORE_L1_DA_MODE,
@@ -1318,6 +1334,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
         drop(storage_txn);
 
         let execution_config = self.execution_config;
+        let hot_state_cache = self.hot_state_cache.clone();
 
         let chain_id = self.chain_id.clone();
         let reader = self.storage_reader.clone();
@@ -1332,7 +1349,10 @@ impl JsonRpcServer for JsonRpcServerImpl {
                 maybe_pending_data,
                 state_number,
                 block_number,
+                BlockContextOverrides::default(),
+                None,
                 &execution_config,
+                Some(&hot_state_cache),
                 true,
                 true,
                 DONT_IGNORE_L1_DA_MODE,
@@ -1387,6 +1407,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
         drop(storage_txn);
         let state_number = StateNumber::unchecked_right_after_block(block_number);
         let execution_config = self.execution_config;
+        let hot_state_cache = self.hot_state_cache.clone();
 
         let chain_id = self.chain_id.clone();
         let reader = self.storage_reader.clone();
@@ -1400,6 +1421,7 @@ impl JsonRpcServer for JsonRpcServerImpl {
                 state_number,
                 block_number,
                 &execution_config,
+                Some(&hot_state_cache),
                 false,
                 DONT_IGNORE_L1_DA_MODE,
             )
@@ -1642,6 +1664,10 @@ impl JsonRpcServerTrait for JsonRpcServerImpl {
             pending_data,
             pending_classes,
             writer_client,
+            hot_state_cache: HotStateCache::new(
+                execution_config.hot_state_blocks,
+                execution_config.hot_state_max_entries,
+            ),
         }
     }
 
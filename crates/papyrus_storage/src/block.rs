@@ -0,0 +1,69 @@
+//! Interface for appending all the data of a block at once.
+//!
+//! Import [`BlockStorageWriter`] to append a block using a [`StorageTxn`]. The header, body, state
+//! diff, classes and compiled classes of the block are written in the same transaction, so either
+//! all of their markers advance on commit or none of them does.
+
+#[cfg(test)]
+#[path = "block_test.rs"]
+mod block_test;
+
+use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
+use starknet_api::block::{Block, BlockNumber};
+use starknet_api::core::ClassHash;
+use starknet_api::deprecated_contract_class::ContractClass as DeprecatedContractClass;
+use starknet_api::state::{ContractClass, ThinStateDiff};
+
+use crate::body::BodyStorageWriter;
+use crate::class::ClassStorageWriter;
+use crate::compiled_class::CasmStorageWriter;
+use crate::db::RW;
+use crate::header::HeaderStorageWriter;
+use crate::state::StateStorageWriter;
+use crate::{StorageResult, StorageTxn};
+
+/// Interface for appending all the data of a block.
+pub trait BlockStorageWriter
+where
+    Self: Sized,
+{
+    /// Appends the header, body and state diff of a block, the classes it declares and the
+    /// compiled classes of its Cairo 1 classes.
+    ///
+    /// The checks of the individual append functions apply, so the block must be the next one for
+    /// each of the header, body, state and class markers. As with
+    /// [`append_casm`](CasmStorageWriter::append_casm), the compiled class marker only advances
+    /// when a compiled class is appended.
+    // To enforce that no commit happen after a failure, we consume and return Self on success.
+    fn append_block(
+        self,
+        block_number: BlockNumber,
+        block: Block,
+        state_diff: ThinStateDiff,
+        classes: &[(ClassHash, &ContractClass)],
+        deprecated_classes: &[(ClassHash, &DeprecatedContractClass)],
+        casms: &[(ClassHash, &CasmContractClass)],
+    ) -> StorageResult<Self>;
+}
+
+impl<'env> BlockStorageWriter for StorageTxn<'env, RW> {
+    fn append_block(
+        self,
+        block_number: BlockNumber,
+        block: Block,
+        state_diff: ThinStateDiff,
+        classes: &[(ClassHash, &ContractClass)],
+        deprecated_classes: &[(ClassHash, &DeprecatedContractClass)],
+        casms: &[(ClassHash, &CasmContractClass)],
+    ) -> StorageResult<Self> {
+        let mut txn = self
+            .append_header(block_number, &block.header)?
+            .append_body(block_number, block.body)?
+            .append_state_diff(block_number, state_diff)?
+            .append_classes(block_number, classes, deprecated_classes)?;
+        for (class_hash, casm) in casms {
+            txn = txn.append_casm(class_hash, casm)?;
+        }
+        Ok(txn)
+    }
+}
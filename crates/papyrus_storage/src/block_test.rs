@@ -0,0 +1,94 @@
+use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
+use indexmap::indexmap;
+use pretty_assertions::assert_eq;
+use starknet_api::block::{Block, BlockNumber};
+use starknet_api::core::{ClassHash, CompiledClassHash};
+use starknet_api::felt;
+use starknet_api::state::{ContractClass, ThinStateDiff};
+
+use crate::block::BlockStorageWriter;
+use crate::body::BodyStorageReader;
+use crate::class::ClassStorageReader;
+use crate::compiled_class::CasmStorageReader;
+use crate::header::HeaderStorageReader;
+use crate::state::StateStorageReader;
+use crate::test_utils::{get_test_storage, BlockBuilder};
+use crate::StorageReader;
+
+// Returns the header, body, state, class and compiled class markers.
+fn markers(reader: &StorageReader) -> [BlockNumber; 5] {
+    let txn = reader.begin_ro_txn().unwrap();
+    [
+        txn.get_header_marker().unwrap(),
+        txn.get_body_marker().unwrap(),
+        txn.get_state_marker().unwrap(),
+        txn.get_class_marker().unwrap(),
+        txn.get_compiled_class_marker().unwrap(),
+    ]
+}
+
+// Returns the genesis block and a state diff that declares a single class.
+fn genesis_block_declaring_a_class(class_hash: ClassHash) -> (Block, ThinStateDiff) {
+    BlockBuilder::genesis()
+        .state_diff(ThinStateDiff {
+            declared_classes: indexmap! { class_hash => CompiledClassHash(felt!("0x2")) },
+            ..Default::default()
+        })
+        .build()
+}
+
+#[test]
+fn append_block_advances_all_markers_together() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let class_hash = ClassHash(felt!("0x1"));
+    let (block, state_diff) = genesis_block_declaring_a_class(class_hash);
+    let class = ContractClass::default();
+    let casm = CasmContractClass::default();
+
+    writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_block(
+            BlockNumber(0),
+            block.clone(),
+            state_diff.clone(),
+            &[(class_hash, &class)],
+            &[],
+            &[(class_hash, &casm)],
+        )
+        .unwrap()
+        .commit()
+        .unwrap();
+
+    assert_eq!(markers(&reader), [BlockNumber(1); 5]);
+    let txn = reader.begin_ro_txn().unwrap();
+    assert_eq!(txn.get_block_header(BlockNumber(0)).unwrap(), Some(block.header));
+    assert_eq!(txn.get_state_diff(BlockNumber(0)).unwrap(), Some(state_diff));
+    assert_eq!(txn.get_class(&class_hash).unwrap(), Some(class));
+    assert_eq!(txn.get_casm(&class_hash).unwrap(), Some(casm));
+}
+
+#[test]
+fn append_block_without_commit_advances_no_marker() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let class_hash = ClassHash(felt!("0x1"));
+    let (block, state_diff) = genesis_block_declaring_a_class(class_hash);
+
+    // Simulate a crash after the block was appended and before the transaction was committed.
+    let txn = writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_block(
+            BlockNumber(0),
+            block,
+            state_diff,
+            &[(class_hash, &ContractClass::default())],
+            &[],
+            &[(class_hash, &CasmContractClass::default())],
+        )
+        .unwrap();
+    drop(txn);
+
+    assert_eq!(markers(&reader), [BlockNumber(0); 5]);
+    assert!(reader.begin_ro_txn().unwrap().get_block_header(BlockNumber(0)).unwrap().is_none());
+}
@@ -1,14 +1,16 @@
 use assert_matches::assert_matches;
 use pretty_assertions::assert_eq;
 use starknet_api::block::{BlockBody, BlockNumber};
-use starknet_api::transaction::TransactionOffsetInBlock;
+use starknet_api::hash::StarkHash;
+use starknet_api::transaction::{TransactionHash, TransactionOffsetInBlock, TransactionReceipt};
 use test_case::test_case;
 use test_utils::{get_test_block, get_test_body};
 
 use crate::body::{BodyStorageReader, BodyStorageWriter, TransactionIndex};
 use crate::db::table_types::Table;
 use crate::db::{DbError, KeyAlreadyExistsError};
-use crate::test_utils::{get_test_storage, get_test_storage_by_scope};
+use crate::header::HeaderStorageWriter;
+use crate::test_utils::{get_test_storage, get_test_storage_by_scope, BlockBuilder};
 use crate::{OffsetKind, StorageError, StorageScope, StorageWriter};
 
 #[tokio::test]
@@ -376,6 +378,34 @@ async fn revert_transactions() {
     );
 }
 
+#[test]
+fn get_receipt_by_tx_hash() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let body = get_test_body(2, None, None, None);
+    let block = BlockBuilder::genesis().body(body).append(&mut writer);
+
+    let tx_hash = block.body.transaction_hashes[1];
+    let receipt = reader.begin_ro_txn().unwrap().get_receipt_by_tx_hash(&tx_hash).unwrap();
+    assert_eq!(
+        receipt,
+        Some(TransactionReceipt {
+            transaction_hash: tx_hash,
+            block_hash: block.header.block_hash,
+            block_number: BlockNumber(0),
+            output: block.body.transaction_outputs[1].clone(),
+        })
+    );
+
+    let unknown_tx_hash = TransactionHash(StarkHash::from(u64::MAX));
+    assert!(
+        reader.begin_ro_txn().unwrap().get_receipt_by_tx_hash(&unknown_tx_hash).unwrap().is_none()
+    );
+
+    // Once the header is reverted the block isn't part of the canonical chain anymore.
+    writer.begin_rw_txn().unwrap().revert_header(BlockNumber(0)).unwrap().0.commit().unwrap();
+    assert!(reader.begin_ro_txn().unwrap().get_receipt_by_tx_hash(&tx_hash).unwrap().is_none());
+}
+
 fn append_2_bodies(writer: &mut StorageWriter) {
     writer
         .begin_rw_txn()
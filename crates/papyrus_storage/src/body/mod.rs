@@ -54,12 +54,14 @@ use starknet_api::transaction::{
     TransactionHash,
     TransactionOffsetInBlock,
     TransactionOutput,
+    TransactionReceipt,
 };
 use tracing::debug;
 
 use crate::db::serialization::{NoVersionValueWrapper, VersionZeroWrapper};
 use crate::db::table_types::{CommonPrefix, DbCursorTrait, NoValue, SimpleTable, Table};
 use crate::db::{DbTransaction, TableHandle, TransactionKind, RW};
+use crate::header::HeaderStorageReader;
 use crate::{
     FileHandlers,
     MarkerKind,
@@ -110,6 +112,14 @@ pub trait BodyStorageReader {
         tx_hash: &TransactionHash,
     ) -> StorageResult<Option<TransactionIndex>>;
 
+    /// Returns the receipt of the transaction with the given hash.
+    /// Returns None if the transaction isn't part of a stored block of the canonical chain, e.g. if
+    /// the block that contained it was reverted.
+    fn get_receipt_by_tx_hash(
+        &self,
+        tx_hash: &TransactionHash,
+    ) -> StorageResult<Option<TransactionReceipt>>;
+
     /// Returns the transaction hash with the given transaction index.
     fn get_transaction_hash_by_idx(
         &self,
@@ -206,6 +216,30 @@ impl<'env, Mode: TransactionKind> BodyStorageReader for StorageTxn<'env, Mode> {
         Ok(idx)
     }
 
+    fn get_receipt_by_tx_hash(
+        &self,
+        tx_hash: &TransactionHash,
+    ) -> StorageResult<Option<TransactionReceipt>> {
+        let Some(tx_index) = self.get_transaction_idx_by_hash(tx_hash)? else {
+            return Ok(None);
+        };
+        let Some(output) = self.get_transaction_output(tx_index)? else {
+            return Ok(None);
+        };
+        // The block hash is taken from the canonical header, so a transaction whose block header
+        // is missing isn't considered part of the chain.
+        let block_number = tx_index.0;
+        let Some(header) = self.get_block_header(block_number)? else {
+            return Ok(None);
+        };
+        Ok(Some(TransactionReceipt {
+            transaction_hash: *tx_hash,
+            block_hash: header.block_hash,
+            block_number,
+            output,
+        }))
+    }
+
     fn get_transaction_hash_by_idx(
         &self,
         tx_index: &TransactionIndex,
@@ -27,6 +27,22 @@ fn append_casm() {
     assert_eq!(casm, expected_casm);
 }
 
+#[test]
+fn casm_exists() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    assert!(!reader.begin_ro_txn().unwrap().casm_exists(&ClassHash::default()).unwrap());
+
+    writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_casm(&ClassHash::default(), &CasmContractClass::default())
+        .unwrap()
+        .commit()
+        .unwrap();
+
+    assert!(reader.begin_ro_txn().unwrap().casm_exists(&ClassHash::default()).unwrap());
+}
+
 #[test]
 fn casm_rewrite() {
     let ((_, mut writer), _temp_dir) = get_test_storage();
@@ -140,6 +140,31 @@ fn append_2_headers(writer: &mut StorageWriter) {
         .unwrap();
 }
 
+#[test]
+fn db_tables_stats_count_header_entries() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    append_2_headers(&mut writer);
+    writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_block_signature(BlockNumber(0), &BlockSignature::default())
+        .unwrap()
+        .commit()
+        .unwrap();
+
+    let tables_stats = reader.db_tables_stats().unwrap().tables_stats;
+    assert_eq!(tables_stats["headers"].entries, 2);
+    assert_eq!(tables_stats["block_hash_to_number"].entries, 2);
+    assert_eq!(tables_stats["block_signatures"].entries, 1);
+    assert!(tables_stats["headers"].total_size > 0);
+
+    // Reverting a header removes its entries from the tables.
+    writer.begin_rw_txn().unwrap().revert_header(BlockNumber(1)).unwrap().0.commit().unwrap();
+    let tables_stats = reader.db_tables_stats().unwrap().tables_stats;
+    assert_eq!(tables_stats["headers"].entries, 1);
+    assert_eq!(tables_stats["block_hash_to_number"].entries, 1);
+}
+
 #[tokio::test]
 async fn starknet_version() {
     fn block_header(hash: u8, starknet_version: StarknetVersion) -> BlockHeader {
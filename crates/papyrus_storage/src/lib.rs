@@ -46,6 +46,18 @@
 //! assert_eq!(header, Some(BlockHeader::default()));
 //! # Ok::<(), papyrus_storage::StorageError>(())
 //! ```
+//! # Concurrency
+//!
+//! Every read goes through a [`StorageTxn`], which reads from a snapshot of the storage taken when
+//! the transaction began. Changes committed after that, including a block that is committed while
+//! the transaction is open, are not visible to it, so readers never observe a partially written
+//! block. Data that is kept in memory mapped files is only reachable through locations that are
+//! committed in the database, so it follows the same snapshot.
+//!
+//! Consequently, no lock between the writer and the readers is needed. A reader that needs a
+//! consistent view across several reads, e.g. a single RPC request, should use one transaction
+//! for all of them, since separate transactions may observe different blocks.
+//!
 //! # Storage Version
 //!
 //! Attempting to open an existing database using a crate version with a mismatching storage version
@@ -76,6 +88,7 @@
 //! [`libmdbx`]: https://docs.rs/libmdbx/latest/libmdbx/
 
 pub mod base_layer;
+pub mod block;
 pub mod body;
 pub mod class;
 pub mod compiled_class;
@@ -155,7 +168,7 @@ use crate::version::{VersionStorageReader, VersionStorageWriter};
 
 // For more details on the storage version, see the module documentation.
 /// The current version of the storage state code.
-pub const STORAGE_VERSION_STATE: Version = Version { major: 1, minor: 1 };
+pub const STORAGE_VERSION_STATE: Version = Version { major: 1, minor: 2 };
 /// The current version of the storage blocks code.
 pub const STORAGE_VERSION_BLOCKS: Version = Version { major: 2, minor: 0 };
 
@@ -173,6 +186,8 @@ pub fn open_storage(
         declared_classes_block: db_writer.create_simple_table("declared_classes_block")?,
         deprecated_declared_classes: db_writer
             .create_simple_table("deprecated_declared_classes")?,
+        deprecated_declared_classes_block: db_writer
+            .create_simple_table("deprecated_declared_classes_block")?,
         deployed_contracts: db_writer.create_simple_table("deployed_contracts")?,
         events: db_writer.create_common_prefix_table("events")?,
         headers: db_writer.create_simple_table("headers")?,
@@ -410,7 +425,8 @@ pub struct StorageReader {
 
 impl StorageReader {
     /// Takes a snapshot of the current state of the storage and returns a [`StorageTxn`] for
-    /// reading data from the storage.
+    /// reading data from the storage. Changes committed after the snapshot was taken aren't visible
+    /// to the transaction.
     pub fn begin_ro_txn(&self) -> StorageResult<StorageTxn<'_, RO>> {
         Ok(StorageTxn {
             txn: self.db_reader.begin_ro_txn()?,
@@ -519,6 +535,7 @@ struct_field_names! {
         declared_classes: TableIdentifier<ClassHash, VersionZeroWrapper<LocationInFile>, SimpleTable>,
         declared_classes_block: TableIdentifier<ClassHash, NoVersionValueWrapper<BlockNumber>, SimpleTable>,
         deprecated_declared_classes: TableIdentifier<ClassHash, VersionZeroWrapper<IndexedDeprecatedContractClass>, SimpleTable>,
+        deprecated_declared_classes_block: TableIdentifier<ClassHash, NoVersionValueWrapper<BlockNumber>, SimpleTable>,
         // TODO(dvir): consider use here also the CommonPrefix table type.
         deployed_contracts: TableIdentifier<(ContractAddress, BlockNumber), VersionZeroWrapper<ClassHash>, SimpleTable>,
         events: TableIdentifier<(ContractAddress, TransactionIndex), NoVersionValueWrapper<NoValue>, CommonPrefix>,
@@ -603,6 +620,10 @@ pub enum StorageError {
          {block_number}."
     )]
     BlockSignatureForNonExistingBlock { block_number: BlockNumber, block_signature: BlockSignature },
+    #[error("Block {block_number} is missing a header, body, state diff or class.")]
+    MissingBlockData { block_number: BlockNumber },
+    #[error("No block with hash {block_hash} is stored.")]
+    BlockHashNotFound { block_hash: BlockHash },
 }
 
 /// A type alias that maps to std::result::Result<T, StorageError>.
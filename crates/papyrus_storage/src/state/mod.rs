@@ -11,7 +11,7 @@
 //! use papyrus_storage::state::{StateStorageReader, StateStorageWriter};
 //! # use indexmap::IndexMap;
 //! # use papyrus_storage::{db::DbConfig, StorageConfig};
-//! # use starknet_api::block::BlockNumber;
+//! # use starknet_api::block::{BlockHash, BlockNumber};
 //! # use starknet_api::core::{ChainId, ContractAddress};
 //! use starknet_api::state::{StateNumber, ThinStateDiff};
 //!
@@ -54,7 +54,8 @@ pub mod data;
 #[cfg(test)]
 mod state_test;
 
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
 
 use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
 use indexmap::IndexMap;
@@ -67,10 +68,11 @@ use starknet_types_core::felt::Felt;
 use tracing::debug;
 
 use crate::db::serialization::{NoVersionValueWrapper, VersionZeroWrapper};
-use crate::db::table_types::{CommonPrefix, DbCursorTrait, SimpleTable, Table};
-use crate::db::{DbTransaction, TableHandle, TransactionKind, RW};
+use crate::db::table_types::{CommonPrefix, DbCursor, DbCursorTrait, SimpleTable, Table};
+use crate::db::{DbIter, DbTransaction, TableHandle, TransactionKind, RW};
 #[cfg(feature = "document_calls")]
 use crate::document_calls::{add_query, StorageQuery};
+use crate::header::HeaderStorageReader;
 use crate::mmap_file::LocationInFile;
 use crate::state::data::IndexedDeprecatedContractClass;
 use crate::{
@@ -131,6 +133,43 @@ pub trait StateStorageReader<Mode: TransactionKind> {
     fn get_state_diff(&self, block_number: BlockNumber) -> StorageResult<Option<ThinStateDiff>>;
     /// Returns a state reader.
     fn get_state_reader(&self) -> StorageResult<StateReader<'_, Mode>>;
+    /// Returns a state reader and the number of the state right after the block with the given
+    /// hash. Only blocks of the canonical chain are found, since the hashes of reverted blocks are
+    /// removed from the storage.
+    ///
+    /// # Errors
+    /// Returns [`StorageError::BlockHashNotFound`] if no stored block has the given hash, and
+    /// [`StorageError::MissingBlockData`] if the state diff of the block isn't stored yet.
+    fn get_state_at_hash(
+        &self,
+        block_hash: &BlockHash,
+    ) -> StorageResult<(StateReader<'_, Mode>, StateNumber)>;
+    /// Returns the contracts deployed in the given block range, ordered by block number and by
+    /// their order in each block's state diff. Blocks that aren't in the storage yet are ignored.
+    /// Classes replaced in the range are not included.
+    fn deployed_contracts_in_range(
+        &self,
+        block_range: Range<BlockNumber>,
+    ) -> StorageResult<Vec<(BlockNumber, ContractAddress, ClassHash)>>;
+    /// Returns the number of the block that declared the given class or deprecated class. A
+    /// deprecated class may be declared more than once, in which case the first block that
+    /// declared it is returned.
+    fn get_class_declaration_block(
+        &self,
+        class_hash: &ClassHash,
+    ) -> StorageResult<Option<BlockNumber>>;
+    /// Returns the sorted hashes of the stored classes (definitions or compiled classes) that the
+    /// canonical state doesn't reference: they're not declared in a stored block and no deployed
+    /// or replaced contract has them as its class. Such classes are leftovers of reverted blocks
+    /// and can be pruned.
+    fn find_orphaned_classes(&self) -> StorageResult<Vec<ClassHash>>;
+    /// Returns an iterator over the state diffs of the blocks in the given range, in ascending
+    /// block order. The state diffs are read lazily, walking the state diffs table with a cursor.
+    ///
+    /// # Errors
+    /// The iterator yields [`StorageError::MissingBlockData`] for the first block in the range
+    /// whose state diff isn't stored, and ends after it.
+    fn state_diff_iter(&self, range: Range<BlockNumber>) -> StorageResult<StateDiffIter<'_, Mode>>;
 }
 
 type RevertedStateDiff = (
@@ -152,6 +191,19 @@ where
         thin_state_diff: ThinStateDiff,
     ) -> StorageResult<Self>;
 
+    /// Appends consecutive state diffs without classes to the storage, starting at
+    /// `first_block_number`.
+    ///
+    /// Storage and nonce updates that rewrite the value set by an earlier diff in the batch are
+    /// not written to the state tables. The state diff of each block is still stored as is and the
+    /// state marker advances block by block, so the state read at every block is the same as if
+    /// the diffs were appended one by one.
+    fn append_state_diffs(
+        self,
+        first_block_number: BlockNumber,
+        thin_state_diffs: Vec<ThinStateDiff>,
+    ) -> StorageResult<Self>;
+
     /// Removes a state diff from the storage and returns the removed data.
     fn revert_state_diff(
         self,
@@ -181,6 +233,171 @@ impl<'env, Mode: TransactionKind> StateStorageReader<Mode> for StorageTxn<'env,
     fn get_state_reader(&self) -> StorageResult<StateReader<'_, Mode>> {
         StateReader::new(self)
     }
+
+    fn get_state_at_hash(
+        &self,
+        block_hash: &BlockHash,
+    ) -> StorageResult<(StateReader<'_, Mode>, StateNumber)> {
+        let block_number = self
+            .get_block_number_by_hash(block_hash)?
+            .ok_or(StorageError::BlockHashNotFound { block_hash: *block_hash })?;
+        if block_number >= self.get_state_marker()? {
+            return Err(StorageError::MissingBlockData { block_number });
+        }
+        Ok((self.get_state_reader()?, StateNumber::unchecked_right_after_block(block_number)))
+    }
+
+    fn deployed_contracts_in_range(
+        &self,
+        block_range: Range<BlockNumber>,
+    ) -> StorageResult<Vec<(BlockNumber, ContractAddress, ClassHash)>> {
+        let end_block_number = block_range.end.min(self.get_state_marker()?);
+        let mut deployed_contracts = Vec::new();
+        for block_number in block_range.start.iter_up_to(end_block_number) {
+            let Some(thin_state_diff) = self.get_state_diff(block_number)? else {
+                continue;
+            };
+            deployed_contracts.extend(
+                thin_state_diff
+                    .deployed_contracts
+                    .into_iter()
+                    .map(|(address, class_hash)| (block_number, address, class_hash)),
+            );
+        }
+        Ok(deployed_contracts)
+    }
+
+    fn get_class_declaration_block(
+        &self,
+        class_hash: &ClassHash,
+    ) -> StorageResult<Option<BlockNumber>> {
+        let declared_classes_block_table = self.open_table(&self.tables.declared_classes_block)?;
+        if let Some(block_number) = declared_classes_block_table.get(&self.txn, class_hash)? {
+            return Ok(Some(block_number));
+        }
+        let deprecated_declared_classes_block_table =
+            self.open_table(&self.tables.deprecated_declared_classes_block)?;
+        if let Some(block_number) =
+            deprecated_declared_classes_block_table.get(&self.txn, class_hash)?
+        {
+            return Ok(Some(block_number));
+        }
+        // Storages written before the deprecated classes were indexed miss the older declarations
+        // in the index, but the downloaded deprecated classes are stored with their block number.
+        let deprecated_declared_classes_table =
+            self.open_table(&self.tables.deprecated_declared_classes)?;
+        Ok(deprecated_declared_classes_table
+            .get(&self.txn, class_hash)?
+            .map(|indexed_class| indexed_class.block_number))
+    }
+
+    fn state_diff_iter(&self, range: Range<BlockNumber>) -> StorageResult<StateDiffIter<'_, Mode>> {
+        let state_diffs_table = self.open_table(&self.tables.state_diffs)?;
+        Ok(StateDiffIter {
+            cursor: state_diffs_table.cursor(&self.txn)?,
+            file_handlers: &self.file_handlers,
+            cursor_positioned: false,
+            next_block_number: range.start,
+            end_block_number: range.end,
+        })
+    }
+
+    fn find_orphaned_classes(&self) -> StorageResult<Vec<ClassHash>> {
+        let state_marker = self.get_state_marker()?;
+        let declared_classes_table = self.open_table(&self.tables.declared_classes)?;
+        let declared_classes_block_table = self.open_table(&self.tables.declared_classes_block)?;
+        let deprecated_declared_classes_table =
+            self.open_table(&self.tables.deprecated_declared_classes)?;
+        let casms_table = self.open_table(&self.tables.casms)?;
+        let deployed_contracts_table = self.open_table(&self.tables.deployed_contracts)?;
+
+        // Deployed contracts of reverted blocks are deleted, so all the entries are canonical.
+        let mut referenced_classes = HashSet::new();
+        let mut cursor = deployed_contracts_table.cursor(&self.txn)?;
+        for entry in DbIter::new(&mut cursor) {
+            let (_, class_hash) = entry?;
+            referenced_classes.insert(class_hash);
+        }
+        let mut cursor = declared_classes_block_table.cursor(&self.txn)?;
+        for entry in DbIter::new(&mut cursor) {
+            let (class_hash, block_number) = entry?;
+            if block_number < state_marker {
+                referenced_classes.insert(class_hash);
+            }
+        }
+
+        let mut orphaned_classes = BTreeSet::new();
+        let mut cursor = declared_classes_table.cursor(&self.txn)?;
+        for entry in DbIter::new(&mut cursor) {
+            let (class_hash, _) = entry?;
+            if !referenced_classes.contains(&class_hash) {
+                orphaned_classes.insert(class_hash);
+            }
+        }
+        let mut cursor = casms_table.cursor(&self.txn)?;
+        for entry in DbIter::new(&mut cursor) {
+            let (class_hash, _) = entry?;
+            if !referenced_classes.contains(&class_hash) {
+                orphaned_classes.insert(class_hash);
+            }
+        }
+        // A deprecated class is declared in the block it's stored with.
+        let mut cursor = deprecated_declared_classes_table.cursor(&self.txn)?;
+        for entry in DbIter::new(&mut cursor) {
+            let (class_hash, IndexedDeprecatedContractClass { block_number, .. }) = entry?;
+            if block_number >= state_marker && !referenced_classes.contains(&class_hash) {
+                orphaned_classes.insert(class_hash);
+            }
+        }
+        Ok(orphaned_classes.into_iter().collect())
+    }
+}
+
+type StateDiffsTableCursor<'txn, Mode> =
+    DbCursor<'txn, Mode, BlockNumber, VersionZeroWrapper<LocationInFile>, SimpleTable>;
+
+/// An iterator over the state diffs of consecutive blocks, created by
+/// [`StateStorageReader::state_diff_iter`].
+pub struct StateDiffIter<'txn, Mode: TransactionKind> {
+    cursor: StateDiffsTableCursor<'txn, Mode>,
+    file_handlers: &'txn FileHandlers<Mode>,
+    // The cursor is positioned at the first block of the range on the first call to `next`.
+    cursor_positioned: bool,
+    next_block_number: BlockNumber,
+    end_block_number: BlockNumber,
+}
+
+impl<Mode: TransactionKind> StateDiffIter<'_, Mode> {
+    fn read_state_diff(&mut self, block_number: BlockNumber) -> StorageResult<ThinStateDiff> {
+        let entry = if self.cursor_positioned {
+            self.cursor.next()?
+        } else {
+            self.cursor_positioned = true;
+            self.cursor.lower_bound(&block_number)?
+        };
+        match entry {
+            Some((stored_block_number, location)) if stored_block_number == block_number => {
+                self.file_handlers.get_thin_state_diff_unchecked(location)
+            }
+            _ => Err(StorageError::MissingBlockData { block_number }),
+        }
+    }
+}
+
+impl<Mode: TransactionKind> Iterator for StateDiffIter<'_, Mode> {
+    type Item = StorageResult<(BlockNumber, ThinStateDiff)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_block_number >= self.end_block_number {
+            return None;
+        }
+        let block_number = self.next_block_number;
+        let result = self.read_state_diff(block_number);
+        // The iteration ends after an error.
+        self.next_block_number =
+            if result.is_ok() { block_number.unchecked_next() } else { self.end_block_number };
+        Some(result.map(|state_diff| (block_number, state_diff)))
+    }
 }
 
 /// A single coherent state at a single point in time,
@@ -432,57 +649,39 @@ impl<'env> StateStorageWriter for StorageTxn<'env, RW> {
         block_number: BlockNumber,
         thin_state_diff: ThinStateDiff,
     ) -> StorageResult<Self> {
-        let file_offset_table = self.txn.open_table(&self.tables.file_offsets)?;
-        let markers_table = self.open_table(&self.tables.markers)?;
-        let state_diffs_table = self.open_table(&self.tables.state_diffs)?;
-        let nonces_table = self.open_table(&self.tables.nonces)?;
-        let deployed_contracts_table = self.open_table(&self.tables.deployed_contracts)?;
-        let storage_table = self.open_table(&self.tables.contract_storage)?;
-        let declared_classes_block_table = self.open_table(&self.tables.declared_classes_block)?;
-
-        // Write state.
-        write_deployed_contracts(
-            &thin_state_diff.deployed_contracts,
-            &self.txn,
+        append_state_diff_with_state_writes(
+            self,
             block_number,
-            &deployed_contracts_table,
-            &nonces_table,
-            &thin_state_diff.nonces,
-        )?;
-        write_storage_diffs(
+            &thin_state_diff,
             &thin_state_diff.storage_diffs,
-            &self.txn,
-            block_number,
-            &storage_table,
-        )?;
-        write_nonces(&thin_state_diff.nonces, &self.txn, block_number, &nonces_table)?;
-        write_replaced_classes(
-            &thin_state_diff.replaced_classes,
-            &self.txn,
-            block_number,
-            &deployed_contracts_table,
-        )?;
+            &thin_state_diff.nonces,
+        )
+    }
 
-        // We don't store the deprecated declared classes' block number.
-        for (class_hash, _) in &thin_state_diff.declared_classes {
-            declared_classes_block_table.insert(&self.txn, class_hash, &block_number)?;
+    #[latency_histogram("storage_append_thin_state_diffs_latency_seconds", false)]
+    fn append_state_diffs(
+        self,
+        first_block_number: BlockNumber,
+        thin_state_diffs: Vec<ThinStateDiff>,
+    ) -> StorageResult<Self> {
+        let mut txn = self;
+        let mut block_number = first_block_number;
+        let mut last_storage_values = HashMap::new();
+        let mut last_nonces = HashMap::new();
+        for thin_state_diff in thin_state_diffs {
+            let storage_diffs =
+                compact_storage_diffs(&thin_state_diff.storage_diffs, &mut last_storage_values);
+            let nonces = compact_nonces(&thin_state_diff.nonces, &mut last_nonces);
+            txn = append_state_diff_with_state_writes(
+                txn,
+                block_number,
+                &thin_state_diff,
+                &storage_diffs,
+                &nonces,
+            )?;
+            block_number = block_number.unchecked_next();
         }
-
-        // Write state diff.
-        let location = self.file_handlers.append_state_diff(&thin_state_diff);
-        state_diffs_table.append(&self.txn, &block_number, &location)?;
-        file_offset_table.upsert(&self.txn, &OffsetKind::ThinStateDiff, &location.next_offset())?;
-
-        update_marker_to_next_block(&self.txn, &markers_table, MarkerKind::State, block_number)?;
-
-        advance_compiled_class_marker_over_blocks_without_classes(
-            &self.txn,
-            &markers_table,
-            &state_diffs_table,
-            &self.file_handlers,
-        )?;
-
-        Ok(self)
+        Ok(txn)
     }
 
     fn revert_state_diff(
@@ -494,6 +693,8 @@ impl<'env> StateStorageWriter for StorageTxn<'env, RW> {
         let declared_classes_block_table = self.open_table(&self.tables.declared_classes_block)?;
         let deprecated_declared_classes_table =
             self.open_table(&self.tables.deprecated_declared_classes)?;
+        let deprecated_declared_classes_block_table =
+            self.open_table(&self.tables.deprecated_declared_classes_block)?;
         // TODO(yair): Consider reverting the compiled classes in their own module.
         let compiled_classes_table = self.open_table(&self.tables.casms)?;
         let deployed_contracts_table = self.open_table(&self.tables.deployed_contracts)?;
@@ -543,6 +744,12 @@ impl<'env> StateStorageWriter for StorageTxn<'env, RW> {
             &deprecated_declared_classes_table,
             &self.file_handlers,
         )?;
+        delete_deprecated_declared_classes_block(
+            &self.txn,
+            block_number,
+            &thin_state_diff,
+            &deprecated_declared_classes_block_table,
+        )?;
         let deleted_compiled_classes = delete_compiled_classes(
             &self.txn,
             thin_state_diff.declared_classes.keys(),
@@ -578,6 +785,104 @@ impl<'env> StateStorageWriter for StorageTxn<'env, RW> {
     }
 }
 
+// Appends the given state diff, writing only the given storage diffs and nonces to the state
+// tables.
+fn append_state_diff_with_state_writes<'env>(
+    txn: StorageTxn<'env, RW>,
+    block_number: BlockNumber,
+    thin_state_diff: &ThinStateDiff,
+    storage_diffs: &IndexMap<ContractAddress, IndexMap<StorageKey, Felt>>,
+    nonces: &IndexMap<ContractAddress, Nonce>,
+) -> StorageResult<StorageTxn<'env, RW>> {
+    let file_offset_table = txn.txn.open_table(&txn.tables.file_offsets)?;
+    let markers_table = txn.open_table(&txn.tables.markers)?;
+    let state_diffs_table = txn.open_table(&txn.tables.state_diffs)?;
+    let nonces_table = txn.open_table(&txn.tables.nonces)?;
+    let deployed_contracts_table = txn.open_table(&txn.tables.deployed_contracts)?;
+    let storage_table = txn.open_table(&txn.tables.contract_storage)?;
+    let declared_classes_block_table = txn.open_table(&txn.tables.declared_classes_block)?;
+    let deprecated_declared_classes_block_table =
+        txn.open_table(&txn.tables.deprecated_declared_classes_block)?;
+
+    // Write state.
+    write_deployed_contracts(
+        &thin_state_diff.deployed_contracts,
+        &txn.txn,
+        block_number,
+        &deployed_contracts_table,
+        &nonces_table,
+        &thin_state_diff.nonces,
+    )?;
+    write_storage_diffs(storage_diffs, &txn.txn, block_number, &storage_table)?;
+    write_nonces(nonces, &txn.txn, block_number, &nonces_table)?;
+    write_replaced_classes(
+        &thin_state_diff.replaced_classes,
+        &txn.txn,
+        block_number,
+        &deployed_contracts_table,
+    )?;
+
+    for (class_hash, _) in &thin_state_diff.declared_classes {
+        declared_classes_block_table.insert(&txn.txn, class_hash, &block_number)?;
+    }
+    // A deprecated class may be declared more than once. Keep the block that declared it first.
+    for class_hash in &thin_state_diff.deprecated_declared_classes {
+        if deprecated_declared_classes_block_table.get(&txn.txn, class_hash)?.is_none() {
+            deprecated_declared_classes_block_table.insert(&txn.txn, class_hash, &block_number)?;
+        }
+    }
+
+    // Write state diff.
+    let location = txn.file_handlers.append_state_diff(thin_state_diff);
+    state_diffs_table.append(&txn.txn, &block_number, &location)?;
+    file_offset_table.upsert(&txn.txn, &OffsetKind::ThinStateDiff, &location.next_offset())?;
+
+    update_marker_to_next_block(&txn.txn, &markers_table, MarkerKind::State, block_number)?;
+
+    advance_compiled_class_marker_over_blocks_without_classes(
+        &txn.txn,
+        &markers_table,
+        &state_diffs_table,
+        &txn.file_handlers,
+    )?;
+
+    Ok(txn)
+}
+
+// Returns the storage diffs that change the values last set in the batch, and records the new
+// values.
+fn compact_storage_diffs(
+    storage_diffs: &IndexMap<ContractAddress, IndexMap<StorageKey, Felt>>,
+    last_storage_values: &mut HashMap<(ContractAddress, StorageKey), Felt>,
+) -> IndexMap<ContractAddress, IndexMap<StorageKey, Felt>> {
+    let mut compacted_storage_diffs = IndexMap::new();
+    for (address, storage_entries) in storage_diffs {
+        let compacted_storage_entries = storage_entries
+            .iter()
+            .filter(|(key, value)| {
+                last_storage_values.insert((*address, **key), **value) != Some(**value)
+            })
+            .map(|(key, value)| (*key, *value))
+            .collect::<IndexMap<_, _>>();
+        if !compacted_storage_entries.is_empty() {
+            compacted_storage_diffs.insert(*address, compacted_storage_entries);
+        }
+    }
+    compacted_storage_diffs
+}
+
+// Returns the nonces that change the nonces last set in the batch, and records the new nonces.
+fn compact_nonces(
+    nonces: &IndexMap<ContractAddress, Nonce>,
+    last_nonces: &mut HashMap<ContractAddress, Nonce>,
+) -> IndexMap<ContractAddress, Nonce> {
+    nonces
+        .iter()
+        .filter(|(address, nonce)| last_nonces.insert(**address, **nonce) != Some(**nonce))
+        .map(|(address, nonce)| (*address, *nonce))
+        .collect()
+}
+
 #[latency_histogram("storage_update_marker_to_next_block_latency_seconds", true)]
 fn update_marker_to_next_block<'env>(
     txn: &DbTransaction<'env, RW>,
@@ -764,6 +1069,22 @@ fn delete_deprecated_declared_classes<'env>(
     Ok(deleted_data)
 }
 
+// Deletes the declaration block of the deprecated classes that were first declared in the given
+// block.
+fn delete_deprecated_declared_classes_block<'env>(
+    txn: &'env DbTransaction<'env, RW>,
+    block_number: BlockNumber,
+    thin_state_diff: &ThinStateDiff,
+    deprecated_declared_classes_block_table: &'env DeclaredClassesBlockTable<'env>,
+) -> StorageResult<()> {
+    for class_hash in &thin_state_diff.deprecated_declared_classes {
+        if deprecated_declared_classes_block_table.get(txn, class_hash)? == Some(block_number) {
+            deprecated_declared_classes_block_table.delete(txn, class_hash)?;
+        }
+    }
+    Ok(())
+}
+
 fn delete_compiled_classes<'a, 'env>(
     txn: &'env DbTransaction<'env, RW>,
     class_hashes: impl Iterator<Item = &'a ClassHash>,
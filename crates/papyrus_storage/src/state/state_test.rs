@@ -1,21 +1,25 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use assert_matches::assert_matches;
 use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
 use indexmap::{indexmap, IndexMap};
 use pretty_assertions::assert_eq;
-use starknet_api::block::BlockNumber;
+use starknet_api::block::{BlockHash, BlockHeader, BlockNumber};
 use starknet_api::core::{ClassHash, CompiledClassHash, ContractAddress, Nonce, PatriciaKey};
 use starknet_api::deprecated_contract_class::ContractClass as DeprecatedContractClass;
 use starknet_api::hash::StarkHash;
 use starknet_api::state::{ContractClass, StateNumber, StorageKey, ThinStateDiff};
-use starknet_api::{felt, patricia_key};
+use starknet_api::{contract_address, felt, patricia_key};
 use starknet_types_core::felt::Felt;
 use test_utils::get_test_state_diff;
 
 use crate::class::{ClassStorageReader, ClassStorageWriter};
 use crate::compiled_class::{CasmStorageReader, CasmStorageWriter};
+use crate::db::table_types::Table;
+use crate::header::HeaderStorageWriter;
 use crate::state::{StateStorageReader, StateStorageWriter};
 use crate::test_utils::get_test_storage;
-use crate::StorageWriter;
+use crate::{StorageError, StorageWriter};
 
 #[test]
 fn get_class_definition_at() {
@@ -254,6 +258,135 @@ fn append_state_diff() {
     assert_eq!(statetxn.get_storage_at(state2, &c1, &key0).unwrap(), felt!("0x0"));
 }
 
+#[test]
+fn append_state_diffs_compacted() {
+    let c0 = ContractAddress(patricia_key!("0x11"));
+    let key0 = StorageKey(patricia_key!("0x1001"));
+    let key1 = StorageKey(patricia_key!("0x101"));
+    // Consecutive diffs that keep rewriting the same slots, some of them with unchanged values.
+    let diffs = vec![
+        ThinStateDiff {
+            deployed_contracts: indexmap! { c0 => ClassHash(felt!("0x4")) },
+            storage_diffs: indexmap! {
+                c0 => indexmap! { key0 => felt!("0x1"), key1 => felt!("0x1") },
+            },
+            nonces: indexmap! { c0 => Nonce(felt!("0x1")) },
+            ..Default::default()
+        },
+        ThinStateDiff {
+            storage_diffs: indexmap! {
+                c0 => indexmap! { key0 => felt!("0x1"), key1 => felt!("0x2") },
+            },
+            nonces: indexmap! { c0 => Nonce(felt!("0x1")) },
+            ..Default::default()
+        },
+        ThinStateDiff {
+            storage_diffs: indexmap! {
+                c0 => indexmap! { key0 => felt!("0x1"), key1 => felt!("0x2") },
+            },
+            nonces: indexmap! { c0 => Nonce(felt!("0x2")) },
+            ..Default::default()
+        },
+    ];
+    let expected_values_per_state = [
+        (felt!("0x0"), felt!("0x0"), None),
+        (felt!("0x1"), felt!("0x1"), Some(Nonce(felt!("0x1")))),
+        (felt!("0x1"), felt!("0x2"), Some(Nonce(felt!("0x1")))),
+        (felt!("0x1"), felt!("0x2"), Some(Nonce(felt!("0x2")))),
+    ];
+
+    let mut num_written_entries = Vec::new();
+    for is_batched in [false, true] {
+        let ((reader, mut writer), _temp_dir) = get_test_storage();
+        let mut txn = writer.begin_rw_txn().unwrap();
+        if is_batched {
+            txn = txn.append_state_diffs(BlockNumber(0), diffs.clone()).unwrap();
+        } else {
+            for (block_number, diff) in diffs.iter().enumerate() {
+                let block_number = BlockNumber(block_number.try_into().unwrap());
+                txn = txn.append_state_diff(block_number, diff.clone()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+
+        let txn = reader.begin_ro_txn().unwrap();
+        assert_eq!(txn.get_state_marker().unwrap(), BlockNumber(3));
+        for (block_number, diff) in diffs.iter().enumerate() {
+            let block_number = BlockNumber(block_number.try_into().unwrap());
+            assert_eq!(txn.get_state_diff(block_number).unwrap().as_ref(), Some(diff));
+        }
+        let state_reader = txn.get_state_reader().unwrap();
+        for (block_number, (value0, value1, nonce)) in expected_values_per_state.iter().enumerate()
+        {
+            let state_number =
+                StateNumber::right_before_block(BlockNumber(block_number.try_into().unwrap()));
+            assert_eq!(state_reader.get_storage_at(state_number, &c0, &key0).unwrap(), *value0);
+            assert_eq!(state_reader.get_storage_at(state_number, &c0, &key1).unwrap(), *value1);
+            assert_eq!(state_reader.get_nonce_at(state_number, &c0).unwrap(), *nonce);
+        }
+
+        let tables_stats = reader.db_tables_stats().unwrap().tables_stats;
+        num_written_entries
+            .push(tables_stats["contract_storage"].entries + tables_stats["nonces"].entries);
+    }
+
+    let [num_entries_one_by_one, num_entries_batched] = num_written_entries[..] else {
+        panic!("Expected two storages.");
+    };
+    // key0 is written once instead of 3 times, key1 twice instead of 3 times and the nonce twice
+    // instead of 3 times.
+    assert_eq!(num_entries_one_by_one, 9);
+    assert_eq!(num_entries_batched, 5);
+}
+
+#[test]
+fn deployed_contracts_in_range() {
+    let c0 = ContractAddress(patricia_key!("0x11"));
+    let c1 = ContractAddress(patricia_key!("0x12"));
+    let c2 = ContractAddress(patricia_key!("0x13"));
+    let c3 = ContractAddress(patricia_key!("0x14"));
+    let cl0 = ClassHash(felt!("0x4"));
+    let cl1 = ClassHash(felt!("0x5"));
+    let diffs = vec![
+        ThinStateDiff { deployed_contracts: indexmap! { c0 => cl0 }, ..Default::default() },
+        ThinStateDiff {
+            deployed_contracts: indexmap! { c1 => cl1, c2 => cl0 },
+            ..Default::default()
+        },
+        // Replaced classes aren't deployments.
+        ThinStateDiff { replaced_classes: indexmap! { c0 => cl1 }, ..Default::default() },
+        ThinStateDiff { deployed_contracts: indexmap! { c3 => cl1 }, ..Default::default() },
+    ];
+
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let mut txn = writer.begin_rw_txn().unwrap();
+    for (block_number, diff) in diffs.into_iter().enumerate() {
+        txn = txn.append_state_diff(BlockNumber(block_number.try_into().unwrap()), diff).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = reader.begin_ro_txn().unwrap();
+    assert_eq!(
+        txn.deployed_contracts_in_range(BlockNumber(0)..BlockNumber(4)).unwrap(),
+        vec![
+            (BlockNumber(0), c0, cl0),
+            (BlockNumber(1), c1, cl1),
+            (BlockNumber(1), c2, cl0),
+            (BlockNumber(3), c3, cl1),
+        ]
+    );
+    assert_eq!(
+        txn.deployed_contracts_in_range(BlockNumber(1)..BlockNumber(3)).unwrap(),
+        vec![(BlockNumber(1), c1, cl1), (BlockNumber(1), c2, cl0)]
+    );
+    assert!(txn.deployed_contracts_in_range(BlockNumber(2)..BlockNumber(3)).unwrap().is_empty());
+    // Blocks that aren't in the storage yet are ignored.
+    assert_eq!(
+        txn.deployed_contracts_in_range(BlockNumber(3)..BlockNumber(10)).unwrap(),
+        vec![(BlockNumber(3), c3, cl1)]
+    );
+}
+
 #[test]
 fn test_update_compiled_class_marker() {
     let ((_, mut writer), _temp_dir) = get_test_storage();
@@ -281,7 +414,7 @@ fn test_get_class_after_append_thin_state_diff() {
             },
         )
         .unwrap();
-    assert_eq!(txn.get_class_marker().unwrap(), BlockNumber(0));
+    assert_eq!(txn.get_class_marker().unwrap(), BlockNumber(1));
 
     let state_reader = txn.get_state_reader().unwrap();
     let state_number = StateNumber::unchecked_right_after_block(BlockNumber(0));
@@ -771,3 +904,309 @@ fn declare_revert_declare_scenario() {
             .is_some()
     );
 }
+
+#[test]
+fn find_orphaned_classes() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let canonical_class_hash = ClassHash(felt!("0xc1"));
+    let first_class_hash = ClassHash(felt!("0xc2"));
+    let second_class_hash = ClassHash(felt!("0xc3"));
+    let diff0 = ThinStateDiff {
+        declared_classes: indexmap! { canonical_class_hash => CompiledClassHash::default() },
+        ..Default::default()
+    };
+    let diff1 = ThinStateDiff {
+        declared_classes: indexmap! {
+            first_class_hash => CompiledClassHash::default(),
+            second_class_hash => CompiledClassHash::default(),
+        },
+        ..Default::default()
+    };
+    // Only the compiled class of the second class of block 1 is downloaded.
+    writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_state_diff(BlockNumber(0), diff0)
+        .unwrap()
+        .append_state_diff(BlockNumber(1), diff1)
+        .unwrap()
+        .append_classes(BlockNumber(0), &[(canonical_class_hash, &ContractClass::default())], &[])
+        .unwrap()
+        .append_casm(&canonical_class_hash, &CasmContractClass::default())
+        .unwrap()
+        .append_casm(&second_class_hash, &CasmContractClass::default())
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert!(reader.begin_ro_txn().unwrap().find_orphaned_classes().unwrap().is_empty());
+
+    // Reverting block 1 deletes its compiled classes only up to the first missing one, so the
+    // compiled class of the second class is left behind.
+    let (txn, _) = writer.begin_rw_txn().unwrap().revert_state_diff(BlockNumber(1)).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(
+        reader.begin_ro_txn().unwrap().find_orphaned_classes().unwrap(),
+        vec![second_class_hash]
+    );
+}
+
+#[test]
+fn dropping_uncommitted_state_diff_write_changes_nothing() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let address = contract_address!("0x100");
+    let key = StorageKey(patricia_key!("0x10"));
+    let class_hash = ClassHash(felt!("0x1"));
+    writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_state_diff(
+            BlockNumber(0),
+            ThinStateDiff {
+                storage_diffs: indexmap! { address => indexmap! { key => felt!("0x1") } },
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .append_classes(BlockNumber(0), &[], &[])
+        .unwrap()
+        .commit()
+        .unwrap();
+
+    // Simulate a crash in the middle of writing the state diff of block 1 and its classes, as the
+    // sync does, by dropping the transaction before committing it.
+    let txn = writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_state_diff(
+            BlockNumber(1),
+            ThinStateDiff {
+                storage_diffs: indexmap! { address => indexmap! { key => felt!("0x2") } },
+                declared_classes: indexmap! { class_hash => CompiledClassHash::default() },
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .append_classes(BlockNumber(1), &[(class_hash, &ContractClass::default())], &[])
+        .unwrap();
+    drop(txn);
+
+    let txn = reader.begin_ro_txn().unwrap();
+    assert_eq!(txn.get_state_marker().unwrap(), BlockNumber(1));
+    assert_eq!(txn.get_class_marker().unwrap(), BlockNumber(1));
+    assert_eq!(txn.get_state_diff(BlockNumber(1)).unwrap(), None);
+    assert_eq!(txn.get_class(&class_hash).unwrap(), None);
+    let state_number = StateNumber::unchecked_right_after_block(BlockNumber(1));
+    let state_reader = txn.get_state_reader().unwrap();
+    assert_eq!(state_reader.get_storage_at(state_number, &address, &key).unwrap(), felt!("0x1"));
+}
+
+#[test]
+fn reads_during_commits_see_whole_blocks() {
+    const N_BLOCKS: u64 = 50;
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let address = contract_address!("0x1");
+    let keys = [StorageKey(patricia_key!("0x10")), StorageKey(patricia_key!("0x11"))];
+    let done = AtomicBool::new(false);
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for block in 0..N_BLOCKS {
+                let value = Felt::from(block + 1);
+                let state_diff = ThinStateDiff {
+                    storage_diffs: indexmap!(
+                        address => keys.iter().map(|key| (*key, value)).collect()
+                    ),
+                    nonces: indexmap!(address => Nonce(value)),
+                    ..Default::default()
+                };
+                writer
+                    .begin_rw_txn()
+                    .unwrap()
+                    .append_state_diff(BlockNumber(block), state_diff)
+                    .unwrap()
+                    .commit()
+                    .unwrap();
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+
+        // Each read sees the state either before or after a block, never a part of a block.
+        while !done.load(Ordering::SeqCst) {
+            let txn = reader.begin_ro_txn().unwrap();
+            let state_marker = txn.get_state_marker().unwrap();
+            let Some(last_block) = state_marker.prev() else {
+                continue;
+            };
+            let expected_value = Felt::from(state_marker.0);
+            let state_reader = txn.get_state_reader().unwrap();
+            let state_number = StateNumber(state_marker);
+            for key in &keys {
+                assert_eq!(
+                    state_reader.get_storage_at(state_number, &address, key).unwrap(),
+                    expected_value
+                );
+            }
+            assert_eq!(
+                state_reader.get_nonce_at(state_number, &address).unwrap(),
+                Some(Nonce(expected_value))
+            );
+            assert!(txn.get_state_diff(last_block).unwrap().is_some());
+        }
+    });
+}
+
+#[test]
+fn get_state_at_hash() {
+    let address = contract_address!("0x11");
+    let key = StorageKey(patricia_key!("0x1"));
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let mut txn = writer.begin_rw_txn().unwrap();
+    for block_number in 0..3 {
+        let header = BlockHeader {
+            block_number: BlockNumber(block_number),
+            block_hash: BlockHash(StarkHash::from(block_number + 1)),
+            ..Default::default()
+        };
+        let state_diff = ThinStateDiff {
+            storage_diffs: indexmap!(address => indexmap!(key => Felt::from(block_number))),
+            ..Default::default()
+        };
+        txn = txn
+            .append_header(BlockNumber(block_number), &header)
+            .unwrap()
+            .append_state_diff(BlockNumber(block_number), state_diff)
+            .unwrap();
+    }
+    // The header of block 3 is stored, but not its state diff.
+    let header = BlockHeader {
+        block_number: BlockNumber(3),
+        block_hash: BlockHash(StarkHash::from(4_u64)),
+        ..Default::default()
+    };
+    txn.append_header(BlockNumber(3), &header).unwrap().commit().unwrap();
+
+    let txn = reader.begin_ro_txn().unwrap();
+    let (state_reader, state_number) =
+        txn.get_state_at_hash(&BlockHash(StarkHash::from(2_u64))).unwrap();
+    assert_eq!(state_number, StateNumber::unchecked_right_after_block(BlockNumber(1)));
+    assert_eq!(state_reader.get_storage_at(state_number, &address, &key).unwrap(), Felt::ONE);
+
+    assert_matches!(
+        txn.get_state_at_hash(&BlockHash(StarkHash::from(4_u64))),
+        Err(StorageError::MissingBlockData { block_number: BlockNumber(3) })
+    );
+    let unknown_hash = BlockHash(StarkHash::from(5_u64));
+    assert_matches!(
+        txn.get_state_at_hash(&unknown_hash),
+        Err(StorageError::BlockHashNotFound { block_hash }) if block_hash == unknown_hash
+    );
+}
+
+#[test]
+fn state_diff_iter() {
+    let address = contract_address!("0x11");
+    let key = StorageKey(patricia_key!("0x1"));
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let mut txn = writer.begin_rw_txn().unwrap();
+    for block_number in 0..100 {
+        let state_diff = ThinStateDiff {
+            storage_diffs: indexmap!(address => indexmap!(key => Felt::from(block_number))),
+            ..Default::default()
+        };
+        txn = txn.append_state_diff(BlockNumber(block_number), state_diff).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = reader.begin_ro_txn().unwrap();
+    let state_diffs = txn
+        .state_diff_iter(BlockNumber(0)..BlockNumber(100))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(state_diffs.len(), 100);
+    for (expected_block_number, (block_number, state_diff)) in (0..100).zip(state_diffs) {
+        assert_eq!(block_number, BlockNumber(expected_block_number));
+        assert_eq!(state_diff.storage_diffs[&address][&key], Felt::from(expected_block_number));
+    }
+
+    // A sub range starts at its first block.
+    let mut iter = txn.state_diff_iter(BlockNumber(40)..BlockNumber(42)).unwrap();
+    assert_eq!(iter.next().unwrap().unwrap().0, BlockNumber(40));
+    assert_eq!(iter.next().unwrap().unwrap().0, BlockNumber(41));
+    assert!(iter.next().is_none());
+
+    // Blocks past the state marker are missing.
+    let mut iter = txn.state_diff_iter(BlockNumber(99)..BlockNumber(101)).unwrap();
+    assert_eq!(iter.next().unwrap().unwrap().0, BlockNumber(99));
+    assert_matches!(
+        iter.next(),
+        Some(Err(StorageError::MissingBlockData { block_number: BlockNumber(100) }))
+    );
+    assert!(iter.next().is_none());
+
+    // Remove the state diff of a block in the middle of the range.
+    let txn = writer.begin_rw_txn().unwrap();
+    let state_diffs_table = txn.txn.open_table(&txn.tables.state_diffs).unwrap();
+    state_diffs_table.delete(&txn.txn, &BlockNumber(50)).unwrap();
+    txn.commit().unwrap();
+
+    let txn = reader.begin_ro_txn().unwrap();
+    let mut iter = txn.state_diff_iter(BlockNumber(0)..BlockNumber(100)).unwrap();
+    for expected_block_number in 0..50 {
+        assert_eq!(iter.next().unwrap().unwrap().0, BlockNumber(expected_block_number));
+    }
+    assert_matches!(
+        iter.next(),
+        Some(Err(StorageError::MissingBlockData { block_number: BlockNumber(50) }))
+    );
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn get_class_declaration_block() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let class = ClassHash(felt!("0x10"));
+    let later_class = ClassHash(felt!("0x11"));
+    let deprecated_class = ClassHash(felt!("0x20"));
+    let later_deprecated_class = ClassHash(felt!("0x21"));
+    let diff0 = ThinStateDiff {
+        declared_classes: indexmap! { class => CompiledClassHash::default() },
+        deprecated_declared_classes: vec![deprecated_class],
+        ..Default::default()
+    };
+    let diff1 = ThinStateDiff {
+        declared_classes: indexmap! { later_class => CompiledClassHash::default() },
+        ..Default::default()
+    };
+    // Declares a deprecated class again.
+    let diff2 = ThinStateDiff {
+        deprecated_declared_classes: vec![deprecated_class, later_deprecated_class],
+        ..Default::default()
+    };
+    writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_state_diff(BlockNumber(0), diff0)
+        .unwrap()
+        .append_state_diff(BlockNumber(1), diff1)
+        .unwrap()
+        .append_state_diff(BlockNumber(2), diff2)
+        .unwrap()
+        .commit()
+        .unwrap();
+
+    let declaration_block = |class_hash| {
+        reader.begin_ro_txn().unwrap().get_class_declaration_block(&class_hash).unwrap()
+    };
+    assert_eq!(declaration_block(class), Some(BlockNumber(0)));
+    assert_eq!(declaration_block(later_class), Some(BlockNumber(1)));
+    assert_eq!(declaration_block(deprecated_class), Some(BlockNumber(0)));
+    assert_eq!(declaration_block(later_deprecated_class), Some(BlockNumber(2)));
+    assert_eq!(declaration_block(ClassHash(felt!("0x30"))), None);
+
+    // Reverting the block that declared a deprecated class again keeps its first declaration.
+    let (txn, _) = writer.begin_rw_txn().unwrap().revert_state_diff(BlockNumber(2)).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(declaration_block(deprecated_class), Some(BlockNumber(0)));
+    assert_eq!(declaration_block(later_deprecated_class), None);
+}
@@ -1,13 +1,23 @@
 #![allow(clippy::unwrap_used)]
 //! Test utilities for the storage crate users.
 
+use starknet_api::block::{Block, BlockBody, BlockHash, BlockHeader, BlockNumber};
 use starknet_api::core::ChainId;
+use starknet_api::hash::StarkHash;
+use starknet_api::state::ThinStateDiff;
 use tempfile::{tempdir, TempDir};
 
+use crate::body::BodyStorageWriter;
 use crate::db::DbConfig;
+use crate::header::HeaderStorageWriter;
 use crate::mmap_file::MmapFileConfig;
+use crate::state::StateStorageWriter;
 use crate::{open_storage, StorageConfig, StorageReader, StorageScope, StorageWriter};
 
+#[cfg(test)]
+#[path = "test_utils_test.rs"]
+mod test_utils_test;
+
 /// Returns a db config and the temporary directory that holds this db.
 /// The TempDir object is returned as a handler for the lifetime of this object (the temp
 /// directory), thus make sure the directory won't be destroyed. The caller should propagate the
@@ -73,3 +83,85 @@ pub fn get_test_storage_with_config_by_scope(
 
     ((reader, writer), config, temp_dir)
 }
+
+/// Builds a self-consistent [`Block`] and its [`ThinStateDiff`] for testing purposes.
+///
+/// The header's block number and parent hash are taken from the parent block and its transaction
+/// and event counts are taken from the body, so tests can chain blocks without tracking hashes.
+/// Unless set explicitly, the block hash is derived from the block number.
+#[derive(Debug, Clone)]
+pub struct BlockBuilder {
+    header: BlockHeader,
+    body: BlockBody,
+    state_diff: ThinStateDiff,
+}
+
+impl BlockBuilder {
+    /// Returns a builder of the first block of the chain.
+    pub fn genesis() -> Self {
+        Self::new(BlockNumber(0), BlockHash::default())
+    }
+
+    /// Returns a builder of the block that follows the block with the given header.
+    pub fn child_of(parent: &BlockHeader) -> Self {
+        Self::new(parent.block_number.unchecked_next(), parent.block_hash)
+    }
+
+    fn new(block_number: BlockNumber, parent_hash: BlockHash) -> Self {
+        Self {
+            header: BlockHeader {
+                block_hash: BlockHash(StarkHash::from(block_number.0 + 1)),
+                parent_hash,
+                block_number,
+                ..Default::default()
+            },
+            body: BlockBody::default(),
+            state_diff: ThinStateDiff::default(),
+        }
+    }
+
+    /// Sets the hash of the block.
+    pub fn block_hash(mut self, block_hash: BlockHash) -> Self {
+        self.header.block_hash = block_hash;
+        self
+    }
+
+    /// Sets the body of the block.
+    pub fn body(mut self, body: BlockBody) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Sets the state diff of the block.
+    pub fn state_diff(mut self, state_diff: ThinStateDiff) -> Self {
+        self.state_diff = state_diff;
+        self
+    }
+
+    /// Returns the block and its state diff.
+    pub fn build(self) -> (Block, ThinStateDiff) {
+        let Self { mut header, body, state_diff } = self;
+        header.n_transactions = body.transactions.len();
+        header.n_events = body.transaction_outputs.iter().map(|output| output.events().len()).sum();
+        header.state_diff_length = Some(state_diff.len());
+        (Block { header, body }, state_diff)
+    }
+
+    /// Appends the header, body and state diff of the block to the storage and returns the block.
+    pub fn append(self, writer: &mut StorageWriter) -> Block {
+        let (block, state_diff) = self.build();
+        let block_number = block.header.block_number;
+        writer
+            .begin_rw_txn()
+            .unwrap()
+            .append_header(block_number, &block.header)
+            .unwrap()
+            .append_body(block_number, block.body.clone())
+            .unwrap()
+            .append_state_diff(block_number, state_diff)
+            .unwrap()
+            .commit()
+            .unwrap();
+        block
+    }
+}
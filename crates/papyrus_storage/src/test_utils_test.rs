@@ -0,0 +1,35 @@
+use starknet_api::block::{BlockHash, BlockNumber};
+
+use crate::body::BodyStorageReader;
+use crate::header::HeaderStorageReader;
+use crate::test_utils::{get_test_storage, BlockBuilder};
+
+#[test]
+fn block_builder_links_chain() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+
+    let block0 = BlockBuilder::genesis().append(&mut writer);
+    let block1 = BlockBuilder::child_of(&block0.header).append(&mut writer);
+    let block2 = BlockBuilder::child_of(&block1.header).append(&mut writer);
+
+    assert_eq!(block0.header.parent_hash, BlockHash::default());
+    for (block_number, block) in [&block0, &block1, &block2].into_iter().enumerate() {
+        assert_eq!(block.header.block_number, BlockNumber(block_number as u64));
+    }
+    assert_eq!(block1.header.parent_hash, block0.header.block_hash);
+    assert_eq!(block2.header.parent_hash, block1.header.block_hash);
+    assert_ne!(block1.header.block_hash, block0.header.block_hash);
+    assert_ne!(block2.header.block_hash, block1.header.block_hash);
+
+    // The stored chain is linked end to end as well.
+    let txn = reader.begin_ro_txn().unwrap();
+    assert_eq!(txn.get_header_marker().unwrap(), BlockNumber(3));
+    assert_eq!(txn.get_body_marker().unwrap(), BlockNumber(3));
+    let mut parent_hash = BlockHash::default();
+    for block in [block0, block1, block2] {
+        let stored_header = txn.get_block_header(block.header.block_number).unwrap().unwrap();
+        assert_eq!(stored_header.parent_hash, parent_hash);
+        assert_eq!(stored_header.block_hash, block.header.block_hash);
+        parent_hash = stored_header.block_hash;
+    }
+}
@@ -5,21 +5,57 @@ mod utils_test;
 
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Read, Write};
+use std::ops::Range;
 
+use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
 use metrics::{absolute_counter, gauge};
-use serde::Serialize;
-use starknet_api::block::BlockNumber;
-use starknet_api::core::{ChainId, ClassHash, CompiledClassHash};
-use starknet_api::state::{EntryPoint, EntryPointType};
+use serde::{Deserialize, Serialize};
+use starknet_api::block::{
+    Block,
+    BlockBody,
+    BlockHash,
+    BlockHeader,
+    BlockNumber,
+    BlockTimestamp,
+    GasPricePerToken,
+    StarknetVersion,
+};
+use starknet_api::core::{
+    ChainId,
+    ClassHash,
+    CompiledClassHash,
+    EventCommitment,
+    GlobalRoot,
+    ReceiptCommitment,
+    SequencerContractAddress,
+    StateDiffCommitment,
+    TransactionCommitment,
+};
+use starknet_api::data_availability::L1DataAvailabilityMode;
+use starknet_api::deprecated_contract_class::ContractClass as DeprecatedContractClass;
+use starknet_api::state::{ContractClass, EntryPoint, EntryPointType, ThinStateDiff};
 use starknet_types_core::felt::Felt;
 use tracing::debug;
 
+use crate::block::BlockStorageWriter;
+use crate::body::{BodyStorageReader, BodyStorageWriter};
+use crate::class::ClassStorageReader;
 use crate::compiled_class::CasmStorageReader;
+use crate::compression_utils::COMPRESSION_LEVEL;
 use crate::db::table_types::Table;
 use crate::db::RO;
-use crate::state::StateStorageReader;
-use crate::{open_storage, StorageConfig, StorageError, StorageReader, StorageResult, StorageTxn};
+use crate::header::{HeaderStorageReader, HeaderStorageWriter};
+use crate::state::{StateStorageReader, StateStorageWriter};
+use crate::{
+    open_storage,
+    StorageConfig,
+    StorageError,
+    StorageReader,
+    StorageResult,
+    StorageTxn,
+    StorageWriter,
+};
 
 #[derive(Serialize)]
 struct DumpDeclaredClass {
@@ -88,6 +124,304 @@ fn dump_declared_classes_table_by_block_range_internal(
     Ok(())
 }
 
+/// A difference between two storages in the data of a block.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Discrepancy {
+    /// The block headers are different, or the block header exists only in one storage.
+    Header(BlockNumber),
+    /// The block bodies are different, or the block body exists only in one storage.
+    Body(BlockNumber),
+    /// The state diffs are different, or the state diff exists only in one storage.
+    StateDiff(BlockNumber),
+}
+
+/// Compares the headers, bodies and state diffs of two storages block by block in the given range
+/// and returns the differences found, ordered by block number.
+pub fn compare_storages(
+    reader_a: &StorageReader,
+    reader_b: &StorageReader,
+    block_range: Range<BlockNumber>,
+) -> StorageResult<Vec<Discrepancy>> {
+    let txn_a = reader_a.begin_ro_txn()?;
+    let txn_b = reader_b.begin_ro_txn()?;
+    let mut discrepancies = Vec::new();
+    for block_number in block_range.start.iter_up_to(block_range.end) {
+        if txn_a.get_block_header(block_number)? != txn_b.get_block_header(block_number)? {
+            discrepancies.push(Discrepancy::Header(block_number));
+        }
+        if txn_a.get_block_transactions(block_number)?
+            != txn_b.get_block_transactions(block_number)?
+            || txn_a.get_block_transaction_hashes(block_number)?
+                != txn_b.get_block_transaction_hashes(block_number)?
+            || txn_a.get_block_transaction_outputs(block_number)?
+                != txn_b.get_block_transaction_outputs(block_number)?
+        {
+            discrepancies.push(Discrepancy::Body(block_number));
+        }
+        if txn_a.get_state_diff(block_number)? != txn_b.get_state_diff(block_number)? {
+            discrepancies.push(Discrepancy::StateDiff(block_number));
+        }
+    }
+    Ok(discrepancies)
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedBlock {
+    header: ExportedBlockHeader,
+    body: BlockBody,
+    state_diff: ThinStateDiff,
+}
+
+// The serialization of BlockHeader skips some of its fields, so the export uses its own copy.
+#[derive(Serialize, Deserialize)]
+struct ExportedBlockHeader {
+    block_hash: BlockHash,
+    parent_hash: BlockHash,
+    block_number: BlockNumber,
+    l1_gas_price: GasPricePerToken,
+    l1_data_gas_price: GasPricePerToken,
+    state_root: GlobalRoot,
+    sequencer: SequencerContractAddress,
+    timestamp: BlockTimestamp,
+    l1_da_mode: L1DataAvailabilityMode,
+    state_diff_commitment: Option<StateDiffCommitment>,
+    state_diff_length: Option<usize>,
+    transaction_commitment: Option<TransactionCommitment>,
+    event_commitment: Option<EventCommitment>,
+    n_transactions: usize,
+    n_events: usize,
+    receipt_commitment: Option<ReceiptCommitment>,
+    starknet_version: StarknetVersion,
+}
+
+impl From<BlockHeader> for ExportedBlockHeader {
+    fn from(header: BlockHeader) -> Self {
+        let BlockHeader {
+            block_hash,
+            parent_hash,
+            block_number,
+            l1_gas_price,
+            l1_data_gas_price,
+            state_root,
+            sequencer,
+            timestamp,
+            l1_da_mode,
+            state_diff_commitment,
+            state_diff_length,
+            transaction_commitment,
+            event_commitment,
+            n_transactions,
+            n_events,
+            receipt_commitment,
+            starknet_version,
+        } = header;
+        Self {
+            block_hash,
+            parent_hash,
+            block_number,
+            l1_gas_price,
+            l1_data_gas_price,
+            state_root,
+            sequencer,
+            timestamp,
+            l1_da_mode,
+            state_diff_commitment,
+            state_diff_length,
+            transaction_commitment,
+            event_commitment,
+            n_transactions,
+            n_events,
+            receipt_commitment,
+            starknet_version,
+        }
+    }
+}
+
+impl From<ExportedBlockHeader> for BlockHeader {
+    fn from(header: ExportedBlockHeader) -> Self {
+        let ExportedBlockHeader {
+            block_hash,
+            parent_hash,
+            block_number,
+            l1_gas_price,
+            l1_data_gas_price,
+            state_root,
+            sequencer,
+            timestamp,
+            l1_da_mode,
+            state_diff_commitment,
+            state_diff_length,
+            transaction_commitment,
+            event_commitment,
+            n_transactions,
+            n_events,
+            receipt_commitment,
+            starknet_version,
+        } = header;
+        Self {
+            block_hash,
+            parent_hash,
+            block_number,
+            l1_gas_price,
+            l1_data_gas_price,
+            state_root,
+            sequencer,
+            timestamp,
+            l1_da_mode,
+            state_diff_commitment,
+            state_diff_length,
+            transaction_commitment,
+            event_commitment,
+            n_transactions,
+            n_events,
+            receipt_commitment,
+            starknet_version,
+        }
+    }
+}
+
+/// Writes the headers, bodies and state diffs of the given block range to `destination`, streamed
+/// through a zstd encoder. The output can be loaded into another storage with [`import_range`].
+pub fn export_range(
+    reader: &StorageReader,
+    block_range: Range<BlockNumber>,
+    destination: impl Write,
+) -> StorageResult<()> {
+    let txn = reader.begin_ro_txn()?;
+    let mut encoder = zstd::stream::write::Encoder::new(destination, COMPRESSION_LEVEL)?;
+    for block_number in block_range.start.iter_up_to(block_range.end) {
+        serde_json::to_writer(&mut encoder, &get_exported_block(&txn, block_number)?)?;
+    }
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+fn get_exported_block(
+    txn: &StorageTxn<'_, RO>,
+    block_number: BlockNumber,
+) -> StorageResult<ExportedBlock> {
+    let missing_block_data = || StorageError::MissingBlockData { block_number };
+    Ok(ExportedBlock {
+        header: txn.get_block_header(block_number)?.ok_or_else(missing_block_data)?.into(),
+        body: BlockBody {
+            transactions: txn
+                .get_block_transactions(block_number)?
+                .ok_or_else(missing_block_data)?,
+            transaction_outputs: txn
+                .get_block_transaction_outputs(block_number)?
+                .ok_or_else(missing_block_data)?,
+            transaction_hashes: txn
+                .get_block_transaction_hashes(block_number)?
+                .ok_or_else(missing_block_data)?,
+        },
+        state_diff: txn.get_state_diff(block_number)?.ok_or_else(missing_block_data)?,
+    })
+}
+
+/// Appends the blocks written by [`export_range`] to the storage, decompressing `source` as it is
+/// read. Each block is committed in its own transaction. Returns the number of imported blocks.
+pub fn import_range(writer: &mut StorageWriter, source: impl Read) -> StorageResult<usize> {
+    let decoder = zstd::stream::read::Decoder::new(source)?;
+    let mut imported_blocks = 0;
+    for block in serde_json::Deserializer::from_reader(decoder).into_iter::<ExportedBlock>() {
+        let ExportedBlock { header, body, state_diff } = block?;
+        let header = BlockHeader::from(header);
+        let block_number = header.block_number;
+        writer
+            .begin_rw_txn()?
+            .append_header(block_number, &header)?
+            .append_body(block_number, body)?
+            .append_state_diff(block_number, state_diff)?
+            .commit()?;
+        imported_blocks += 1;
+    }
+    Ok(imported_blocks)
+}
+
+#[derive(Serialize, Deserialize)]
+struct CheckpointBlock {
+    block: ExportedBlock,
+    classes: Vec<(ClassHash, ContractClass)>,
+    deprecated_classes: Vec<(ClassHash, DeprecatedContractClass)>,
+    casms: Vec<(ClassHash, CasmContractClass)>,
+}
+
+/// Writes a checkpoint of the blocks before `block_number` to `destination`, streamed through a
+/// zstd encoder. On top of what [`export_range`] writes, the checkpoint holds the classes declared
+/// in each block and the compiled classes of the Cairo 1 classes, so that a fresh storage can be
+/// bootstrapped from it with [`import_checkpoint`].
+pub fn export_checkpoint(
+    reader: &StorageReader,
+    block_number: BlockNumber,
+    destination: impl Write,
+) -> StorageResult<()> {
+    let txn = reader.begin_ro_txn()?;
+    let mut encoder = zstd::stream::write::Encoder::new(destination, COMPRESSION_LEVEL)?;
+    for current_block_number in BlockNumber(0).iter_up_to(block_number) {
+        let missing_block_data =
+            || StorageError::MissingBlockData { block_number: current_block_number };
+        let block = get_exported_block(&txn, current_block_number)?;
+        let mut classes = Vec::new();
+        let mut casms = Vec::new();
+        for class_hash in block.state_diff.declared_classes.keys() {
+            let class = txn.get_class(class_hash)?.ok_or_else(missing_block_data)?;
+            classes.push((*class_hash, class));
+            let casm = txn.get_casm(class_hash)?.ok_or_else(missing_block_data)?;
+            casms.push((*class_hash, casm));
+        }
+        let mut deprecated_classes = Vec::new();
+        for class_hash in &block.state_diff.deprecated_declared_classes {
+            let class = txn.get_deprecated_class(class_hash)?.ok_or_else(missing_block_data)?;
+            deprecated_classes.push((*class_hash, class));
+        }
+        let checkpoint_block = CheckpointBlock { block, classes, deprecated_classes, casms };
+        serde_json::to_writer(&mut encoder, &checkpoint_block)?;
+    }
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+/// Loads a checkpoint written by [`export_checkpoint`] into an empty storage. Each block is
+/// appended with all of its data in its own transaction, so the header, body, state and class
+/// markers advance together and sync can resume right after the checkpoint. Returns the block
+/// number the markers point at.
+pub fn import_checkpoint(
+    writer: &mut StorageWriter,
+    source: impl Read,
+) -> StorageResult<BlockNumber> {
+    let decoder = zstd::stream::read::Decoder::new(source)?;
+    let mut next_block_number = BlockNumber(0);
+    for checkpoint_block in
+        serde_json::Deserializer::from_reader(decoder).into_iter::<CheckpointBlock>()
+    {
+        let CheckpointBlock {
+            block: ExportedBlock { header, body, state_diff },
+            classes,
+            deprecated_classes,
+            casms,
+        } = checkpoint_block?;
+        let block = Block { header: header.into(), body };
+        let block_number = block.header.block_number;
+        writer
+            .begin_rw_txn()?
+            .append_block(
+                block_number,
+                block,
+                state_diff,
+                &as_refs(&classes),
+                &as_refs(&deprecated_classes),
+                &as_refs(&casms),
+            )?
+            .commit()?;
+        next_block_number = block_number.unchecked_next();
+    }
+    Ok(next_block_number)
+}
+
+fn as_refs<T>(classes: &[(ClassHash, T)]) -> Vec<(ClassHash, &T)> {
+    classes.iter().map(|(class_hash, class)| (*class_hash, class)).collect()
+}
+
 // TODO(dvir): consider adding storage size metrics.
 // TODO(dvir): relocate all the storage metrics in one module and export them (also in other
 // crates).
@@ -1,22 +1,41 @@
 use std::collections::HashMap;
 use std::fs;
 
+use assert_matches::assert_matches;
+use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
 use indexmap::indexmap;
 use metrics_exporter_prometheus::PrometheusBuilder;
 use pretty_assertions::assert_eq;
 use prometheus_parse::Value::{Counter, Gauge};
-use starknet_api::block::BlockNumber;
-use starknet_api::core::{ClassHash, CompiledClassHash};
+use starknet_api::block::{BlockBody, BlockHash, BlockHeader, BlockNumber};
+use starknet_api::core::{ClassHash, CompiledClassHash, ContractAddress, Nonce, PatriciaKey};
+use starknet_api::deprecated_contract_class::ContractClass as DeprecatedContractClass;
 use starknet_api::hash::StarkHash;
-use starknet_api::state::{ContractClass, ThinStateDiff};
+use starknet_api::state::{ContractClass, StateNumber, StorageKey, ThinStateDiff};
+use starknet_api::transaction::TransactionHash;
+use starknet_api::{felt, patricia_key};
 use starknet_types_core::felt::Felt;
-use test_utils::prometheus_is_contained;
+use test_utils::{get_test_body, prometheus_is_contained};
 
 use super::update_storage_metrics;
-use crate::class::ClassStorageWriter;
-use crate::state::StateStorageWriter;
-use crate::test_utils::get_test_storage;
-use crate::utils::{dump_declared_classes_table_by_block_range_internal, DumpDeclaredClass};
+use crate::block::BlockStorageWriter;
+use crate::body::{BodyStorageReader, BodyStorageWriter};
+use crate::class::{ClassStorageReader, ClassStorageWriter};
+use crate::compiled_class::CasmStorageReader;
+use crate::header::{HeaderStorageReader, HeaderStorageWriter};
+use crate::state::{StateStorageReader, StateStorageWriter};
+use crate::test_utils::{get_test_storage, BlockBuilder};
+use crate::utils::{
+    compare_storages,
+    dump_declared_classes_table_by_block_range_internal,
+    export_checkpoint,
+    export_range,
+    import_checkpoint,
+    import_range,
+    Discrepancy,
+    DumpDeclaredClass,
+};
+use crate::{StorageError, StorageReader, StorageWriter};
 
 // TODO(yael): fix dump_table_to_file.
 #[test]
@@ -115,3 +134,266 @@ fn update_storage_metrics_test() {
     assert!(0f64 < last_transaction);
     assert!(last_transaction < 100f64);
 }
+
+fn append_block(
+    writer: &mut StorageWriter,
+    block_number: BlockNumber,
+    header: BlockHeader,
+    body: BlockBody,
+    state_diff: ThinStateDiff,
+) {
+    writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_header(block_number, &header)
+        .unwrap()
+        .append_body(block_number, body)
+        .unwrap()
+        .append_state_diff(block_number, state_diff)
+        .unwrap()
+        .commit()
+        .unwrap();
+}
+
+#[test]
+fn compare_storages_reports_discrepancies() {
+    let ((reader_a, mut writer_a), _temp_dir_a) = get_test_storage();
+    let ((reader_b, mut writer_b), _temp_dir_b) = get_test_storage();
+    let header = |block_number: u64| BlockHeader {
+        block_number: BlockNumber(block_number),
+        block_hash: BlockHash(block_number.into()),
+        ..Default::default()
+    };
+    let state_diff = |nonce: u64| ThinStateDiff {
+        nonces: indexmap! { ContractAddress(patricia_key!("0x11")) => Nonce(nonce.into()) },
+        ..Default::default()
+    };
+
+    for writer in [&mut writer_a, &mut writer_b] {
+        append_block(writer, BlockNumber(0), header(0), BlockBody::default(), state_diff(0));
+    }
+    assert!(compare_storages(&reader_a, &reader_b, BlockNumber(0)..BlockNumber(1))
+        .unwrap()
+        .is_empty());
+
+    // Diverge in a different part of each block, and leave block 4 only in the first storage.
+    append_block(&mut writer_a, BlockNumber(1), header(1), BlockBody::default(), state_diff(1));
+    append_block(
+        &mut writer_b,
+        BlockNumber(1),
+        BlockHeader { block_hash: BlockHash(felt!("0x100")), ..header(1) },
+        BlockBody::default(),
+        state_diff(1),
+    );
+    append_block(&mut writer_a, BlockNumber(2), header(2), BlockBody::default(), state_diff(2));
+    append_block(
+        &mut writer_b,
+        BlockNumber(2),
+        header(2),
+        get_test_body(1, None, None, None),
+        state_diff(2),
+    );
+    append_block(&mut writer_a, BlockNumber(3), header(3), BlockBody::default(), state_diff(3));
+    append_block(&mut writer_b, BlockNumber(3), header(3), BlockBody::default(), state_diff(100));
+    append_block(&mut writer_a, BlockNumber(4), header(4), BlockBody::default(), state_diff(4));
+
+    assert_eq!(
+        compare_storages(&reader_a, &reader_b, BlockNumber(0)..BlockNumber(5)).unwrap(),
+        vec![
+            Discrepancy::Header(BlockNumber(1)),
+            Discrepancy::Body(BlockNumber(2)),
+            Discrepancy::StateDiff(BlockNumber(3)),
+            Discrepancy::Header(BlockNumber(4)),
+            Discrepancy::Body(BlockNumber(4)),
+            Discrepancy::StateDiff(BlockNumber(4)),
+        ]
+    );
+}
+
+#[test]
+fn export_and_import_range_round_trip() {
+    const N_BLOCKS: u64 = 5;
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    for block_number in 0..N_BLOCKS {
+        let mut body = get_test_body(3, None, None, None);
+        // Transaction hashes must be unique across blocks.
+        for (i, transaction_hash) in body.transaction_hashes.iter_mut().enumerate() {
+            *transaction_hash = TransactionHash((block_number * 10 + i as u64).into());
+        }
+        append_block(
+            &mut writer,
+            BlockNumber(block_number),
+            BlockHeader {
+                block_number: BlockNumber(block_number),
+                block_hash: BlockHash(block_number.into()),
+                n_transactions: 3,
+                ..Default::default()
+            },
+            body,
+            ThinStateDiff {
+                nonces: indexmap! {
+                    ContractAddress(patricia_key!("0x11")) => Nonce(block_number.into())
+                },
+                ..Default::default()
+            },
+        );
+    }
+
+    let mut exported = Vec::new();
+    export_range(&reader, BlockNumber(0)..BlockNumber(N_BLOCKS), &mut exported).unwrap();
+
+    let mut uncompressed = Vec::new();
+    zstd::stream::copy_decode(exported.as_slice(), &mut uncompressed).unwrap();
+    assert!(exported.len() < uncompressed.len());
+
+    let ((imported_reader, mut imported_writer), _imported_temp_dir) = get_test_storage();
+    assert_eq!(import_range(&mut imported_writer, exported.as_slice()).unwrap(), N_BLOCKS as usize);
+    assert!(compare_storages(&reader, &imported_reader, BlockNumber(0)..BlockNumber(N_BLOCKS))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn export_range_fails_on_missing_block() {
+    let ((reader, _writer), _temp_dir) = get_test_storage();
+    let result = export_range(&reader, BlockNumber(0)..BlockNumber(1), Vec::new());
+    assert_matches!(result, Err(StorageError::MissingBlockData { block_number: BlockNumber(0) }));
+}
+
+// Returns the class hash and a storage value of the given contract and the definitions of the given
+// classes, right after the given block.
+fn read_state_after(
+    reader: &StorageReader,
+    block_number: BlockNumber,
+    contract_address: ContractAddress,
+    storage_key: StorageKey,
+    class_hash: ClassHash,
+    deprecated_class_hash: ClassHash,
+) -> (Option<ClassHash>, Felt, Option<ContractClass>, Option<DeprecatedContractClass>) {
+    let txn = reader.begin_ro_txn().unwrap();
+    let state_reader = txn.get_state_reader().unwrap();
+    let state_number = StateNumber::unchecked_right_after_block(block_number);
+    (
+        state_reader.get_class_hash_at(state_number, &contract_address).unwrap(),
+        state_reader.get_storage_at(state_number, &contract_address, &storage_key).unwrap(),
+        state_reader.get_class_definition_at(state_number, &class_hash).unwrap(),
+        state_reader
+            .get_deprecated_class_definition_at(state_number, &deprecated_class_hash)
+            .unwrap(),
+    )
+}
+
+#[test]
+fn export_and_import_checkpoint_round_trip() {
+    const LAST_BLOCK_NUMBER: BlockNumber = BlockNumber(10);
+    const CLASS_DECLARATION_BLOCK_NUMBER: BlockNumber = BlockNumber(3);
+    let contract_address = ContractAddress(patricia_key!("0x11"));
+    let storage_key = StorageKey(patricia_key!("0x12"));
+    let class_hash = ClassHash(felt!("0x13"));
+    let deprecated_class_hash = ClassHash(felt!("0x14"));
+    let class = ContractClass::default();
+    let deprecated_class = DeprecatedContractClass::default();
+    let casm = CasmContractClass::default();
+
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let mut parent_header: Option<BlockHeader> = None;
+    for block_number in BlockNumber(0).iter_up_to(LAST_BLOCK_NUMBER.unchecked_next()) {
+        let mut state_diff = ThinStateDiff {
+            storage_diffs: indexmap! {
+                contract_address => indexmap! { storage_key => Felt::from(block_number.0) },
+            },
+            ..Default::default()
+        };
+        let mut classes = vec![];
+        let mut deprecated_classes = vec![];
+        let mut casms = vec![];
+        if block_number == BlockNumber(0) {
+            state_diff.deployed_contracts = indexmap! { contract_address => deprecated_class_hash };
+            state_diff.deprecated_declared_classes = vec![deprecated_class_hash];
+            deprecated_classes.push((deprecated_class_hash, &deprecated_class));
+        }
+        if block_number == CLASS_DECLARATION_BLOCK_NUMBER {
+            state_diff.declared_classes =
+                indexmap! { class_hash => CompiledClassHash(felt!("0x15")) };
+            state_diff.replaced_classes = indexmap! { contract_address => class_hash };
+            classes.push((class_hash, &class));
+            casms.push((class_hash, &casm));
+        }
+        let builder = match &parent_header {
+            None => BlockBuilder::genesis(),
+            Some(parent_header) => BlockBuilder::child_of(parent_header),
+        };
+        let (block, state_diff) = builder.state_diff(state_diff).build();
+        parent_header = Some(block.header.clone());
+        writer
+            .begin_rw_txn()
+            .unwrap()
+            .append_block(block_number, block, state_diff, &classes, &deprecated_classes, &casms)
+            .unwrap()
+            .commit()
+            .unwrap();
+    }
+
+    let mut exported = Vec::new();
+    export_checkpoint(&reader, LAST_BLOCK_NUMBER.unchecked_next(), &mut exported).unwrap();
+
+    let ((imported_reader, mut imported_writer), _imported_temp_dir) = get_test_storage();
+    assert_eq!(
+        import_checkpoint(&mut imported_writer, exported.as_slice()).unwrap(),
+        LAST_BLOCK_NUMBER.unchecked_next()
+    );
+
+    let imported_txn = imported_reader.begin_ro_txn().unwrap();
+    assert_eq!(imported_txn.get_header_marker().unwrap(), LAST_BLOCK_NUMBER.unchecked_next());
+    assert_eq!(imported_txn.get_body_marker().unwrap(), LAST_BLOCK_NUMBER.unchecked_next());
+    assert_eq!(imported_txn.get_state_marker().unwrap(), LAST_BLOCK_NUMBER.unchecked_next());
+    assert_eq!(imported_txn.get_class_marker().unwrap(), LAST_BLOCK_NUMBER.unchecked_next());
+    assert_eq!(imported_txn.get_casm(&class_hash).unwrap(), Some(casm));
+    let checkpoint_range = BlockNumber(0)..LAST_BLOCK_NUMBER.unchecked_next();
+    assert!(compare_storages(&reader, &imported_reader, checkpoint_range).unwrap().is_empty());
+
+    let read_state = |reader| {
+        read_state_after(
+            reader,
+            LAST_BLOCK_NUMBER,
+            contract_address,
+            storage_key,
+            class_hash,
+            deprecated_class_hash,
+        )
+    };
+    let state_at_last_block = read_state(&imported_reader);
+    assert_eq!(
+        state_at_last_block,
+        (Some(class_hash), Felt::from(LAST_BLOCK_NUMBER.0), Some(class), Some(deprecated_class))
+    );
+    assert_eq!(state_at_last_block, read_state(&reader));
+}
+
+#[test]
+fn export_checkpoint_fails_on_missing_class() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let (block, state_diff) = BlockBuilder::genesis()
+        .state_diff(ThinStateDiff {
+            declared_classes: indexmap! {
+                ClassHash(felt!("0x13")) => CompiledClassHash(felt!("0x15"))
+            },
+            ..Default::default()
+        })
+        .build();
+    // Append the block without its class.
+    writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_header(BlockNumber(0), &block.header)
+        .unwrap()
+        .append_body(BlockNumber(0), block.body)
+        .unwrap()
+        .append_state_diff(BlockNumber(0), state_diff)
+        .unwrap()
+        .commit()
+        .unwrap();
+
+    let result = export_checkpoint(&reader, BlockNumber(1), Vec::new());
+    assert_matches!(result, Err(StorageError::MissingBlockData { block_number: BlockNumber(0) }));
+}
@@ -0,0 +1,63 @@
+/// The state of a [`CircuitBreaker`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CircuitBreakerState {
+    /// The central source is called normally.
+    #[default]
+    Closed,
+    /// The central source failed too many times in a row and isn't called until a cool-down
+    /// passes.
+    Open,
+    /// The cool-down passed and the central source is called again to test whether it recovered.
+    HalfOpen,
+}
+
+/// Tracks the consecutive failures of the central source and decides whether the sync should stop
+/// calling it for a while.
+#[derive(Debug, Default)]
+pub(crate) struct CircuitBreaker {
+    state: CircuitBreakerState,
+    consecutive_failures: usize,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn state(&self) -> CircuitBreakerState {
+        self.state
+    }
+
+    /// Records a failed call to the central source. Returns the new state if the breaker changed
+    /// its state.
+    pub(crate) fn record_failure(&mut self, error_threshold: usize) -> Option<CircuitBreakerState> {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        match self.state {
+            CircuitBreakerState::Closed if self.consecutive_failures >= error_threshold => {
+                self.transition(CircuitBreakerState::Open)
+            }
+            CircuitBreakerState::HalfOpen => self.transition(CircuitBreakerState::Open),
+            CircuitBreakerState::Closed | CircuitBreakerState::Open => None,
+        }
+    }
+
+    /// Records a successful call to the central source. Returns the new state if the breaker
+    /// changed its state.
+    pub(crate) fn record_success(&mut self) -> Option<CircuitBreakerState> {
+        self.consecutive_failures = 0;
+        self.transition(CircuitBreakerState::Closed)
+    }
+
+    /// Lets calls to the central source test whether it recovered, once the cool-down passed.
+    /// Returns the new state if the breaker changed its state.
+    pub(crate) fn half_open(&mut self) -> Option<CircuitBreakerState> {
+        match self.state {
+            CircuitBreakerState::Open => self.transition(CircuitBreakerState::HalfOpen),
+            CircuitBreakerState::Closed | CircuitBreakerState::HalfOpen => None,
+        }
+    }
+
+    fn transition(&mut self, state: CircuitBreakerState) -> Option<CircuitBreakerState> {
+        if self.state == state {
+            return None;
+        }
+        self.state = state;
+        Some(state)
+    }
+}
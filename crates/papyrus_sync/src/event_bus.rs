@@ -0,0 +1,116 @@
+use libp2p::PeerId;
+use starknet_api::block::{BlockHash, BlockNumber};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tracing::warn;
+
+use crate::circuit_breaker::CircuitBreakerState;
+use crate::SyncEvent;
+
+/// The number of notifications that can wait in the channel of a subscriber. Once the channel is
+/// full, new notifications of the subscriber are dropped until it receives the waiting ones.
+pub const SUBSCRIBER_CHANNEL_CAPACITY: usize = 1000;
+
+/// The kinds of [`SyncNotification`]s a consumer can subscribe to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// New blocks, state diffs and base layer blocks that were stored, and catching up with the
+    /// central source.
+    Progress,
+    /// Compiled classes that were stored.
+    ClassDeclaration,
+    /// Blocks that were reverted.
+    Reorg,
+    /// Peers that supplied invalid data and should be penalized.
+    PeerPenalty,
+    /// State changes of the circuit breaker of the central source.
+    CircuitBreaker,
+}
+
+/// A notification that the sync publishes to the consumers of the [`SyncEventBus`].
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum SyncNotification {
+    /// An event received from the sources that was stored.
+    Stored(SyncEvent),
+    /// A block was reverted.
+    BlockReverted { block_number: BlockNumber, block_hash: BlockHash },
+    /// All the blocks from `to` (inclusive) up to `from` (exclusive) were reverted together. Sent
+    /// after the [`SyncNotification::BlockReverted`] notifications of these blocks.
+    Reverted { from: BlockNumber, to: BlockNumber },
+    /// A peer supplied data that failed validation.
+    PeerSuppliedInvalidData { peer_id: PeerId, block_number: BlockNumber },
+    /// The state reached the latest block of the central source.
+    CaughtUp,
+    /// The circuit breaker of the central source changed its state.
+    CircuitBreakerStateChanged { state: CircuitBreakerState },
+}
+
+impl SyncEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            SyncEvent::NoProgress
+            | SyncEvent::BlockAvailable { .. }
+            | SyncEvent::StateDiffAvailable { .. }
+            | SyncEvent::NewBaseLayerBlock { .. } => EventKind::Progress,
+            SyncEvent::CompiledClassAvailable { .. } => EventKind::ClassDeclaration,
+        }
+    }
+}
+
+impl SyncNotification {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            SyncNotification::Stored(event) => event.kind(),
+            SyncNotification::CaughtUp => EventKind::Progress,
+            SyncNotification::BlockReverted { .. } | SyncNotification::Reverted { .. } => {
+                EventKind::Reorg
+            }
+            SyncNotification::PeerSuppliedInvalidData { .. } => EventKind::PeerPenalty,
+            SyncNotification::CircuitBreakerStateChanged { .. } => EventKind::CircuitBreaker,
+        }
+    }
+}
+
+/// Distributes the notifications of the sync to the consumers that subscribed to their kind.
+#[derive(Default)]
+pub struct SyncEventBus {
+    subscribers: Vec<(EventKind, Sender<SyncNotification>)>,
+}
+
+impl SyncEventBus {
+    /// Returns a receiver of all the future notifications of the given kind. Up to
+    /// [`SUBSCRIBER_CHANNEL_CAPACITY`] notifications wait for the receiver, and the notifications
+    /// that don't fit are dropped.
+    pub fn subscribe(&mut self, filter: EventKind) -> Receiver<SyncNotification> {
+        let (sender, receiver) = channel(SUBSCRIBER_CHANNEL_CAPACITY);
+        self.subscribers.push((filter, sender));
+        receiver
+    }
+
+    pub fn has_subscribers(&self, kind: EventKind) -> bool {
+        self.subscribers.iter().any(|(filter, _)| *filter == kind)
+    }
+
+    /// Sends the notification to the subscribers of its kind, without waiting for subscribers
+    /// whose channel is full. Subscribers that dropped their receiver are removed.
+    pub fn publish(&mut self, notification: &SyncNotification) {
+        let kind = notification.kind();
+        self.subscribers.retain(|(filter, sender)| {
+            if *filter != kind {
+                return true;
+            }
+            match sender.try_send(notification.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!(
+                        "A subscriber of {kind:?} sync notifications isn't receiving them fast \
+                         enough, dropping a notification."
+                    );
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+}
@@ -0,0 +1,32 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+use crate::StateSyncError;
+
+/// An error the sync encountered and the time it was encountered at.
+#[derive(Clone, Debug)]
+pub struct RecordedError {
+    pub error: Arc<StateSyncError>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Keeps the last error the sync recovered from, so it can be inspected after the recovery.
+///
+/// Clones of the recorder share the recorded error.
+#[derive(Clone, Debug, Default)]
+pub struct LastErrorRecorder {
+    last_error: Arc<Mutex<Option<RecordedError>>>,
+}
+
+impl LastErrorRecorder {
+    /// Returns the last recorded error, or None if the sync didn't encounter an error yet.
+    pub fn last_error(&self) -> Option<RecordedError> {
+        self.last_error.lock().expect("Last error lock is poisoned").clone()
+    }
+
+    pub(crate) fn record(&self, error: StateSyncError) {
+        let recorded_error = RecordedError { error: Arc::new(error), timestamp: Utc::now() };
+        *self.last_error.lock().expect("Last error lock is poisoned") = Some(recorded_error);
+    }
+}
@@ -5,22 +5,40 @@
 #[cfg(test)]
 mod sync_test;
 
+pub mod circuit_breaker;
+pub mod event_bus;
+pub mod last_error;
 mod pending_sync;
+pub mod retry;
 pub mod sources;
+pub mod status;
+#[cfg(feature = "metrics")]
+mod sync_metrics;
+pub mod synced;
 
-use std::cmp::min;
+use std::cmp::{max, min};
 use std::collections::BTreeMap;
+use std::future::Future;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_stream::try_stream;
 use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
 use chrono::{TimeZone, Utc};
-use futures_util::{pin_mut, select, Stream, StreamExt};
+use circuit_breaker::{CircuitBreaker, CircuitBreakerState};
+use event_bus::{EventKind, SyncEventBus, SyncNotification};
+use futures_util::future::{self, FusedFuture};
+use futures_util::stream::FuturesOrdered;
+use futures_util::{pin_mut, select, FutureExt, Stream, StreamExt};
 use indexmap::IndexMap;
+use last_error::{LastErrorRecorder, RecordedError};
+use libp2p::PeerId;
 use papyrus_common::pending_classes::PendingClasses;
 use papyrus_common::{metrics as papyrus_metrics, BlockHashAndNumber};
-use papyrus_config::converters::deserialize_seconds_to_duration;
+use papyrus_config::converters::{
+    deserialize_milliseconds_to_duration,
+    deserialize_seconds_to_duration,
+};
 use papyrus_config::dumping::{ser_param, SerializeConfig};
 use papyrus_config::{ParamPath, ParamPrivacyInput, SerializedParam};
 use papyrus_proc_macros::latency_histogram;
@@ -28,23 +46,34 @@ use papyrus_storage::base_layer::{BaseLayerStorageReader, BaseLayerStorageWriter
 use papyrus_storage::body::BodyStorageWriter;
 use papyrus_storage::class::ClassStorageWriter;
 use papyrus_storage::compiled_class::{CasmStorageReader, CasmStorageWriter};
-use papyrus_storage::db::DbError;
+use papyrus_storage::db::{DbError, RW};
 use papyrus_storage::header::{HeaderStorageReader, HeaderStorageWriter};
 use papyrus_storage::state::{StateStorageReader, StateStorageWriter};
-use papyrus_storage::{StorageError, StorageReader, StorageWriter};
+use papyrus_storage::{StorageError, StorageReader, StorageResult, StorageTxn, StorageWriter};
+use retry::{is_retryable_central_error, retry_with_backoff, Backoff, RetryPolicy};
 use serde::{Deserialize, Serialize};
 use sources::base_layer::BaseLayerSourceError;
 use starknet_api::block::{Block, BlockHash, BlockNumber, BlockSignature};
 use starknet_api::core::{ClassHash, CompiledClassHash, SequencerPublicKey};
 use starknet_api::deprecated_contract_class::ContractClass as DeprecatedContractClass;
-use starknet_api::state::{StateDiff, ThinStateDiff};
+use starknet_api::state::{StateDiff, StateNumber, ThinStateDiff};
 use starknet_client::reader::PendingData;
-use tokio::sync::RwLock;
+use status::SyncStatusHandle;
+use synced::SyncedHandle;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{oneshot, RwLock};
 use tracing::{debug, error, info, instrument, trace, warn};
+use validator::{Validate, ValidationError};
 
 use crate::pending_sync::sync_pending_data;
 use crate::sources::base_layer::{BaseLayerSourceTrait, EthereumBaseLayerSource};
-use crate::sources::central::{CentralError, CentralSource, CentralSourceTrait};
+use crate::sources::central::{
+    estimate_central_state_update_size,
+    CentralError,
+    CentralSource,
+    CentralSourceTrait,
+    CentralStateUpdate,
+};
 use crate::sources::pending::{PendingError, PendingSource, PendingSourceTrait};
 
 // TODO(shahak): Consider adding genesis hash to the config to support chains that have
@@ -59,17 +88,57 @@ const PENDING_SLEEP_DURATION: Duration = Duration::from_millis(500);
 // Sleep duration, in seconds, between sync progress checks.
 const SLEEP_TIME_SYNC_PROGRESS: Duration = Duration::from_secs(300);
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+// The number of blocks the state can be behind the latest block of the central source while the
+// sync is still considered synced.
+const SYNCED_BLOCKS_TOLERANCE: u64 = 1;
+
+// The number of times the header of a block is checked before its state diff is emitted, waiting
+// block_propagation_sleep_duration between the checks.
+const MAX_HEADER_CHECKS: usize = 3;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Validate)]
+#[validate(schema(function = "validate_sync_config"))]
 pub struct SyncConfig {
     #[serde(deserialize_with = "deserialize_seconds_to_duration")]
     pub block_propagation_sleep_duration: Duration,
     #[serde(deserialize_with = "deserialize_seconds_to_duration")]
     pub base_layer_propagation_sleep_duration: Duration,
     #[serde(deserialize_with = "deserialize_seconds_to_duration")]
-    pub recoverable_error_sleep_duration: Duration,
+    pub base_backoff: Duration,
+    #[serde(deserialize_with = "deserialize_seconds_to_duration")]
+    pub max_backoff: Duration,
+    pub backoff_jitter_ratio: f64,
     pub blocks_max_stream_size: u32,
     pub state_updates_max_stream_size: u32,
+    pub max_parallel_state_diff_downloads: usize,
+    pub min_confirmations: u64,
+    pub sync_pending_data: bool,
     pub verify_blocks: bool,
+    pub verify_state_diffs: bool,
+    pub circuit_breaker_error_threshold: usize,
+    #[serde(deserialize_with = "deserialize_seconds_to_duration")]
+    pub circuit_breaker_cool_down: Duration,
+    pub central_retry_max_attempts: usize,
+    #[serde(deserialize_with = "deserialize_milliseconds_to_duration")]
+    pub central_retry_base_delay: Duration,
+    #[serde(deserialize_with = "deserialize_milliseconds_to_duration")]
+    pub central_retry_max_delay: Duration,
+    pub central_retry_jitter_ratio: f64,
+    #[serde(deserialize_with = "deserialize_milliseconds_to_duration")]
+    pub slow_store_warning_threshold: Duration,
+}
+
+impl SyncConfig {
+    /// The policy for retrying failed calls to the central source.
+    pub fn central_retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.central_retry_max_attempts,
+            base_delay: self.central_retry_base_delay,
+            max_delay: self.central_retry_max_delay,
+            jitter_ratio: self.central_retry_jitter_ratio,
+            is_retryable: is_retryable_central_error,
+        }
+    }
 }
 
 impl SerializeConfig for SyncConfig {
@@ -88,10 +157,25 @@ impl SerializeConfig for SyncConfig {
                 ParamPrivacyInput::Public,
             ),
             ser_param(
-                "recoverable_error_sleep_duration",
-                &self.recoverable_error_sleep_duration.as_secs(),
+                "base_backoff",
+                &self.base_backoff.as_secs(),
                 "Waiting time in seconds before restarting synchronization after a recoverable \
-                 error.",
+                 error. The waiting time doubles on consecutive errors and is reset once state \
+                 diffs are synced again.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "max_backoff",
+                &self.max_backoff.as_secs(),
+                "Max waiting time in seconds before restarting synchronization after a \
+                 recoverable error.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "backoff_jitter_ratio",
+                &self.backoff_jitter_ratio,
+                "The waiting time after a recoverable error is randomly changed by up to this \
+                 ratio of it.",
                 ParamPrivacyInput::Public,
             ),
             ser_param(
@@ -106,12 +190,93 @@ impl SerializeConfig for SyncConfig {
                 "Max amount of state updates to download in a stream.",
                 ParamPrivacyInput::Public,
             ),
+            ser_param(
+                "max_parallel_state_diff_downloads",
+                &self.max_parallel_state_diff_downloads,
+                "Max amount of state diffs to download concurrently. State diffs are still \
+                 processed in order of their block number. The state diffs that are downloaded \
+                 concurrently are also bounded by central.sync_memory_budget_bytes.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "min_confirmations",
+                &self.min_confirmations,
+                "Number of newest downloaded blocks whose state diffs aren't downloaded until \
+                 newer blocks follow them, since they're the most likely to be reverted. Must be \
+                 0 if sync_pending_data is true.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "sync_pending_data",
+                &self.sync_pending_data,
+                "Whether to sync the pending data once the state of the latest block is synced. \
+                 Must be false if min_confirmations is positive, since the state of the latest \
+                 block isn't synced then.",
+                ParamPrivacyInput::Public,
+            ),
             ser_param(
                 "verify_blocks",
                 &self.verify_blocks,
                 "Whether to verify incoming blocks.",
                 ParamPrivacyInput::Public,
             ),
+            ser_param(
+                "verify_state_diffs",
+                &self.verify_state_diffs,
+                "Whether to verify that incoming state diffs are internally consistent before \
+                 storing them.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "circuit_breaker_error_threshold",
+                &self.circuit_breaker_error_threshold,
+                "Number of consecutive central source errors after which the sync stops calling \
+                 the central source until the cool-down passes.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "circuit_breaker_cool_down",
+                &self.circuit_breaker_cool_down.as_secs(),
+                "Time in seconds to wait before calling the central source again after it failed \
+                 too many times in a row. While the central source keeps failing, it replaces the \
+                 waiting time before restarting synchronization.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "central_retry_max_attempts",
+                &self.central_retry_max_attempts,
+                "Max number of times a call to the central source is made, including the first \
+                 one, before its error is returned.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "central_retry_base_delay",
+                &self.central_retry_base_delay.as_millis(),
+                "Waiting time in milliseconds before retrying a failed call to the central \
+                 source. The waiting time doubles on each retry of the same call.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "central_retry_max_delay",
+                &self.central_retry_max_delay.as_millis(),
+                "Max waiting time in milliseconds before retrying a failed call to the central \
+                 source.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "central_retry_jitter_ratio",
+                &self.central_retry_jitter_ratio,
+                "The waiting time before retrying a failed call to the central source is randomly \
+                 changed by up to this ratio of it.",
+                ParamPrivacyInput::Public,
+            ),
+            ser_param(
+                "slow_store_warning_threshold",
+                &self.slow_store_warning_threshold.as_millis(),
+                "Time in milliseconds that storing downloaded data may take before a warning is \
+                 logged. Slow stores mean the storage, not the download, is slowing down the sync.",
+                ParamPrivacyInput::Public,
+            ),
         ])
     }
 }
@@ -121,14 +286,36 @@ impl Default for SyncConfig {
         SyncConfig {
             block_propagation_sleep_duration: Duration::from_secs(2),
             base_layer_propagation_sleep_duration: Duration::from_secs(10),
-            recoverable_error_sleep_duration: Duration::from_secs(3),
+            base_backoff: Duration::from_secs(3),
+            max_backoff: Duration::from_secs(60),
+            backoff_jitter_ratio: 0.1,
             blocks_max_stream_size: 1000,
             state_updates_max_stream_size: 1000,
+            max_parallel_state_diff_downloads: 1,
+            min_confirmations: 0,
+            sync_pending_data: true,
             verify_blocks: true,
+            verify_state_diffs: true,
+            circuit_breaker_error_threshold: 10,
+            circuit_breaker_cool_down: Duration::from_secs(60),
+            central_retry_max_attempts: 3,
+            central_retry_base_delay: Duration::from_millis(100),
+            central_retry_max_delay: Duration::from_secs(5),
+            central_retry_jitter_ratio: 0.1,
+            slow_store_warning_threshold: Duration::from_secs(1),
         }
     }
 }
 
+fn validate_sync_config(config: &SyncConfig) -> Result<(), ValidationError> {
+    if config.min_confirmations > 0 && config.sync_pending_data {
+        return Err(ValidationError::new(
+            "sync_pending_data must be false if min_confirmations is positive",
+        ));
+    }
+    Ok(())
+}
+
 // Orchestrates specific network interfaces (e.g. central, p2p, l1) and writes to Storage and shared
 // memory.
 pub struct GenericStateSync<
@@ -146,6 +333,10 @@ pub struct GenericStateSync<
     reader: StorageReader,
     writer: StorageWriter,
     sequencer_pub_key: Option<SequencerPublicKey>,
+    event_bus: SyncEventBus,
+    synced_handle: SyncedHandle,
+    last_error_recorder: LastErrorRecorder,
+    circuit_breaker: CircuitBreaker,
 }
 
 pub type StateSyncResult = Result<(), StateSyncError>;
@@ -172,6 +363,8 @@ pub enum StateSyncError {
     },
     #[error("Header for block {block_number} wasn't found when trying to store base layer block.")]
     BaseLayerBlockWithoutMatchingHeader { block_number: BlockNumber },
+    #[error("Header for block {block_number} wasn't found when trying to store its state diff.")]
+    StateDiffWithoutMatchingHeader { block_number: BlockNumber },
     #[error(transparent)]
     BaseLayerSourceError(#[from] BaseLayerSourceError),
     #[error(
@@ -185,10 +378,17 @@ pub enum StateSyncError {
     },
     #[error("Sequencer public key changed from {old:?} to {new:?}.")]
     SequencerPubKeyChanged { old: SequencerPublicKey, new: SequencerPublicKey },
+    #[error(
+        "Peer {peer_id} supplied a state diff for block {block_number} that doesn't match the \
+         stored header."
+    )]
+    PeerSuppliedInvalidStateDiff { peer_id: PeerId, block_number: BlockNumber },
+    #[error("The state diff of block {block_number} isn't internally consistent.")]
+    InconsistentStateDiff { block_number: BlockNumber },
 }
 
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum SyncEvent {
     NoProgress,
     BlockAvailable {
@@ -205,6 +405,9 @@ pub enum SyncEvent {
         // state diff.
         // Note: Since 0.11 new classes can not be implicitly declared.
         deployed_contract_class_definitions: IndexMap<ClassHash, DeprecatedContractClass>,
+        // The peer that supplied the state diff, if it was downloaded from p2p. The peer is
+        // penalized if the state diff turns out to be invalid.
+        source_peer: Option<PeerId>,
     },
     CompiledClassAvailable {
         class_hash: ClassHash,
@@ -224,13 +427,79 @@ impl<
 > GenericStateSync<TCentralSource, TPendingSource, TBaseLayerSource>
 {
     pub async fn run(&mut self) -> StateSyncResult {
+        self.run_until(future::pending::<()>()).await
+    }
+
+    /// Runs the sync until `shutdown` receives a message or its sender is dropped, and then
+    /// returns `Ok(())`.
+    ///
+    /// The shutdown is checked between sync events and while waiting before restarting the sync
+    /// after an error, so a state diff that is being written is either fully committed or not
+    /// written at all.
+    pub async fn run_until_shutdown(&mut self, shutdown: oneshot::Receiver<()>) -> StateSyncResult {
+        self.run_until(shutdown.map(|_| ())).await
+    }
+
+    // Failures of the central source are handled in three layers:
+    //  1. Each call, and the download of each state diff, is retried as the central retry policy
+    //     defines, which makes at most central_retry_max_attempts calls, with up to
+    //     central_retry_max_delay between them. Errors in the streams of blocks and compiled
+    //     classes skip this layer.
+    //  2. A call that still fails stops the sync, which restarts after a waiting time that grows
+    //     from base_backoff to max_backoff on consecutive errors.
+    //  3. Once circuit_breaker_error_threshold errors of the central source happen in a row, the
+    //     circuit breaker opens and the sync waits circuit_breaker_cool_down before each restart
+    //     instead, until the central source succeeds again.
+    // So during an outage the central source is called once per cool-down (with its retries), and
+    // the sync resumes at most a cool-down and the retries of a single call after the outage ends.
+    async fn run_until(&mut self, shutdown: impl Future<Output = ()>) -> StateSyncResult {
         info!("State sync started.");
+        let shutdown = shutdown.fuse();
+        pin_mut!(shutdown);
+        let mut backoff = Backoff::new(
+            self.config.base_backoff,
+            self.config.max_backoff,
+            self.config.backoff_jitter_ratio,
+        );
+        let mut state_marker = self.state_marker();
         loop {
-            match self.sync_while_ok().await {
+            match self.sync_while_ok(&mut shutdown).await {
                 // A recoverable error occurred. Sleep and try syncing again.
                 Err(err) if is_recoverable(&err) => {
                     warn!("Recoverable error encountered while syncing, error: {}", err);
-                    tokio::time::sleep(self.config.recoverable_error_sleep_duration).await;
+                    if matches!(err, StateSyncError::CentralSourceError(_)) {
+                        let transition = self
+                            .circuit_breaker
+                            .record_failure(self.config.circuit_breaker_error_threshold);
+                        self.publish_circuit_breaker_transition(transition);
+                    }
+                    self.last_error_recorder.record(err);
+                    // State diffs were synced since the previous error, so this isn't a
+                    // consecutive error.
+                    let current_state_marker = self.state_marker();
+                    if current_state_marker > state_marker {
+                        backoff.reset();
+                    }
+                    state_marker = current_state_marker;
+                    if self.circuit_breaker.state() == CircuitBreakerState::Open {
+                        let cool_down = self.config.circuit_breaker_cool_down;
+                        debug!(
+                            "The central source keeps failing, restarting sync in {cool_down:?}."
+                        );
+                        if sleep_unless_shutdown(cool_down, &mut shutdown).await {
+                            info!("State sync stopped.");
+                            return Ok(());
+                        }
+                        let transition = self.circuit_breaker.half_open();
+                        self.publish_circuit_breaker_transition(transition);
+                        continue;
+                    }
+                    let delay = backoff.next_delay();
+                    debug!("Restarting sync in {delay:?}.");
+                    if sleep_unless_shutdown(delay, &mut shutdown).await {
+                        info!("State sync stopped.");
+                        return Ok(());
+                    }
                     continue;
                 }
                 // Unrecoverable errors.
@@ -238,8 +507,10 @@ impl<
                     error!("Fatal error while syncing: {}", err);
                     return Err(err);
                 }
+                // The sync was shut down.
                 Ok(_) => {
-                    unreachable!("Sync should either return with an error or continue forever.")
+                    info!("State sync stopped.");
+                    return Ok(());
                 }
             }
         }
@@ -258,14 +529,33 @@ impl<
                 | StateSyncError::BaseLayerSourceError(_)
                 | StateSyncError::ParentBlockHashMismatch { .. }
                 | StateSyncError::BaseLayerHashMismatch { .. }
-                | StateSyncError::BaseLayerBlockWithoutMatchingHeader { .. } => true,
+                | StateSyncError::BaseLayerBlockWithoutMatchingHeader { .. }
+                | StateSyncError::StateDiffWithoutMatchingHeader { .. }
+                | StateSyncError::PeerSuppliedInvalidStateDiff { .. }
+                | StateSyncError::InconsistentStateDiff { .. } => true,
                 StateSyncError::SequencerPubKeyChanged { .. } => false,
             }
         }
     }
 
+    fn publish_circuit_breaker_transition(&mut self, transition: Option<CircuitBreakerState>) {
+        if let Some(state) = transition {
+            info!("The circuit breaker of the central source is now {state:?}.");
+            self.event_bus.publish(&SyncNotification::CircuitBreakerStateChanged { state });
+        }
+    }
+
+    // Returns None if the state marker couldn't be read.
+    fn state_marker(&self) -> Option<BlockNumber> {
+        self.reader.begin_ro_txn().and_then(|txn| txn.get_state_marker()).ok()
+    }
+
     async fn track_sequencer_public_key_changes(&mut self) -> StateSyncResult {
-        let sequencer_pub_key = self.central_source.get_sequencer_pub_key().await?;
+        let sequencer_pub_key = retry_with_backoff(
+            || self.central_source.get_sequencer_pub_key(),
+            &self.config.central_retry_policy(),
+        )
+        .await?;
         match self.sequencer_pub_key {
             // First time setting the sequencer public key.
             None => {
@@ -293,7 +583,11 @@ impl<
     //  1. If needed, revert blocks from the end of the chain.
     //  2. Create infinite block and state diff streams to fetch data from the central source.
     //  3. Fetch data from the streams with unblocking wait while there is no new data.
-    async fn sync_while_ok(&mut self) -> StateSyncResult {
+    // Returns Ok once `shutdown` completes, which is checked only between sync events.
+    async fn sync_while_ok(
+        &mut self,
+        shutdown: &mut (impl FusedFuture<Output = ()> + Unpin),
+    ) -> StateSyncResult {
         if self.config.verify_blocks {
             self.track_sequencer_public_key_changes().await?;
         }
@@ -305,9 +599,11 @@ impl<
             self.shared_highest_block.clone(),
             self.pending_data.clone(),
             self.pending_classes.clone(),
+            self.config.sync_pending_data,
             self.config.block_propagation_sleep_duration,
             PENDING_SLEEP_DURATION,
             self.config.blocks_max_stream_size,
+            self.config.central_retry_policy(),
         )
         .fuse();
         let state_diff_stream = stream_new_state_diffs(
@@ -315,6 +611,9 @@ impl<
             self.central_source.clone(),
             self.config.block_propagation_sleep_duration,
             self.config.state_updates_max_stream_size,
+            self.config.max_parallel_state_diff_downloads,
+            self.config.min_confirmations,
+            self.config.central_retry_policy(),
         )
         .fuse();
         let compiled_class_stream = stream_new_compiled_classes(
@@ -350,6 +649,7 @@ impl<
               res = compiled_class_stream.next() => res,
               res = base_layer_block_stream.next() => res,
               res = check_sync_progress.next() => res,
+              _ = shutdown => return Ok(()),
               complete => break,
             }
             .expect("Received None as a sync event.")?;
@@ -359,8 +659,99 @@ impl<
         unreachable!("Fetching data loop should never return.");
     }
 
-    // Tries to store the incoming data.
+    /// Returns a receiver of the notifications of the given kind that the sync publishes from now
+    /// on.
+    pub fn subscribe(&mut self, filter: EventKind) -> Receiver<SyncNotification> {
+        self.event_bus.subscribe(filter)
+    }
+
+    /// Whether the state is synced up to the latest block of the central source, up to a small
+    /// tolerance.
+    pub fn is_synced(&self) -> bool {
+        self.synced_handle.is_synced()
+    }
+
+    /// Returns a handle that is shared with the sync, to check whether it's synced while it runs.
+    pub fn synced_handle(&self) -> SyncedHandle {
+        self.synced_handle.clone()
+    }
+
+    /// Returns the last error the sync recovered from and when it was encountered. The error is
+    /// kept after the sync recovers.
+    pub fn last_error(&self) -> Option<RecordedError> {
+        self.last_error_recorder.last_error()
+    }
+
+    /// Returns a recorder that is shared with the sync, to inspect its last error while it runs.
+    pub fn last_error_recorder(&self) -> LastErrorRecorder {
+        self.last_error_recorder.clone()
+    }
+
+    /// Returns a handle for taking snapshots of the sync's progress while it runs.
+    pub fn status_handle(&self) -> SyncStatusHandle {
+        SyncStatusHandle::new(self.reader.clone(), self.shared_highest_block.clone())
+    }
+
+    // Compares the state marker to the latest block of the central source, and publishes
+    // CaughtUp when the sync becomes synced.
+    async fn update_is_synced(&mut self) -> StateSyncResult {
+        let Some(latest_block) = *self.shared_highest_block.read().await else {
+            return Ok(());
+        };
+        let state_marker = self.reader.begin_ro_txn()?.get_state_marker()?;
+        // The state diffs of the newest min_confirmations blocks are synced only once newer blocks
+        // follow them, so the sync is synced once it reaches the block before them.
+        let target_block_number =
+            BlockNumber(latest_block.block_number.0.saturating_sub(self.config.min_confirmations));
+        let missing_blocks = target_block_number.unchecked_next().0.saturating_sub(state_marker.0);
+        let is_synced = missing_blocks <= SYNCED_BLOCKS_TOLERANCE;
+        let was_synced = self.synced_handle.set(is_synced);
+        if is_synced && !was_synced {
+            info!("Caught up with the latest block {}.", latest_block.block_number);
+            self.event_bus.publish(&SyncNotification::CaughtUp);
+        }
+        Ok(())
+    }
+
+    // Tries to store the incoming data and publishes the event if it was stored.
     async fn process_sync_event(&mut self, sync_event: SyncEvent) -> StateSyncResult {
+        // Cloning the event is expensive, so it's done only if someone consumes it.
+        let notification_to_publish = self
+            .event_bus
+            .has_subscribers(sync_event.kind())
+            .then(|| SyncNotification::Stored(sync_event.clone()));
+        let is_from_central_source = matches!(
+            sync_event,
+            SyncEvent::BlockAvailable { .. }
+                | SyncEvent::StateDiffAvailable { source_peer: None, .. }
+                | SyncEvent::CompiledClassAvailable { .. }
+        );
+        let event_kind = sync_event.kind();
+        let store_start = Instant::now();
+        self.store_sync_event(sync_event)?;
+        let store_duration = store_start.elapsed();
+        // The events are downloaded only as fast as they're stored, so slow stores throttle the
+        // downloads.
+        if store_duration >= self.config.slow_store_warning_threshold {
+            warn!(
+                "Storing a {event_kind:?} sync event took {store_duration:?}, which is more than \
+                 the threshold of {:?}. The storage is slowing down the sync.",
+                self.config.slow_store_warning_threshold
+            );
+            #[cfg(feature = "metrics")]
+            sync_metrics::record_slow_store();
+        }
+        if let Some(notification) = notification_to_publish {
+            self.event_bus.publish(&notification);
+        }
+        if is_from_central_source {
+            let transition = self.circuit_breaker.record_success();
+            self.publish_circuit_breaker_transition(transition);
+        }
+        self.update_is_synced().await
+    }
+
+    fn store_sync_event(&mut self, sync_event: SyncEvent) -> StateSyncResult {
         match sync_event {
             SyncEvent::BlockAvailable { block_number, block, signature } => {
                 self.store_block(block_number, block, &signature)
@@ -370,11 +761,13 @@ impl<
                 block_hash,
                 state_diff,
                 deployed_contract_class_definitions,
+                source_peer,
             } => self.store_state_diff(
                 block_number,
                 block_hash,
                 state_diff,
                 deployed_contract_class_definitions,
+                source_peer,
             ),
             SyncEvent::CompiledClassAvailable {
                 class_hash,
@@ -442,8 +835,44 @@ impl<
         block_hash: BlockHash,
         state_diff: StateDiff,
         deployed_contract_class_definitions: IndexMap<ClassHash, DeprecatedContractClass>,
+        source_peer: Option<PeerId>,
     ) -> StateSyncResult {
         // TODO(dan): verifications - verify state diff against stored header.
+        // Headers and state diffs are synced by separate streams. The state diffs of the central
+        // source are emitted only after their header is stored, but the header might have been
+        // reverted since then, and peers may supply state diffs of blocks whose header isn't
+        // stored. In that case the state diff is dropped and downloaded again once the header is
+        // stored.
+        let Some(stored_block_hash) = self
+            .reader
+            .begin_ro_txn()?
+            .get_block_header(block_number)?
+            .map(|header| header.block_hash)
+        else {
+            debug!("The header of block {block_number} isn't stored, dropping its state diff.");
+            return Err(StateSyncError::StateDiffWithoutMatchingHeader { block_number });
+        };
+        // Unlike the central source, peers aren't trusted, so at least make sure the state diff
+        // belongs to the stored block.
+        if let Some(peer_id) = source_peer {
+            if stored_block_hash != block_hash {
+                warn!(
+                    "Peer {} supplied a state diff of block {} with hash {:#064x}, but the stored \
+                     block hash is {:#064x}.",
+                    peer_id, block_number, block_hash.0, stored_block_hash.0
+                );
+                self.event_bus
+                    .publish(&SyncNotification::PeerSuppliedInvalidData { peer_id, block_number });
+                return Err(StateSyncError::PeerSuppliedInvalidStateDiff { peer_id, block_number });
+            }
+        }
+        if self.config.verify_state_diffs {
+            self.verify_state_diff_consistency(
+                block_number,
+                &state_diff,
+                &deployed_contract_class_definitions,
+            )?;
+        }
         debug!("Storing state diff.");
         trace!("StateDiff data: {state_diff:#?}");
 
@@ -451,6 +880,8 @@ impl<
         // classes.
         let (thin_state_diff, classes, deprecated_classes) =
             ThinStateDiff::from_state_diff(state_diff);
+        #[cfg(feature = "metrics")]
+        let state_diff_size = sync_metrics::estimate_thin_state_diff_size(&thin_state_diff);
         self.writer
             .begin_rw_txn()?
             .append_state_diff(block_number, thin_state_diff)?
@@ -465,6 +896,8 @@ impl<
             )?
             .commit()?;
 
+        #[cfg(feature = "metrics")]
+        sync_metrics::record_stored_state_diff(state_diff_size);
         metrics::gauge!(
             papyrus_metrics::PAPYRUS_STATE_MARKER,
             block_number.unchecked_next().0 as f64
@@ -481,6 +914,57 @@ impl<
         Ok(())
     }
 
+    // Checks that the state diff is internally consistent:
+    //  1. No class is declared both as a Cairo 1 class and as a deprecated class.
+    //  2. The class of every deployed contract is defined, either in the state diff, in the
+    //     deployed contract class definitions or in an earlier block.
+    fn verify_state_diff_consistency(
+        &self,
+        block_number: BlockNumber,
+        state_diff: &StateDiff,
+        deployed_contract_class_definitions: &IndexMap<ClassHash, DeprecatedContractClass>,
+    ) -> StateSyncResult {
+        if let Some(class_hash) = state_diff
+            .declared_classes
+            .keys()
+            .find(|class_hash| state_diff.deprecated_declared_classes.contains_key(*class_hash))
+        {
+            warn!(
+                "Class {} is declared as both a Cairo 1 and a deprecated class in the state diff \
+                 of block {}.",
+                class_hash, block_number
+            );
+            return Err(StateSyncError::InconsistentStateDiff { block_number });
+        }
+
+        let txn = self.reader.begin_ro_txn()?;
+        let state_reader = txn.get_state_reader()?;
+        let state_number = StateNumber::right_before_block(block_number);
+        for class_hash in state_diff.deployed_contracts.values() {
+            if state_diff.declared_classes.contains_key(class_hash)
+                || state_diff.deprecated_declared_classes.contains_key(class_hash)
+                || deployed_contract_class_definitions.contains_key(class_hash)
+            {
+                continue;
+            }
+            let is_declared_before = state_reader
+                .get_class_definition_block_number(class_hash)?
+                .is_some_and(|declared_block_number| declared_block_number < block_number)
+                || state_reader
+                    .get_deprecated_class_definition_at(state_number, class_hash)?
+                    .is_some();
+            if !is_declared_before {
+                warn!(
+                    "The state diff of block {} deploys a contract of class {}, which isn't \
+                     defined.",
+                    block_number, class_hash
+                );
+                return Err(StateSyncError::InconsistentStateDiff { block_number });
+            }
+        }
+        Ok(())
+    }
+
     #[latency_histogram("sync_store_compiled_class_latency_seconds", false)]
     #[instrument(skip(self, compiled_class), level = "debug", err)]
     fn store_compiled_class(
@@ -503,9 +987,8 @@ impl<
                 debug!("Added compiled class.");
                 Ok(())
             }
-            // TODO(yair): Modify the stream so it skips already stored classes.
-            // Compiled classes rewrite is valid because the stream downloads from the beginning of
-            // the block instead of the last downloaded class.
+            // The stream skips classes that are already stored when it reaches their block, but a
+            // class can still be stored between that check and this write.
             Err(StorageError::InnerError(DbError::KeyAlreadyExists(..))) => {
                 debug!("Compiled class of {class_hash} already stored.");
                 Ok(())
@@ -592,44 +1075,43 @@ impl<
         debug!("Handling block reverts.");
         let header_marker = self.reader.begin_ro_txn()?.get_header_marker()?;
 
-        // Revert last blocks if needed.
-        let mut last_block_in_storage = header_marker.prev();
-        while let Some(block_number) = last_block_in_storage {
-            if self.should_revert_block(block_number).await? {
-                self.revert_block(block_number)?;
-                last_block_in_storage = block_number.prev();
-            } else {
+        // Walk back from the last stored block to find the first block that needs to be reverted.
+        // The central source may have jumped back several blocks.
+        let mut first_reverted_block = header_marker;
+        while let Some(block_number) = first_reverted_block.prev() {
+            if !self.should_revert_block(block_number).await? {
                 break;
             }
+            first_reverted_block = block_number;
+        }
+        if first_reverted_block < header_marker {
+            self.revert_blocks_to(first_reverted_block)?;
         }
         Ok(())
     }
 
-    // TODO(dan): update necessary metrics.
-    // Deletes the block data from the storage.
-    #[allow(clippy::expect_fun_call)]
+    // Deletes the data of all the blocks from the given block number onwards from the storage, in a
+    // single transaction.
     #[instrument(skip(self), level = "debug", err)]
-    fn revert_block(&mut self, block_number: BlockNumber) -> StateSyncResult {
-        debug!("Reverting block.");
-
-        let mut txn = self.writer.begin_rw_txn()?;
-        txn = txn.try_revert_base_layer_marker(block_number)?;
-        let res = txn.revert_header(block_number)?;
-        txn = res.0;
-        let mut reverted_block_hash: Option<BlockHash> = None;
-        if let Some(header) = res.1 {
-            reverted_block_hash = Some(header.block_hash);
+    fn revert_blocks_to(&mut self, target_block_number: BlockNumber) -> StateSyncResult {
+        debug!("Reverting blocks.");
 
-            let res = txn.revert_body(block_number)?;
-            txn = res.0;
+        let txn = self.writer.begin_rw_txn()?;
+        let header_marker = txn.get_header_marker()?;
+        let (txn, reverted_blocks) = revert_to(txn, target_block_number)?;
+        txn.commit()?;
+        #[cfg(feature = "metrics")]
+        sync_metrics::record_revert(reverted_blocks.len());
 
-            let res = txn.revert_state_diff(block_number)?;
-            txn = res.0;
+        for (block_number, block_hash) in reverted_blocks {
+            info!(hash = %block_hash, "Reverted block {block_number}.");
+            self.event_bus.publish(&SyncNotification::BlockReverted { block_number, block_hash });
         }
-
-        txn.commit()?;
-        if let Some(hash) = reverted_block_hash {
-            info!(%hash, "Reverted block.");
+        if target_block_number < header_marker {
+            self.event_bus.publish(&SyncNotification::Reverted {
+                from: header_marker,
+                to: target_block_number,
+            });
         }
         Ok(())
     }
@@ -637,7 +1119,12 @@ impl<
     /// Checks if centrals block hash at the block number is different from ours (or doesn't exist).
     /// If so, a revert is required.
     async fn should_revert_block(&self, block_number: BlockNumber) -> Result<bool, StateSyncError> {
-        if let Some(central_block_hash) = self.central_source.get_block_hash(block_number).await? {
+        let maybe_central_block_hash = retry_with_backoff(
+            || self.central_source.get_block_hash(block_number),
+            &self.config.central_retry_policy(),
+        )
+        .await?;
+        if let Some(central_block_hash) = maybe_central_block_hash {
             let storage_block_header =
                 self.reader.begin_ro_txn()?.get_block_header(block_number)?;
 
@@ -651,6 +1138,29 @@ impl<
         }
     }
 }
+
+// Reverts the blocks from the last stored block down to the given block number (inclusive), so that
+// the markers are rewound to it. Returns the numbers and hashes of the reverted blocks, starting
+// from the last one.
+fn revert_to(
+    mut txn: StorageTxn<'_, RW>,
+    target_block_number: BlockNumber,
+) -> StorageResult<(StorageTxn<'_, RW>, Vec<(BlockNumber, BlockHash)>)> {
+    let header_marker = txn.get_header_marker()?;
+    let mut reverted_blocks = Vec::new();
+    for block_number in (target_block_number.0..header_marker.0).rev().map(BlockNumber) {
+        txn = txn.try_revert_base_layer_marker(block_number)?;
+        let (reverted_txn, reverted_header, _) = txn.revert_header(block_number)?;
+        txn = reverted_txn;
+        if let Some(header) = reverted_header {
+            txn = txn.revert_body(block_number)?.0;
+            txn = txn.revert_state_diff(block_number)?.0;
+            reverted_blocks.push((block_number, header.block_hash));
+        }
+    }
+    Ok((txn, reverted_blocks))
+}
+
 // TODO(dvir): consider gathering in a single pending argument instead.
 #[allow(clippy::too_many_arguments)]
 fn stream_new_blocks<
@@ -663,14 +1173,17 @@ fn stream_new_blocks<
     shared_highest_block: Arc<RwLock<Option<BlockHashAndNumber>>>,
     pending_data: Arc<RwLock<PendingData>>,
     pending_classes: Arc<RwLock<PendingClasses>>,
+    sync_pending_data: bool,
     block_propagation_sleep_duration: Duration,
     pending_sleep_duration: Duration,
     max_stream_size: u32,
+    retry_policy: RetryPolicy,
 ) -> impl Stream<Item = Result<SyncEvent, StateSyncError>> {
     try_stream! {
         loop {
             let header_marker = reader.begin_ro_txn()?.get_header_marker()?;
-            let latest_central_block = central_source.get_latest_block().await?;
+            let latest_central_block =
+                retry_with_backoff(|| central_source.get_latest_block(), &retry_policy).await?;
             *shared_highest_block.write().await = latest_central_block;
             let central_block_marker = latest_central_block.map_or(
                 BlockNumber::default(), |block| block.block_number.unchecked_next()
@@ -680,7 +1193,7 @@ fn stream_new_blocks<
             );
             if header_marker == central_block_marker {
                 // Only if the node have the last block and state (without casms), sync pending data.
-                if reader.begin_ro_txn()?.get_state_marker()? == header_marker{
+                if sync_pending_data && reader.begin_ro_txn()?.get_state_marker()? == header_marker{
                     // Here is the only place we update the pending data.
                     debug!("Start polling for pending data.");
                     sync_pending_data(
@@ -690,6 +1203,7 @@ fn stream_new_blocks<
                         pending_data.clone(),
                         pending_classes.clone(),
                         pending_sleep_duration,
+                        retry_policy,
                     ).await?;
                 }
                 else{
@@ -716,43 +1230,211 @@ fn stream_new_state_diffs<TCentralSource: CentralSourceTrait + Sync + Send>(
     central_source: Arc<TCentralSource>,
     block_propagation_sleep_duration: Duration,
     max_stream_size: u32,
+    max_parallel_downloads: usize,
+    min_confirmations: u64,
+    retry_policy: RetryPolicy,
 ) -> impl Stream<Item = Result<SyncEvent, StateSyncError>> {
     try_stream! {
         loop {
             let txn = reader.begin_ro_txn()?;
             let state_marker = txn.get_state_marker()?;
-            let last_block_number = txn.get_header_marker()?;
+            // The state diffs of the newest blocks are left for later, until enough blocks follow
+            // them.
+            let target_block_number =
+                BlockNumber(txn.get_header_marker()?.0.saturating_sub(min_confirmations));
             drop(txn);
-            if state_marker == last_block_number {
+            if state_marker >= target_block_number {
                 debug!("State updates syncing reached the last downloaded block, waiting for more blocks.");
                 tokio::time::sleep(block_propagation_sleep_duration).await;
                 continue;
             }
-            let up_to = min(last_block_number, BlockNumber(state_marker.0 + max_stream_size as u64));
+            let up_to =
+                min(target_block_number, BlockNumber(state_marker.0 + max_stream_size as u64));
             debug!("Downloading state diffs [{} - {}).", state_marker, up_to);
+            if max_parallel_downloads > 1 {
+                // Each block is downloaded separately so that a window of blocks can be fetched
+                // concurrently. The results are still released in order of their block number.
+                let memory_budget_bytes = central_source.memory_budget_bytes();
+                let mut downloads = FuturesOrdered::new();
+                let mut next_block_number = state_marker;
+                // The estimated size of the largest state update seen so far. Used as the
+                // estimated size of each state update that is being downloaded.
+                let mut max_state_update_size = 0;
+                loop {
+                    // Like in the central source, a single state update is always downloaded so
+                    // that the sync advances, and until the first one arrives there's no
+                    // estimation of their size.
+                    while next_block_number < up_to
+                        && downloads.len() < max_parallel_downloads
+                        && (downloads.is_empty()
+                            || (max_state_update_size != 0
+                                && (downloads.len() + 1) * max_state_update_size
+                                    <= memory_budget_bytes))
+                    {
+                        downloads.push_back(download_state_diff(
+                            central_source.clone(),
+                            next_block_number,
+                            retry_policy,
+                        ));
+                        next_block_number = next_block_number.unchecked_next();
+                    }
+                    let Some(maybe_state_update) = downloads.next().await else {
+                        break;
+                    };
+                    let Some(state_update) = maybe_state_update? else {
+                        break;
+                    };
+                    max_state_update_size = max(
+                        max_state_update_size,
+                        estimate_central_state_update_size(&state_update),
+                    );
+                    let (
+                        block_number,
+                        block_hash,
+                        mut state_diff,
+                        deployed_contract_class_definitions,
+                    ) = state_update;
+                    if !wait_for_header(&reader, block_number, block_propagation_sleep_duration)
+                        .await?
+                    {
+                        debug!(
+                            "The header of block {block_number} isn't stored, downloading the \
+                             state diffs again from the state marker."
+                        );
+                        break;
+                    }
+                    sort_state_diff(&mut state_diff);
+                    yield SyncEvent::StateDiffAvailable {
+                        block_number,
+                        block_hash,
+                        state_diff,
+                        deployed_contract_class_definitions,
+                        source_peer: None,
+                    };
+                }
+                continue;
+            }
             let state_diff_stream =
                 central_source.stream_state_updates(state_marker, up_to).fuse();
             pin_mut!(state_diff_stream);
 
-            while let Some(maybe_state_diff) = state_diff_stream.next().await {
-                let (
+            let mut next_block_number = state_marker;
+            loop {
+                #[cfg(feature = "metrics")]
+                let download_start = std::time::Instant::now();
+                let Some(maybe_state_diff) = state_diff_stream.next().await else {
+                    break;
+                };
+                let (maybe_state_update, is_retried) = match maybe_state_diff {
+                    // The failed block is downloaded again as the retry policy defines, instead of
+                    // restarting the sync.
+                    Err(err) if (retry_policy.is_retryable)(&err) => {
+                        warn!(
+                            "Failed downloading the state diff of block {next_block_number}, \
+                             retrying. Error: {err:?}."
+                        );
+                        let maybe_state_update = download_state_diff(
+                            central_source.clone(),
+                            next_block_number,
+                            retry_policy,
+                        )
+                        .await?;
+                        (maybe_state_update, true)
+                    }
+                    maybe_state_diff => {
+                        let state_update = maybe_state_diff?;
+                        #[cfg(feature = "metrics")]
+                        sync_metrics::record_state_diff_download_latency(download_start.elapsed());
+                        (Some(state_update), false)
+                    }
+                };
+                let Some((
                     block_number,
                     block_hash,
                     mut state_diff,
                     deployed_contract_class_definitions,
-                ) = maybe_state_diff?;
+                )) = maybe_state_update
+                else {
+                    break;
+                };
+                if !wait_for_header(&reader, block_number, block_propagation_sleep_duration)
+                    .await?
+                {
+                    debug!(
+                        "The header of block {block_number} isn't stored, downloading the state \
+                         diffs again from the state marker."
+                    );
+                    break;
+                }
+                next_block_number = block_number.unchecked_next();
                 sort_state_diff(&mut state_diff);
                 yield SyncEvent::StateDiffAvailable {
                     block_number,
                     block_hash,
                     state_diff,
                     deployed_contract_class_definitions,
+                    source_peer: None,
                 };
+                // The failed block was downloaded on its own. The rest of the range is downloaded
+                // in a new stream, from the state marker the block advanced.
+                if is_retried {
+                    break;
+                }
             }
         }
     }
 }
 
+// Returns whether the header of the block is stored. The header is checked up to
+// MAX_HEADER_CHECKS times, since the headers are synced by a separate stream and might arrive a
+// bit later, e.g. right after a reorg.
+async fn wait_for_header(
+    reader: &StorageReader,
+    block_number: BlockNumber,
+    sleep_duration: Duration,
+) -> Result<bool, StateSyncError> {
+    let mut n_checks = 1;
+    loop {
+        if reader.begin_ro_txn()?.get_block_header(block_number)?.is_some() {
+            return Ok(true);
+        }
+        if n_checks == MAX_HEADER_CHECKS {
+            return Ok(false);
+        }
+        n_checks += 1;
+        tokio::time::sleep(sleep_duration).await;
+    }
+}
+
+// Downloads the state update of a single block, retrying as the given policy defines. Returns None
+// if the central source didn't return a state update for the block.
+async fn download_state_diff<TCentralSource: CentralSourceTrait + Sync + Send>(
+    central_source: Arc<TCentralSource>,
+    block_number: BlockNumber,
+    retry_policy: RetryPolicy,
+) -> Result<Option<CentralStateUpdate>, StateSyncError> {
+    let central_source = &central_source;
+    #[cfg(feature = "metrics")]
+    let download_start = std::time::Instant::now();
+    let maybe_state_update = retry_with_backoff(
+        || async move {
+            central_source
+                .stream_state_updates(block_number, block_number.unchecked_next())
+                .next()
+                .await
+                .transpose()
+        },
+        &retry_policy,
+    )
+    .await?;
+    #[cfg(feature = "metrics")]
+    sync_metrics::record_state_diff_download_latency(download_start.elapsed());
+    Ok(maybe_state_update)
+}
+
+/// Sorts the entries of the state diff by their keys. The state diff can't hold duplicate keys:
+/// repeated entries in a central state update are collapsed when it's converted, and the last one
+/// wins.
 pub fn sort_state_diff(diff: &mut StateDiff) {
     diff.declared_classes.sort_unstable_keys();
     diff.deprecated_declared_classes.sort_unstable_keys();
@@ -791,6 +1473,10 @@ impl StateSync {
             reader,
             writer,
             sequencer_pub_key: None,
+            event_bus: SyncEventBus::default(),
+            synced_handle: SyncedHandle::default(),
+            last_error_recorder: LastErrorRecorder::default(),
+            circuit_breaker: CircuitBreaker::default(),
         }
     }
 }
@@ -876,6 +1562,18 @@ fn stream_new_base_layer_block<TBaseLayerSource: BaseLayerSourceTrait + Sync>(
     }
 }
 
+// Sleeps for the given duration, returning early if `shutdown` completes. Returns whether
+// `shutdown` completed.
+async fn sleep_unless_shutdown(
+    duration: Duration,
+    shutdown: &mut (impl FusedFuture<Output = ()> + Unpin),
+) -> bool {
+    select! {
+        _ = tokio::time::sleep(duration).fuse() => false,
+        _ = shutdown => true,
+    }
+}
+
 // This function is used to check if the sync is stuck.
 // TODO: fix the bug and remove this function.
 // TODO(dvir): add a test for this scenario.
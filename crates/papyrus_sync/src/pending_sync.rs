@@ -14,6 +14,7 @@ use starknet_types_core::felt::Felt;
 use tokio::sync::RwLock;
 use tracing::{debug, trace};
 
+use crate::retry::{retry_with_backoff, RetryPolicy};
 use crate::sources::central::CentralSourceTrait;
 use crate::sources::pending::PendingSourceTrait;
 use crate::StateSyncError;
@@ -29,6 +30,7 @@ pub(crate) async fn sync_pending_data<
     pending_data: Arc<RwLock<PendingData>>,
     pending_classes: Arc<RwLock<PendingClasses>>,
     sleep_duration: Duration,
+    retry_policy: RetryPolicy,
 ) -> Result<(), StateSyncError> {
     let txn = reader.begin_ro_txn()?;
     let header_marker = txn.get_header_marker()?;
@@ -78,6 +80,7 @@ pub(crate) async fn sync_pending_data<
                                 class_hash,
                                 central_source.clone(),
                                 pending_classes.clone(),
+                                retry_policy,
                             )
                             .boxed(),
                         );
@@ -88,6 +91,7 @@ pub(crate) async fn sync_pending_data<
                                 class_hash,
                                 central_source.clone(),
                                 pending_classes.clone(),
+                                retry_policy,
                             )
                             .boxed(),
                         );
@@ -100,6 +104,7 @@ pub(crate) async fn sync_pending_data<
                                 class_hash,
                                 central_source.clone(),
                                 pending_classes.clone(),
+                                retry_policy,
                             )
                             .boxed(),
                         );
@@ -188,8 +193,9 @@ async fn get_pending_class<TCentralSource: CentralSourceTrait + Sync + Send + 's
     class_hash: ClassHash,
     central_source: Arc<TCentralSource>,
     pending_classes: Arc<RwLock<PendingClasses>>,
+    retry_policy: RetryPolicy,
 ) -> Result<PendingSyncTaskResult, StateSyncError> {
-    let class = central_source.get_class(class_hash).await?;
+    let class = retry_with_backoff(|| central_source.get_class(class_hash), &retry_policy).await?;
     pending_classes.write().await.add_class(class_hash, class);
     Ok(PendingSyncTaskResult::DownloadedClassOrCompiledClass)
 }
@@ -198,8 +204,10 @@ async fn get_pending_compiled_class<TCentralSource: CentralSourceTrait + Sync +
     class_hash: ClassHash,
     central_source: Arc<TCentralSource>,
     pending_classes: Arc<RwLock<PendingClasses>>,
+    retry_policy: RetryPolicy,
 ) -> Result<PendingSyncTaskResult, StateSyncError> {
-    let compiled_class = central_source.get_compiled_class(class_hash).await?;
+    let compiled_class =
+        retry_with_backoff(|| central_source.get_compiled_class(class_hash), &retry_policy).await?;
     pending_classes.write().await.add_compiled_class(class_hash, compiled_class);
     Ok(PendingSyncTaskResult::DownloadedClassOrCompiledClass)
 }
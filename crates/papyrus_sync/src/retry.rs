@@ -0,0 +1,101 @@
+#[cfg(test)]
+#[path = "retry_test.rs"]
+mod retry_test;
+
+use std::cmp::min;
+use std::future::Future;
+use std::time::Duration;
+
+use rand::Rng;
+use tracing::warn;
+
+use crate::sources::central::CentralError;
+
+/// When and how often a failing call to the central source is retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// The maximal number of times the call is made, including the first one.
+    pub max_attempts: usize,
+    /// The waiting time before the first retry. It doubles on each retry, up to `max_delay`.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Each waiting time is randomly changed by up to this ratio of it.
+    pub jitter_ratio: f64,
+    /// Whether retrying might help after the given error. Other errors are returned immediately.
+    pub is_retryable: fn(&CentralError) -> bool,
+}
+
+/// Whether the central source might succeed if called again after failing with the given error.
+pub fn is_retryable_central_error(error: &CentralError) -> bool {
+    // We don't use here catch-all pattern to enforce conscious decision for each error kind.
+    match error {
+        // The data might not have propagated to the central source yet.
+        CentralError::ClientError(_)
+        | CentralError::StateUpdateNotFound
+        | CentralError::ClassNotFound
+        | CentralError::CompiledClassNotFound { .. }
+        | CentralError::BlockNotFound { .. } => true,
+        CentralError::ClientCreation(_)
+        | CentralError::StarknetApiError(_)
+        | CentralError::StorageError(_)
+        | CentralError::BadContractClassType
+        | CentralError::BlockAndSignatureVersionMismatch => false,
+    }
+}
+
+/// Calls `operation` until it succeeds, fails with an error that isn't retryable or runs out of
+/// attempts, waiting between the attempts as the policy defines. Returns the result of the last
+/// attempt.
+pub(crate) async fn retry_with_backoff<T, TFuture>(
+    mut operation: impl FnMut() -> TFuture,
+    policy: &RetryPolicy,
+) -> Result<T, CentralError>
+where
+    TFuture: Future<Output = Result<T, CentralError>>,
+{
+    let mut backoff = Backoff::new(policy.base_delay, policy.max_delay, policy.jitter_ratio);
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Err(err) if attempt < policy.max_attempts && (policy.is_retryable)(&err) => {
+                let delay = backoff.next_delay();
+                warn!(
+                    "Central source call failed on attempt {attempt}/{}, retrying in {delay:?}. \
+                     Error: {err:?}.",
+                    policy.max_attempts
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Exponentially growing waiting times between attempts, with random jitter.
+pub(crate) struct Backoff {
+    base: Duration,
+    max: Duration,
+    jitter_ratio: f64,
+    current: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new(base: Duration, max: Duration, jitter_ratio: f64) -> Self {
+        Backoff { base, max, jitter_ratio, current: min(base, max) }
+    }
+
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = min(self.current.saturating_mul(2), self.max);
+        if self.jitter_ratio <= 0.0 {
+            return delay;
+        }
+        let jitter = rand::thread_rng().gen_range(-self.jitter_ratio..=self.jitter_ratio);
+        delay.mul_f64((1.0 + jitter).max(0.0))
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.current = min(self.base, self.max);
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use assert_matches::assert_matches;
+use starknet_api::block::BlockNumber;
+
+use super::{is_retryable_central_error, retry_with_backoff, RetryPolicy};
+use crate::sources::central::CentralError;
+
+const MAX_ATTEMPTS: usize = 3;
+
+fn test_policy() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: MAX_ATTEMPTS,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(4),
+        jitter_ratio: 0.5,
+        is_retryable: is_retryable_central_error,
+    }
+}
+
+#[tokio::test]
+async fn succeeds_after_retries() {
+    let mut n_attempts = 0;
+    let result = retry_with_backoff(
+        || {
+            n_attempts += 1;
+            let result = if n_attempts < MAX_ATTEMPTS {
+                Err(CentralError::BlockNotFound { block_number: BlockNumber(0) })
+            } else {
+                Ok(n_attempts)
+            };
+            async move { result }
+        },
+        &test_policy(),
+    )
+    .await;
+    assert_eq!(result.unwrap(), MAX_ATTEMPTS);
+}
+
+#[tokio::test]
+async fn returns_last_error_when_attempts_are_exhausted() {
+    let mut n_attempts = 0;
+    let result: Result<(), _> = retry_with_backoff(
+        || {
+            n_attempts += 1;
+            let block_number = BlockNumber(n_attempts.try_into().unwrap());
+            async move { Err(CentralError::BlockNotFound { block_number }) }
+        },
+        &test_policy(),
+    )
+    .await;
+    assert_eq!(n_attempts, MAX_ATTEMPTS);
+    // The error of the last attempt is returned.
+    let last_block_number = BlockNumber(MAX_ATTEMPTS.try_into().unwrap());
+    assert_matches!(
+        result,
+        Err(CentralError::BlockNotFound { block_number }) if block_number == last_block_number
+    );
+}
+
+#[tokio::test]
+async fn non_retryable_error_is_returned_immediately() {
+    let mut n_attempts = 0;
+    let result: Result<(), _> = retry_with_backoff(
+        || {
+            n_attempts += 1;
+            async { Err(CentralError::BadContractClassType) }
+        },
+        &test_policy(),
+    )
+    .await;
+    assert_eq!(n_attempts, 1);
+    assert_matches!(result, Err(CentralError::BadContractClassType));
+}
@@ -1,7 +1,10 @@
 #[cfg(test)]
 #[path = "central_test.rs"]
 mod central_test;
+mod fallback;
 mod state_update_stream;
+#[cfg(any(feature = "testing", test))]
+pub mod test_utils;
 
 use std::collections::{BTreeMap, HashMap};
 use std::num::NonZeroUsize;
@@ -15,13 +18,15 @@ use futures_util::StreamExt;
 use indexmap::IndexMap;
 use itertools::chain;
 use lru::LruCache;
+use metrics::counter;
 #[cfg(test)]
 use mockall::automock;
 use papyrus_common::pending_classes::ApiContractClass;
-use papyrus_common::BlockHashAndNumber;
+use papyrus_common::{metrics as papyrus_metrics, BlockHashAndNumber};
 use papyrus_config::converters::{deserialize_optional_map, serialize_optional_map};
 use papyrus_config::dumping::{append_sub_config_name, ser_param, SerializeConfig};
 use papyrus_config::{ParamPath, ParamPrivacyInput, SerializedParam};
+use papyrus_storage::compiled_class::CasmStorageReader;
 use papyrus_storage::state::StateStorageReader;
 use papyrus_storage::{StorageError, StorageReader};
 use serde::{Deserialize, Serialize};
@@ -40,6 +45,8 @@ use starknet_client::reader::{
 use starknet_client::{ClientCreationError, RetryConfig};
 use tracing::{debug, trace};
 
+pub use self::fallback::FallbackCentralSource;
+pub(crate) use self::state_update_stream::estimate_central_state_update_size;
 use self::state_update_stream::{StateUpdateStream, StateUpdateStreamConfig};
 
 type CentralResult<T> = Result<T, CentralError>;
@@ -52,6 +59,7 @@ pub struct CentralSourceConfig {
     pub max_state_updates_to_download: usize,
     pub max_state_updates_to_store_in_memory: usize,
     pub max_classes_to_download: usize,
+    pub sync_memory_budget_bytes: usize,
     // TODO(dan): validate that class_cache_size is a positive integer.
     pub class_cache_size: usize,
     pub retry_config: RetryConfig,
@@ -66,6 +74,7 @@ impl Default for CentralSourceConfig {
             max_state_updates_to_download: 20,
             max_state_updates_to_store_in_memory: 20,
             max_classes_to_download: 20,
+            sync_memory_budget_bytes: 1 << 30,
             class_cache_size: 100,
             retry_config: RetryConfig {
                 retry_base_millis: 30,
@@ -116,6 +125,13 @@ impl SerializeConfig for CentralSourceConfig {
                 "Maximum number of classes to download at a given time.",
                 ParamPrivacyInput::Public,
             ),
+            ser_param(
+                "sync_memory_budget_bytes",
+                &self.sync_memory_budget_bytes,
+                "Maximum estimated size in bytes of the state updates and classes that are \
+                 downloaded or stored in memory at a given time.",
+                ParamPrivacyInput::Public,
+            ),
             ser_param(
                 "class_cache_size",
                 &self.class_cache_size,
@@ -199,11 +215,15 @@ pub trait CentralSourceTrait {
     ) -> Result<CasmContractClass, CentralError>;
 
     async fn get_sequencer_pub_key(&self) -> Result<SequencerPublicKey, CentralError>;
+
+    // The maximal estimated size of the state updates, including their classes, that are
+    // downloaded or kept in memory at a given time.
+    fn memory_budget_bytes(&self) -> usize;
 }
 
 pub(crate) type BlocksStream<'a> =
     BoxStream<'a, Result<(BlockNumber, Block, BlockSignature), CentralError>>;
-type CentralStateUpdate =
+pub(crate) type CentralStateUpdate =
     (BlockNumber, BlockHash, StateDiff, IndexMap<ClassHash, DeprecatedContractClass>);
 pub(crate) type StateUpdatesStream<'a> = BoxStream<'a, CentralResult<CentralStateUpdate>>;
 type CentralCompiledClass = (ClassHash, CompiledClassHash, CasmContractClass);
@@ -310,11 +330,28 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> CentralSourceTrait
                 })
                 .flat_map(|maybe_state_diff| match maybe_state_diff {
                     Ok(state_diff) => {
-                        state_diff
+                        // Compiled classes that were already stored (e.g. by a previous pass that
+                        // was interrupted in the middle of a block) aren't downloaded again.
+                        let mut skipped = 0;
+                        let class_hashes = state_diff
                             .declared_classes
                             .into_iter()
-                            .map(Ok)
-                            .collect()
+                            .filter_map(|(class_hash, compiled_class_hash)| {
+                                match txn.casm_exists(&class_hash) {
+                                    Ok(true) => {
+                                        skipped += 1;
+                                        None
+                                    }
+                                    Ok(false) => Some(Ok((class_hash, compiled_class_hash))),
+                                    Err(err) => Some(Err(CentralError::StorageError(err))),
+                                }
+                            })
+                            .collect::<Vec<_>>();
+                        if skipped > 0 {
+                            debug!("Skipped {skipped} already stored compiled classes.");
+                            counter!(papyrus_metrics::PAPYRUS_NUM_SKIPPED_COMPILED_CLASSES, skipped);
+                        }
+                        class_hashes
                     }
                     Err(err) => vec![Err(err)],
                 });
@@ -396,6 +433,10 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> CentralSourceTrait
     async fn get_sequencer_pub_key(&self) -> Result<SequencerPublicKey, CentralError> {
         Ok(self.starknet_client.sequencer_pub_key().await.map_err(Arc::new)?)
     }
+
+    fn memory_budget_bytes(&self) -> usize {
+        self.state_update_stream_config.memory_budget_bytes
+    }
 }
 
 fn client_to_central_block(
@@ -463,6 +504,7 @@ impl CentralSource {
                 max_state_updates_to_download: config.max_state_updates_to_download,
                 max_state_updates_to_store_in_memory: config.max_state_updates_to_store_in_memory,
                 max_classes_to_download: config.max_classes_to_download,
+                memory_budget_bytes: config.sync_memory_budget_bytes,
             },
             class_cache: Arc::from(Mutex::new(LruCache::new(
                 NonZeroUsize::new(config.class_cache_size)
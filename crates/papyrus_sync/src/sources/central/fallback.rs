@@ -0,0 +1,252 @@
+#[cfg(test)]
+#[path = "fallback_test.rs"]
+mod fallback_test;
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_stream::stream;
+use async_trait::async_trait;
+use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
+use futures::stream::BoxStream;
+use futures_util::StreamExt;
+use papyrus_common::pending_classes::ApiContractClass;
+use papyrus_common::BlockHashAndNumber;
+use starknet_api::block::{BlockHash, BlockNumber};
+use starknet_api::core::{ClassHash, SequencerPublicKey};
+use tracing::{trace, warn};
+
+use super::{
+    BlocksStream,
+    CentralError,
+    CentralResult,
+    CentralSourceTrait,
+    CompiledClassesStream,
+    StateUpdatesStream,
+};
+
+/// A central source that serves each request from the first of its sources that doesn't fail.
+///
+/// The sources are tried in order, starting from the last source that served a request
+/// successfully, so that a failing source isn't tried again on every request while another source
+/// works. A stream that fails in the middle is resumed by the next source from the first item that
+/// wasn't yielded yet.
+pub struct FallbackCentralSource<TCentralSource: CentralSourceTrait + Send + Sync> {
+    sources: Vec<TCentralSource>,
+    preferred_source: AtomicUsize,
+}
+
+impl<TCentralSource: CentralSourceTrait + Send + Sync> FallbackCentralSource<TCentralSource> {
+    /// Creates a fallback source over the given sources, ordered from the most to the least
+    /// preferred. Panics if there are no sources.
+    pub fn new(sources: Vec<TCentralSource>) -> Self {
+        assert!(!sources.is_empty(), "A fallback central source needs at least one source.");
+        Self { sources, preferred_source: AtomicUsize::new(0) }
+    }
+
+    // Returns the indices of the sources in the order they should be tried, starting from the
+    // preferred source.
+    fn source_order(&self) -> impl Iterator<Item = usize> + '_ {
+        let preferred_source = self.preferred_source.load(Ordering::Relaxed);
+        (0..self.sources.len()).map(move |i| (preferred_source + i) % self.sources.len())
+    }
+
+    fn next_source(&self, source_index: usize) -> usize {
+        (source_index + 1) % self.sources.len()
+    }
+
+    async fn call_with_fallback<'a, T, TFuture>(
+        &'a self,
+        call: impl Fn(&'a TCentralSource) -> TFuture,
+    ) -> CentralResult<T>
+    where
+        TFuture: Future<Output = CentralResult<T>>,
+    {
+        let mut last_error = None;
+        for source_index in self.source_order() {
+            match call(&self.sources[source_index]).await {
+                Ok(result) => {
+                    self.preferred_source.store(source_index, Ordering::Relaxed);
+                    return Ok(result);
+                }
+                Err(err) => {
+                    warn!("Central source {source_index} failed: {err:?}.");
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.expect("A fallback central source has at least one source."))
+    }
+
+    // Streams the items of the given block range. When a source fails, the next source resumes
+    // the range from the block after the last yielded one. The stream fails only if all the
+    // sources failed on the same block.
+    fn stream_blocks_with_fallback<'a, T: Send + 'a>(
+        &'a self,
+        initial_block_number: BlockNumber,
+        up_to_block_number: BlockNumber,
+        open_stream: impl Fn(
+            &'a TCentralSource,
+            BlockNumber,
+            BlockNumber,
+        ) -> BoxStream<'a, CentralResult<T>>
+        + Send
+        + 'a,
+        item_block_number: impl Fn(&T) -> BlockNumber + Send + 'a,
+    ) -> BoxStream<'a, CentralResult<T>> {
+        stream! {
+            let mut next_block_number = initial_block_number;
+            let mut source_index = self.preferred_source.load(Ordering::Relaxed);
+            let mut n_failed_sources = 0;
+            'sources: loop {
+                let mut items =
+                    open_stream(&self.sources[source_index], next_block_number, up_to_block_number);
+                while let Some(maybe_item) = items.next().await {
+                    match maybe_item {
+                        Ok(item) => {
+                            let block_number = item_block_number(&item);
+                            trace!(
+                                "Block {block_number} was served by central source {source_index}."
+                            );
+                            self.preferred_source.store(source_index, Ordering::Relaxed);
+                            n_failed_sources = 0;
+                            next_block_number = block_number.unchecked_next();
+                            yield Ok(item);
+                        }
+                        Err(err) => {
+                            n_failed_sources += 1;
+                            if n_failed_sources == self.sources.len() {
+                                yield Err(err);
+                                return;
+                            }
+                            warn!(
+                                "Central source {source_index} failed on block \
+                                 {next_block_number}, falling back to the next source: {err:?}."
+                            );
+                            source_index = self.next_source(source_index);
+                            continue 'sources;
+                        }
+                    }
+                }
+                return;
+            }
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl<TCentralSource: CentralSourceTrait + Send + Sync> CentralSourceTrait
+    for FallbackCentralSource<TCentralSource>
+{
+    async fn get_latest_block(&self) -> Result<Option<BlockHashAndNumber>, CentralError> {
+        self.call_with_fallback(|source| source.get_latest_block()).await
+    }
+
+    fn stream_new_blocks(
+        &self,
+        initial_block_number: BlockNumber,
+        up_to_block_number: BlockNumber,
+    ) -> BlocksStream<'_> {
+        self.stream_blocks_with_fallback(
+            initial_block_number,
+            up_to_block_number,
+            |source, initial, up_to| source.stream_new_blocks(initial, up_to),
+            |(block_number, _, _)| *block_number,
+        )
+    }
+
+    fn stream_state_updates(
+        &self,
+        initial_block_number: BlockNumber,
+        up_to_block_number: BlockNumber,
+    ) -> StateUpdatesStream<'_> {
+        self.stream_blocks_with_fallback(
+            initial_block_number,
+            up_to_block_number,
+            |source, initial, up_to| source.stream_state_updates(initial, up_to),
+            |(block_number, _, _, _)| *block_number,
+        )
+    }
+
+    async fn get_block_hash(
+        &self,
+        block_number: BlockNumber,
+    ) -> Result<Option<BlockHash>, CentralError> {
+        self.call_with_fallback(|source| source.get_block_hash(block_number)).await
+    }
+
+    // The compiled classes aren't ordered by block, so when a source fails the next source streams
+    // the whole range again and the classes that were already yielded are skipped.
+    fn stream_compiled_classes(
+        &self,
+        initial_block_number: BlockNumber,
+        up_to_block_number: BlockNumber,
+    ) -> CompiledClassesStream<'_> {
+        stream! {
+            let mut yielded_class_hashes = HashSet::new();
+            let mut source_index = self.preferred_source.load(Ordering::Relaxed);
+            let mut n_failed_sources = 0;
+            'sources: loop {
+                let mut compiled_classes = self.sources[source_index]
+                    .stream_compiled_classes(initial_block_number, up_to_block_number);
+                while let Some(maybe_compiled_class) = compiled_classes.next().await {
+                    match maybe_compiled_class {
+                        Ok(compiled_class) => {
+                            if !yielded_class_hashes.insert(compiled_class.0) {
+                                continue;
+                            }
+                            trace!(
+                                "Compiled class {} was served by central source {source_index}.",
+                                compiled_class.0
+                            );
+                            self.preferred_source.store(source_index, Ordering::Relaxed);
+                            n_failed_sources = 0;
+                            yield Ok(compiled_class);
+                        }
+                        Err(err) => {
+                            n_failed_sources += 1;
+                            if n_failed_sources == self.sources.len() {
+                                yield Err(err);
+                                return;
+                            }
+                            warn!(
+                                "Central source {source_index} failed to stream compiled classes, \
+                                 falling back to the next source: {err:?}."
+                            );
+                            source_index = self.next_source(source_index);
+                            continue 'sources;
+                        }
+                    }
+                }
+                return;
+            }
+        }
+        .boxed()
+    }
+
+    async fn get_class(&self, class_hash: ClassHash) -> Result<ApiContractClass, CentralError> {
+        self.call_with_fallback(|source| source.get_class(class_hash)).await
+    }
+
+    async fn get_compiled_class(
+        &self,
+        class_hash: ClassHash,
+    ) -> Result<CasmContractClass, CentralError> {
+        self.call_with_fallback(|source| source.get_compiled_class(class_hash)).await
+    }
+
+    async fn get_sequencer_pub_key(&self) -> Result<SequencerPublicKey, CentralError> {
+        self.call_with_fallback(|source| source.get_sequencer_pub_key()).await
+    }
+
+    // Any of the sources may serve the state updates, so the smallest budget is kept.
+    fn memory_budget_bytes(&self) -> usize {
+        self.sources
+            .iter()
+            .map(|source| source.memory_budget_bytes())
+            .min()
+            .expect("A fallback central source has at least one source.")
+    }
+}
@@ -0,0 +1,120 @@
+use assert_matches::assert_matches;
+use async_stream::stream;
+use futures_util::StreamExt;
+use indexmap::IndexMap;
+use mockall::predicate::eq;
+use papyrus_common::BlockHashAndNumber;
+use starknet_api::block::{BlockHash, BlockNumber};
+use starknet_api::state::StateDiff;
+
+use super::FallbackCentralSource;
+use crate::sources::central::{
+    CentralError,
+    CentralSourceTrait,
+    MockCentralSourceTrait,
+    StateUpdatesStream,
+};
+
+// Returns a stream of the state updates in the given range that fails on the given block.
+fn state_updates_stream_failing_on(
+    initial_block_number: BlockNumber,
+    up_to_block_number: BlockNumber,
+    failing_block_number: Option<BlockNumber>,
+) -> StateUpdatesStream<'static> {
+    stream! {
+        for block_number in initial_block_number.iter_up_to(up_to_block_number) {
+            if Some(block_number) == failing_block_number {
+                yield Err(CentralError::BlockNotFound { block_number });
+                return;
+            }
+            yield Ok((block_number, BlockHash::default(), StateDiff::default(), IndexMap::new()));
+        }
+    }
+    .boxed()
+}
+
+#[tokio::test]
+async fn stream_state_updates_resumes_on_next_source_without_gaps() {
+    const UP_TO_BLOCK_NUMBER: BlockNumber = BlockNumber(7);
+
+    // The first source fails on block 2, the second source takes over and fails on block 4, and
+    // the first source serves the rest of the range.
+    let mut first_source = MockCentralSourceTrait::new();
+    first_source
+        .expect_stream_state_updates()
+        .with(eq(BlockNumber(0)), eq(UP_TO_BLOCK_NUMBER))
+        .times(1)
+        .returning(|initial, up_to| {
+            state_updates_stream_failing_on(initial, up_to, Some(BlockNumber(2)))
+        });
+    first_source
+        .expect_stream_state_updates()
+        .with(eq(BlockNumber(4)), eq(UP_TO_BLOCK_NUMBER))
+        .times(1)
+        .returning(|initial, up_to| state_updates_stream_failing_on(initial, up_to, None));
+    let mut second_source = MockCentralSourceTrait::new();
+    second_source
+        .expect_stream_state_updates()
+        .with(eq(BlockNumber(2)), eq(UP_TO_BLOCK_NUMBER))
+        .times(1)
+        .returning(|initial, up_to| {
+            state_updates_stream_failing_on(initial, up_to, Some(BlockNumber(4)))
+        });
+
+    let fallback_source = FallbackCentralSource::new(vec![first_source, second_source]);
+    let block_numbers = fallback_source
+        .stream_state_updates(BlockNumber(0), UP_TO_BLOCK_NUMBER)
+        .map(|state_update| state_update.unwrap().0)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(block_numbers, BlockNumber(0).iter_up_to(UP_TO_BLOCK_NUMBER).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn stream_state_updates_fails_when_all_sources_fail_on_the_same_block() {
+    const FAILING_BLOCK_NUMBER: BlockNumber = BlockNumber(2);
+
+    let mut first_source = MockCentralSourceTrait::new();
+    first_source.expect_stream_state_updates().times(1).returning(|initial, up_to| {
+        state_updates_stream_failing_on(initial, up_to, Some(FAILING_BLOCK_NUMBER))
+    });
+    let mut second_source = MockCentralSourceTrait::new();
+    second_source
+        .expect_stream_state_updates()
+        .with(eq(FAILING_BLOCK_NUMBER), eq(BlockNumber(5)))
+        .times(1)
+        .returning(|initial, up_to| {
+            state_updates_stream_failing_on(initial, up_to, Some(FAILING_BLOCK_NUMBER))
+        });
+
+    let fallback_source = FallbackCentralSource::new(vec![first_source, second_source]);
+    let state_updates = fallback_source
+        .stream_state_updates(BlockNumber(0), BlockNumber(5))
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(state_updates.len(), 3);
+    assert_matches!(state_updates[1], Ok((BlockNumber(1), ..)));
+    assert_matches!(
+        state_updates[2],
+        Err(CentralError::BlockNotFound { block_number: FAILING_BLOCK_NUMBER })
+    );
+}
+
+#[tokio::test]
+async fn calls_stick_to_the_last_working_source() {
+    let latest_block =
+        BlockHashAndNumber { block_hash: BlockHash::default(), block_number: BlockNumber(1) };
+
+    // The first source fails once and must not be called again while the second source works.
+    let mut first_source = MockCentralSourceTrait::new();
+    first_source
+        .expect_get_latest_block()
+        .times(1)
+        .returning(|| Err(CentralError::BlockNotFound { block_number: BlockNumber(1) }));
+    let mut second_source = MockCentralSourceTrait::new();
+    second_source.expect_get_latest_block().times(2).returning(move || Ok(Some(latest_block)));
+
+    let fallback_source = FallbackCentralSource::new(vec![first_source, second_source]);
+    assert_eq!(fallback_source.get_latest_block().await.unwrap(), Some(latest_block));
+    assert_eq!(fallback_source.get_latest_block().await.unwrap(), Some(latest_block));
+}
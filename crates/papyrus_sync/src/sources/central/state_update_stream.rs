@@ -1,4 +1,9 @@
+#[cfg(test)]
+#[path = "state_update_stream_test.rs"]
+mod state_update_stream_test;
+
 use std::collections::VecDeque;
+use std::mem::size_of;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::Poll;
@@ -10,23 +15,43 @@ use lru::LruCache;
 use papyrus_storage::state::StateStorageReader;
 use papyrus_storage::StorageReader;
 use starknet_api::block::BlockNumber;
-use starknet_api::core::ClassHash;
-use starknet_api::state::{StateDiff, StateNumber};
-use starknet_client::reader::{ReaderClientResult, StarknetReader, StateUpdate};
+use starknet_api::core::{ClassHash, CompiledClassHash, ContractAddress, Nonce};
+use starknet_api::deprecated_contract_class::{
+    ContractClass as DeprecatedContractClass,
+    ContractClassAbiEntry,
+    EntryPoint as DeprecatedEntryPoint,
+};
+use starknet_api::state::{ContractClass, EntryPoint, StateDiff, StateNumber, StorageKey};
+use starknet_client::reader::{
+    DeclaredClassHashEntry,
+    DeployedContract,
+    ReaderClientResult,
+    ReplacedClass,
+    StarknetReader,
+    StateUpdate,
+    StorageEntry,
+};
 use tracing::log::trace;
 use tracing::{debug, instrument};
 
+use starknet_types_core::felt::Felt;
+
 use super::{ApiContractClass, CentralResult, CentralStateUpdate};
 use crate::CentralError;
 
 type TasksQueue<T> = FuturesOrdered<Pin<Box<dyn Future<Output = T> + Send>>>;
 type NumberOfClasses = usize;
+type SizeInBytes = usize;
 
 #[derive(Clone)]
 pub struct StateUpdateStreamConfig {
     pub max_state_updates_to_download: usize,
     pub max_state_updates_to_store_in_memory: usize,
     pub max_classes_to_download: usize,
+    // The estimated size of the state updates and classes that are being downloaded or are kept in
+    // memory won't exceed this budget, except for a single state update and its classes that are
+    // larger than it.
+    pub memory_budget_bytes: usize,
 }
 
 pub(crate) struct StateUpdateStream<TStarknetClient: StarknetReader + Send + 'static> {
@@ -35,11 +60,22 @@ pub(crate) struct StateUpdateStream<TStarknetClient: StarknetReader + Send + 'st
     starknet_client: Arc<TStarknetClient>,
     storage_reader: StorageReader,
     download_state_update_tasks: TasksQueue<(BlockNumber, ReaderClientResult<Option<StateUpdate>>)>,
-    // Contains NumberOfClasses so we don't need to calculate it from the StateUpdate.
-    downloaded_state_updates: VecDeque<(BlockNumber, NumberOfClasses, StateUpdate)>,
+    // Contains NumberOfClasses and SizeInBytes so we don't need to calculate them from the
+    // StateUpdate.
+    downloaded_state_updates: VecDeque<(BlockNumber, NumberOfClasses, SizeInBytes, StateUpdate)>,
+    // The estimated size of the state updates in `downloaded_state_updates`.
+    downloaded_state_updates_size: SizeInBytes,
+    // The estimated size of the largest state update seen so far. Used as the estimated size of
+    // each state update that is being downloaded.
+    max_state_update_size: SizeInBytes,
     classes_to_download: VecDeque<ClassHash>,
     download_class_tasks: TasksQueue<CentralResult<Option<ApiContractClass>>>,
-    downloaded_classes: VecDeque<ApiContractClass>,
+    downloaded_classes: VecDeque<(SizeInBytes, ApiContractClass)>,
+    // The estimated size of the classes in `downloaded_classes`.
+    downloaded_classes_size: SizeInBytes,
+    // The estimated size of the largest class seen so far. Used as the estimated size of each class
+    // that is being downloaded.
+    max_class_size: SizeInBytes,
     class_cache: Arc<Mutex<LruCache<ClassHash, ApiContractClass>>>,
     config: StateUpdateStreamConfig,
 }
@@ -102,6 +138,8 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> StateUpdateStream<
             downloaded_state_updates: VecDeque::with_capacity(
                 config.max_state_updates_to_store_in_memory,
             ),
+            downloaded_state_updates_size: 0,
+            max_state_update_size: 0,
             classes_to_download: VecDeque::with_capacity(
                 config.max_state_updates_to_store_in_memory * 5,
             ),
@@ -109,6 +147,8 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> StateUpdateStream<
             downloaded_classes: VecDeque::with_capacity(
                 config.max_state_updates_to_store_in_memory * 5,
             ),
+            downloaded_classes_size: 0,
+            max_class_size: 0,
             config,
             class_cache,
         }
@@ -116,16 +156,18 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> StateUpdateStream<
 
     // Returns data needed for the next block CentralStateUpdate, or None if it is not yet ready.
     fn next_output(&mut self) -> Option<CentralResult<CentralStateUpdate>> {
-        let (_, n_classes, _) = self.downloaded_state_updates.front()?;
+        let (_, n_classes, _, _) = self.downloaded_state_updates.front()?;
         if self.downloaded_classes.len() < *n_classes {
             return None;
         }
-        let (block_number, n_classes, state_update) =
+        let (block_number, n_classes, size, state_update) =
             self.downloaded_state_updates.pop_front().expect("Should have a value");
+        self.downloaded_state_updates_size -= size;
         let class_hashes = state_update.state_diff.class_hashes();
-        let classes = self.downloaded_classes.drain(..n_classes);
+        let classes = self.downloaded_classes.drain(..n_classes).collect::<Vec<_>>();
+        self.downloaded_classes_size -= classes.iter().map(|(size, _)| size).sum::<SizeInBytes>();
         let classes: IndexMap<ClassHash, ApiContractClass> =
-            class_hashes.into_iter().zip(classes).collect();
+            class_hashes.into_iter().zip(classes.into_iter().map(|(_, class)| class)).collect();
         Some(client_to_central_state_update(block_number, Ok((state_update, classes))))
     }
 
@@ -149,7 +191,9 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> StateUpdateStream<
 
     // Adds more class downloading tasks.
     fn schedule_class_downloads(self: &mut std::pin::Pin<&mut Self>, should_poll_again: &mut bool) {
-        while self.download_class_tasks.len() < self.config.max_classes_to_download {
+        while self.download_class_tasks.len() < self.config.max_classes_to_download
+            && self.has_memory_for_another_class()
+        {
             let Some(class_hash) = self.classes_to_download.pop_front() else {
                 break;
             };
@@ -180,7 +224,10 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> StateUpdateStream<
         match maybe_class {
             // Add to downloaded classes.
             Ok(Some(class)) => {
-                self.downloaded_classes.push_back(class);
+                let size = estimate_class_size(&class);
+                self.downloaded_classes_size += size;
+                self.max_class_size = self.max_class_size.max(size);
+                self.downloaded_classes.push_back((size, class));
                 Ok(())
             }
             // Class was not found.
@@ -197,6 +244,7 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> StateUpdateStream<
     ) {
         while self.initial_block_number < self.up_to_block_number
             && self.download_state_update_tasks.len() < self.config.max_state_updates_to_download
+            && self.has_memory_for_another_state_update()
         {
             let current_block_number = self.initial_block_number;
             let starknet_client = self.starknet_client.clone();
@@ -208,6 +256,46 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> StateUpdateStream<
         }
     }
 
+    // Returns whether downloading another state update keeps the estimated size of the state
+    // updates and classes in memory within the memory budget. A state update is always downloaded
+    // if there are no other state updates in memory, so that the stream advances.
+    fn has_memory_for_another_state_update(&self) -> bool {
+        if self.download_state_update_tasks.is_empty() && self.downloaded_state_updates.is_empty() {
+            return true;
+        }
+        // Until the first state update arrives, there's no estimation of its size.
+        self.max_state_update_size != 0 && self.has_memory_for(self.max_state_update_size)
+    }
+
+    // Returns whether downloading another class keeps the estimated size of the state updates and
+    // classes in memory within the memory budget. The classes of the next state update to return
+    // are always downloaded, so that the stream advances. Classes are downloaded in the order of
+    // their state updates, so these are the first classes that are downloaded or kept in memory.
+    fn has_memory_for_another_class(&self) -> bool {
+        let n_classes_of_next_state_update =
+            self.downloaded_state_updates.front().map_or(0, |(_, n_classes, _, _)| *n_classes);
+        if self.downloaded_classes.len() + self.download_class_tasks.len()
+            < n_classes_of_next_state_update
+        {
+            return true;
+        }
+        // Until the first class arrives, there's no estimation of its size.
+        self.max_class_size != 0 && self.has_memory_for(self.max_class_size)
+    }
+
+    fn has_memory_for(&self, size: SizeInBytes) -> bool {
+        self.estimated_memory_usage() + size <= self.config.memory_budget_bytes
+    }
+
+    // The estimated size of the state updates and classes that are being downloaded or are kept in
+    // memory.
+    fn estimated_memory_usage(&self) -> SizeInBytes {
+        self.downloaded_state_updates_size
+            + self.download_state_update_tasks.len() * self.max_state_update_size
+            + self.downloaded_classes_size
+            + self.download_class_tasks.len() * self.max_class_size
+    }
+
     // Checks for finished state update downloading tasks.
     // Checks for finished class downloading tasks and adds the result to `downloaded_classes`.
     fn handle_downloaded_state_updates(
@@ -233,7 +321,15 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> StateUpdateStream<
                 let hashes = state_update.state_diff.class_hashes();
                 let n_classes = hashes.len();
                 self.classes_to_download.append(&mut VecDeque::from(hashes));
-                self.downloaded_state_updates.push_back((block_number, n_classes, state_update));
+                let size = estimate_state_update_size(&state_update);
+                self.downloaded_state_updates_size += size;
+                self.max_state_update_size = self.max_state_update_size.max(size);
+                self.downloaded_state_updates.push_back((
+                    block_number,
+                    n_classes,
+                    size,
+                    state_update,
+                ));
                 Ok(())
             }
             // Class was not found.
@@ -244,6 +340,104 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> StateUpdateStream<
     }
 }
 
+// Estimates the amount of memory a state update takes.
+fn estimate_state_update_size(state_update: &StateUpdate) -> SizeInBytes {
+    let state_diff = &state_update.state_diff;
+    let n_storage_entries: usize = state_diff.storage_diffs.values().map(Vec::len).sum();
+    size_of::<StateUpdate>()
+        + state_diff.storage_diffs.len() * size_of::<(ContractAddress, Vec<StorageEntry>)>()
+        + n_storage_entries * size_of::<StorageEntry>()
+        + state_diff.deployed_contracts.len() * size_of::<DeployedContract>()
+        + state_diff.declared_classes.len() * size_of::<DeclaredClassHashEntry>()
+        + state_diff.old_declared_contracts.len() * size_of::<ClassHash>()
+        + state_diff.nonces.len() * size_of::<(ContractAddress, Nonce)>()
+        + state_diff.replaced_classes.len() * size_of::<ReplacedClass>()
+}
+
+// Estimates the amount of memory a state update that was converted to a central state update takes,
+// including the definitions of its classes.
+pub(crate) fn estimate_central_state_update_size(
+    central_state_update: &CentralStateUpdate,
+) -> SizeInBytes {
+    let (_, _, state_diff, deployed_contract_class_definitions) = central_state_update;
+    let n_storage_entries: usize = state_diff.storage_diffs.values().map(IndexMap::len).sum();
+    let declared_classes_size: SizeInBytes = state_diff
+        .declared_classes
+        .values()
+        .map(|(_, class)| {
+            size_of::<(ClassHash, CompiledClassHash)>() + estimate_cairo1_class_size(class)
+        })
+        .sum();
+    let deprecated_classes_size: SizeInBytes = state_diff
+        .deprecated_declared_classes
+        .values()
+        .chain(deployed_contract_class_definitions.values())
+        .map(|class| size_of::<ClassHash>() + estimate_deprecated_class_size(class))
+        .sum();
+    size_of::<CentralStateUpdate>()
+        + state_diff.storage_diffs.len()
+            * size_of::<(ContractAddress, IndexMap<StorageKey, Felt>)>()
+        + n_storage_entries * size_of::<(StorageKey, Felt)>()
+        + state_diff.deployed_contracts.len() * size_of::<(ContractAddress, ClassHash)>()
+        + state_diff.nonces.len() * size_of::<(ContractAddress, Nonce)>()
+        + state_diff.replaced_classes.len() * size_of::<(ContractAddress, ClassHash)>()
+        + declared_classes_size
+        + deprecated_classes_size
+}
+
+// Estimates the amount of memory a class takes.
+fn estimate_class_size(class: &ApiContractClass) -> SizeInBytes {
+    match class {
+        ApiContractClass::DeprecatedContractClass(class) => estimate_deprecated_class_size(class),
+        ApiContractClass::ContractClass(class) => estimate_cairo1_class_size(class),
+    }
+}
+
+fn estimate_cairo1_class_size(class: &ContractClass) -> SizeInBytes {
+    let n_entry_points: usize = class.entry_points_by_type.values().map(Vec::len).sum();
+    size_of::<ContractClass>()
+        + class.sierra_program.len() * size_of::<Felt>()
+        + n_entry_points * size_of::<EntryPoint>()
+        + class.abi.len()
+}
+
+fn estimate_deprecated_class_size(class: &DeprecatedContractClass) -> SizeInBytes {
+    let n_entry_points: usize = class.entry_points_by_type.values().map(Vec::len).sum();
+    let n_abi_entries = class.abi.as_ref().map_or(0, Vec::len);
+    let program = &class.program;
+    let program_size: SizeInBytes = [
+        &program.attributes,
+        &program.builtins,
+        &program.compiler_version,
+        &program.data,
+        &program.debug_info,
+        &program.hints,
+        &program.identifiers,
+        &program.main_scope,
+        &program.prime,
+        &program.reference_manager,
+    ]
+    .into_iter()
+    .map(estimate_json_size)
+    .sum();
+    size_of::<DeprecatedContractClass>()
+        + n_entry_points * size_of::<DeprecatedEntryPoint>()
+        + n_abi_entries * size_of::<ContractClassAbiEntry>()
+        + program_size
+}
+
+fn estimate_json_size(value: &serde_json::Value) -> SizeInBytes {
+    let content_size = match value {
+        serde_json::Value::String(string) => string.len(),
+        serde_json::Value::Array(values) => values.iter().map(estimate_json_size).sum(),
+        serde_json::Value::Object(map) => {
+            map.iter().map(|(key, value)| key.len() + estimate_json_size(value)).sum()
+        }
+        _ => 0,
+    };
+    size_of::<serde_json::Value>() + content_size
+}
+
 fn client_to_central_state_update(
     current_block_number: BlockNumber,
     maybe_client_state_update: CentralResult<(StateUpdate, IndexMap<ClassHash, ApiContractClass>)>,
@@ -272,6 +466,8 @@ fn client_to_central_state_update(
                 deployed_contracts: IndexMap::from_iter(
                     deployed_contracts.iter().map(|dc| (dc.address, dc.class_hash)),
                 ),
+                // Central may list a storage key more than once. Collecting the entries into a map
+                // keeps the last value written to the key.
                 storage_diffs: IndexMap::from_iter(storage_diffs.into_iter().map(
                     |(address, entries)| {
                         (address, entries.into_iter().map(|se| (se.key, se.value)).collect())
@@ -0,0 +1,201 @@
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+use futures_util::StreamExt;
+use indexmap::{indexmap, IndexMap};
+use lru::LruCache;
+use papyrus_storage::test_utils::get_test_storage;
+use starknet_api::block::BlockNumber;
+use starknet_api::core::{ClassHash, CompiledClassHash, ContractAddress, PatriciaKey};
+use starknet_api::state::StorageKey;
+use starknet_api::{felt, patricia_key};
+use starknet_client::reader::{
+    ContractClass,
+    DeclaredClassHashEntry,
+    GenericContractClass,
+    MockStarknetReader,
+    StateDiff,
+    StateUpdate,
+    StorageEntry,
+};
+use starknet_types_core::felt::Felt;
+
+use super::{client_to_central_state_update, StateUpdateStream, StateUpdateStreamConfig};
+use crate::sort_state_diff;
+
+const MAX_STATE_UPDATES_TO_DOWNLOAD: usize = 10;
+
+// Streams large state updates with the given memory budget and returns the maximal number of state
+// updates that were downloaded or kept in memory at once.
+async fn max_state_updates_in_memory(memory_budget_bytes: usize) -> usize {
+    const N_BLOCKS: u64 = 20;
+    // Each state update takes at least N_STORAGE_ENTRIES * size_of::<StorageEntry>() = 64KB.
+    const N_STORAGE_ENTRIES: u64 = 1000;
+
+    let state_update = StateUpdate {
+        state_diff: StateDiff {
+            storage_diffs: indexmap!(
+                ContractAddress(patricia_key!("0x1")) => (0..N_STORAGE_ENTRIES)
+                    .map(|i| StorageEntry { key: StorageKey::from(i), value: Felt::from(i) })
+                    .collect()
+            ),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut mock = MockStarknetReader::new();
+    mock.expect_state_update()
+        .times(N_BLOCKS as usize)
+        .returning(move |_| Ok(Some(state_update.clone())));
+    let ((reader, _), _temp_dir) = get_test_storage();
+    let mut stream = StateUpdateStream::new(
+        BlockNumber(0),
+        BlockNumber(N_BLOCKS),
+        Arc::new(mock),
+        reader,
+        StateUpdateStreamConfig {
+            max_state_updates_to_download: MAX_STATE_UPDATES_TO_DOWNLOAD,
+            max_state_updates_to_store_in_memory: MAX_STATE_UPDATES_TO_DOWNLOAD,
+            max_classes_to_download: 10,
+            memory_budget_bytes,
+        },
+        Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(2).unwrap()))),
+    );
+
+    let mut max_state_updates_in_memory = 0;
+    let mut n_received_state_updates = 0;
+    while let Some(state_update) = stream.next().await {
+        state_update.unwrap();
+        n_received_state_updates += 1;
+        // The returned state update was in memory together with the remaining ones.
+        let n_state_updates_in_memory =
+            stream.download_state_update_tasks.len() + stream.downloaded_state_updates.len() + 1;
+        max_state_updates_in_memory = max_state_updates_in_memory.max(n_state_updates_in_memory);
+    }
+    assert_eq!(n_received_state_updates, N_BLOCKS);
+    max_state_updates_in_memory
+}
+
+#[tokio::test]
+async fn memory_budget_limits_state_updates_in_memory() {
+    // Enough for two state updates but not for three.
+    assert_eq!(max_state_updates_in_memory(160_000).await, 2);
+    // A single state update is downloaded even if it exceeds the budget.
+    assert_eq!(max_state_updates_in_memory(1).await, 1);
+    // Without a limiting budget, the number of concurrent downloads is the limit.
+    assert_eq!(max_state_updates_in_memory(1 << 30).await, MAX_STATE_UPDATES_TO_DOWNLOAD);
+}
+
+// Streams small state updates that declare large classes with the given memory budget and returns
+// the maximal number of classes that were downloaded or kept in memory at once.
+async fn max_classes_in_memory(memory_budget_bytes: usize) -> usize {
+    const N_BLOCKS: u64 = 20;
+    const N_CLASSES_PER_BLOCK: u64 = 2;
+    // Each class takes at least SIERRA_PROGRAM_LENGTH * size_of::<Felt>() = 128KB.
+    const SIERRA_PROGRAM_LENGTH: usize = 4000;
+
+    let mut mock = MockStarknetReader::new();
+    mock.expect_state_update().times(N_BLOCKS as usize).returning(|block_number| {
+        let declared_classes = (0..N_CLASSES_PER_BLOCK)
+            .map(|i| {
+                let class_index = block_number.0 * N_CLASSES_PER_BLOCK + i;
+                DeclaredClassHashEntry {
+                    class_hash: ClassHash(Felt::from(class_index)),
+                    compiled_class_hash: CompiledClassHash(Felt::from(class_index)),
+                }
+            })
+            .collect();
+        Ok(Some(StateUpdate {
+            state_diff: StateDiff { declared_classes, ..Default::default() },
+            ..Default::default()
+        }))
+    });
+    mock.expect_class_by_hash().returning(|_| {
+        Ok(Some(GenericContractClass::Cairo1ContractClass(ContractClass {
+            sierra_program: vec![Felt::ONE; SIERRA_PROGRAM_LENGTH],
+            ..Default::default()
+        })))
+    });
+    let ((reader, _), _temp_dir) = get_test_storage();
+    let mut stream = StateUpdateStream::new(
+        BlockNumber(0),
+        BlockNumber(N_BLOCKS),
+        Arc::new(mock),
+        reader,
+        StateUpdateStreamConfig {
+            max_state_updates_to_download: MAX_STATE_UPDATES_TO_DOWNLOAD,
+            max_state_updates_to_store_in_memory: MAX_STATE_UPDATES_TO_DOWNLOAD,
+            max_classes_to_download: 10,
+            memory_budget_bytes,
+        },
+        Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(2).unwrap()))),
+    );
+
+    let mut max_classes_in_memory = 0;
+    let mut n_received_state_updates = 0;
+    while let Some(state_update) = stream.next().await {
+        let (_, _, state_diff, _) = state_update.unwrap();
+        n_received_state_updates += 1;
+        // The classes of the returned state update were in memory together with the remaining
+        // ones.
+        let n_classes_in_memory = stream.download_class_tasks.len()
+            + stream.downloaded_classes.len()
+            + state_diff.declared_classes.len();
+        max_classes_in_memory = max_classes_in_memory.max(n_classes_in_memory);
+    }
+    assert_eq!(n_received_state_updates, N_BLOCKS);
+    max_classes_in_memory
+}
+
+#[tokio::test]
+async fn memory_budget_limits_classes_in_memory() {
+    // Enough for the two classes of a single state update but not for a third class.
+    assert_eq!(max_classes_in_memory(300_000).await, 2);
+    // The classes of a single state update are downloaded even if they exceed the budget.
+    assert_eq!(max_classes_in_memory(1).await, 2);
+    // Without a limiting budget, the classes of several state updates are downloaded at once.
+    assert!(max_classes_in_memory(1 << 30).await > 2);
+}
+
+#[test]
+fn duplicate_storage_entries_keep_the_last_value() {
+    let address0 = ContractAddress(patricia_key!("0x0"));
+    let address1 = ContractAddress(patricia_key!("0x1"));
+    let key0 = StorageKey(patricia_key!("0x0"));
+    let key1 = StorageKey(patricia_key!("0x1"));
+    let state_update = StateUpdate {
+        state_diff: StateDiff {
+            storage_diffs: indexmap!(
+                address1 => vec![
+                    StorageEntry { key: key1, value: felt!("0x1") },
+                    StorageEntry { key: key0, value: felt!("0x2") },
+                    StorageEntry { key: key1, value: felt!("0x3") },
+                ],
+                address0 => vec![
+                    StorageEntry { key: key0, value: felt!("0x4") },
+                    StorageEntry { key: key0, value: felt!("0x5") },
+                ],
+            ),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let (_, _, mut state_diff, _) =
+        client_to_central_state_update(BlockNumber(0), Ok((state_update, IndexMap::new())))
+            .unwrap();
+    sort_state_diff(&mut state_diff);
+
+    let storage_diffs: Vec<_> = state_diff
+        .storage_diffs
+        .into_iter()
+        .map(|(address, entries)| (address, entries.into_iter().collect::<Vec<_>>()))
+        .collect();
+    assert_eq!(
+        storage_diffs,
+        vec![
+            (address0, vec![(key0, felt!("0x5"))]),
+            (address1, vec![(key0, felt!("0x2")), (key1, felt!("0x3"))]),
+        ]
+    );
+}
@@ -0,0 +1,257 @@
+#[cfg(test)]
+#[path = "test_utils_test.rs"]
+mod test_utils_test;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_stream::stream;
+use async_trait::async_trait;
+use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
+use futures_util::StreamExt;
+use indexmap::IndexMap;
+use papyrus_common::pending_classes::ApiContractClass;
+use papyrus_common::BlockHashAndNumber;
+use starknet_api::block::{Block, BlockHash, BlockNumber, BlockSignature};
+use starknet_api::core::{ClassHash, SequencerPublicKey};
+use starknet_api::state::StateDiff;
+
+use super::{
+    BlocksStream,
+    CentralError,
+    CentralSourceTrait,
+    CompiledClassesStream,
+    StateUpdatesStream,
+};
+
+/// A central source that serves canned blocks, state diffs and classes, for testing code that
+/// syncs from central without hand-rolling a mock of [`CentralSourceTrait`].
+///
+/// Create it with [`MockCentralSourceBuilder`]. Like the real source, a stream yields an error for
+/// the first block it doesn't have and ends after yielding an error.
+#[derive(Default)]
+pub struct MockCentralSource {
+    blocks: Vec<CannedBlock>,
+    classes: HashMap<ClassHash, ApiContractClass>,
+    compiled_classes: HashMap<ClassHash, CasmContractClass>,
+    sequencer_pub_key: SequencerPublicKey,
+}
+
+struct CannedBlock {
+    block: Block,
+    signature: BlockSignature,
+    state_diff: StateDiff,
+    delay: Duration,
+    block_errors: Mutex<VecDeque<CentralError>>,
+    state_update_errors: Mutex<VecDeque<CentralError>>,
+}
+
+/// Builder of a [`MockCentralSource`].
+#[derive(Default)]
+pub struct MockCentralSourceBuilder {
+    source: MockCentralSource,
+}
+
+impl MockCentralSourceBuilder {
+    /// Adds the next block and its state diff. The first block added is block 0, regardless of the
+    /// block number in its header.
+    pub fn block(self, block: Block, state_diff: StateDiff) -> Self {
+        self.block_with_signature(block, BlockSignature::default(), state_diff)
+    }
+
+    /// Like [`block`](Self::block), with the given signature for the block.
+    pub fn block_with_signature(
+        mut self,
+        block: Block,
+        signature: BlockSignature,
+        state_diff: StateDiff,
+    ) -> Self {
+        self.source.blocks.push(CannedBlock {
+            block,
+            signature,
+            state_diff,
+            delay: Duration::ZERO,
+            block_errors: Mutex::new(VecDeque::new()),
+            state_update_errors: Mutex::new(VecDeque::new()),
+        });
+        self
+    }
+
+    /// Delays yielding the given block and its state update in the streams. Panics if the block
+    /// wasn't added.
+    pub fn delay(mut self, block_number: BlockNumber, delay: Duration) -> Self {
+        self.canned_block(block_number).delay = delay;
+        self
+    }
+
+    /// Makes the next stream of blocks that reaches the given block yield the given error instead
+    /// of the block. Injecting several errors for the same block fails that many streams. Panics if
+    /// the block wasn't added.
+    pub fn block_error(mut self, block_number: BlockNumber, error: CentralError) -> Self {
+        self.canned_block(block_number).block_errors.get_mut().unwrap().push_back(error);
+        self
+    }
+
+    /// Like [`block_error`](Self::block_error), for the streams of state updates.
+    pub fn state_update_error(mut self, block_number: BlockNumber, error: CentralError) -> Self {
+        self.canned_block(block_number).state_update_errors.get_mut().unwrap().push_back(error);
+        self
+    }
+
+    /// Adds a class to be returned by [`get_class`](CentralSourceTrait::get_class).
+    pub fn class(mut self, class_hash: ClassHash, class: ApiContractClass) -> Self {
+        self.source.classes.insert(class_hash, class);
+        self
+    }
+
+    /// Adds a compiled class. Compiled classes are returned by
+    /// [`get_compiled_class`](CentralSourceTrait::get_compiled_class) and streamed for the classes
+    /// declared in the state diffs.
+    pub fn compiled_class(mut self, class_hash: ClassHash, casm: CasmContractClass) -> Self {
+        self.source.compiled_classes.insert(class_hash, casm);
+        self
+    }
+
+    /// Sets the sequencer public key.
+    pub fn sequencer_pub_key(mut self, sequencer_pub_key: SequencerPublicKey) -> Self {
+        self.source.sequencer_pub_key = sequencer_pub_key;
+        self
+    }
+
+    /// Returns the mock source.
+    pub fn build(self) -> MockCentralSource {
+        self.source
+    }
+
+    fn canned_block(&mut self, block_number: BlockNumber) -> &mut CannedBlock {
+        let index = usize::try_from(block_number.0).expect("Block number should fit in usize.");
+        self.source
+            .blocks
+            .get_mut(index)
+            .unwrap_or_else(|| panic!("Block {block_number} wasn't added to the mock source."))
+    }
+}
+
+impl MockCentralSource {
+    fn get_canned_block(&self, block_number: BlockNumber) -> Option<&CannedBlock> {
+        usize::try_from(block_number.0).ok().and_then(|index| self.blocks.get(index))
+    }
+}
+
+#[async_trait]
+impl CentralSourceTrait for MockCentralSource {
+    async fn get_latest_block(&self) -> Result<Option<BlockHashAndNumber>, CentralError> {
+        Ok(self.blocks.len().checked_sub(1).map(|index| BlockHashAndNumber {
+            block_hash: self.blocks[index].block.header.block_hash,
+            block_number: BlockNumber(index.try_into().expect("usize should fit in u64.")),
+        }))
+    }
+
+    fn stream_new_blocks(
+        &self,
+        initial_block_number: BlockNumber,
+        up_to_block_number: BlockNumber,
+    ) -> BlocksStream<'_> {
+        stream! {
+            for block_number in initial_block_number.iter_up_to(up_to_block_number) {
+                let Some(canned_block) = self.get_canned_block(block_number) else {
+                    yield Err(CentralError::BlockNotFound { block_number });
+                    return;
+                };
+                tokio::time::sleep(canned_block.delay).await;
+                // Bind the error first, so the lock isn't held while yielding.
+                let error = canned_block.block_errors.lock().unwrap().pop_front();
+                if let Some(error) = error {
+                    yield Err(error);
+                    return;
+                }
+                yield Ok((block_number, canned_block.block.clone(), canned_block.signature));
+            }
+        }
+        .boxed()
+    }
+
+    fn stream_state_updates(
+        &self,
+        initial_block_number: BlockNumber,
+        up_to_block_number: BlockNumber,
+    ) -> StateUpdatesStream<'_> {
+        stream! {
+            for block_number in initial_block_number.iter_up_to(up_to_block_number) {
+                let Some(canned_block) = self.get_canned_block(block_number) else {
+                    yield Err(CentralError::StateUpdateNotFound);
+                    return;
+                };
+                tokio::time::sleep(canned_block.delay).await;
+                let error = canned_block.state_update_errors.lock().unwrap().pop_front();
+                if let Some(error) = error {
+                    yield Err(error);
+                    return;
+                }
+                yield Ok((
+                    block_number,
+                    canned_block.block.header.block_hash,
+                    canned_block.state_diff.clone(),
+                    IndexMap::new(),
+                ));
+            }
+        }
+        .boxed()
+    }
+
+    async fn get_block_hash(
+        &self,
+        block_number: BlockNumber,
+    ) -> Result<Option<BlockHash>, CentralError> {
+        Ok(self
+            .get_canned_block(block_number)
+            .map(|canned_block| canned_block.block.header.block_hash))
+    }
+
+    fn stream_compiled_classes(
+        &self,
+        initial_block_number: BlockNumber,
+        up_to_block_number: BlockNumber,
+    ) -> CompiledClassesStream<'_> {
+        stream! {
+            for block_number in initial_block_number.iter_up_to(up_to_block_number) {
+                let Some(canned_block) = self.get_canned_block(block_number) else {
+                    yield Err(CentralError::StateUpdateNotFound);
+                    return;
+                };
+                let declared_classes = &canned_block.state_diff.declared_classes;
+                for (class_hash, (compiled_class_hash, _class)) in declared_classes {
+                    let Some(casm) = self.compiled_classes.get(class_hash) else {
+                        yield Err(CentralError::CompiledClassNotFound { class_hash: *class_hash });
+                        return;
+                    };
+                    yield Ok((*class_hash, *compiled_class_hash, casm.clone()));
+                }
+            }
+        }
+        .boxed()
+    }
+
+    async fn get_class(&self, class_hash: ClassHash) -> Result<ApiContractClass, CentralError> {
+        self.classes.get(&class_hash).cloned().ok_or(CentralError::ClassNotFound)
+    }
+
+    async fn get_compiled_class(
+        &self,
+        class_hash: ClassHash,
+    ) -> Result<CasmContractClass, CentralError> {
+        self.compiled_classes
+            .get(&class_hash)
+            .cloned()
+            .ok_or(CentralError::CompiledClassNotFound { class_hash })
+    }
+
+    async fn get_sequencer_pub_key(&self) -> Result<SequencerPublicKey, CentralError> {
+        Ok(self.sequencer_pub_key)
+    }
+
+    fn memory_budget_bytes(&self) -> usize {
+        usize::MAX
+    }
+}
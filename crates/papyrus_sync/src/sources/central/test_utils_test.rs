@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use assert_matches::assert_matches;
+use futures_util::StreamExt;
+use starknet_api::block::{Block, BlockNumber};
+use starknet_api::state::StateDiff;
+use tokio::time::Instant;
+
+use super::{MockCentralSource, MockCentralSourceBuilder};
+use crate::sources::central::{CentralError, CentralSourceTrait};
+
+const N_BLOCKS: u64 = 3;
+
+fn builder_with_blocks() -> MockCentralSourceBuilder {
+    (0..N_BLOCKS).fold(MockCentralSourceBuilder::default(), |builder, _| {
+        builder.block(Block::default(), StateDiff::default())
+    })
+}
+
+// Returns the block numbers yielded by a stream of blocks, and the error it ended with, if any.
+async fn stream_blocks(source: &MockCentralSource) -> (Vec<BlockNumber>, Option<CentralError>) {
+    let mut block_numbers = Vec::new();
+    let mut stream = source.stream_new_blocks(BlockNumber(0), BlockNumber(N_BLOCKS));
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok((block_number, _block, _signature)) => block_numbers.push(block_number),
+            Err(err) => return (block_numbers, Some(err)),
+        }
+    }
+    (block_numbers, None)
+}
+
+#[tokio::test]
+async fn injected_block_error_fails_a_single_stream() {
+    let source = builder_with_blocks()
+        .block_error(BlockNumber(1), CentralError::BlockNotFound { block_number: BlockNumber(1) })
+        .build();
+
+    let (block_numbers, error) = stream_blocks(&source).await;
+    assert_eq!(block_numbers, vec![BlockNumber(0)]);
+    assert_matches!(error, Some(CentralError::BlockNotFound { block_number: BlockNumber(1) }));
+
+    let (block_numbers, error) = stream_blocks(&source).await;
+    assert_eq!(block_numbers, BlockNumber(0).iter_up_to(BlockNumber(N_BLOCKS)).collect::<Vec<_>>());
+    assert!(error.is_none());
+
+    // The error was injected only to the stream of blocks.
+    let state_updates = source
+        .stream_state_updates(BlockNumber(0), BlockNumber(N_BLOCKS))
+        .collect::<Vec<_>>()
+        .await;
+    assert!(state_updates.iter().all(Result::is_ok));
+}
+
+#[tokio::test]
+async fn stream_fails_on_missing_block() {
+    let source = builder_with_blocks().build();
+
+    let mut stream = source.stream_new_blocks(BlockNumber(N_BLOCKS - 1), BlockNumber(N_BLOCKS + 2));
+    assert_matches!(
+        stream.next().await,
+        Some(Ok((block_number, _, _))) if block_number.0 == N_BLOCKS - 1
+    );
+    assert_matches!(
+        stream.next().await,
+        Some(Err(CentralError::BlockNotFound { block_number })) if block_number.0 == N_BLOCKS
+    );
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn delayed_block_is_yielded_after_the_delay() {
+    const DELAY: Duration = Duration::from_secs(10);
+    let source = builder_with_blocks().delay(BlockNumber(1), DELAY).build();
+
+    let mut stream = source.stream_state_updates(BlockNumber(0), BlockNumber(N_BLOCKS));
+    let start = Instant::now();
+    assert_matches!(stream.next().await, Some(Ok((BlockNumber(0), _, _, _))));
+    assert_eq!(start.elapsed(), Duration::ZERO);
+    assert_matches!(stream.next().await, Some(Ok((BlockNumber(1), _, _, _))));
+    assert_eq!(start.elapsed(), DELAY);
+    assert_matches!(stream.next().await, Some(Ok((BlockNumber(2), _, _, _))));
+    assert_eq!(start.elapsed(), DELAY);
+}
@@ -1,10 +1,12 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use assert_matches::assert_matches;
 use async_stream::stream;
 use async_trait::async_trait;
 use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
+use chrono::Utc;
 use futures::StreamExt;
 use indexmap::IndexMap;
 use papyrus_common::pending_classes::{ApiContractClass, PendingClasses};
@@ -20,17 +22,25 @@ use starknet_api::crypto::utils::PublicKey;
 use starknet_api::felt;
 use starknet_api::state::StateDiff;
 use starknet_client::reader::PendingData;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{oneshot, Mutex, Notify, RwLock};
 use tracing::{debug, error};
 
 use super::pending::MockPendingSourceTrait;
-use crate::sources::base_layer::{BaseLayerSourceTrait, MockBaseLayerSourceTrait};
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerState};
+use crate::event_bus::{EventKind, SyncEventBus, SyncNotification};
+use crate::last_error::LastErrorRecorder;
+use crate::sources::base_layer::{
+    BaseLayerSourceError,
+    BaseLayerSourceTrait,
+    MockBaseLayerSourceTrait,
+};
 use crate::sources::central::{
     BlocksStream,
     CompiledClassesStream,
     MockCentralSourceTrait,
     StateUpdatesStream,
 };
+use crate::synced::SyncedHandle;
 use crate::{
     CentralError,
     CentralSourceTrait,
@@ -90,10 +100,24 @@ fn get_test_sync_config(verify_blocks: bool) -> SyncConfig {
     SyncConfig {
         block_propagation_sleep_duration: SYNC_SLEEP_DURATION,
         base_layer_propagation_sleep_duration: BASE_LAYER_SLEEP_DURATION,
-        recoverable_error_sleep_duration: SYNC_SLEEP_DURATION,
+        base_backoff: SYNC_SLEEP_DURATION,
+        max_backoff: SYNC_SLEEP_DURATION,
+        backoff_jitter_ratio: 0.0,
         blocks_max_stream_size: STREAM_SIZE,
         state_updates_max_stream_size: STREAM_SIZE,
+        max_parallel_state_diff_downloads: 1,
+        min_confirmations: 0,
+        sync_pending_data: true,
         verify_blocks,
+        verify_state_diffs: true,
+        circuit_breaker_error_threshold: 10,
+        circuit_breaker_cool_down: SYNC_SLEEP_DURATION,
+        // Failed calls to the central source are retried by restarting the sync.
+        central_retry_max_attempts: 1,
+        central_retry_base_delay: Duration::ZERO,
+        central_retry_max_delay: Duration::ZERO,
+        central_retry_jitter_ratio: 0.0,
+        slow_store_warning_threshold: Duration::from_secs(1),
     }
 }
 
@@ -120,6 +144,10 @@ async fn run_sync(
         reader,
         writer,
         sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
     };
 
     state_sync.run().await?;
@@ -578,6 +606,10 @@ async fn sync_with_revert() {
         async fn get_sequencer_pub_key(&self) -> Result<SequencerPublicKey, CentralError> {
             unimplemented!()
         }
+
+        fn memory_budget_bytes(&self) -> usize {
+            unimplemented!()
+        }
     }
 }
 
@@ -686,6 +718,429 @@ async fn sequencer_pub_key_management() {
     );
 }
 
+#[tokio::test]
+async fn last_error_is_kept_after_recovery() {
+    let _ = simple_logger::init_with_env();
+
+    let recovered = Arc::new(AtomicBool::new(false));
+    let recovered_copy = recovered.clone();
+    let mut central_mock = MockCentralSourceTrait::new();
+    // Fail on the first request and succeed afterwards.
+    central_mock
+        .expect_get_sequencer_pub_key()
+        .times(1)
+        .returning(|| Err(CentralError::BlockNotFound { block_number: BlockNumber(0) }));
+    central_mock
+        .expect_get_sequencer_pub_key()
+        .returning(|| Ok(SequencerPublicKey(PublicKey(felt!("0x111")))));
+    // The latest block is requested only once the sync recovered.
+    central_mock.expect_get_latest_block().returning(move || {
+        recovered_copy.store(true, Ordering::SeqCst);
+        Ok(None)
+    });
+
+    // Mock base_layer without any block.
+    let mut base_layer_mock = MockBaseLayerSourceTrait::new();
+    base_layer_mock.expect_latest_proved_block().returning(|| Ok(None));
+
+    let mut pending_source = MockPendingSourceTrait::new();
+    pending_source.expect_get_pending_data().returning(|| Ok(PendingData::default()));
+
+    let ((reader, writer), _temp_dir) = get_test_storage();
+    let config = get_test_sync_config(true);
+    let mut state_sync = GenericStateSync {
+        config,
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(central_mock),
+        pending_source: Arc::new(pending_source),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(base_layer_mock),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    let last_error_recorder = state_sync.last_error_recorder();
+    assert!(last_error_recorder.last_error().is_none());
+
+    let before_error = Utc::now();
+    tokio::time::timeout(config.block_propagation_sleep_duration * 4, state_sync.run())
+        .await
+        .expect_err("Expecting sync to keep running after recovering.");
+    assert!(recovered.load(Ordering::SeqCst));
+
+    let recorded_error = last_error_recorder.last_error().expect("Expecting a recorded error.");
+    assert_matches!(
+        *recorded_error.error,
+        StateSyncError::CentralSourceError(CentralError::BlockNotFound {
+            block_number: BlockNumber(0)
+        })
+    );
+    assert!(before_error <= recorded_error.timestamp && recorded_error.timestamp <= Utc::now());
+    assert_eq!(state_sync.last_error().unwrap().timestamp, recorded_error.timestamp);
+}
+
+#[tokio::test]
+async fn synced_handle_is_updated_while_sync_runs() {
+    const N_BLOCKS: u64 = 3;
+    const LATEST_BLOCK_NUMBER: BlockNumber = BlockNumber(N_BLOCKS - 1);
+    let _ = simple_logger::init_with_env();
+
+    let mut central_mock = MockCentralSourceTrait::new();
+    central_mock.expect_get_latest_block().returning(|| {
+        Ok(Some(BlockHashAndNumber {
+            block_number: LATEST_BLOCK_NUMBER,
+            block_hash: create_block_hash(LATEST_BLOCK_NUMBER, false),
+        }))
+    });
+    central_mock.expect_stream_new_blocks().returning(|initial, up_to| {
+        let blocks_stream: BlocksStream<'_> = stream! {
+            for block_number in initial.iter_up_to(up_to) {
+                let header = BlockHeader {
+                    block_number,
+                    block_hash: create_block_hash(block_number, false),
+                    parent_hash: create_block_hash(block_number.prev().unwrap_or_default(), false),
+                    ..BlockHeader::default()
+                };
+                yield Ok((
+                    block_number,
+                    Block { header, body: BlockBody::default() },
+                    BlockSignature::default(),
+                ));
+            }
+        }
+        .boxed();
+        blocks_stream
+    });
+    central_mock.expect_stream_state_updates().returning(|initial, up_to| {
+        let state_stream: StateUpdatesStream<'_> = stream! {
+            for block_number in initial.iter_up_to(up_to) {
+                yield Ok((
+                    block_number,
+                    create_block_hash(block_number, false),
+                    StateDiff::default(),
+                    IndexMap::new(),
+                ));
+            }
+        }
+        .boxed();
+        state_stream
+    });
+    central_mock.expect_get_block_hash().returning(|bn| Ok(Some(create_block_hash(bn, false))));
+
+    let mut base_layer_mock = MockBaseLayerSourceTrait::new();
+    base_layer_mock.expect_latest_proved_block().returning(|| Ok(None));
+
+    let mut pending_source = MockPendingSourceTrait::new();
+    pending_source.expect_get_pending_data().returning(|| Ok(PendingData::default()));
+
+    let ((reader, writer), _temp_dir) = get_test_storage();
+    let mut state_sync = GenericStateSync {
+        config: get_test_sync_config(false),
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(central_mock),
+        pending_source: Arc::new(pending_source),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(base_layer_mock),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    let synced_handle = state_sync.synced_handle();
+    assert!(!synced_handle.is_synced());
+
+    // The handle is polled while the sync runs, without access to the sync itself.
+    let synced_future = async {
+        while !synced_handle.is_synced() {
+            tokio::time::sleep(SYNC_SLEEP_DURATION / 10).await;
+        }
+    };
+    tokio::select! {
+        sync_result = state_sync.run() => panic!("Sync stopped unexpectedly: {sync_result:?}."),
+        synced_result = tokio::time::timeout(Duration::from_secs(5), synced_future) => {
+            synced_result.expect("The sync didn't become synced.")
+        }
+    }
+}
+
+#[tokio::test]
+async fn backoff_grows_on_consecutive_errors_and_resets_after_progress() {
+    const BASE_BACKOFF: Duration = Duration::from_millis(100);
+    const N_FAILURES_BEFORE_PROGRESS: usize = 3;
+    const N_FAILURES: usize = 6;
+    let _ = simple_logger::init_with_env();
+
+    let ((reader, writer), _temp_dir) = get_test_storage();
+    let failure_times = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    // Central fails a fixed number of times before serving a single block.
+    let central_failure_times = failure_times.clone();
+    let mut central_mock = MockCentralSourceTrait::new();
+    central_mock.expect_get_latest_block().returning(move || {
+        let mut failure_times = central_failure_times.lock().unwrap();
+        if failure_times.len() < N_FAILURES_BEFORE_PROGRESS {
+            failure_times.push(Instant::now());
+            return Err(CentralError::BlockNotFound { block_number: BlockNumber(0) });
+        }
+        Ok(Some(BlockHashAndNumber {
+            block_number: BlockNumber(0),
+            block_hash: create_block_hash(BlockNumber(0), false),
+        }))
+    });
+    central_mock.expect_stream_new_blocks().returning(|initial, up_to| {
+        let blocks_stream: BlocksStream<'_> = stream! {
+            for block_number in initial.iter_up_to(up_to) {
+                let header = BlockHeader {
+                    block_number,
+                    block_hash: create_block_hash(block_number, false),
+                    ..BlockHeader::default()
+                };
+                yield Ok((
+                    block_number,
+                    Block { header, body: BlockBody::default() },
+                    BlockSignature::default(),
+                ));
+            }
+        }
+        .boxed();
+        blocks_stream
+    });
+    central_mock.expect_stream_state_updates().returning(|initial, up_to| {
+        let state_stream: StateUpdatesStream<'_> = stream! {
+            for block_number in initial.iter_up_to(up_to) {
+                yield Ok((
+                    block_number,
+                    create_block_hash(block_number, false),
+                    StateDiff::default(),
+                    IndexMap::new(),
+                ));
+            }
+        }
+        .boxed();
+        state_stream
+    });
+    central_mock.expect_get_block_hash().returning(|bn| Ok(Some(create_block_hash(bn, false))));
+
+    // The base layer fails persistently once the state diff of the block was synced.
+    let base_layer_reader = reader.clone();
+    let base_layer_failure_times = failure_times.clone();
+    let mut base_layer_mock = MockBaseLayerSourceTrait::new();
+    base_layer_mock.expect_latest_proved_block().returning(move || {
+        let state_marker = base_layer_reader.begin_ro_txn().unwrap().get_state_marker().unwrap();
+        if state_marker == BlockNumber(0) {
+            return Ok(None);
+        }
+        base_layer_failure_times.lock().unwrap().push(Instant::now());
+        Err(BaseLayerSourceError::BaseLayerSourceCreationError("Failure.".to_owned()))
+    });
+
+    let config = SyncConfig {
+        base_backoff: BASE_BACKOFF,
+        max_backoff: BASE_BACKOFF * 10,
+        ..get_test_sync_config(false)
+    };
+    let sync_future = run_sync(reader, writer, central_mock, base_layer_mock, config);
+    let failures_future = async {
+        while failure_times.lock().unwrap().len() < N_FAILURES {
+            tokio::time::sleep(BASE_LAYER_SLEEP_DURATION).await;
+        }
+    };
+    tokio::select! {
+        sync_result = sync_future => panic!("Sync stopped unexpectedly: {sync_result:?}."),
+        _ = tokio::time::timeout(Duration::from_secs(5), failures_future) => {}
+    }
+
+    let failure_times = failure_times.lock().unwrap();
+    assert!(failure_times.len() >= N_FAILURES, "Sync didn't fail {N_FAILURES} times.");
+    let delays: Vec<_> =
+        failure_times.windows(2).map(|window| window[1].duration_since(window[0])).collect();
+    // Consecutive errors of the central source.
+    assert!(delays[0] >= BASE_BACKOFF);
+    assert!(delays[1] >= BASE_BACKOFF * 2);
+    assert!(delays[2] >= BASE_BACKOFF * 4);
+    // The state diff was synced before the base layer failed, so the delay was reset.
+    assert!(delays[3] >= BASE_BACKOFF && delays[3] < BASE_BACKOFF * 2);
+    // Consecutive errors of the base layer.
+    assert!(delays[4] >= BASE_BACKOFF * 2);
+}
+
+#[tokio::test]
+async fn circuit_breaker_opens_on_consecutive_failures_and_recovers() {
+    const ERROR_THRESHOLD: usize = 3;
+    const N_FAILURES: usize = 5;
+    const COOL_DOWN: Duration = Duration::from_millis(100);
+    let _ = simple_logger::init_with_env();
+
+    // Central fails more times than the threshold before serving a single block.
+    let call_times = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let central_call_times = call_times.clone();
+    let mut central_mock = MockCentralSourceTrait::new();
+    central_mock.expect_get_latest_block().returning(move || {
+        let mut call_times = central_call_times.lock().unwrap();
+        call_times.push(Instant::now());
+        if call_times.len() <= N_FAILURES {
+            return Err(CentralError::BlockNotFound { block_number: BlockNumber(0) });
+        }
+        Ok(Some(BlockHashAndNumber {
+            block_number: BlockNumber(0),
+            block_hash: create_block_hash(BlockNumber(0), false),
+        }))
+    });
+    central_mock.expect_stream_new_blocks().returning(|initial, up_to| {
+        let blocks_stream: BlocksStream<'_> = stream! {
+            for block_number in initial.iter_up_to(up_to) {
+                let header = BlockHeader {
+                    block_number,
+                    block_hash: create_block_hash(block_number, false),
+                    ..BlockHeader::default()
+                };
+                yield Ok((
+                    block_number,
+                    Block { header, body: BlockBody::default() },
+                    BlockSignature::default(),
+                ));
+            }
+        }
+        .boxed();
+        blocks_stream
+    });
+    central_mock
+        .expect_stream_state_updates()
+        .returning(|_initial, _up_to| futures::stream::pending().boxed());
+    central_mock.expect_get_block_hash().returning(|bn| Ok(Some(create_block_hash(bn, false))));
+
+    let mut base_layer_mock = MockBaseLayerSourceTrait::new();
+    base_layer_mock.expect_latest_proved_block().returning(|| Ok(None));
+
+    let mut pending_source = MockPendingSourceTrait::new();
+    pending_source.expect_get_pending_data().returning(|| Ok(PendingData::default()));
+
+    let ((reader, writer), _temp_dir) = get_test_storage();
+    let config = SyncConfig {
+        base_backoff: BASE_LAYER_SLEEP_DURATION,
+        circuit_breaker_error_threshold: ERROR_THRESHOLD,
+        circuit_breaker_cool_down: COOL_DOWN,
+        ..get_test_sync_config(false)
+    };
+    let mut state_sync = GenericStateSync {
+        config,
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(central_mock),
+        pending_source: Arc::new(pending_source),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(base_layer_mock),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    let mut circuit_breaker_receiver = state_sync.subscribe(EventKind::CircuitBreaker);
+
+    let states_future = async {
+        let mut states = Vec::new();
+        while let Some(event) = circuit_breaker_receiver.recv().await {
+            let SyncNotification::CircuitBreakerStateChanged { state } = event else {
+                panic!("Unexpected event {event:?}.");
+            };
+            states.push(state);
+            if state == CircuitBreakerState::Closed {
+                break;
+            }
+        }
+        states
+    };
+    let states = tokio::select! {
+        sync_result = state_sync.run() => panic!("Sync stopped unexpectedly: {sync_result:?}."),
+        states = tokio::time::timeout(Duration::from_secs(5), states_future) => {
+            states.expect("The circuit breaker didn't recover.")
+        }
+    };
+
+    // The breaker opens on the third failure and each following failure while testing recovery
+    // opens it again, until the block is downloaded.
+    assert_eq!(
+        states,
+        vec![
+            CircuitBreakerState::Open,
+            CircuitBreakerState::HalfOpen,
+            CircuitBreakerState::Open,
+            CircuitBreakerState::HalfOpen,
+            CircuitBreakerState::Open,
+            CircuitBreakerState::HalfOpen,
+            CircuitBreakerState::Closed,
+        ]
+    );
+    // The central source isn't called while the breaker is open.
+    let call_times = call_times.lock().unwrap();
+    for window in call_times[ERROR_THRESHOLD - 1..=N_FAILURES].windows(2) {
+        assert!(window[1].duration_since(window[0]) >= COOL_DOWN);
+    }
+}
+
+#[tokio::test]
+async fn shutdown_while_waiting_to_restart_stops_sync_promptly() {
+    const BACKOFF: Duration = Duration::from_secs(60);
+    let _ = simple_logger::init_with_env();
+
+    // Fail on every request so the sync keeps waiting to restart.
+    let failed = Arc::new(Notify::new());
+    let failed_copy = failed.clone();
+    let mut central_mock = MockCentralSourceTrait::new();
+    central_mock.expect_get_sequencer_pub_key().returning(move || {
+        failed_copy.notify_one();
+        Err(CentralError::BlockNotFound { block_number: BlockNumber(0) })
+    });
+
+    let ((reader, writer), _temp_dir) = get_test_storage();
+    let config = SyncConfig {
+        base_backoff: BACKOFF,
+        max_backoff: BACKOFF,
+        ..get_test_sync_config(true)
+    };
+    let mut state_sync = GenericStateSync {
+        config,
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(central_mock),
+        pending_source: Arc::new(MockPendingSourceTrait::new()),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(MockBaseLayerSourceTrait::new()),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+
+    let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+    let shutdown_future = async {
+        failed.notified().await;
+        shutdown_sender.send(()).unwrap();
+    };
+    let (sync_result, ()) = tokio::time::timeout(
+        Duration::from_secs(5),
+        futures::future::join(state_sync.run_until_shutdown(shutdown_receiver), shutdown_future),
+    )
+    .await
+    .expect("Sync didn't stop after shutdown.");
+    sync_result.unwrap();
+}
+
 fn create_block_hash(bn: BlockNumber, is_reverted_block: bool) -> BlockHash {
     if is_reverted_block {
         BlockHash(felt!(format!("0x{}10", bn.0).as_str()))
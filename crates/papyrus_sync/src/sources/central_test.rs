@@ -8,6 +8,7 @@ use indexmap::{indexmap, IndexMap};
 use lru::LruCache;
 use mockall::predicate;
 use papyrus_storage::class::ClassStorageWriter;
+use papyrus_storage::compiled_class::CasmStorageWriter;
 use papyrus_storage::state::StateStorageWriter;
 use papyrus_storage::test_utils::get_test_storage;
 use pretty_assertions::assert_eq;
@@ -546,6 +547,55 @@ async fn stream_compiled_classes() {
     }
 }
 
+#[tokio::test]
+async fn stream_compiled_classes_skips_stored_classes() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let stored_class_hash = ClassHash(felt!("0x1"));
+    writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_state_diff(
+            BlockNumber(0),
+            ThinStateDiff {
+                declared_classes: indexmap! {
+                    ClassHash(felt!("0x0")) => CompiledClassHash(felt!("0x0")),
+                    stored_class_hash => CompiledClassHash(felt!("0x1")),
+                    ClassHash(felt!("0x2")) => CompiledClassHash(felt!("0x2")),
+                },
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .append_casm(&stored_class_hash, &CasmContractClass::default())
+        .unwrap()
+        .commit()
+        .unwrap();
+
+    let mut mock = MockStarknetReader::new();
+    mock.expect_compiled_class_by_hash()
+        .with(predicate::ne(stored_class_hash))
+        .times(2)
+        .returning(|_x| Ok(Some(CasmContractClass::default())));
+
+    let central_source = GenericCentralSource {
+        concurrent_requests: TEST_CONCURRENT_REQUESTS,
+        starknet_client: Arc::new(mock),
+        storage_reader: reader,
+        state_update_stream_config: state_update_stream_config_for_test(),
+        class_cache: get_test_class_cache(),
+        compiled_class_cache: get_test_compiled_class_cache(),
+    };
+
+    let stream = central_source.stream_compiled_classes(BlockNumber(0), BlockNumber(1));
+    pin_mut!(stream);
+
+    for expected_class_hash in [ClassHash(felt!("0x0")), ClassHash(felt!("0x2"))] {
+        let (class_hash, _, _) = stream.next().await.unwrap().unwrap();
+        assert_eq!(class_hash, expected_class_hash);
+    }
+    assert!(stream.next().await.is_none());
+}
+
 #[tokio::test]
 async fn get_class() {
     let mut mock = MockStarknetReader::new();
@@ -646,6 +696,7 @@ fn state_update_stream_config_for_test() -> StateUpdateStreamConfig {
         max_state_updates_to_download: 10,
         max_state_updates_to_store_in_memory: 10,
         max_classes_to_download: 10,
+        memory_budget_bytes: 1 << 30,
     }
 }
 
@@ -3,6 +3,7 @@
 mod pending_test;
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 #[cfg(test)]
@@ -14,6 +15,8 @@ use starknet_client::reader::{
     StarknetReader,
 };
 use starknet_client::ClientCreationError;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 
 // TODO(dvir): add pending config.
 use super::central::CentralSourceConfig;
@@ -50,6 +53,39 @@ impl<TStarknetClient: StarknetReader + Send + Sync + 'static> PendingSourceTrait
     }
 }
 
+/// A pending source that serves the last pending data it fetched until the refresh interval
+/// passes, so that frequent readers don't query the underlying source on every call. Failed
+/// fetches are not cached.
+pub struct CachedPendingSource<TPendingSource: PendingSourceTrait + Send + Sync> {
+    pending_source: TPendingSource,
+    refresh_interval: Duration,
+    cache: Mutex<Option<(Instant, PendingData)>>,
+}
+
+impl<TPendingSource: PendingSourceTrait + Send + Sync> CachedPendingSource<TPendingSource> {
+    pub fn new(pending_source: TPendingSource, refresh_interval: Duration) -> Self {
+        Self { pending_source, refresh_interval, cache: Mutex::new(None) }
+    }
+}
+
+#[async_trait]
+impl<TPendingSource: PendingSourceTrait + Send + Sync> PendingSourceTrait
+    for CachedPendingSource<TPendingSource>
+{
+    async fn get_pending_data(&self) -> Result<PendingData, PendingError> {
+        // The lock is held during the fetch so that concurrent readers share a single fetch.
+        let mut cache = self.cache.lock().await;
+        if let Some((fetch_time, pending_data)) = cache.as_ref() {
+            if fetch_time.elapsed() < self.refresh_interval {
+                return Ok(pending_data.clone());
+            }
+        }
+        let pending_data = self.pending_source.get_pending_data().await?;
+        *cache = Some((Instant::now(), pending_data.clone()));
+        Ok(pending_data)
+    }
+}
+
 pub type PendingSource = GenericPendingSource<StarknetFeederGatewayClient>;
 
 impl PendingSource {
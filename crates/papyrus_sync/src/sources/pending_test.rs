@@ -1,9 +1,22 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use pretty_assertions::assert_eq;
+use starknet_api::block::BlockHash;
+use starknet_api::felt;
+use starknet_client::reader::objects::pending_data::{
+    DeprecatedPendingBlock,
+    PendingBlockOrDeprecated,
+};
 use starknet_client::reader::{MockStarknetReader, PendingData};
 
-use crate::sources::pending::{GenericPendingSource, PendingSourceTrait};
+use crate::sources::pending::{
+    CachedPendingSource,
+    GenericPendingSource,
+    MockPendingSourceTrait,
+    PendingError,
+    PendingSourceTrait,
+};
 
 #[tokio::test]
 async fn get_pending_data() {
@@ -18,3 +31,56 @@ async fn get_pending_data() {
     let pending_data = pending_source.get_pending_data().await.unwrap();
     assert_eq!(pending_data, PendingData::default());
 }
+
+fn pending_data_with_parent(parent_block_hash: BlockHash) -> PendingData {
+    PendingData {
+        block: PendingBlockOrDeprecated::Deprecated(DeprecatedPendingBlock {
+            parent_block_hash,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn cached_pending_source_serves_cache_within_refresh_interval() {
+    let pending_data = pending_data_with_parent(BlockHash(felt!("0x1")));
+    let mut mock = MockPendingSourceTrait::new();
+    let returned_pending_data = pending_data.clone();
+    mock.expect_get_pending_data().times(1).returning(move || Ok(returned_pending_data.clone()));
+
+    let cached_source = CachedPendingSource::new(mock, Duration::from_secs(3600));
+    for _ in 0..3 {
+        assert_eq!(cached_source.get_pending_data().await.unwrap(), pending_data);
+    }
+}
+
+#[tokio::test]
+async fn cached_pending_source_refetches_after_refresh_interval() {
+    let mut pending_datas = vec![
+        pending_data_with_parent(BlockHash(felt!("0x1"))),
+        pending_data_with_parent(BlockHash(felt!("0x2"))),
+    ];
+    let expected_pending_datas = pending_datas.clone();
+    let mut mock = MockPendingSourceTrait::new();
+    mock.expect_get_pending_data().times(2).returning(move || Ok(pending_datas.remove(0)));
+
+    // With no refresh interval, every call reaches the underlying source.
+    let cached_source = CachedPendingSource::new(mock, Duration::ZERO);
+    for expected_pending_data in expected_pending_datas {
+        assert_eq!(cached_source.get_pending_data().await.unwrap(), expected_pending_data);
+    }
+}
+
+#[tokio::test]
+async fn cached_pending_source_doesnt_cache_errors() {
+    let pending_data = pending_data_with_parent(BlockHash(felt!("0x1")));
+    let mut mock = MockPendingSourceTrait::new();
+    mock.expect_get_pending_data().times(1).returning(|| Err(PendingError::PendingBlockNotFound));
+    let returned_pending_data = pending_data.clone();
+    mock.expect_get_pending_data().times(1).returning(move || Ok(returned_pending_data.clone()));
+
+    let cached_source = CachedPendingSource::new(mock, Duration::from_secs(3600));
+    assert!(cached_source.get_pending_data().await.is_err());
+    assert_eq!(cached_source.get_pending_data().await.unwrap(), pending_data);
+}
@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use papyrus_common::BlockHashAndNumber;
+use papyrus_storage::header::HeaderStorageReader;
+use papyrus_storage::state::StateStorageReader;
+use papyrus_storage::{StorageReader, StorageResult};
+use starknet_api::block::BlockNumber;
+use tokio::sync::RwLock;
+
+/// A snapshot of the progress of the sync.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncStatus {
+    /// The first block number for which the node doesn't have a state diff.
+    pub state_marker: BlockNumber,
+    /// The first block number for which the node doesn't have a header.
+    pub header_marker: BlockNumber,
+    /// The latest block of the central source, as last polled by the sync. None if the sync
+    /// didn't poll the central source yet or the central source has no blocks.
+    pub central_source_tip: Option<BlockNumber>,
+    /// The number of blocks up to the central source tip whose state diff the node doesn't have.
+    /// None if the central source tip is unknown.
+    pub blocks_behind: Option<u64>,
+}
+
+/// Takes snapshots of the sync's status while it runs.
+///
+/// The central source tip is the one the sync caches each time it polls the central source for new
+/// blocks, so taking a snapshot never queries the central source and never blocks the sync.
+#[derive(Clone)]
+pub struct SyncStatusHandle {
+    reader: StorageReader,
+    shared_highest_block: Arc<RwLock<Option<BlockHashAndNumber>>>,
+}
+
+impl SyncStatusHandle {
+    pub(crate) fn new(
+        reader: StorageReader,
+        shared_highest_block: Arc<RwLock<Option<BlockHashAndNumber>>>,
+    ) -> Self {
+        Self { reader, shared_highest_block }
+    }
+
+    /// Returns the current status of the sync.
+    pub async fn status(&self) -> StorageResult<SyncStatus> {
+        let central_source_tip =
+            self.shared_highest_block.read().await.map(|block| block.block_number);
+        let txn = self.reader.begin_ro_txn()?;
+        let state_marker = txn.get_state_marker()?;
+        let header_marker = txn.get_header_marker()?;
+        let blocks_behind =
+            central_source_tip.map(|tip| tip.unchecked_next().0.saturating_sub(state_marker.0));
+        Ok(SyncStatus { state_marker, header_marker, central_source_tip, blocks_behind })
+    }
+}
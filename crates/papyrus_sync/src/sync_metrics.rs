@@ -0,0 +1,41 @@
+use std::mem::size_of;
+use std::time::Duration;
+
+use indexmap::IndexMap;
+use papyrus_common::metrics as papyrus_metrics;
+use starknet_api::core::{ClassHash, CompiledClassHash, ContractAddress, Nonce};
+use starknet_api::state::{StorageKey, ThinStateDiff};
+use starknet_types_core::felt::Felt;
+
+pub(crate) fn record_stored_state_diff(state_diff_size: usize) {
+    metrics::counter!(papyrus_metrics::PAPYRUS_SYNC_NUM_STORED_STATE_DIFFS, 1);
+    metrics::counter!(papyrus_metrics::PAPYRUS_SYNC_STATE_DIFF_BYTES, state_diff_size as u64);
+}
+
+pub(crate) fn record_slow_store() {
+    metrics::counter!(papyrus_metrics::PAPYRUS_SYNC_NUM_SLOW_STORES, 1);
+}
+
+pub(crate) fn record_revert(n_reverted_blocks: usize) {
+    metrics::counter!(papyrus_metrics::PAPYRUS_SYNC_NUM_REVERTS, 1);
+    metrics::counter!(papyrus_metrics::PAPYRUS_SYNC_NUM_REVERTED_BLOCKS, n_reverted_blocks as u64);
+}
+
+pub(crate) fn record_state_diff_download_latency(latency: Duration) {
+    metrics::histogram!(
+        papyrus_metrics::PAPYRUS_SYNC_STATE_DIFF_DOWNLOAD_LATENCY_SECONDS,
+        latency.as_secs_f64()
+    );
+}
+
+// Estimates the size in bytes of the data in a thin state diff.
+pub(crate) fn estimate_thin_state_diff_size(thin_state_diff: &ThinStateDiff) -> usize {
+    let n_storage_entries: usize = thin_state_diff.storage_diffs.values().map(IndexMap::len).sum();
+    thin_state_diff.deployed_contracts.len() * size_of::<(ContractAddress, ClassHash)>()
+        + thin_state_diff.storage_diffs.len() * size_of::<ContractAddress>()
+        + n_storage_entries * size_of::<(StorageKey, Felt)>()
+        + thin_state_diff.declared_classes.len() * size_of::<(ClassHash, CompiledClassHash)>()
+        + thin_state_diff.deprecated_declared_classes.len() * size_of::<ClassHash>()
+        + thin_state_diff.nonces.len() * size_of::<(ContractAddress, Nonce)>()
+        + thin_state_diff.replaced_classes.len() * size_of::<(ContractAddress, ClassHash)>()
+}
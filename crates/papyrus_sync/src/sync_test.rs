@@ -1,21 +1,28 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use assert_matches::assert_matches;
+use async_stream::stream;
 use cairo_lang_starknet_classes::casm_contract_class::CasmContractClass;
 use futures_util::StreamExt;
 use indexmap::IndexMap;
+use libp2p::PeerId;
+use mockall::predicate::eq;
 use papyrus_common::pending_classes::{ApiContractClass, PendingClasses, PendingClassesTrait};
+use papyrus_common::BlockHashAndNumber;
 use papyrus_storage::base_layer::BaseLayerStorageReader;
-use papyrus_storage::header::HeaderStorageWriter;
-use papyrus_storage::test_utils::get_test_storage;
+use papyrus_storage::body::BodyStorageReader;
+use papyrus_storage::compiled_class::CasmStorageReader;
+use papyrus_storage::header::{HeaderStorageReader, HeaderStorageWriter};
+use papyrus_storage::state::{StateStorageReader, StateStorageWriter};
+use papyrus_storage::test_utils::{get_test_storage, BlockBuilder};
 use papyrus_storage::{StorageReader, StorageWriter};
 use pretty_assertions::assert_eq;
-use starknet_api::block::{BlockHash, BlockHeader, BlockNumber};
+use starknet_api::block::{Block, BlockHash, BlockHeader, BlockNumber, BlockSignature};
 use starknet_api::core::{ClassHash, CompiledClassHash, ContractAddress, Nonce, PatriciaKey};
 use starknet_api::deprecated_contract_class::ContractClass as DeprecatedContractClass;
 use starknet_api::hash::StarkHash;
-use starknet_api::state::{ContractClass, StateDiff, StorageKey};
+use starknet_api::state::{ContractClass, StateDiff, StorageKey, ThinStateDiff};
 use starknet_api::{felt, patricia_key};
 use starknet_client::reader::objects::pending_data::{
     AcceptedOnL2ExtraData,
@@ -26,21 +33,37 @@ use starknet_client::reader::objects::pending_data::{
 use starknet_client::reader::objects::state::StateDiff as ClientStateDiff;
 use starknet_client::reader::objects::transaction::Transaction as ClientTransaction;
 use starknet_client::reader::{DeclaredClassHashEntry, PendingData};
-use test_utils::{get_rng, GetTestInstance};
+use test_utils::{get_rng, get_test_block, GetTestInstance};
 use tokio::sync::RwLock;
+use validator::Validate;
 
+use crate::circuit_breaker::CircuitBreaker;
+use crate::event_bus::{EventKind, SyncEventBus, SyncNotification, SUBSCRIBER_CHANNEL_CAPACITY};
+use crate::last_error::LastErrorRecorder;
 use crate::sources::base_layer::MockBaseLayerSourceTrait;
-use crate::sources::central::MockCentralSourceTrait;
+use crate::sources::central::{
+    BlocksStream,
+    CentralError,
+    MockCentralSourceTrait,
+    StateUpdatesStream,
+};
 use crate::sources::pending::MockPendingSourceTrait;
+use crate::status::SyncStatusHandle;
+use crate::synced::SyncedHandle;
 use crate::{
     sort_state_diff,
     stream_new_base_layer_block,
+    stream_new_blocks,
+    stream_new_compiled_classes,
+    stream_new_state_diffs,
     sync_pending_data,
+    wait_for_header,
     GenericStateSync,
     StateSyncError,
     SyncConfig,
     SyncEvent,
     GENESIS_HASH,
+    SYNCED_BLOCKS_TOLERANCE,
 };
 
 // TODO(anatg): Add a test to check that the sync calls the sort_state_diff function
@@ -171,6 +194,429 @@ async fn stream_new_base_layer_block_no_blocks_on_base_layer() {
     assert_matches!(event, SyncEvent::NewBaseLayerBlock { block_number: BlockNumber(1), .. });
 }
 
+// Returns a stream of the state updates in the given range that fails on the given block.
+fn state_updates_stream_failing_on(
+    initial_block_number: BlockNumber,
+    up_to_block_number: BlockNumber,
+    failing_block_number: Option<BlockNumber>,
+) -> StateUpdatesStream<'static> {
+    stream! {
+        for block_number in initial_block_number.iter_up_to(up_to_block_number) {
+            if Some(block_number) == failing_block_number {
+                yield Err(CentralError::BlockNotFound { block_number });
+                return;
+            }
+            yield Ok((block_number, BlockHash::default(), StateDiff::default(), IndexMap::new()));
+        }
+    }
+    .boxed()
+}
+
+#[tokio::test]
+async fn stream_new_state_diffs_retries_failed_block() {
+    const FAILING_BLOCK_NUMBER: BlockNumber = BlockNumber(3);
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+
+    // Header marker points to to block number 5.
+    add_headers(5, &mut writer);
+
+    // Block 3 fails twice. The first failure is in the stream of the whole range and the second is
+    // in the first retry of the block on its own. The rest of the range is downloaded in a new
+    // stream.
+    let mut mock = MockCentralSourceTrait::new();
+    mock.expect_stream_state_updates()
+        .with(eq(BlockNumber(0)), eq(BlockNumber(5)))
+        .times(1)
+        .returning(|initial, up_to| {
+            state_updates_stream_failing_on(initial, up_to, Some(FAILING_BLOCK_NUMBER))
+        });
+    let mut failing_block_number = Some(FAILING_BLOCK_NUMBER);
+    mock.expect_stream_state_updates()
+        .with(eq(FAILING_BLOCK_NUMBER), eq(FAILING_BLOCK_NUMBER.unchecked_next()))
+        .times(2)
+        .returning(move |initial, up_to| {
+            state_updates_stream_failing_on(initial, up_to, failing_block_number.take())
+        });
+    mock.expect_stream_state_updates()
+        .with(eq(FAILING_BLOCK_NUMBER.unchecked_next()), eq(BlockNumber(5)))
+        .times(1)
+        .returning(|initial, up_to| state_updates_stream_failing_on(initial, up_to, None));
+
+    let mut stream = stream_new_state_diffs(
+        reader,
+        Arc::new(mock),
+        Duration::from_millis(0),
+        10,
+        1,
+        0,
+        SyncConfig::default().central_retry_policy(),
+    )
+    .boxed();
+
+    for expected_block_number in 0..5 {
+        let event = stream.next().await.unwrap().unwrap();
+        assert_matches!(
+            event,
+            SyncEvent::StateDiffAvailable { block_number, .. }
+            if block_number == BlockNumber(expected_block_number)
+        );
+        writer
+            .begin_rw_txn()
+            .unwrap()
+            .append_state_diff(BlockNumber(expected_block_number), ThinStateDiff::default())
+            .unwrap()
+            .commit()
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn stream_new_state_diffs_downloads_a_large_gap_in_batches() {
+    const N_BLOCKS: u64 = 7;
+    const MAX_STREAM_SIZE: u32 = 3;
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    add_headers(N_BLOCKS, &mut writer);
+
+    // The gap of 7 blocks is split into ranges of at most 3 blocks, each requested once.
+    let mut mock = MockCentralSourceTrait::new();
+    for (initial, up_to) in [(0, 3), (3, 6), (6, 7)] {
+        mock.expect_stream_state_updates()
+            .with(eq(BlockNumber(initial)), eq(BlockNumber(up_to)))
+            .times(1)
+            .returning(|initial, up_to| state_updates_stream_failing_on(initial, up_to, None));
+    }
+
+    let mut stream = stream_new_state_diffs(
+        reader,
+        Arc::new(mock),
+        Duration::from_millis(0),
+        MAX_STREAM_SIZE,
+        1,
+        0,
+        SyncConfig::default().central_retry_policy(),
+    )
+    .boxed();
+
+    for expected_block_number in 0..N_BLOCKS {
+        let event = stream.next().await.unwrap().unwrap();
+        assert_matches!(
+            event,
+            SyncEvent::StateDiffAvailable { block_number, .. }
+            if block_number == BlockNumber(expected_block_number)
+        );
+        // Storing the state diff advances the state marker, from which the next batch starts.
+        writer
+            .begin_rw_txn()
+            .unwrap()
+            .append_state_diff(BlockNumber(expected_block_number), ThinStateDiff::default())
+            .unwrap()
+            .commit()
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn stream_new_state_diffs_leaves_the_newest_blocks_until_confirmed() {
+    const MIN_CONFIRMATIONS: u64 = 3;
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    add_headers(5, &mut writer);
+
+    // Only the state diffs of blocks with at least 3 blocks after them are requested.
+    let mut mock = MockCentralSourceTrait::new();
+    for (initial, up_to) in [(0, 2), (2, 4)] {
+        mock.expect_stream_state_updates()
+            .with(eq(BlockNumber(initial)), eq(BlockNumber(up_to)))
+            .times(1)
+            .returning(|initial, up_to| state_updates_stream_failing_on(initial, up_to, None));
+    }
+
+    let mut stream = stream_new_state_diffs(
+        reader,
+        Arc::new(mock),
+        Duration::from_millis(0),
+        10,
+        1,
+        MIN_CONFIRMATIONS,
+        SyncConfig::default().central_retry_policy(),
+    )
+    .boxed();
+
+    for expected_block_number in 0..4 {
+        // Two more blocks confirm blocks 2 and 3.
+        if expected_block_number == 2 {
+            for block_number in 5..7 {
+                let header = BlockHeader {
+                    block_number: BlockNumber(block_number),
+                    block_hash: BlockHash(block_number.into()),
+                    ..BlockHeader::default()
+                };
+                writer
+                    .begin_rw_txn()
+                    .unwrap()
+                    .append_header(BlockNumber(block_number), &header)
+                    .unwrap()
+                    .commit()
+                    .unwrap();
+            }
+        }
+        let event = stream.next().await.unwrap().unwrap();
+        assert_matches!(
+            event,
+            SyncEvent::StateDiffAvailable { block_number, .. }
+            if block_number == BlockNumber(expected_block_number)
+        );
+        writer
+            .begin_rw_txn()
+            .unwrap()
+            .append_state_diff(BlockNumber(expected_block_number), ThinStateDiff::default())
+            .unwrap()
+            .commit()
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn stream_new_blocks_yields_bodies_in_block_order() {
+    const N_BLOCKS: u64 = 4;
+    let ((reader, _writer), _temp_dir) = get_test_storage();
+
+    // Each block has a different number of transactions, so that its body can be told apart.
+    let blocks = (0..N_BLOCKS)
+        .map(|block_number| {
+            let n_transactions = usize::try_from(block_number).unwrap() + 1;
+            let mut block = get_test_block(n_transactions, None, None, None);
+            block.header.block_number = BlockNumber(block_number);
+            block
+        })
+        .collect::<Vec<_>>();
+
+    let mut mock = MockCentralSourceTrait::new();
+    mock.expect_get_latest_block().returning(|| {
+        Ok(Some(BlockHashAndNumber {
+            block_hash: BlockHash::default(),
+            block_number: BlockNumber(N_BLOCKS - 1),
+        }))
+    });
+    let streamed_blocks = blocks.clone();
+    mock.expect_stream_new_blocks()
+        .with(eq(BlockNumber(0)), eq(BlockNumber(N_BLOCKS)))
+        .times(1)
+        .returning(move |_, _| {
+            let streamed_blocks = streamed_blocks.clone();
+            let blocks_stream: BlocksStream<'_> = stream! {
+                for block in streamed_blocks {
+                    yield Ok((block.header.block_number, block, BlockSignature::default()));
+                }
+            }
+            .boxed();
+            blocks_stream
+        });
+
+    let mut stream = stream_new_blocks(
+        reader,
+        Arc::new(mock),
+        Arc::new(MockPendingSourceTrait::new()),
+        Arc::new(RwLock::new(None)),
+        Arc::new(RwLock::new(PendingData::default())),
+        Arc::new(RwLock::new(PendingClasses::default())),
+        true,
+        Duration::from_millis(0),
+        Duration::from_millis(0),
+        1000,
+        SyncConfig::default().central_retry_policy(),
+    )
+    .boxed();
+
+    // The header and body of a block arrive together, so bodies keep the order of the headers.
+    for expected_block in blocks {
+        let event = stream.next().await.unwrap().unwrap();
+        assert_matches!(
+            event,
+            SyncEvent::BlockAvailable { block_number, block, .. }
+            if block_number == expected_block.header.block_number && block == expected_block
+        );
+    }
+}
+
+const STATE_DIFF_DOWNLOAD_DELAY: Duration = Duration::from_millis(20);
+
+// Returns a stream of the state updates in the given range, where each state update takes longer to
+// download the lower its block number is.
+fn delayed_state_updates_stream(
+    initial_block_number: BlockNumber,
+    up_to_block_number: BlockNumber,
+    n_blocks: u64,
+) -> StateUpdatesStream<'static> {
+    stream! {
+        for block_number in initial_block_number.iter_up_to(up_to_block_number) {
+            let delay_factor = u32::try_from(n_blocks - block_number.0).unwrap();
+            tokio::time::sleep(STATE_DIFF_DOWNLOAD_DELAY * delay_factor).await;
+            yield Ok((block_number, BlockHash::default(), StateDiff::default(), IndexMap::new()));
+        }
+    }
+    .boxed()
+}
+
+// Streams the state diffs of the first n_blocks blocks, asserts they arrive in order and returns
+// the time it took.
+async fn stream_delayed_state_diffs(
+    n_blocks: u64,
+    max_parallel_downloads: usize,
+    memory_budget_bytes: usize,
+) -> Duration {
+    let (reader, mut writer) = get_test_storage().0;
+    add_headers(n_blocks, &mut writer);
+
+    let mut mock = MockCentralSourceTrait::new();
+    mock.expect_stream_state_updates()
+        .returning(move |initial, up_to| delayed_state_updates_stream(initial, up_to, n_blocks));
+    mock.expect_memory_budget_bytes().return_const(memory_budget_bytes);
+
+    let mut stream = stream_new_state_diffs(
+        reader,
+        Arc::new(mock),
+        Duration::from_millis(0),
+        1000,
+        max_parallel_downloads,
+        0,
+        SyncConfig::default().central_retry_policy(),
+    )
+    .boxed();
+
+    let start = Instant::now();
+    for expected_block_number in 0..n_blocks {
+        let event = stream.next().await.unwrap().unwrap();
+        assert_matches!(
+            event,
+            SyncEvent::StateDiffAvailable { block_number, .. }
+            if block_number == BlockNumber(expected_block_number)
+        );
+    }
+    start.elapsed()
+}
+
+#[tokio::test]
+async fn stream_new_state_diffs_in_parallel() {
+    const N_BLOCKS: u64 = 8;
+
+    let sequential_duration = stream_delayed_state_diffs(N_BLOCKS, 1, usize::MAX).await;
+    let parallel_duration =
+        stream_delayed_state_diffs(N_BLOCKS, N_BLOCKS as usize, usize::MAX).await;
+    assert!(
+        parallel_duration * 2 < sequential_duration,
+        "Parallel download took {parallel_duration:?}, sequential download took \
+         {sequential_duration:?}."
+    );
+}
+
+#[tokio::test]
+async fn memory_budget_limits_parallel_state_diff_downloads() {
+    const N_BLOCKS: u64 = 8;
+
+    // A single state diff is downloaded at a time if the budget can't hold two of them.
+    let limited_duration = stream_delayed_state_diffs(N_BLOCKS, N_BLOCKS as usize, 1).await;
+    let parallel_duration =
+        stream_delayed_state_diffs(N_BLOCKS, N_BLOCKS as usize, usize::MAX).await;
+    assert!(
+        parallel_duration * 2 < limited_duration,
+        "Parallel download took {parallel_duration:?}, download limited by the memory budget \
+         took {limited_duration:?}."
+    );
+}
+
+// Streams the state diffs of two blocks, where the header of the second block is reverted while
+// the state diffs are downloaded, and asserts that only the state diff of the first block is
+// emitted.
+async fn stream_state_diffs_with_reverted_header(max_parallel_downloads: usize) {
+    let (reader, mut writer) = get_test_storage().0;
+    add_headers(2, &mut writer);
+    let writer = Arc::new(Mutex::new(writer));
+
+    let mut mock = MockCentralSourceTrait::new();
+    let mut is_header_reverted = false;
+    let mock_writer = writer.clone();
+    mock.expect_stream_state_updates().returning(move |initial, up_to| {
+        if !is_header_reverted {
+            let (txn, _, _) = mock_writer
+                .lock()
+                .unwrap()
+                .begin_rw_txn()
+                .unwrap()
+                .revert_header(BlockNumber(1))
+                .unwrap();
+            txn.commit().unwrap();
+            is_header_reverted = true;
+        }
+        delayed_state_updates_stream(initial, up_to, 2)
+    });
+    mock.expect_memory_budget_bytes().return_const(usize::MAX);
+
+    let mut stream = stream_new_state_diffs(
+        reader,
+        Arc::new(mock),
+        Duration::from_millis(1),
+        1000,
+        max_parallel_downloads,
+        0,
+        SyncConfig::default().central_retry_policy(),
+    )
+    .boxed();
+
+    let event = stream.next().await.unwrap().unwrap();
+    assert_matches!(event, SyncEvent::StateDiffAvailable { block_number: BlockNumber(0), .. });
+    writer
+        .lock()
+        .unwrap()
+        .begin_rw_txn()
+        .unwrap()
+        .append_state_diff(BlockNumber(0), ThinStateDiff::default())
+        .unwrap()
+        .commit()
+        .unwrap();
+
+    // The state diff of block 1 is dropped without failing the stream, which waits for the header
+    // of block 1 to be stored again.
+    let result = tokio::time::timeout(Duration::from_millis(200), stream.next()).await;
+    assert!(result.is_err(), "Unexpected result {result:?}.");
+}
+
+#[tokio::test]
+async fn state_diff_without_header_is_not_emitted() {
+    stream_state_diffs_with_reverted_header(1).await;
+    stream_state_diffs_with_reverted_header(2).await;
+}
+
+#[tokio::test]
+async fn wait_for_header_until_it_is_stored() {
+    const SLEEP_DURATION: Duration = Duration::from_millis(50);
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    add_headers(1, &mut writer);
+
+    assert!(wait_for_header(&reader, BlockNumber(0), SLEEP_DURATION).await.unwrap());
+
+    // The header of block 1 is stored after the first check.
+    let store_header = tokio::spawn(async move {
+        tokio::time::sleep(SLEEP_DURATION / 2).await;
+        let header = BlockHeader {
+            block_number: BlockNumber(1),
+            block_hash: BlockHash(1_u64.into()),
+            ..BlockHeader::default()
+        };
+        writer
+            .begin_rw_txn()
+            .unwrap()
+            .append_header(BlockNumber(1), &header)
+            .unwrap()
+            .commit()
+            .unwrap();
+    });
+    assert!(wait_for_header(&reader, BlockNumber(1), SLEEP_DURATION).await.unwrap());
+    store_header.await.unwrap();
+
+    // The wait is bounded.
+    assert!(!wait_for_header(&reader, BlockNumber(2), SLEEP_DURATION).await.unwrap());
+}
+
 #[test]
 fn store_base_layer_block_test() {
     let (reader, mut writer) = get_test_storage().0;
@@ -200,6 +646,10 @@ fn store_base_layer_block_test() {
         reader,
         writer,
         sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
     };
 
     // Trying to store a block without a header in the storage.
@@ -218,6 +668,576 @@ fn store_base_layer_block_test() {
     assert_eq!(base_layer_marker, BlockNumber(1));
 }
 
+#[tokio::test]
+async fn each_declared_class_gets_a_single_compiled_class_stored() {
+    const N_BLOCKS: u64 = 3;
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+
+    // Block 0 declares a class, block 1 declares no classes and block 2 declares two classes.
+    let declared_classes = [
+        vec![ClassHash(felt!("0x1"))],
+        vec![],
+        vec![ClassHash(felt!("0x2")), ClassHash(felt!("0x3"))],
+    ];
+    add_headers(N_BLOCKS, &mut writer);
+    for (block_number, class_hashes) in declared_classes.iter().enumerate() {
+        let state_diff = ThinStateDiff {
+            declared_classes: class_hashes
+                .iter()
+                .map(|class_hash| (*class_hash, CompiledClassHash::default()))
+                .collect(),
+            ..ThinStateDiff::default()
+        };
+        writer
+            .begin_rw_txn()
+            .unwrap()
+            .append_state_diff(BlockNumber(block_number.try_into().unwrap()), state_diff)
+            .unwrap()
+            .commit()
+            .unwrap();
+    }
+
+    let mut mock = MockCentralSourceTrait::new();
+    let compiled_classes: Vec<_> = declared_classes
+        .iter()
+        .flatten()
+        .map(|class_hash| (*class_hash, CompiledClassHash::default(), CasmContractClass::default()))
+        .collect();
+    mock.expect_stream_compiled_classes()
+        .with(eq(BlockNumber(0)), eq(BlockNumber(N_BLOCKS)))
+        .times(1)
+        .returning(move |_, _| {
+            futures_util::stream::iter(compiled_classes.clone()).map(Ok).boxed()
+        });
+    let mut stream =
+        stream_new_compiled_classes(reader.clone(), Arc::new(mock), Duration::from_millis(10), 10)
+            .boxed();
+
+    let mut gen_state_sync = GenericStateSync {
+        config: SyncConfig::default(),
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(MockCentralSourceTrait::new()),
+        pending_source: Arc::new(MockPendingSourceTrait::new()),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(MockBaseLayerSourceTrait::new()),
+        reader: reader.clone(),
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    for _ in declared_classes.iter().flatten() {
+        let event = stream.next().await.unwrap().unwrap();
+        assert_matches!(event, SyncEvent::CompiledClassAvailable { .. });
+        gen_state_sync.process_sync_event(event).await.unwrap();
+    }
+    // Once all the compiled classes are stored, the stream waits for new state diffs.
+    assert!(tokio::time::timeout(Duration::from_millis(100), stream.next()).await.is_err());
+
+    let txn = reader.begin_ro_txn().unwrap();
+    for class_hash in declared_classes.iter().flatten() {
+        assert!(txn.get_casm(class_hash).unwrap().is_some());
+    }
+    assert_eq!(txn.get_compiled_class_marker().unwrap(), BlockNumber(N_BLOCKS));
+}
+
+#[tokio::test]
+async fn event_bus_consumers_receive_only_their_kind() {
+    let ((reader, writer), _temp_dir) = get_test_storage();
+    let block_hash = BlockHash(felt!("0x1"));
+    let mut gen_state_sync = GenericStateSync {
+        config: SyncConfig::default(),
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(MockCentralSourceTrait::new()),
+        pending_source: Arc::new(MockPendingSourceTrait::new()),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(MockBaseLayerSourceTrait::new()),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    let mut reorg_receiver = gen_state_sync.subscribe(EventKind::Reorg);
+    let mut progress_receiver = gen_state_sync.subscribe(EventKind::Progress);
+
+    let block =
+        Block { header: BlockHeader { block_hash, ..BlockHeader::default() }, ..Block::default() };
+    gen_state_sync
+        .process_sync_event(SyncEvent::BlockAvailable {
+            block_number: BlockNumber(0),
+            block,
+            signature: BlockSignature::default(),
+        })
+        .await
+        .unwrap();
+    gen_state_sync
+        .process_sync_event(SyncEvent::NewBaseLayerBlock {
+            block_number: BlockNumber(0),
+            block_hash,
+        })
+        .await
+        .unwrap();
+    gen_state_sync.revert_blocks_to(BlockNumber(0)).unwrap();
+
+    assert_matches!(
+        progress_receiver.try_recv().unwrap(),
+        SyncNotification::Stored(SyncEvent::BlockAvailable { block_number: BlockNumber(0), .. })
+    );
+    assert_matches!(
+        progress_receiver.try_recv().unwrap(),
+        SyncNotification::Stored(SyncEvent::NewBaseLayerBlock { block_number: BlockNumber(0), .. })
+    );
+    assert!(progress_receiver.try_recv().is_err());
+
+    assert_matches!(
+        reorg_receiver.try_recv().unwrap(),
+        SyncNotification::BlockReverted { block_number: BlockNumber(0), block_hash: reverted_hash }
+        if reverted_hash == block_hash
+    );
+    assert_matches!(
+        reorg_receiver.try_recv().unwrap(),
+        SyncNotification::Reverted { from: BlockNumber(1), to: BlockNumber(0) }
+    );
+    assert!(reorg_receiver.try_recv().is_err());
+}
+
+#[test]
+fn event_bus_drops_notifications_of_a_full_subscriber() {
+    let mut event_bus = SyncEventBus::default();
+    let mut full_receiver = event_bus.subscribe(EventKind::Progress);
+    let mut other_receiver = event_bus.subscribe(EventKind::Progress);
+
+    for _ in 0..SUBSCRIBER_CHANNEL_CAPACITY {
+        event_bus.publish(&SyncNotification::CaughtUp);
+    }
+    for _ in 0..SUBSCRIBER_CHANNEL_CAPACITY {
+        other_receiver.try_recv().unwrap();
+    }
+    // The channel of full_receiver is full, so it misses this notification.
+    event_bus.publish(&SyncNotification::CaughtUp);
+    other_receiver.try_recv().unwrap();
+    for _ in 0..SUBSCRIBER_CHANNEL_CAPACITY {
+        full_receiver.try_recv().unwrap();
+    }
+    assert!(full_receiver.try_recv().is_err());
+
+    // Once it received the waiting notifications, the subscriber receives new ones.
+    event_bus.publish(&SyncNotification::CaughtUp);
+    full_receiver.try_recv().unwrap();
+
+    // Subscribers that dropped their receiver are removed.
+    drop(full_receiver);
+    drop(other_receiver);
+    assert!(event_bus.has_subscribers(EventKind::Progress));
+    event_bus.publish(&SyncNotification::CaughtUp);
+    assert!(!event_bus.has_subscribers(EventKind::Progress));
+}
+
+#[tokio::test]
+async fn deep_reorg_reverts_all_blocks_together() {
+    const N_BLOCKS: u64 = 6;
+    const N_REVERTED_BLOCKS: u64 = 5;
+    const FIRST_REVERTED_BLOCK: BlockNumber = BlockNumber(N_BLOCKS - N_REVERTED_BLOCKS);
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let mut block = BlockBuilder::genesis().append(&mut writer);
+    let mut block_hashes = vec![block.header.block_hash];
+    for _ in 1..N_BLOCKS {
+        block = BlockBuilder::child_of(&block.header).append(&mut writer);
+        block_hashes.push(block.header.block_hash);
+    }
+
+    // Central agrees only on the first block.
+    let mut central_mock = MockCentralSourceTrait::new();
+    let first_block_hash = block_hashes[0];
+    central_mock.expect_get_block_hash().returning(move |block_number| {
+        Ok(Some(if block_number < FIRST_REVERTED_BLOCK {
+            first_block_hash
+        } else {
+            BlockHash(felt!("0x666"))
+        }))
+    });
+    let mut gen_state_sync = GenericStateSync {
+        config: SyncConfig::default(),
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(central_mock),
+        pending_source: Arc::new(MockPendingSourceTrait::new()),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(MockBaseLayerSourceTrait::new()),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    let mut reorg_receiver = gen_state_sync.subscribe(EventKind::Reorg);
+
+    gen_state_sync.handle_block_reverts().await.unwrap();
+
+    for block_number in (FIRST_REVERTED_BLOCK.0..N_BLOCKS).rev() {
+        let expected_block_hash = block_hashes[usize::try_from(block_number).unwrap()];
+        assert_matches!(
+            reorg_receiver.try_recv().unwrap(),
+            SyncNotification::BlockReverted { block_number: reverted_block_number, block_hash }
+            if reverted_block_number == BlockNumber(block_number)
+                && block_hash == expected_block_hash
+        );
+    }
+    assert_matches!(
+        reorg_receiver.try_recv().unwrap(),
+        SyncNotification::Reverted { from: BlockNumber(N_BLOCKS), to } if to == FIRST_REVERTED_BLOCK
+    );
+    assert!(reorg_receiver.try_recv().is_err());
+
+    let txn = gen_state_sync.reader.begin_ro_txn().unwrap();
+    assert_eq!(txn.get_header_marker().unwrap(), FIRST_REVERTED_BLOCK);
+    assert_eq!(txn.get_body_marker().unwrap(), FIRST_REVERTED_BLOCK);
+    assert_eq!(txn.get_state_marker().unwrap(), FIRST_REVERTED_BLOCK);
+    for block_number in FIRST_REVERTED_BLOCK.0..N_BLOCKS {
+        assert!(txn.get_state_diff(BlockNumber(block_number)).unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn invalid_state_diff_from_peer_penalizes_it() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    add_headers(2, &mut writer);
+    let mut gen_state_sync = GenericStateSync {
+        config: SyncConfig::default(),
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(MockCentralSourceTrait::new()),
+        pending_source: Arc::new(MockPendingSourceTrait::new()),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(MockBaseLayerSourceTrait::new()),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    let mut progress_receiver = gen_state_sync.subscribe(EventKind::Progress);
+    let mut penalty_receiver = gen_state_sync.subscribe(EventKind::PeerPenalty);
+    let peer_id = PeerId::random();
+    let state_diff_event = |block_number: u64, block_hash: u64| SyncEvent::StateDiffAvailable {
+        block_number: BlockNumber(block_number),
+        block_hash: BlockHash(block_hash.into()),
+        state_diff: StateDiff::default(),
+        deployed_contract_class_definitions: IndexMap::new(),
+        source_peer: Some(peer_id),
+    };
+
+    gen_state_sync.process_sync_event(state_diff_event(0, 0)).await.unwrap();
+    assert_matches!(
+        progress_receiver.try_recv().unwrap(),
+        SyncNotification::Stored(SyncEvent::StateDiffAvailable {
+            block_number: BlockNumber(0),
+            source_peer,
+            ..
+        }) if source_peer == Some(peer_id)
+    );
+    assert!(penalty_receiver.try_recv().is_err());
+
+    // The stored hash of block 1 is 1.
+    let result = gen_state_sync.process_sync_event(state_diff_event(1, 5)).await;
+    assert_matches!(
+        result,
+        Err(StateSyncError::PeerSuppliedInvalidStateDiff {
+            peer_id: penalized_peer_id,
+            block_number: BlockNumber(1),
+        }) if penalized_peer_id == peer_id
+    );
+    assert_matches!(
+        penalty_receiver.try_recv().unwrap(),
+        SyncNotification::PeerSuppliedInvalidData {
+            peer_id: penalized_peer_id,
+            block_number: BlockNumber(1),
+        } if penalized_peer_id == peer_id
+    );
+    assert!(progress_receiver.try_recv().is_err());
+    assert_eq!(
+        gen_state_sync.reader.begin_ro_txn().unwrap().get_state_marker().unwrap(),
+        BlockNumber(1)
+    );
+}
+
+#[tokio::test]
+async fn state_diff_before_its_header_is_stored_again_after_the_header() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    add_headers(1, &mut writer);
+    let mut gen_state_sync = GenericStateSync {
+        config: SyncConfig::default(),
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(MockCentralSourceTrait::new()),
+        pending_source: Arc::new(MockPendingSourceTrait::new()),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(MockBaseLayerSourceTrait::new()),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    let mut penalty_receiver = gen_state_sync.subscribe(EventKind::PeerPenalty);
+    let state_diff_event =
+        |block_number: u64, source_peer: Option<PeerId>| SyncEvent::StateDiffAvailable {
+            block_number: BlockNumber(block_number),
+            block_hash: BlockHash(block_number.into()),
+            state_diff: StateDiff::default(),
+            deployed_contract_class_definitions: IndexMap::new(),
+            source_peer,
+        };
+    gen_state_sync.process_sync_event(state_diff_event(0, None)).await.unwrap();
+
+    // The header of block 1 isn't stored yet, from both the central source and a peer.
+    for source_peer in [None, Some(PeerId::random())] {
+        let result = gen_state_sync.process_sync_event(state_diff_event(1, source_peer)).await;
+        assert_matches!(
+            result,
+            Err(StateSyncError::StateDiffWithoutMatchingHeader { block_number: BlockNumber(1) })
+        );
+        assert_eq!(
+            gen_state_sync.reader.begin_ro_txn().unwrap().get_state_marker().unwrap(),
+            BlockNumber(1)
+        );
+    }
+    // A missing header isn't the fault of the peer.
+    assert!(penalty_receiver.try_recv().is_err());
+
+    let header = BlockHeader {
+        block_number: BlockNumber(1),
+        block_hash: BlockHash(1_u64.into()),
+        ..BlockHeader::default()
+    };
+    gen_state_sync
+        .writer
+        .begin_rw_txn()
+        .unwrap()
+        .append_header(BlockNumber(1), &header)
+        .unwrap()
+        .commit()
+        .unwrap();
+    gen_state_sync.process_sync_event(state_diff_event(1, None)).await.unwrap();
+    assert_eq!(
+        gen_state_sync.reader.begin_ro_txn().unwrap().get_state_marker().unwrap(),
+        BlockNumber(2)
+    );
+}
+
+#[tokio::test]
+async fn state_diff_with_truncated_class_definitions_is_rejected() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    add_headers(2, &mut writer);
+    let mut gen_state_sync = GenericStateSync {
+        config: SyncConfig::default(),
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(MockCentralSourceTrait::new()),
+        pending_source: Arc::new(MockPendingSourceTrait::new()),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(MockBaseLayerSourceTrait::new()),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    let class_hash0 = ClassHash(felt!("0x10"));
+    let class_hash1 = ClassHash(felt!("0x11"));
+    let deployed_contract_class_definitions = IndexMap::from([
+        (class_hash0, DeprecatedContractClass::default()),
+        (class_hash1, DeprecatedContractClass::default()),
+    ]);
+    let state_diff_event = |block_number: u64,
+                            deployed_contracts: IndexMap<ContractAddress, ClassHash>,
+                            deployed_contract_class_definitions| {
+        SyncEvent::StateDiffAvailable {
+            block_number: BlockNumber(block_number),
+            block_hash: BlockHash(block_number.into()),
+            state_diff: StateDiff { deployed_contracts, ..StateDiff::default() },
+            deployed_contract_class_definitions,
+            source_peer: None,
+        }
+    };
+    let deployed_contracts = IndexMap::from([
+        (ContractAddress(patricia_key!("0x1")), class_hash0),
+        (ContractAddress(patricia_key!("0x2")), class_hash1),
+    ]);
+
+    // The definition of the class of the second deployed contract is missing.
+    let mut truncated_class_definitions = deployed_contract_class_definitions.clone();
+    truncated_class_definitions.pop();
+    let result = gen_state_sync
+        .process_sync_event(state_diff_event(
+            0,
+            deployed_contracts.clone(),
+            truncated_class_definitions,
+        ))
+        .await;
+    assert_matches!(
+        result,
+        Err(StateSyncError::InconsistentStateDiff { block_number: BlockNumber(0) })
+    );
+    assert_eq!(
+        gen_state_sync.reader.begin_ro_txn().unwrap().get_state_marker().unwrap(),
+        BlockNumber(0)
+    );
+
+    gen_state_sync
+        .process_sync_event(state_diff_event(
+            0,
+            deployed_contracts,
+            deployed_contract_class_definitions,
+        ))
+        .await
+        .unwrap();
+
+    // Classes that were defined in an earlier block don't need to be defined again.
+    let deployed_contracts = IndexMap::from([(ContractAddress(patricia_key!("0x3")), class_hash1)]);
+    gen_state_sync
+        .process_sync_event(state_diff_event(1, deployed_contracts, IndexMap::new()))
+        .await
+        .unwrap();
+    assert_eq!(
+        gen_state_sync.reader.begin_ro_txn().unwrap().get_state_marker().unwrap(),
+        BlockNumber(2)
+    );
+}
+
+#[tokio::test]
+async fn is_synced_once_state_reaches_latest_block() {
+    const N_BLOCKS: u64 = 3;
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    add_headers(N_BLOCKS, &mut writer);
+    let latest_block = BlockHashAndNumber {
+        block_hash: BlockHash((N_BLOCKS - 1).into()),
+        block_number: BlockNumber(N_BLOCKS - 1),
+    };
+    let mut gen_state_sync = GenericStateSync {
+        config: SyncConfig::default(),
+        shared_highest_block: Arc::new(RwLock::new(Some(latest_block))),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(MockCentralSourceTrait::new()),
+        pending_source: Arc::new(MockPendingSourceTrait::new()),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(MockBaseLayerSourceTrait::new()),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    let mut progress_receiver = gen_state_sync.subscribe(EventKind::Progress);
+    assert!(!gen_state_sync.is_synced());
+
+    let mut caught_up_events = 0;
+    for block_number in 0..N_BLOCKS {
+        gen_state_sync
+            .process_sync_event(SyncEvent::StateDiffAvailable {
+                block_number: BlockNumber(block_number),
+                block_hash: BlockHash(block_number.into()),
+                state_diff: StateDiff::default(),
+                deployed_contract_class_definitions: IndexMap::new(),
+                source_peer: None,
+            })
+            .await
+            .unwrap();
+        // The sync is synced once it's within SYNCED_BLOCKS_TOLERANCE blocks of the latest block.
+        let missing_blocks = N_BLOCKS - block_number - 1;
+        assert_eq!(gen_state_sync.is_synced(), missing_blocks <= SYNCED_BLOCKS_TOLERANCE);
+        while let Ok(event) = progress_receiver.try_recv() {
+            if let SyncNotification::CaughtUp = event {
+                caught_up_events += 1;
+            }
+        }
+    }
+    assert!(gen_state_sync.is_synced());
+    assert_eq!(caught_up_events, 1);
+}
+
+#[tokio::test]
+async fn is_synced_once_state_reaches_latest_confirmed_block() {
+    const N_BLOCKS: u64 = 6;
+    const MIN_CONFIRMATIONS: u64 = 3;
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    add_headers(N_BLOCKS, &mut writer);
+    let latest_block = BlockHashAndNumber {
+        block_hash: BlockHash((N_BLOCKS - 1).into()),
+        block_number: BlockNumber(N_BLOCKS - 1),
+    };
+    let mut gen_state_sync = GenericStateSync {
+        config: SyncConfig {
+            min_confirmations: MIN_CONFIRMATIONS,
+            sync_pending_data: false,
+            ..SyncConfig::default()
+        },
+        shared_highest_block: Arc::new(RwLock::new(Some(latest_block))),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(MockCentralSourceTrait::new()),
+        pending_source: Arc::new(MockPendingSourceTrait::new()),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(MockBaseLayerSourceTrait::new()),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+    let mut progress_receiver = gen_state_sync.subscribe(EventKind::Progress);
+
+    // The state diffs of the newest MIN_CONFIRMATIONS blocks aren't synced.
+    let mut caught_up_events = 0;
+    for block_number in 0..N_BLOCKS - MIN_CONFIRMATIONS {
+        gen_state_sync
+            .process_sync_event(SyncEvent::StateDiffAvailable {
+                block_number: BlockNumber(block_number),
+                block_hash: BlockHash(block_number.into()),
+                state_diff: StateDiff::default(),
+                deployed_contract_class_definitions: IndexMap::new(),
+                source_peer: None,
+            })
+            .await
+            .unwrap();
+        let missing_blocks = N_BLOCKS - MIN_CONFIRMATIONS - block_number - 1;
+        assert_eq!(gen_state_sync.is_synced(), missing_blocks <= SYNCED_BLOCKS_TOLERANCE);
+        while let Ok(event) = progress_receiver.try_recv() {
+            if let SyncNotification::CaughtUp = event {
+                caught_up_events += 1;
+            }
+        }
+    }
+    assert!(gen_state_sync.is_synced());
+    assert_eq!(caught_up_events, 1);
+}
+
+#[test]
+fn min_confirmations_require_pending_sync_to_be_disabled() {
+    let config = SyncConfig { min_confirmations: 1, ..SyncConfig::default() };
+    assert!(config.validate().is_err());
+    assert!(SyncConfig { sync_pending_data: false, ..config }.validate().is_ok());
+    assert!(SyncConfig::default().validate().is_ok());
+}
+
 // Adds to the storage 'headers_num' headers.
 fn add_headers(headers_num: u64, writer: &mut StorageWriter) {
     for i in 0..headers_num {
@@ -280,6 +1300,7 @@ async fn test_pending_sync(
         pending_data_lock.clone(),
         pending_classes_lock.clone(),
         Duration::ZERO,
+        SyncConfig::default().central_retry_policy(),
     )
     .await
     .unwrap();
@@ -770,3 +1791,96 @@ async fn pending_sync_classes_are_cleaned_on_first_pending_data_from_latest_bloc
     )
     .await
 }
+
+#[tokio::test]
+async fn status_reports_fewer_blocks_behind_as_state_diffs_are_stored() {
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    let shared_highest_block = Arc::new(RwLock::new(None));
+    let status_handle = SyncStatusHandle::new(reader, shared_highest_block.clone());
+
+    // The central source wasn't polled yet.
+    let status = status_handle.status().await.unwrap();
+    assert_eq!(status.central_source_tip, None);
+    assert_eq!(status.blocks_behind, None);
+
+    // The central source advances to block 4 and the sync downloads the headers up to it.
+    *shared_highest_block.write().await =
+        Some(BlockHashAndNumber { block_hash: BlockHash::default(), block_number: BlockNumber(4) });
+    add_headers(5, &mut writer);
+    let status = status_handle.status().await.unwrap();
+    assert_eq!(status.header_marker, BlockNumber(5));
+    assert_eq!(status.central_source_tip, Some(BlockNumber(4)));
+    assert_eq!(status.blocks_behind, Some(5));
+
+    for block_number in BlockNumber(0).iter_up_to(BlockNumber(5)) {
+        writer
+            .begin_rw_txn()
+            .unwrap()
+            .append_state_diff(block_number, ThinStateDiff::default())
+            .unwrap()
+            .commit()
+            .unwrap();
+        let status = status_handle.status().await.unwrap();
+        assert_eq!(status.state_marker, block_number.unchecked_next());
+        assert_eq!(status.blocks_behind, Some(4 - block_number.0));
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn stored_state_diffs_and_slow_stores_are_counted() {
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use papyrus_common::metrics as papyrus_metrics;
+    use prometheus_parse::Value::Counter;
+    use test_utils::prometheus_is_contained;
+
+    let handle = PrometheusBuilder::new().install_recorder().unwrap();
+    let ((reader, mut writer), _temp_dir) = get_test_storage();
+    add_headers(2, &mut writer);
+    // With no threshold, every store counts as slow, as it would with a slow storage.
+    let mut gen_state_sync = GenericStateSync {
+        config: SyncConfig { slow_store_warning_threshold: Duration::ZERO, ..Default::default() },
+        shared_highest_block: Arc::new(RwLock::new(None)),
+        pending_data: Arc::new(RwLock::new(PendingData::default())),
+        central_source: Arc::new(MockCentralSourceTrait::new()),
+        pending_source: Arc::new(MockPendingSourceTrait::new()),
+        pending_classes: Arc::new(RwLock::new(PendingClasses::default())),
+        base_layer_source: Arc::new(MockBaseLayerSourceTrait::new()),
+        reader,
+        writer,
+        sequencer_pub_key: None,
+        event_bus: SyncEventBus::default(),
+        synced_handle: SyncedHandle::default(),
+        last_error_recorder: LastErrorRecorder::default(),
+        circuit_breaker: CircuitBreaker::default(),
+    };
+
+    for block_number in BlockNumber(0).iter_up_to(BlockNumber(2)) {
+        gen_state_sync
+            .process_sync_event(SyncEvent::StateDiffAvailable {
+                block_number,
+                block_hash: BlockHash(block_number.0.into()),
+                state_diff: StateDiff::default(),
+                deployed_contract_class_definitions: IndexMap::new(),
+                source_peer: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            prometheus_is_contained(
+                handle.render(),
+                papyrus_metrics::PAPYRUS_SYNC_NUM_STORED_STATE_DIFFS,
+                &[]
+            ),
+            Some(Counter((block_number.0 + 1) as f64))
+        );
+        assert_eq!(
+            prometheus_is_contained(
+                handle.render(),
+                papyrus_metrics::PAPYRUS_SYNC_NUM_SLOW_STORES,
+                &[]
+            ),
+            Some(Counter((block_number.0 + 1) as f64))
+        );
+    }
+}
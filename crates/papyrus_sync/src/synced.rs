@@ -0,0 +1,23 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Tells whether the state is synced up to the latest block of the central source, up to a small
+/// tolerance.
+///
+/// Clones of the handle share the flag, so it can be read while the sync runs.
+#[derive(Clone, Debug, Default)]
+pub struct SyncedHandle {
+    is_synced: Arc<AtomicBool>,
+}
+
+impl SyncedHandle {
+    /// Whether the state is synced.
+    pub fn is_synced(&self) -> bool {
+        self.is_synced.load(Ordering::Acquire)
+    }
+
+    // Sets whether the state is synced and returns whether it was synced before.
+    pub(crate) fn set(&self, is_synced: bool) -> bool {
+        self.is_synced.swap(is_synced, Ordering::AcqRel)
+    }
+}